@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Vendored so builds don't need protoc installed
+    // SAFETY: build scripts are single threaded
+    unsafe {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_prost_build::compile_protos("proto/telegram_proxy.proto")?;
+    Ok(())
+}
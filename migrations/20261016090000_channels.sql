@@ -0,0 +1,12 @@
+-- Channels and the bot identity that delivers their messages
+CREATE TABLE channels
+(
+    name       text PRIMARY KEY NOT NULL CHECK (LENGTH(name) > 0),
+    bot        text             NOT NULL DEFAULT 'default',
+    created_at integer          NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+-- Existing channels were all served by the single bot
+INSERT INTO channels (name)
+SELECT DISTINCT channel_name
+FROM subscriptions;
@@ -0,0 +1,35 @@
+-- Channels are namespaced by tenant, so two products can use the same channel name
+CREATE TABLE channels_new
+(
+    tenant_id  text    NOT NULL DEFAULT 'default' CHECK (LENGTH(tenant_id) > 0),
+    name       text    NOT NULL CHECK (LENGTH(name) > 0),
+    bot        text    NOT NULL DEFAULT 'default',
+    created_at integer NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (tenant_id, name)
+) STRICT;
+
+INSERT INTO channels_new (name, bot, created_at)
+SELECT name, bot, created_at
+FROM channels;
+
+DROP TABLE channels;
+ALTER TABLE channels_new RENAME TO channels;
+
+-- Existing subscriptions belong to the default tenant
+ALTER TABLE subscriptions ADD COLUMN tenant_id text NOT NULL DEFAULT 'default';
+
+DROP INDEX idx_telegram_channel;
+CREATE UNIQUE INDEX idx_telegram_channel ON subscriptions (tenant_id, telegram_id, channel_name);
+
+DROP INDEX idx_channel;
+CREATE INDEX idx_channel ON subscriptions (tenant_id, channel_name);
+
+-- API keys identify the tenant a request acts on; only a hash of the key is stored
+CREATE TABLE api_keys
+(
+    id         integer PRIMARY KEY NOT NULL,
+    tenant_id  text                NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    name       text                NOT NULL,
+    key_hash   text                NOT NULL UNIQUE,
+    created_at integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
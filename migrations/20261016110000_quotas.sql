@@ -0,0 +1,12 @@
+-- Optional per-key daily message quota, NULL means unlimited
+ALTER TABLE api_keys ADD COLUMN daily_quota integer;
+
+-- Messages sent per UTC day; api_key_id is 0 for requests without a tenant API key
+CREATE TABLE daily_usage
+(
+    day        text    NOT NULL,
+    tenant_id  text    NOT NULL,
+    api_key_id integer NOT NULL DEFAULT 0,
+    messages   integer NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, tenant_id, api_key_id)
+) STRICT;
@@ -0,0 +1,12 @@
+-- Inbound webhooks rendering arbitrary JSON into a message with handlebars templates
+CREATE TABLE hooks
+(
+    id         text PRIMARY KEY NOT NULL,
+    tenant_id  text             NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    -- Both may reference fields of the inbound JSON, e.g. "{{alert.channel}}"
+    channel    text             NOT NULL,
+    template   text             NOT NULL,
+    created_at integer          NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_hooks_tenant ON hooks (tenant_id);
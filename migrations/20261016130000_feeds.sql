@@ -0,0 +1,21 @@
+-- RSS/Atom feeds polled into channels
+CREATE TABLE feeds
+(
+    id             integer PRIMARY KEY NOT NULL,
+    tenant_id      text                NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    url            text                NOT NULL,
+    channel_name   text                NOT NULL,
+    -- NULL until the first poll, which only records the existing entries
+    last_polled_at integer,
+    created_at     integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_feeds_tenant ON feeds (tenant_id);
+
+CREATE TABLE feed_entries
+(
+    feed_id    integer NOT NULL REFERENCES feeds (id) ON DELETE CASCADE,
+    guid       text    NOT NULL,
+    created_at integer NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (feed_id, guid)
+) STRICT;
@@ -0,0 +1,17 @@
+-- Messages sent on a cron schedule
+CREATE TABLE recurring_messages
+(
+    id           integer PRIMARY KEY NOT NULL,
+    tenant_id    text                NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    channel_name text                NOT NULL,
+    body         text                NOT NULL,
+    cron         text                NOT NULL,
+    -- IANA name the cron expression is evaluated in
+    timezone     text                NOT NULL DEFAULT 'UTC',
+    -- NULL once the schedule has no future occurrence
+    next_run_at  integer,
+    created_at   integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_recurring_next_run ON recurring_messages (next_run_at);
+CREATE INDEX idx_recurring_tenant ON recurring_messages (tenant_id);
@@ -0,0 +1,19 @@
+-- Messages sent through the API, with the Telegram copy delivered to each recipient
+CREATE TABLE messages
+(
+    id           integer PRIMARY KEY NOT NULL,
+    tenant_id    text                NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    -- NULL for broadcasts
+    channel_name text,
+    text         text                NOT NULL,
+    created_at   integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE TABLE message_copies
+(
+    message_id          integer NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    telegram_id         integer NOT NULL,
+    bot                 text    NOT NULL,
+    telegram_message_id integer NOT NULL,
+    PRIMARY KEY (message_id, telegram_id)
+) STRICT;
@@ -0,0 +1,36 @@
+-- Native Telegram polls sent through the API
+CREATE TABLE polls
+(
+    id                      integer PRIMARY KEY NOT NULL,
+    tenant_id               text                NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    channel_name            text                NOT NULL,
+    question                text                NOT NULL,
+    -- JSON array of option texts
+    options                 text                NOT NULL,
+    is_anonymous            integer             NOT NULL,
+    allows_multiple_answers integer             NOT NULL,
+    created_at              integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+-- The poll each recipient got; Telegram gives every copy its own poll id
+CREATE TABLE poll_copies
+(
+    poll_id          integer NOT NULL REFERENCES polls (id) ON DELETE CASCADE,
+    telegram_id      integer NOT NULL,
+    telegram_poll_id text    NOT NULL UNIQUE,
+    -- JSON array of votes per option from the latest poll update, the only
+    -- results Telegram reports for anonymous polls
+    voter_counts     text,
+    total_voters     integer,
+    PRIMARY KEY (poll_id, telegram_id)
+) STRICT;
+
+-- Answers of non-anonymous polls, from poll_answer updates
+CREATE TABLE poll_answers
+(
+    poll_id    integer NOT NULL REFERENCES polls (id) ON DELETE CASCADE,
+    voter_id   integer NOT NULL,
+    -- JSON array of 0-based option indexes
+    option_ids text    NOT NULL,
+    PRIMARY KEY (poll_id, voter_id)
+) STRICT;
@@ -0,0 +1,2 @@
+-- Default for requests that don't say whether recipients may forward or save messages
+ALTER TABLE channels ADD COLUMN protect_content integer NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Default for requests that don't say whether links in messages get a preview
+ALTER TABLE channels ADD COLUMN disable_link_preview integer NOT NULL DEFAULT 0;
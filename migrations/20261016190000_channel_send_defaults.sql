@@ -0,0 +1,3 @@
+-- Remaining send options a channel can default: formatting and notification sound
+ALTER TABLE channels ADD COLUMN parse_mode text;
+ALTER TABLE channels ADD COLUMN silent integer NOT NULL DEFAULT 0;
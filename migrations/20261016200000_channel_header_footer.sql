@@ -0,0 +1,3 @@
+-- Handlebars templates placed above and below every message sent to the channel
+ALTER TABLE channels ADD COLUMN header text;
+ALTER TABLE channels ADD COLUMN footer text;
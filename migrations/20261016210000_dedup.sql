@@ -0,0 +1,16 @@
+-- Seconds an identical message to the channel gets the earlier result instead of
+-- being sent again, NULL disables
+ALTER TABLE channels ADD COLUMN dedup_window_secs integer;
+
+-- Results of recent sends to channels with a dedup window, by hash of the message
+CREATE TABLE recent_sends
+(
+    tenant_id    text    NOT NULL,
+    channel_name text    NOT NULL,
+    message_hash text    NOT NULL,
+    sent         integer NOT NULL,
+    errors       integer NOT NULL,
+    message_id   integer REFERENCES messages (id) ON DELETE SET NULL,
+    created_at   integer NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (tenant_id, channel_name, message_hash)
+) STRICT;
@@ -0,0 +1,10 @@
+-- Deliveries per UTC day and channel; channel_name is '' for broadcasts
+CREATE TABLE daily_stats
+(
+    day          text    NOT NULL,
+    tenant_id    text    NOT NULL,
+    channel_name text    NOT NULL,
+    sent         integer NOT NULL DEFAULT 0,
+    errors       integer NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, tenant_id, channel_name)
+) STRICT;
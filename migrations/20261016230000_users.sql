@@ -0,0 +1,18 @@
+-- People administering the proxy, authenticating with their own token over HTTP
+-- or with their Telegram account in the bot; only a hash of the token is stored
+CREATE TABLE users
+(
+    id          integer PRIMARY KEY NOT NULL,
+    name        text                NOT NULL UNIQUE CHECK (LENGTH(name) > 0),
+    token_hash  text                NOT NULL UNIQUE,
+    telegram_id integer UNIQUE,
+    created_at  integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE TABLE user_roles
+(
+    user_id    integer NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role       text    NOT NULL CHECK (role IN ('admin', 'channel_owner', 'viewer')),
+    created_at integer NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (user_id, role)
+) STRICT;
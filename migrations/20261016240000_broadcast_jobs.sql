@@ -0,0 +1,25 @@
+-- Background broadcasts still delivering, so they resume after a restart
+CREATE TABLE broadcast_jobs
+(
+    id         text PRIMARY KEY NOT NULL,
+    tenant_id  text             NOT NULL,
+    message    text             NOT NULL,
+    -- JSON send options
+    options    text             NOT NULL,
+    pin        integer          NOT NULL DEFAULT 0,
+    total      integer          NOT NULL,
+    sent       integer          NOT NULL DEFAULT 0,
+    errors     integer          NOT NULL DEFAULT 0,
+    created_at integer          NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+-- Every recipient of a job; attempted ones keep the id of the Telegram message they got
+CREATE TABLE broadcast_job_recipients
+(
+    job_id              text    NOT NULL REFERENCES broadcast_jobs (id) ON DELETE CASCADE,
+    telegram_id         integer NOT NULL,
+    bot                 text    NOT NULL,
+    attempted           integer NOT NULL DEFAULT 0,
+    telegram_message_id integer,
+    PRIMARY KEY (job_id, telegram_id)
+) STRICT;
@@ -0,0 +1,18 @@
+-- Text messages that couldn't be delivered to a recipient even after retrying,
+-- kept with everything needed to send them again
+CREATE TABLE dead_letters
+(
+    id           integer PRIMARY KEY NOT NULL,
+    tenant_id    text                NOT NULL,
+    -- NULL for broadcasts
+    channel_name text,
+    telegram_id  integer             NOT NULL,
+    bot          text                NOT NULL,
+    message      text                NOT NULL,
+    -- JSON send options
+    options      text                NOT NULL,
+    failure      text                NOT NULL,
+    created_at   integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_dead_letters_tenant ON dead_letters (tenant_id, id);
@@ -0,0 +1,2 @@
+-- Messages the channel may send per minute, later ones waiting for room, NULL disables
+ALTER TABLE channels ADD COLUMN rate_limit_per_minute integer;
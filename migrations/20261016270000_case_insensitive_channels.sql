@@ -0,0 +1,38 @@
+-- Channel names are case-insensitive and stored lowercased. Case variants of a name
+-- become one channel: the oldest keeps its bot and settings, and a user subscribed
+-- to several variants keeps one subscription.
+DELETE FROM subscriptions
+WHERE EXISTS (SELECT 1
+              FROM subscriptions o
+              WHERE o.tenant_id = subscriptions.tenant_id
+                AND o.telegram_id = subscriptions.telegram_id
+                AND lower(o.channel_name) = lower(subscriptions.channel_name)
+                AND o.id < subscriptions.id);
+UPDATE subscriptions SET channel_name = lower(channel_name) WHERE channel_name != lower(channel_name);
+
+DELETE FROM channels
+WHERE EXISTS (SELECT 1
+              FROM channels o
+              WHERE o.tenant_id = channels.tenant_id
+                AND lower(o.name) = lower(channels.name)
+                AND (o.created_at, o.rowid) < (channels.created_at, channels.rowid));
+UPDATE channels SET name = lower(name) WHERE name != lower(name);
+
+-- Counts of the variants add up
+INSERT INTO daily_stats (day, tenant_id, channel_name, sent, errors)
+SELECT day, tenant_id, lower(channel_name), SUM(sent), SUM(errors)
+FROM daily_stats
+WHERE channel_name != lower(channel_name)
+GROUP BY day, tenant_id, lower(channel_name)
+ON CONFLICT (day, tenant_id, channel_name) DO UPDATE
+SET sent = sent + excluded.sent, errors = errors + excluded.errors;
+DELETE FROM daily_stats WHERE channel_name != lower(channel_name);
+
+-- Only a dedup cache, the variants' entries are dropped
+DELETE FROM recent_sends WHERE channel_name != lower(channel_name);
+
+UPDATE feeds SET channel_name = lower(channel_name) WHERE channel_name != lower(channel_name);
+UPDATE recurring_messages SET channel_name = lower(channel_name) WHERE channel_name != lower(channel_name);
+UPDATE messages SET channel_name = lower(channel_name) WHERE channel_name != lower(channel_name);
+UPDATE polls SET channel_name = lower(channel_name) WHERE channel_name != lower(channel_name);
+UPDATE dead_letters SET channel_name = lower(channel_name) WHERE channel_name != lower(channel_name);
@@ -0,0 +1,11 @@
+-- Other names a channel can be sent to or subscribed by, e.g. its name before a rename
+CREATE TABLE channel_aliases
+(
+    tenant_id    text    NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    alias        text    NOT NULL CHECK (LENGTH(alias) > 0),
+    channel_name text    NOT NULL CHECK (LENGTH(channel_name) > 0),
+    created_at   integer NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (tenant_id, alias)
+) STRICT;
+
+CREATE INDEX idx_channel_aliases_channel ON channel_aliases (tenant_id, channel_name);
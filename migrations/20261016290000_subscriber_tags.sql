@@ -0,0 +1,11 @@
+-- Free-form labels on a tenant's subscribers, e.g. their region, to target sends with
+CREATE TABLE subscriber_tags
+(
+    tenant_id   text    NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    telegram_id integer NOT NULL,
+    tag         text    NOT NULL CHECK (LENGTH(tag) > 0),
+    created_at  integer NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (tenant_id, telegram_id, tag)
+) STRICT;
+
+CREATE INDEX idx_subscriber_tags_tag ON subscriber_tags (tenant_id, tag);
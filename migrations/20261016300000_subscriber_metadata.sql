@@ -0,0 +1,10 @@
+-- A small JSON object per subscriber, e.g. their team, locale or id in another system,
+-- listed with their subscriptions and available to personalized messages
+CREATE TABLE subscriber_metadata
+(
+    tenant_id   text    NOT NULL CHECK (LENGTH(tenant_id) > 0),
+    telegram_id integer NOT NULL,
+    metadata    text    NOT NULL CHECK (json_valid(metadata)),
+    updated_at  integer NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (tenant_id, telegram_id)
+) STRICT;
@@ -0,0 +1,10 @@
+-- Telegram profile of everyone who talked to a bot, so subscribers can be told apart.
+-- Separate from `users`, which are the people calling the API.
+CREATE TABLE telegram_users
+(
+    telegram_id   integer PRIMARY KEY NOT NULL,
+    username      text,
+    first_name    text    NOT NULL,
+    language_code text,
+    updated_at    integer NOT NULL DEFAULT (unixepoch())
+) STRICT;
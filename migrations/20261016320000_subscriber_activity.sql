@@ -0,0 +1,14 @@
+-- When each subscriber last got a message from a tenant, to spot dormant ones
+CREATE TABLE subscriber_activity
+(
+    tenant_id         text    NOT NULL,
+    telegram_id       integer NOT NULL,
+    last_delivered_at integer NOT NULL,
+    PRIMARY KEY (tenant_id, telegram_id)
+) STRICT;
+
+-- Last time the user messaged a bot, updated with every message unlike `updated_at`
+ALTER TABLE telegram_users
+    ADD COLUMN last_seen_at integer NOT NULL DEFAULT 0;
+UPDATE telegram_users
+SET last_seen_at = updated_at;
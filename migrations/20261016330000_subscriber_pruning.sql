@@ -0,0 +1,37 @@
+-- Runs of failed deliveries per subscriber, so the ones who are gone can be pruned.
+-- Rebuilt as a failed delivery may come before any successful one.
+CREATE TABLE subscriber_activity_new
+(
+    tenant_id         text    NOT NULL,
+    telegram_id       integer NOT NULL,
+    last_delivered_at integer,
+    -- First failure since the last successful delivery, NULL while they're reachable
+    failing_since     integer,
+    -- Set when a failure showed they blocked the bot, cleared like `failing_since`
+    blocked_at        integer,
+    -- Flagged by the pruning job instead of removed
+    flagged_at        integer,
+    PRIMARY KEY (tenant_id, telegram_id)
+) STRICT;
+
+INSERT INTO subscriber_activity_new (tenant_id, telegram_id, last_delivered_at)
+SELECT tenant_id, telegram_id, last_delivered_at
+FROM subscriber_activity;
+
+DROP TABLE subscriber_activity;
+ALTER TABLE subscriber_activity_new RENAME TO subscriber_activity;
+
+-- What the pruning job did, for the report
+CREATE TABLE pruned_subscribers
+(
+    id          integer PRIMARY KEY NOT NULL,
+    tenant_id   text                NOT NULL,
+    telegram_id integer             NOT NULL,
+    reason      text                NOT NULL CHECK (reason IN ('blocked', 'failing')),
+    action      text                NOT NULL CHECK (action IN ('removed', 'flagged')),
+    -- JSON array of the channels they were subscribed to
+    channels    text                NOT NULL,
+    pruned_at   integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_pruned_subscribers_tenant ON pruned_subscribers (tenant_id, pruned_at);
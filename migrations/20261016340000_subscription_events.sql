@@ -0,0 +1,19 @@
+-- Every subscribe and unsubscribe, kept after the subscription itself is gone
+CREATE TABLE subscription_events
+(
+    id           integer PRIMARY KEY NOT NULL,
+    tenant_id    text                NOT NULL,
+    telegram_id  integer             NOT NULL,
+    channel_name text                NOT NULL,
+    event        text                NOT NULL CHECK (event IN ('subscribed', 'unsubscribed')),
+    -- What made the change: a bot command, an API call or the pruning job
+    source       text                NOT NULL CHECK (source IN ('bot', 'api', 'pruning')),
+    created_at   integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_subscription_events_channel ON subscription_events (tenant_id, channel_name, created_at);
+
+-- Subscriptions so far could only be made through the bot
+INSERT INTO subscription_events (tenant_id, telegram_id, channel_name, event, source, created_at)
+SELECT tenant_id, telegram_id, channel_name, 'subscribed', 'bot', created_at
+FROM subscriptions;
@@ -0,0 +1,23 @@
+-- Links of sent messages rewritten to pass through GET /r/{token}
+CREATE TABLE tracked_links
+(
+    id         integer PRIMARY KEY NOT NULL,
+    token      text                NOT NULL UNIQUE,
+    tenant_id  text                NOT NULL,
+    -- NULL until the message is stored, after its delivery
+    message_id integer REFERENCES messages (id) ON DELETE CASCADE,
+    url        text                NOT NULL,
+    created_at integer             NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_tracked_links_message ON tracked_links (message_id);
+
+CREATE TABLE link_clicks
+(
+    link_id     integer NOT NULL REFERENCES tracked_links (id) ON DELETE CASCADE,
+    -- Recipient whose copy of the message the link was in
+    telegram_id integer NOT NULL,
+    created_at  integer NOT NULL DEFAULT (unixepoch())
+) STRICT;
+
+CREATE INDEX idx_link_clicks_link ON link_clicks (link_id);
@@ -0,0 +1,2 @@
+-- Finds the stored message a chat's Telegram message is a copy of, e.g. for button presses
+CREATE INDEX idx_message_copies_telegram_message ON message_copies (telegram_id, telegram_message_id);
@@ -0,0 +1,2 @@
+-- Listed when users search channels in the bots' inline mode
+ALTER TABLE channels ADD COLUMN public integer NOT NULL DEFAULT 0;
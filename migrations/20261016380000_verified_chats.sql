@@ -0,0 +1,7 @@
+-- Chats that passed the bot's challenge, needed before a first subscription when
+-- SUBSCRIBE_CHALLENGE is on
+CREATE TABLE verified_chats
+(
+    telegram_id integer PRIMARY KEY NOT NULL,
+    verified_at integer NOT NULL DEFAULT (unixepoch())
+) STRICT;
@@ -0,0 +1,14 @@
+-- Subscriptions to these channels wait for the user to press Confirm
+ALTER TABLE channels ADD COLUMN double_opt_in integer NOT NULL DEFAULT 0;
+
+-- Subscriptions waiting for confirmation, dropped once confirmed or expired
+CREATE TABLE pending_subscriptions
+(
+    id           integer PRIMARY KEY NOT NULL,
+    tenant_id    text    NOT NULL,
+    telegram_id  integer NOT NULL,
+    channel_name text    NOT NULL,
+    bot          text    NOT NULL,
+    created_at   integer NOT NULL DEFAULT (unixepoch()),
+    UNIQUE (tenant_id, telegram_id, channel_name)
+) STRICT;
@@ -0,0 +1,2 @@
+-- Messages sent to these channels carry a button unsubscribing the recipient
+ALTER TABLE channels ADD COLUMN unsubscribe_button integer NOT NULL DEFAULT 0;
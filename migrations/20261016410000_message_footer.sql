@@ -0,0 +1,2 @@
+-- Footer template a send chose instead of the channel's, '' for none, so edits keep it
+ALTER TABLE messages ADD COLUMN footer text;
@@ -0,0 +1,2 @@
+-- Parse mode and inline buttons a message was sent with, as JSON, so edits keep them
+ALTER TABLE messages ADD COLUMN format text;
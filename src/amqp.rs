@@ -0,0 +1,71 @@
+//! Consumes `{channel, message}` messages from a RabbitMQ queue
+
+use anyhow::Result;
+use futures::StreamExt;
+use lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions, BasicRejectOptions,
+    QueueDeclareOptions,
+};
+use lapin::types::FieldTable;
+use lapin::{Connection, ConnectionProperties};
+
+use crate::config::AmqpConfig;
+use crate::service::{ServiceError, Services};
+
+/// Delivers every message of the queue, acknowledging it only once delivery finished
+pub async fn consume(config: AmqpConfig, services: Services) -> Result<()> {
+    let connection = Connection::connect(&config.url, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+    channel
+        .queue_declare(
+            &config.queue,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    channel
+        .basic_qos(config.prefetch, BasicQosOptions::default())
+        .await?;
+
+    let mut consumer = channel
+        .basic_consume(
+            &config.queue,
+            "telegram-bot-proxy",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    log::info!(
+        "Consuming AMQP queue '{}' for tenant '{}'",
+        config.queue,
+        config.tenant
+    );
+
+    while let Some(delivery) = consumer.next().await {
+        let delivery = delivery?;
+        match crate::ingest::deliver_record(&services, &config.tenant, &delivery.data).await {
+            Ok(_) => delivery.ack(BasicAckOptions::default()).await?,
+            // Requeued so it's retried once the database is back
+            Err(ServiceError::Database(e)) => {
+                log::error!("Database error: {}", e);
+                delivery
+                    .nack(BasicNackOptions {
+                        requeue: true,
+                        ..Default::default()
+                    })
+                    .await?
+            }
+            Err(e) => {
+                log::error!("Rejecting AMQP message: {}", e);
+                delivery
+                    .reject(BasicRejectOptions { requeue: false })
+                    .await?
+            }
+        }
+    }
+
+    anyhow::bail!("AMQP consumer on '{}' closed", config.queue)
+}
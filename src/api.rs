@@ -1,13 +1,99 @@
-use actix_web::{HttpResponse, Result, get, post, web};
-use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix_multipart::Multipart;
+use actix_web::error::JsonPayloadError;
+use actix_web::http::{StatusCode, header};
+use actix_web::{HttpRequest, HttpResponse, delete, get, post, put, web};
+use bytes::BytesMut;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::future::LocalBoxFuture;
+use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use sqlx::SqlitePool;
-use teloxide::prelude::*;
+use teloxide::types::{LinkPreviewOptions, ParseMode};
+use tokio::sync::broadcast::error::RecvError;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::config::Config;
+use crate::db::{AliasOutcome, ChannelSettings, MergeOutcome, RenameOutcome};
+use crate::delivery::{
+    CallbackButton, DeliveryReport, FailedRecipient, Failure, MAX_CALLBACK_DATA_BYTES,
+    MAX_MESSAGE_CHARS, SendOptions, link_preview, text_len,
+};
+use crate::jobs::{Cancellation, JobProgress};
+use crate::lanes::Priority;
+use crate::locations::NewLocation;
+use crate::media::{MediaKind, MediaOptions, MediaSource, NewMedia, NewMediaGroup, NewSticker};
+use crate::polls::NewPoll;
+use crate::quota::Usage;
+use crate::roles::Role;
+use crate::service::{ErrorCode, ServiceError, Services, Tenant};
 
-#[derive(Deserialize, Serialize)]
+/// What every handler returns
+pub type Result<T, E = ApiError> = std::result::Result<T, E>;
+
+#[derive(Deserialize, Serialize, Validate)]
 pub struct SendMessageRequest {
+    #[validate(custom(function = "valid_channel_name"))]
     channel_name: String,
+    #[validate(custom(function = "valid_message"))]
     message: String,
+    /// Pin the delivered message in each recipient's chat
+    #[serde(default)]
+    pin: bool,
+    /// Formatting of `message`, the channel's default when unset
+    parse_mode: Option<ParseMode>,
+    /// Deliver without a notification sound, the channel's default when unset
+    silent: Option<bool>,
+    /// Keep recipients from forwarding or saving the message, the channel's default when unset
+    protect_content: Option<bool>,
+    /// `message_id` of an earlier send to reply to in each recipient's chat
+    reply_to: Option<i64>,
+    /// Send links without a preview, the channel's default when unset
+    disable_link_preview: Option<bool>,
+    /// Telegram's full preview options, taking precedence over `disable_link_preview`
+    link_preview_options: Option<LinkPreviewOptions>,
+    /// `transactional` (default) or `bulk`
+    priority: Option<Priority>,
+    /// Only send to the subscribers having every one of these tags
+    #[serde(default)]
+    #[validate(custom(function = "valid_tags"))]
+    tags: Vec<String>,
+    /// Render `message` per recipient as a template of their metadata
+    #[serde(default)]
+    personalize: bool,
+    /// Rewrite the links in `message` to record who clicks them
+    #[serde(default)]
+    track_clicks: bool,
+    /// Rows of inline buttons, presses are posted to the button callback
+    #[serde(default)]
+    #[validate(custom(function = "valid_buttons"))]
+    buttons: Vec<Vec<CallbackButton>>,
+    /// Template replacing the channel's footer for this message, `""` for none
+    #[validate(custom(function = "valid_template"))]
+    footer: Option<String>,
+}
+
+impl SendMessageRequest {
+    fn options(&self) -> SendOptions {
+        SendOptions {
+            parse_mode: self.parse_mode,
+            silent: self.silent,
+            protect_content: self.protect_content,
+            link_preview: self
+                .link_preview_options
+                .clone()
+                .or(self.disable_link_preview.map(link_preview)),
+            reply_to: self.reply_to,
+            priority: self.priority,
+            personalize: self.personalize,
+            track_clicks: self.track_clicks,
+            buttons: self.buttons.clone(),
+            footer: self.footer.clone(),
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -15,6 +101,21 @@ pub struct SendMessageResponse {
     sent: usize,
     errors: usize,
     channel: String,
+    /// Id to edit the delivered copies by, absent when nothing was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<i64>,
+    /// Copies pinned, when pinning was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pinned: Option<usize>,
+    /// The counts are those of an identical message sent within the channel's dedup window
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    duplicate: bool,
+    /// telegram_ids of the recipients that weren't reached, by reason
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<Failure, Vec<i64>>,
+    /// The same recipients with their reason, in the order they failed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed_recipients: Vec<FailedRecipient>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +123,20 @@ pub struct BroadcastResponse {
     sent: usize,
     errors: usize,
     total_subscribers: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<i64>,
+    /// Copies pinned, when pinning was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pinned: Option<usize>,
+    /// telegram_ids of the recipients that weren't reached, by reason
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<Failure, Vec<i64>>,
+    /// The same recipients with their reason, in the order they failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    failed_recipients: Vec<FailedRecipient>,
+    /// Only the admins got the message, `total_subscribers` is who would have
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    preview: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -29,6 +144,23 @@ pub struct Subscription {
     telegram_id: i64,
     channel_name: String,
     created_at: Option<DateTime<Utc>>,
+    /// Stored with `PUT /subscribers/{telegram_id}/metadata`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Value>,
+    /// Telegram profile, known once the subscriber talked to a bot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    first_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language_code: Option<String>,
+    /// Last message the tenant got through to them
+    last_delivered_at: Option<DateTime<Utc>>,
+    /// Last time they messaged a bot
+    last_seen_at: Option<DateTime<Utc>>,
+    /// Set by the pruning job when it's configured to flag unreachable subscribers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flagged_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -37,6 +169,23 @@ pub struct GetSubscriptionsResponse {
     total: usize,
 }
 
+/// Flags flipped by background tasks in `serve`, read by `/ready`
+#[derive(Default)]
+pub struct Readiness {
+    bot_validated: AtomicBool,
+    shutting_down: AtomicBool,
+}
+
+impl Readiness {
+    pub fn mark_bot_validated(&self) {
+        self.bot_validated.store(true, Ordering::Relaxed);
+    }
+
+    pub fn mark_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+}
+
 #[get("/health")]
 pub async fn health_check() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -44,201 +193,762 @@ pub async fn health_check() -> Result<HttpResponse> {
     })))
 }
 
+#[get("/live")]
+pub async fn live() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+    })))
+}
+
+/// Migrations run before the server starts, so only the pool, the bot token
+/// and shutdown state need checking here
+#[get("/ready")]
+pub async fn ready(
+    readiness: web::Data<Readiness>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let database = pool.acquire().await.is_ok();
+    let bot = readiness.bot_validated.load(Ordering::Relaxed);
+    let shutting_down = readiness.shutting_down.load(Ordering::Relaxed);
+
+    let body = serde_json::json!({
+        "status": if database && bot && !shutting_down { "ready" } else { "not_ready" },
+        "checks": {
+            "database": database,
+            "bot": bot,
+            "shutting_down": shutting_down,
+        },
+    });
+
+    if database && bot && !shutting_down {
+        Ok(HttpResponse::Ok().json(body))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(body))
+    }
+}
+
+#[get("/metrics")]
+pub async fn metrics(pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+    let body = format!(
+        "# HELP db_pool_max_connections Maximum number of database connections.\n\
+         # TYPE db_pool_max_connections gauge\n\
+         db_pool_max_connections {}\n\
+         # HELP db_pool_connections Database connections currently open.\n\
+         # TYPE db_pool_connections gauge\n\
+         db_pool_connections {}\n\
+         # HELP db_pool_idle_connections Open database connections not in use.\n\
+         # TYPE db_pool_idle_connections gauge\n\
+         db_pool_idle_connections {}\n",
+        pool.options().get_max_connections(),
+        pool.size(),
+        pool.num_idle(),
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body))
+}
+
+/// `report` as the caller may see it: anonymous callers only get the counts, without
+/// the telegram_ids of who wasn't reached
+fn report_for(tenant: &Tenant, mut report: DeliveryReport) -> DeliveryReport {
+    if !tenant.is_authenticated() {
+        report.failures.clear();
+        report.failed_recipients.clear();
+    }
+    report
+}
+
 #[post("/send-message")]
 pub async fn send_message(
-    req: web::Json<SendMessageRequest>,
-    pool: web::Data<SqlitePool>,
-    bot: web::Data<Bot>,
+    tenant: Tenant,
+    req: Valid<SendMessageRequest>,
+    services: web::Data<Services>,
 ) -> Result<HttpResponse> {
-    if req.message.len() > 1000 {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Message too long (max 1000 chars)"
-        })));
+    let report = services
+        .send_message_with(
+            &tenant,
+            &req.channel_name,
+            &req.message,
+            &req.options(),
+            &req.tags,
+        )
+        .await?;
+    let report = report_for(&tenant, report);
+
+    let pinned = pin_if_requested(&services, &tenant, req.pin, report.message_id).await;
+
+    Ok(HttpResponse::Ok().json(SendMessageResponse {
+        sent: report.sent,
+        errors: report.errors,
+        channel: req.channel_name.clone(),
+        message_id: report.message_id,
+        pinned,
+        duplicate: report.duplicate,
+        failures: report.failures,
+        failed_recipients: report.failed_recipients,
+    }))
+}
+
+/// Pins a just-sent message when the request asked for it, returning the copies pinned
+async fn pin_if_requested(
+    services: &Services,
+    tenant: &Tenant,
+    pin: bool,
+    message_id: Option<i64>,
+) -> Option<usize> {
+    let message_id = message_id.filter(|_| pin)?;
+    match services.pin_message(tenant, message_id).await {
+        Ok(report) => Some(report.sent),
+        Err(e) => {
+            log::error!("Failed to pin message {}: {}", message_id, e);
+            Some(0)
+        }
     }
+}
+
+/// Checks the bearer token carries at least `minimum`: the admin key or a user token
+async fn authorize(req: actix_web::HttpRequest, minimum: Role) -> Result<Role> {
+    let services = req
+        .app_data::<web::Data<Services>>()
+        .expect("services should be registered");
 
-    if !crate::db::validate_channel_name(&req.channel_name) {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid channel name. Only letters, numbers, and underscores are allowed."
-        })));
+    let bearer_token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let role = match bearer_token {
+        Some(token) => services.role(token).await?,
+        None => None,
+    };
+
+    match role {
+        Some(role) if role >= minimum => Ok(role),
+        Some(_) => Err(ApiError::Forbidden(minimum)),
+        None => Err(ApiError::Unauthorized(
+            ErrorCode::Unauthorized,
+            "Invalid or missing authorization",
+        )),
     }
+}
+
+/// Extractor requiring the caller to hold a role
+macro_rules! role_extractor {
+    ($(#[$doc:meta])* $name:ident, $role:expr) => {
+        $(#[$doc])*
+        pub struct $name;
 
-    let subscribers = match crate::db::get_subscribers(&pool, &req.channel_name).await {
-        Ok(subs) => {
-            if subs.is_empty() {
-                return Ok(HttpResponse::Ok().json(SendMessageResponse {
-                    sent: 0,
-                    errors: 0,
-                    channel: req.channel_name.clone(),
-                }));
+        impl actix_web::FromRequest for $name {
+            type Error = ApiError;
+            type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+            fn from_request(
+                req: &actix_web::HttpRequest,
+                _: &mut actix_web::dev::Payload,
+            ) -> Self::Future {
+                let req = req.clone();
+                Box::pin(async move { authorize(req, $role).await.map(|_| $name) })
             }
-            subs
-        }
-        Err(e) => {
-            log::error!("Database error: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error occurred"
-            })));
         }
     };
+}
 
-    let results = futures::future::join_all(subscribers.into_iter().map(|telegram_id| {
-        let bot = bot.clone();
-        let message = req.message.clone();
-        async move { bot.send_message(ChatId(telegram_id), message).await.is_ok() }
-    }))
-    .await;
+role_extractor!(
+    /// The admin key or an admin user
+    Authenticated,
+    Role::Admin
+);
+role_extractor!(
+    /// Allowed to manage channels
+    ChannelOwner,
+    Role::ChannelOwner
+);
+role_extractor!(
+    /// Allowed to read channels, messages and stats
+    Viewer,
+    Role::Viewer
+);
 
-    let sent = results.iter().filter(|&&success| success).count();
-    let errors = results.len() - sent;
+/// Error a handler answers with, as `{"error": <message>, "code": <code>}` and the
+/// matching status
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(ErrorCode, String),
+    /// Problems with each field that failed validation
+    InvalidFields(BTreeMap<String, Vec<String>>),
+    Unauthorized(ErrorCode, &'static str),
+    /// The caller lacks this role
+    Forbidden(Role),
+    NotFound(ErrorCode, &'static str),
+    Conflict(ErrorCode, &'static str),
+    PayloadTooLarge(String),
+    UnsupportedMediaType,
+    QuotaExceeded {
+        remaining: Option<i64>,
+    },
+    /// A Telegram call the request depends on failed
+    Telegram(Failure),
+    /// Logged, the caller only learns that the database failed
+    Database(anyhow::Error),
+    /// The server is missing configuration, logged
+    Misconfigured(&'static str),
+}
 
-    Ok(HttpResponse::Ok().json(SendMessageResponse {
-        sent,
-        errors,
-        channel: req.channel_name.clone(),
-    }))
+impl ApiError {
+    /// A rejected request without a more specific [`ErrorCode`]
+    pub fn invalid(message: impl Into<String>) -> Self {
+        ApiError::BadRequest(ErrorCode::InvalidRequest, message.into())
+    }
+
+    /// Stable identifier clients can branch on instead of the message
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ApiError::BadRequest(code, _)
+            | ApiError::Unauthorized(code, _)
+            | ApiError::NotFound(code, _)
+            | ApiError::Conflict(code, _) => *code,
+            ApiError::InvalidFields(_) => ErrorCode::ValidationFailed,
+            ApiError::Forbidden(_) => ErrorCode::Forbidden,
+            ApiError::PayloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            ApiError::UnsupportedMediaType => ErrorCode::UnsupportedMediaType,
+            ApiError::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            ApiError::Telegram(Failure::RateLimited) => ErrorCode::RateLimited,
+            ApiError::Telegram(Failure::Network) => ErrorCode::TelegramUnavailable,
+            ApiError::Telegram(_) => ErrorCode::TelegramError,
+            ApiError::Database(_) => ErrorCode::DatabaseError,
+            ApiError::Misconfigured(_) => ErrorCode::ServerMisconfigured,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::BadRequest(_, message) | ApiError::PayloadTooLarge(message) => {
+                write!(f, "{}", message)
+            }
+            ApiError::InvalidFields(_) => write!(f, "Invalid request"),
+            ApiError::Unauthorized(_, message)
+            | ApiError::NotFound(_, message)
+            | ApiError::Conflict(_, message) => write!(f, "{}", message),
+            ApiError::Forbidden(role) => write!(f, "Requires the {} role", role.as_str()),
+            ApiError::UnsupportedMediaType => write!(f, "Content-Type must be application/json"),
+            ApiError::QuotaExceeded { .. } => write!(f, "Daily message quota exceeded"),
+            ApiError::Telegram(failure) => {
+                write!(f, "{}", ServiceError::Telegram(*failure))
+            }
+            ApiError::Database(_) => write!(f, "Database error occurred"),
+            ApiError::Misconfigured(_) => write!(f, "Server configuration error"),
+        }
+    }
+}
+
+impl actix_web::ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(..) | ApiError::InvalidFields(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(..) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(..) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(..) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::QuotaExceeded { .. } | ApiError::Telegram(Failure::RateLimited) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::Telegram(Failure::Network) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Telegram(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Database(_) | ApiError::Misconfigured(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = serde_json::json!({
+            "error": self.to_string(),
+            "code": self.code().as_str(),
+        });
+        match self {
+            ApiError::InvalidFields(fields) => body["fields"] = serde_json::json!(fields),
+            ApiError::QuotaExceeded { remaining } => {
+                body["remaining"] = serde_json::json!(remaining)
+            }
+            ApiError::Database(e) => log::error!("Database error: {}", e),
+            ApiError::Misconfigured(problem) => log::error!("{}", problem),
+            _ => {}
+        }
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+impl From<ServiceError> for ApiError {
+    fn from(e: ServiceError) -> Self {
+        match e {
+            ServiceError::InvalidRequest(code, message) => {
+                ApiError::BadRequest(code, message.to_string())
+            }
+            ServiceError::Unauthorized => {
+                ApiError::Unauthorized(ErrorCode::Unauthorized, "Invalid or missing authorization")
+            }
+            ServiceError::NotFound(code, message) => ApiError::NotFound(code, message),
+            ServiceError::QuotaExceeded { remaining } => ApiError::QuotaExceeded { remaining },
+            ServiceError::Telegram(failure) => ApiError::Telegram(failure),
+            ServiceError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let fields = errors
+            .field_errors()
+            .into_iter()
+            .map(|(field, errors)| {
+                let problems = errors
+                    .iter()
+                    .map(|e| e.message.as_deref().unwrap_or(&e.code).to_string())
+                    .collect();
+                (field.to_string(), problems)
+            })
+            .collect();
+        ApiError::InvalidFields(fields)
+    }
+}
+
+/// JSON body checked against its `validator` rules, answering 400 with every invalid
+/// field otherwise
+pub struct Valid<T>(T);
+
+impl<T> Valid<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
 }
 
-pub struct Authenticated;
+impl<T> std::ops::Deref for Valid<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
 
-impl actix_web::FromRequest for Authenticated {
+impl<T: serde::de::DeserializeOwned + Validate + 'static> actix_web::FromRequest for Valid<T> {
     type Error = actix_web::Error;
-    type Future = std::future::Ready<Result<Self, Self::Error>>;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
 
-    fn from_request(req: &actix_web::HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
-        let super_secret_key = std::env::var("SUPER_SECRET_KEY").unwrap_or_else(|_| String::new());
-
-        if super_secret_key.is_empty() {
-            log::error!("SUPER_SECRET_KEY is not set in environment");
-            return std::future::ready(Err(actix_web::error::ErrorInternalServerError(
-                serde_json::json!({
-                    "error": "Server configuration error"
-                }),
-            )));
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let body = json.await?.into_inner();
+            body.validate().map_err(ApiError::from)?;
+            Ok(Valid(body))
+        })
+    }
+}
+
+/// Answers a JSON body that couldn't be read with an [`ApiError`], instead of plain text
+pub fn json_error(e: JsonPayloadError, max_bytes: usize) -> actix_web::Error {
+    match e {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+            ApiError::PayloadTooLarge(format!("Request body too large (max {} bytes)", max_bytes))
         }
+        JsonPayloadError::ContentType => ApiError::UnsupportedMediaType,
+        JsonPayloadError::Deserialize(e) => {
+            ApiError::BadRequest(ErrorCode::InvalidJson, format!("Invalid JSON: {}", e))
+        }
+        e => ApiError::invalid(e.to_string()),
+    }
+    .into()
+}
 
-        let auth_header = req
-            .headers()
-            .get("Authorization")
-            .and_then(|h| h.to_str().ok());
+fn invalid(message: impl Into<std::borrow::Cow<'static, str>>) -> ValidationError {
+    ValidationError::new("invalid").with_message(message.into())
+}
 
-        match auth_header {
-            Some(header) if header == format!("Bearer {}", super_secret_key) => {
-                std::future::ready(Ok(Authenticated))
-            }
-            _ => std::future::ready(Err(actix_web::error::ErrorUnauthorized(
-                serde_json::json!({
-                    "error": "Invalid or missing authorization"
-                }),
-            ))),
+fn valid_channel_name(channel_name: &str) -> Result<(), ValidationError> {
+    match crate::db::channel_name_error(channel_name) {
+        Some(problem) => Err(invalid(problem)),
+        None => Ok(()),
+    }
+}
+
+fn valid_message(message: &str) -> Result<(), ValidationError> {
+    if text_len(message) > MAX_MESSAGE_CHARS {
+        Err(invalid("Message too long (max 1000 chars)"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Message text that is neither empty nor longer than Telegram allows
+fn valid_message_text(text: &str) -> Result<(), ValidationError> {
+    if text.is_empty() || text_len(text) > MAX_MESSAGE_CHARS {
+        Err(invalid("Must be between 1 and 1000 chars"))
+    } else {
+        Ok(())
+    }
+}
+
+fn valid_channel_names(channels: &[String]) -> Result<(), ValidationError> {
+    if channels.is_empty() {
+        return Err(invalid("Channels cannot be empty"));
+    }
+    channels
+        .iter()
+        .try_for_each(|channel_name| valid_channel_name(channel_name))
+}
+
+fn valid_tags(tags: &[String]) -> Result<(), ValidationError> {
+    match tags.iter().find_map(|tag| crate::db::tag_error(tag)) {
+        Some(problem) => Err(invalid(problem)),
+        None => Ok(()),
+    }
+}
+
+fn valid_buttons(buttons: &[Vec<CallbackButton>]) -> Result<(), ValidationError> {
+    for button in buttons.iter().flatten() {
+        if button.text.trim().is_empty() {
+            return Err(invalid("Button text cannot be empty"));
+        }
+        if button.data.is_empty() || button.data.len() > MAX_CALLBACK_DATA_BYTES {
+            return Err(invalid("Button data must be between 1 and 64 bytes"));
+        }
+        if let Some(prefix) = crate::bot::RESERVED_DATA_PREFIXES
+            .iter()
+            .find(|prefix| button.data.starts_with(*prefix))
+        {
+            return Err(invalid(format!(
+                "Button data cannot start with '{}'",
+                prefix
+            )));
         }
     }
+    Ok(())
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct BroadcastRequest {
-    message: String,
+fn valid_tenant_id(tenant_id: &str) -> Result<(), ValidationError> {
+    if crate::db::validate_tenant_id(tenant_id) {
+        Ok(())
+    } else {
+        Err(invalid(
+            "Invalid tenant id. Only letters, numbers, and underscores are allowed.",
+        ))
+    }
 }
 
-#[post("/broadcast")]
-pub async fn broadcast(
-    _auth: Authenticated,
-    req: web::Json<BroadcastRequest>,
-    pool: web::Data<SqlitePool>,
-    bot: web::Data<Bot>,
-) -> Result<HttpResponse> {
-    // Validate message length
-    if req.message.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Message cannot be empty"
-        })));
+fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        Err(invalid("Cannot be empty"))
+    } else {
+        Ok(())
+    }
+}
+
+fn valid_template(template: &str) -> Result<(), ValidationError> {
+    match crate::hooks::validate(template) {
+        Some(e) => Err(invalid(format!("Invalid template: {}", e))),
+        None => Ok(()),
     }
+}
 
-    if req.message.len() > 1000 {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Message too long (max 1000 chars)"
-        })));
+fn valid_feed_url(url: &str) -> Result<(), ValidationError> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        Ok(())
+    } else {
+        Err(invalid("Feed URL must be http or https"))
     }
+}
 
-    // Get all subscribers from all channels
-    let all_subscribers = match sqlx::query!(
-        "
-        SELECT DISTINCT telegram_id
-        FROM subscriptions
-        "
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    {
-        Ok(rows) => rows.into_iter().map(|r| r.telegram_id).collect::<Vec<_>>(),
-        Err(e) => {
-            log::error!("Database error: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error occurred"
-            })));
+fn valid_cron(cron: &str) -> Result<(), ValidationError> {
+    crate::recurring::parse_schedule(cron)
+        .map(|_| ())
+        .map_err(|e| invalid(format!("Invalid cron expression: {}", e)))
+}
+
+fn valid_timezone(timezone: &str) -> Result<(), ValidationError> {
+    crate::recurring::parse_timezone(timezone)
+        .map(|_| ())
+        .map_err(invalid)
+}
+
+fn valid_parse_mode(parse_mode: &str) -> Result<(), ValidationError> {
+    ParseMode::try_from(parse_mode)
+        .map(|_| ())
+        .map_err(|_| invalid("Invalid parse_mode"))
+}
+
+impl actix_web::FromRequest for Tenant {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &actix_web::HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let services = req
+                .app_data::<web::Data<Services>>()
+                .expect("services should be registered");
+
+            let bearer_token = req
+                .headers()
+                .get("Authorization")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.strip_prefix("Bearer "));
+            let tenant_header = req
+                .headers()
+                .get("X-Tenant-Id")
+                .and_then(|h| h.to_str().ok());
+
+            Ok(services.authenticate(bearer_token, tenant_header).await?)
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+pub struct BroadcastRequest {
+    #[validate(custom(function = "valid_message_text"))]
+    message: String,
+    /// Only subscribers of these channels, each reached once
+    #[validate(custom(function = "valid_channel_names"))]
+    channels: Option<Vec<String>>,
+    /// Respond right away with a job id instead of waiting for delivery
+    #[serde(default, rename = "async")]
+    run_async: bool,
+    #[serde(default)]
+    pin: bool,
+    /// Deliver only to the admins' Telegram accounts to check the formatting
+    #[serde(default)]
+    preview: bool,
+    parse_mode: Option<ParseMode>,
+    #[serde(default)]
+    silent: bool,
+    #[serde(default)]
+    protect_content: bool,
+    #[serde(default)]
+    disable_link_preview: bool,
+    link_preview_options: Option<LinkPreviewOptions>,
+    /// `bulk` (default) or `transactional`
+    priority: Option<Priority>,
+    /// Render `message` per recipient as a template of their metadata
+    #[serde(default)]
+    personalize: bool,
+    /// Rewrite the links in `message` to record who clicks them
+    #[serde(default)]
+    track_clicks: bool,
+    /// Rows of inline buttons, presses are posted to the button callback
+    #[serde(default)]
+    #[validate(custom(function = "valid_buttons"))]
+    buttons: Vec<Vec<CallbackButton>>,
+}
+
+impl BroadcastRequest {
+    fn options(&self) -> SendOptions {
+        SendOptions {
+            parse_mode: self.parse_mode,
+            silent: Some(self.silent),
+            protect_content: Some(self.protect_content),
+            link_preview: Some(
+                self.link_preview_options
+                    .clone()
+                    .unwrap_or_else(|| link_preview(self.disable_link_preview)),
+            ),
+            priority: Some(self.priority.unwrap_or(Priority::Bulk)),
+            personalize: self.personalize,
+            track_clicks: self.track_clicks,
+            buttons: self.buttons.clone(),
+            ..Default::default()
         }
-    };
+    }
+}
+
+#[derive(Serialize)]
+pub struct BroadcastAccepted {
+    job_id: String,
+    total_subscribers: usize,
+}
 
-    let total_subscribers = all_subscribers.len();
+#[post("/broadcast")]
+pub async fn broadcast(
+    _auth: Authenticated,
+    tenant: Tenant,
+    req: Valid<BroadcastRequest>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    // Validates, fetches every subscriber of the tenant (or the channels) and checks the quota
+    let subscribers = services
+        .prepare_broadcast(&tenant, &req.message, req.channels.as_deref())
+        .await?;
 
-    if total_subscribers == 0 {
+    let total_subscribers = subscribers.total;
+    let req = req.into_inner();
+    services.check_click_tracking(&req.options())?;
+    let threshold = services.config.async_broadcast_threshold;
+    let run_async = req.run_async || (threshold > 0 && total_subscribers > threshold);
+    if req.preview {
+        let report = services
+            .preview_broadcast(&tenant, &req.message, &req.options())
+            .await?;
         return Ok(HttpResponse::Ok().json(BroadcastResponse {
-            sent: 0,
-            errors: 0,
-            total_subscribers: 0,
+            sent: report.sent,
+            errors: report.errors,
+            total_subscribers,
+            message_id: None,
+            pinned: None,
+            failures: report.failures,
+            failed_recipients: report.failed_recipients,
+            preview: true,
+        }));
+    }
+    if run_async {
+        let options = req.options();
+        let job_id = services
+            .start_broadcast(
+                tenant,
+                req.channels.as_deref(),
+                req.message,
+                options,
+                req.pin,
+            )
+            .await?;
+        return Ok(HttpResponse::Accepted().json(BroadcastAccepted {
+            job_id,
+            total_subscribers,
         }));
     }
 
-    // Send message to all subscribers
-    let results = futures::future::join_all(all_subscribers.into_iter().map(|telegram_id| {
-        let bot = bot.clone();
-        let message = req.message.clone();
-        async move { bot.send_message(ChatId(telegram_id), message).await.is_ok() }
-    }))
-    .await;
+    let report = services
+        .deliver(
+            &tenant,
+            None,
+            subscribers,
+            &req.message,
+            &req.options(),
+            |_, _, _| {},
+        )
+        .await;
 
-    let sent = results.iter().filter(|&&success| success).count();
-    let errors = results.len() - sent;
+    let pinned = pin_if_requested(&services, &tenant, req.pin, report.message_id).await;
 
     Ok(HttpResponse::Ok().json(BroadcastResponse {
-        sent,
-        errors,
+        sent: report.sent,
+        errors: report.errors,
         total_subscribers,
+        message_id: report.message_id,
+        pinned,
+        failures: report.failures,
+        failed_recipients: report.failed_recipients,
+        preview: false,
     }))
 }
 
+/// Server-sent events with the progress of an async broadcast, ending with a `done` event
+#[get("/broadcasts/{id}/events")]
+pub async fn broadcast_events(
+    _auth: Viewer,
+    tenant: Tenant,
+    id: web::Path<String>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let Some(receiver) = services.jobs.watch(&id, &tenant.id) else {
+        return Err(ApiError::NotFound(
+            ErrorCode::BroadcastNotFound,
+            "Broadcast not found",
+        ));
+    };
+
+    // Emits the current progress, then every change until the job is done
+    let stream = futures::stream::unfold(Some((receiver, true)), |state| async move {
+        let (mut receiver, first) = state?;
+        if !first && receiver.changed().await.is_err() {
+            return None;
+        }
+
+        let progress: JobProgress = receiver.borrow_and_update().clone();
+        let event = if progress.done { "done" } else { "progress" };
+        let data = serde_json::to_string(&progress).expect("progress serializes");
+        let chunk = web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data));
+
+        let next = (!progress.done).then_some((receiver, false));
+        Some((Ok::<_, actix_web::Error>(chunk), next))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream))
+}
+
+/// Progress of an async broadcast, with the final counts once it's done
+#[get("/broadcasts/{id}")]
+pub async fn get_broadcast(
+    _auth: Viewer,
+    tenant: Tenant,
+    id: web::Path<String>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    match services.jobs.watch(&id, &tenant.id) {
+        Some(receiver) => Ok(HttpResponse::Ok().json(&*receiver.borrow())),
+        None => Err(ApiError::NotFound(
+            ErrorCode::BroadcastNotFound,
+            "Broadcast not found",
+        )),
+    }
+}
+
+/// Stops an async broadcast, the recipients it hasn't reached yet are skipped
+#[post("/broadcasts/{id}/cancel")]
+pub async fn cancel_broadcast(
+    _auth: Authenticated,
+    tenant: Tenant,
+    id: web::Path<String>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    match services.jobs.cancel(&id, &tenant.id) {
+        Cancellation::Cancelled(progress) => Ok(HttpResponse::Ok().json(progress)),
+        Cancellation::AlreadyDone => Err(ApiError::Conflict(
+            ErrorCode::BroadcastFinished,
+            "Broadcast already finished",
+        )),
+        Cancellation::NotFound => Err(ApiError::NotFound(
+            ErrorCode::BroadcastNotFound,
+            "Broadcast not found",
+        )),
+    }
+}
+
 #[get("/subscriptions")]
 pub async fn get_subscriptions(
-    _auth: Authenticated,
-    pool: web::Data<SqlitePool>,
+    _auth: Viewer,
+    tenant: Tenant,
+    services: web::Data<Services>,
 ) -> Result<HttpResponse> {
     // Get all subscriptions
-    let subscriptions = match sqlx::query!(
-        "
-        SELECT telegram_id,
-               channel_name,
-               created_at
-        FROM subscriptions
-        ORDER BY channel_name, telegram_id
-        "
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    {
-        Ok(rows) => rows
-            .into_iter()
-            .map(|r| Subscription {
-                telegram_id: r.telegram_id,
-                channel_name: r.channel_name,
-                created_at: DateTime::from_timestamp(r.created_at, 0),
-            })
-            .collect::<Vec<_>>(),
-        Err(e) => {
-            log::error!("Database error: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error occurred"
-            })));
-        }
-    };
+    let subscriptions = services
+        .list_subscriptions(&tenant)
+        .await?
+        .into_iter()
+        .map(|r| Subscription {
+            telegram_id: r.telegram_id,
+            channel_name: r.channel_name,
+            created_at: DateTime::from_timestamp(r.created_at, 0),
+            metadata: r.metadata.and_then(|m| serde_json::from_str(&m).ok()),
+            username: r.username,
+            first_name: r.first_name,
+            language_code: r.language_code,
+            last_delivered_at: r
+                .last_delivered_at
+                .and_then(|t| DateTime::from_timestamp(t, 0)),
+            last_seen_at: r.last_seen_at.and_then(|t| DateTime::from_timestamp(t, 0)),
+            flagged_at: r.flagged_at.and_then(|t| DateTime::from_timestamp(t, 0)),
+        })
+        .collect::<Vec<_>>();
 
     let total = subscriptions.len();
 
@@ -248,9 +958,2187 @@ pub async fn get_subscriptions(
     }))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Serialize)]
+pub struct SubscriberTags {
+    telegram_id: i64,
+    tags: Vec<String>,
+}
+
+fn tags_response(telegram_id: i64, tags: Option<Vec<String>>) -> Result<HttpResponse> {
+    match tags {
+        Some(tags) => Ok(HttpResponse::Ok().json(SubscriberTags { telegram_id, tags })),
+        None => Err(ApiError::NotFound(
+            ErrorCode::SubscriberNotFound,
+            "Subscriber not found",
+        )),
+    }
+}
+
+fn check_tag(tag: &str) -> Result<()> {
+    match crate::db::tag_error(tag) {
+        Some(problem) => Err(ApiError::BadRequest(
+            ErrorCode::InvalidTag,
+            problem.to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[get("/subscribers/{telegram_id}/tags")]
+pub async fn get_subscriber_tags(
+    _auth: Viewer,
+    tenant: Tenant,
+    telegram_id: web::Path<i64>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let telegram_id = telegram_id.into_inner();
+    let tags = crate::db::get_subscriber_tags(&pool, &tenant.id, telegram_id).await?;
+    tags_response(telegram_id, tags)
+}
+
+/// Tags a subscriber, to target sends at subscribers of a channel having the tag
+#[put("/subscribers/{telegram_id}/tags/{tag}")]
+pub async fn add_subscriber_tag(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    path: web::Path<(i64, String)>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let (telegram_id, tag) = path.into_inner();
+    check_tag(&tag)?;
+    crate::db::add_subscriber_tag(&pool, &tenant.id, telegram_id, &tag).await?;
+    let tags = crate::db::get_subscriber_tags(&pool, &tenant.id, telegram_id).await?;
+    tags_response(telegram_id, tags)
+}
+
+#[delete("/subscribers/{telegram_id}/tags/{tag}")]
+pub async fn remove_subscriber_tag(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    path: web::Path<(i64, String)>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let (telegram_id, tag) = path.into_inner();
+    crate::db::remove_subscriber_tag(&pool, &tenant.id, telegram_id, &tag).await?;
+    let tags = crate::db::get_subscriber_tags(&pool, &tenant.id, telegram_id).await?;
+    tags_response(telegram_id, tags)
+}
+
+/// Largest subscriber metadata accepted, serialized
+const MAX_METADATA_BYTES: usize = 4096;
+
+#[derive(Serialize)]
+pub struct SubscriberMetadata {
+    telegram_id: i64,
+    metadata: Value,
+}
+
+/// Replaces what's stored about a subscriber, listed with their subscriptions and
+/// available to personalized messages as `{{metadata.*}}`
+#[put("/subscribers/{telegram_id}/metadata")]
+pub async fn set_subscriber_metadata(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    telegram_id: web::Path<i64>,
+    metadata: web::Json<Value>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let telegram_id = telegram_id.into_inner();
+    let metadata = metadata.into_inner();
+    if !metadata.is_object() {
+        return Err(ApiError::invalid("Metadata must be a JSON object"));
+    }
+    let serialized = metadata.to_string();
+    if serialized.len() > MAX_METADATA_BYTES {
+        return Err(ApiError::invalid("Metadata too large (max 4096 bytes)"));
+    }
+
+    if !crate::db::set_subscriber_metadata(&pool, &tenant.id, telegram_id, &serialized).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::SubscriberNotFound,
+            "Subscriber not found",
+        ));
+    }
+    Ok(HttpResponse::Ok().json(SubscriberMetadata {
+        telegram_id,
+        metadata,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SubscriptionEventsQuery {
+    channel: Option<String>,
+    telegram_id: Option<i64>,
+    since: Option<DateTime<Utc>>,
+    /// `id` of the oldest event of the previous page
+    before: Option<i64>,
+    #[serde(default = "default_messages_limit")]
+    limit: u32,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionEventInfo {
+    id: i64,
+    telegram_id: i64,
+    channel_name: String,
+    /// `subscribed` or `unsubscribed`
+    event: String,
+    /// `bot`, `api` or `pruning`
+    source: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+/// History of subscribes and unsubscribes, newest first
+#[get("/subscription-events")]
+pub async fn list_subscription_events(
+    _auth: Viewer,
+    tenant: Tenant,
+    query: web::Query<SubscriptionEventsQuery>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    let filter = crate::db::SubscriptionEventFilter {
+        channel_name: query.channel,
+        telegram_id: query.telegram_id,
+        since: query.since.map(|since| since.timestamp()),
+        before_id: query.before,
+    };
+    let limit = query.limit.min(100) as i64;
+    let events = crate::db::list_subscription_events(&pool, &tenant.id, &filter, limit).await?;
+    Ok(HttpResponse::Ok().json(
+        events
+            .into_iter()
+            .map(|e| SubscriptionEventInfo {
+                id: e.id,
+                telegram_id: e.telegram_id,
+                channel_name: e.channel_name,
+                event: e.event,
+                source: e.source,
+                created_at: DateTime::from_timestamp(e.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct PruningReportQuery {
+    /// 30 days ago by default
+    since: Option<DateTime<Utc>>,
+    #[serde(default = "default_messages_limit")]
+    limit: u32,
+}
+
+#[derive(Serialize)]
+pub struct PrunedSubscriberInfo {
+    telegram_id: i64,
+    /// `blocked` or `failing`
+    reason: String,
+    /// `removed` or `flagged`
+    action: String,
+    channels: Vec<String>,
+    pruned_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct PruningReport {
+    since: DateTime<Utc>,
+    removed: i64,
+    flagged: i64,
+    /// Pruned for blocking the bot
+    blocked: i64,
+    /// Pruned for deliveries failing too long
+    failing: i64,
+    /// The most recent ones, newest first
+    subscribers: Vec<PrunedSubscriberInfo>,
+}
+
+/// What the pruning job did to the tenant's subscribers
+#[get("/pruned-subscribers")]
+pub async fn pruning_report(
+    _auth: Viewer,
+    tenant: Tenant,
+    query: web::Query<PruningReportQuery>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let since = query
+        .since
+        .unwrap_or_else(|| Utc::now() - chrono::Days::new(30));
+    let limit = query.limit.min(100) as i64;
+    let counts = crate::db::count_pruned_subscribers(&pool, &tenant.id, since.timestamp()).await?;
+    let subscribers =
+        crate::db::list_pruned_subscribers(&pool, &tenant.id, since.timestamp(), limit).await?;
+
+    let sum = |matches: &dyn Fn(&str, &str) -> bool| {
+        counts
+            .iter()
+            .filter(|(reason, action, _)| matches(reason, action))
+            .map(|(_, _, count)| count)
+            .sum()
+    };
+    Ok(HttpResponse::Ok().json(PruningReport {
+        since,
+        removed: sum(&|_, action| action == "removed"),
+        flagged: sum(&|_, action| action == "flagged"),
+        blocked: sum(&|reason, _| reason == "blocked"),
+        failing: sum(&|reason, _| reason == "failing"),
+        subscribers: subscribers
+            .into_iter()
+            .map(|p| PrunedSubscriberInfo {
+                telegram_id: p.telegram_id,
+                reason: p.reason,
+                action: p.action,
+                channels: serde_json::from_str(&p.channels).unwrap_or_default(),
+                pruned_at: DateTime::from_timestamp(p.pruned_at, 0),
+            })
+            .collect(),
+    }))
+}
+
+/// Streams subscribe, unsubscribe and delivery events of the tenant as JSON text messages
+#[get("/ws")]
+pub async fn events_ws(
+    _auth: Viewer,
+    tenant: Tenant,
+    req: HttpRequest,
+    body: web::Payload,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let (response, mut session, mut messages) =
+        actix_ws::handle(&req, body).map_err(|e| ApiError::invalid(e.to_string()))?;
+    let mut receiver = services.events.subscribe();
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(event) if event.tenant_id == tenant.id => {
+                        let json = serde_json::to_string(&event).expect("events serialize");
+                        if session.text(json).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("WebSocket client lagging, skipped {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                message = messages.next() => match message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(custom(function = "valid_tenant_id"))]
+    tenant_id: String,
+    #[validate(custom(function = "not_blank"))]
+    name: String,
+    /// Messages per UTC day, unlimited when omitted
+    #[validate(range(min = 0, message = "Daily quota cannot be negative"))]
+    daily_quota: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CreateApiKeyResponse {
+    id: i64,
+    tenant_id: String,
+    name: String,
+    daily_quota: Option<i64>,
+    /// Only returned once, the database keeps a hash
+    key: String,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyInfo {
+    id: i64,
+    tenant_id: String,
+    name: String,
+    daily_quota: Option<i64>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[post("/api-keys")]
+pub async fn create_api_key(
+    _auth: Authenticated,
+    req: Valid<CreateApiKeyRequest>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let (id, key) =
+        crate::db::create_api_key(&pool, &req.tenant_id, &req.name, req.daily_quota).await?;
+    Ok(HttpResponse::Created().json(CreateApiKeyResponse {
+        id,
+        tenant_id: req.tenant_id.clone(),
+        name: req.name.clone(),
+        daily_quota: req.daily_quota,
+        key,
+    }))
+}
+
+#[get("/api-keys")]
+pub async fn list_api_keys(
+    _auth: Authenticated,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let keys = crate::db::list_api_keys(&pool).await?;
+    Ok(HttpResponse::Ok().json(
+        keys.into_iter()
+            .map(|k| ApiKeyInfo {
+                id: k.id,
+                tenant_id: k.tenant_id,
+                name: k.name,
+                daily_quota: k.daily_quota,
+                created_at: DateTime::from_timestamp(k.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[delete("/api-keys/{id}")]
+pub async fn delete_api_key(
+    _auth: Authenticated,
+    id: web::Path<i64>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    if !crate::db::delete_api_key(&pool, id.into_inner()).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::ApiKeyNotFound,
+            "API key not found",
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateUserRequest {
+    #[validate(custom(function = "not_blank"))]
+    name: String,
+    /// Lets the user run the admin bot commands their roles allow
+    telegram_id: Option<i64>,
+    #[serde(default)]
+    roles: Vec<Role>,
+}
+
+#[derive(Serialize)]
+pub struct CreateUserResponse {
+    #[serde(flatten)]
+    user: UserInfo,
+    /// Only returned once, the database keeps a hash
+    token: String,
+}
+
+#[derive(Serialize)]
+pub struct UserInfo {
+    id: i64,
+    name: String,
+    telegram_id: Option<i64>,
+    roles: Vec<Role>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+impl From<crate::db::User> for UserInfo {
+    fn from(user: crate::db::User) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            telegram_id: user.telegram_id,
+            roles: user.roles,
+            created_at: DateTime::from_timestamp(user.created_at, 0),
+        }
+    }
+}
+
+#[post("/users")]
+pub async fn create_user(
+    _auth: Authenticated,
+    req: Valid<CreateUserRequest>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let created = crate::db::create_user(&pool, &req.name, req.telegram_id, &req.roles).await?;
+    let Some((id, token)) = created else {
+        return Err(ApiError::Conflict(
+            ErrorCode::UserExists,
+            "User name or Telegram id already in use",
+        ));
+    };
+
+    match crate::db::get_user(&pool, id).await? {
+        Some(user) => Ok(HttpResponse::Created().json(CreateUserResponse {
+            user: user.into(),
+            token,
+        })),
+        None => Err(ApiError::NotFound(
+            ErrorCode::UserNotFound,
+            "User not found",
+        )),
+    }
+}
+
+#[get("/users")]
+pub async fn list_users(_auth: Authenticated, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+    let users = crate::db::list_users(&pool).await?;
+    Ok(HttpResponse::Ok().json(users.into_iter().map(UserInfo::from).collect::<Vec<_>>()))
+}
+
+#[delete("/users/{id}")]
+pub async fn delete_user(
+    _auth: Authenticated,
+    id: web::Path<i64>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    if !crate::db::delete_user(&pool, id.into_inner()).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::UserNotFound,
+            "User not found",
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[put("/users/{id}/roles/{role}")]
+pub async fn grant_role(
+    _auth: Authenticated,
+    path: web::Path<(i64, Role)>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let (id, role) = path.into_inner();
+    let result = match crate::db::grant_role(&pool, id, role).await {
+        Ok(()) => crate::db::get_user(&pool, id).await,
+        Err(e) => Err(e),
+    };
+    user_response(result)
+}
+
+#[delete("/users/{id}/roles/{role}")]
+pub async fn revoke_role(
+    _auth: Authenticated,
+    path: web::Path<(i64, Role)>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let (id, role) = path.into_inner();
+    let result = match crate::db::revoke_role(&pool, id, role).await {
+        Ok(()) => crate::db::get_user(&pool, id).await,
+        Err(e) => Err(e),
+    };
+    user_response(result)
+}
+
+/// The user's roles after a change
+fn user_response(result: anyhow::Result<Option<crate::db::User>>) -> Result<HttpResponse> {
+    match result? {
+        Some(user) => Ok(HttpResponse::Ok().json(UserInfo::from(user))),
+        None => Err(ApiError::NotFound(
+            ErrorCode::UserNotFound,
+            "User not found",
+        )),
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateHookRequest {
+    /// Handlebars template rendered to the channel name
+    #[validate(custom(function = "valid_template"))]
+    channel: String,
+    /// Handlebars template rendered to the message
+    #[validate(custom(function = "valid_template"))]
+    template: String,
+}
+
+#[derive(Serialize)]
+pub struct HookInfo {
+    id: String,
+    /// Where the third-party service should POST its JSON
+    url: String,
+    channel: String,
+    template: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+impl From<crate::db::Hook> for HookInfo {
+    fn from(hook: crate::db::Hook) -> Self {
+        Self {
+            url: format!("/hooks/{}", hook.id),
+            id: hook.id,
+            channel: hook.channel,
+            template: hook.template,
+            created_at: DateTime::from_timestamp(hook.created_at, 0),
+        }
+    }
+}
+
+#[post("/hooks")]
+pub async fn create_hook(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    req: Valid<CreateHookRequest>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let id = crate::db::create_hook(&pool, &tenant.id, &req.channel, &req.template).await?;
+    Ok(HttpResponse::Created().json(HookInfo {
+        url: format!("/hooks/{}", id),
+        id,
+        channel: req.channel.clone(),
+        template: req.template.clone(),
+        created_at: Some(Utc::now()),
+    }))
+}
+
+#[get("/hooks")]
+pub async fn list_hooks(
+    _auth: Viewer,
+    tenant: Tenant,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let hooks = crate::db::list_hooks(&pool, &tenant.id).await?;
+    Ok(HttpResponse::Ok().json(hooks.into_iter().map(HookInfo::from).collect::<Vec<_>>()))
+}
+
+#[delete("/hooks/{id}")]
+pub async fn delete_hook(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<String>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    if !crate::db::delete_hook(&pool, &tenant.id, &id).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::HookNotFound,
+            "Hook not found",
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateFeedRequest {
+    #[validate(custom(function = "valid_feed_url"))]
+    url: String,
+    #[validate(custom(function = "valid_channel_name"))]
+    channel_name: String,
+}
+
+#[derive(Serialize)]
+pub struct FeedInfo {
+    id: i64,
+    url: String,
+    channel_name: String,
+    last_polled_at: Option<DateTime<Utc>>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[post("/feeds")]
+pub async fn create_feed(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    req: Valid<CreateFeedRequest>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let id = crate::db::create_feed(&pool, &tenant.id, &req.url, &req.channel_name).await?;
+    Ok(HttpResponse::Created().json(FeedInfo {
+        id,
+        url: req.url.clone(),
+        channel_name: req.channel_name.clone(),
+        last_polled_at: None,
+        created_at: Some(Utc::now()),
+    }))
+}
+
+#[get("/feeds")]
+pub async fn list_feeds(
+    _auth: Viewer,
+    tenant: Tenant,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let feeds = crate::db::list_feeds(&pool, &tenant.id).await?;
+    Ok(HttpResponse::Ok().json(
+        feeds
+            .into_iter()
+            .map(|f| FeedInfo {
+                id: f.id,
+                url: f.url,
+                channel_name: f.channel_name,
+                last_polled_at: f
+                    .last_polled_at
+                    .and_then(|t| DateTime::from_timestamp(t, 0)),
+                created_at: DateTime::from_timestamp(f.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[delete("/feeds/{id}")]
+pub async fn delete_feed(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    if !crate::db::delete_feed(&pool, &tenant.id, id.into_inner()).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::FeedNotFound,
+            "Feed not found",
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RecurringMessageRequest {
+    #[validate(custom(function = "valid_channel_name"))]
+    channel_name: String,
+    #[validate(custom(function = "valid_message_text"))]
+    body: String,
+    /// Standard 5-field cron expression, or 6/7 fields with seconds
+    #[validate(custom(function = "valid_cron"))]
+    cron: String,
+    #[serde(default = "default_timezone")]
+    #[validate(custom(function = "valid_timezone"))]
+    timezone: String,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+#[derive(Serialize)]
+pub struct RecurringMessageInfo {
+    id: i64,
+    channel_name: String,
+    body: String,
+    cron: String,
+    timezone: String,
+    next_run_at: Option<DateTime<Utc>>,
+    /// Omitted when answering an update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime<Utc>>,
+}
+
+impl RecurringMessageRequest {
+    /// Computes the first run of the validated request
+    fn fields(&self) -> Result<crate::db::RecurringMessageFields<'_>> {
+        let schedule = crate::recurring::parse_schedule(&self.cron).map_err(|e| {
+            ApiError::BadRequest(
+                ErrorCode::InvalidSchedule,
+                format!("Invalid cron expression: {}", e),
+            )
+        })?;
+        let timezone = crate::recurring::parse_timezone(&self.timezone)
+            .map_err(|e| ApiError::BadRequest(ErrorCode::InvalidSchedule, e))?;
+
+        Ok(crate::db::RecurringMessageFields {
+            channel_name: &self.channel_name,
+            body: &self.body,
+            cron: &self.cron,
+            timezone: &self.timezone,
+            next_run_at: crate::recurring::next_run(&schedule, timezone, Utc::now()),
+        })
+    }
+
+    fn info(
+        &self,
+        id: i64,
+        next_run_at: Option<i64>,
+        created_at: Option<DateTime<Utc>>,
+    ) -> RecurringMessageInfo {
+        RecurringMessageInfo {
+            id,
+            channel_name: self.channel_name.clone(),
+            body: self.body.clone(),
+            cron: self.cron.clone(),
+            timezone: self.timezone.clone(),
+            next_run_at: next_run_at.and_then(|t| DateTime::from_timestamp(t, 0)),
+            created_at,
+        }
+    }
+}
+
+#[post("/recurring-messages")]
+pub async fn create_recurring_message(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    req: Valid<RecurringMessageRequest>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let fields = req.fields()?;
+
+    let id = crate::db::create_recurring_message(&pool, &tenant.id, &fields).await?;
+    Ok(HttpResponse::Created().json(req.info(id, fields.next_run_at, Some(Utc::now()))))
+}
+
+#[get("/recurring-messages")]
+pub async fn list_recurring_messages(
+    _auth: Viewer,
+    tenant: Tenant,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let rows = crate::db::list_recurring_messages(&pool, &tenant.id).await?;
+    Ok(HttpResponse::Ok().json(
+        rows.into_iter()
+            .map(|r| RecurringMessageInfo {
+                id: r.id,
+                channel_name: r.channel_name,
+                body: r.body,
+                cron: r.cron,
+                timezone: r.timezone,
+                next_run_at: r.next_run_at.and_then(|t| DateTime::from_timestamp(t, 0)),
+                created_at: DateTime::from_timestamp(r.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[put("/recurring-messages/{id}")]
+pub async fn update_recurring_message(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    req: Valid<RecurringMessageRequest>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let fields = req.fields()?;
+
+    let id = id.into_inner();
+    match crate::db::update_recurring_message(&pool, &tenant.id, id, &fields).await? {
+        true => Ok(HttpResponse::Ok().json(req.info(id, fields.next_run_at, None))),
+        false => Err(ApiError::NotFound(
+            ErrorCode::RecurringMessageNotFound,
+            "Recurring message not found",
+        )),
+    }
+}
+
+#[delete("/recurring-messages/{id}")]
+pub async fn delete_recurring_message(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    if !crate::db::delete_recurring_message(&pool, &tenant.id, id.into_inner()).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::RecurringMessageNotFound,
+            "Recurring message not found",
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Validate)]
+pub struct EditMessageRequest {
+    #[validate(custom(function = "valid_message_text"))]
+    text: String,
+}
+
+#[derive(Serialize)]
+pub struct EditMessageResponse {
+    id: i64,
+    channel_name: Option<String>,
+    text: String,
+    created_at: i64,
+    edited: usize,
+    errors: usize,
+    /// telegram_ids of the recipients that weren't reached, by reason
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<Failure, Vec<i64>>,
+}
+
+#[post("/messages/{id}/edit")]
+pub async fn edit_message(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    req: Valid<EditMessageRequest>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let (message, report) = services
+        .edit_message(&tenant, id.into_inner(), &req.text)
+        .await?;
+    Ok(HttpResponse::Ok().json(EditMessageResponse {
+        id: message.id,
+        channel_name: message.channel_name,
+        text: message.text,
+        created_at: message.created_at,
+        edited: report.sent,
+        errors: report.errors,
+        failures: report.failures,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct DeleteMessageResponse {
+    deleted: usize,
+    errors: usize,
+    /// telegram_ids of the recipients that weren't reached, by reason
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<Failure, Vec<i64>>,
+}
+
+#[delete("/messages/{id}")]
+pub async fn delete_message(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let report = services.delete_message(&tenant, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(DeleteMessageResponse {
+        deleted: report.sent,
+        errors: report.errors,
+        failures: report.failures,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct UnpinMessageResponse {
+    unpinned: usize,
+    errors: usize,
+    /// telegram_ids of the recipients that weren't reached, by reason
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<Failure, Vec<i64>>,
+}
+
+#[post("/messages/{id}/unpin")]
+pub async fn unpin_message(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let report = services.unpin_message(&tenant, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(UnpinMessageResponse {
+        unpinned: report.sent,
+        errors: report.errors,
+        failures: report.failures,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct LinkClicksInfo {
+    url: String,
+    clicks: i64,
+    /// Distinct recipients who clicked
+    unique_clicks: i64,
+    /// `unique_clicks` out of the recipients
+    click_through_rate: f64,
+}
+
+#[derive(Serialize)]
+pub struct MessageClicksResponse {
+    message_id: i64,
+    recipients: i64,
+    clicks: i64,
+    /// Distinct recipients who clicked any link
+    unique_clicks: i64,
+    click_through_rate: f64,
+    links: Vec<LinkClicksInfo>,
+}
+
+/// Clicks on the tracked links of a message
+#[get("/messages/{id}/clicks")]
+pub async fn get_message_clicks(
+    _auth: Viewer,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let id = id.into_inner();
+    if crate::db::find_message(&pool, &tenant.id, id)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::NotFound(
+            ErrorCode::MessageNotFound,
+            "Message not found",
+        ));
+    }
+
+    let stats = crate::db::get_message_clicks(&pool, id).await?;
+    let rate = |clickers: i64| match stats.recipients {
+        0 => 0.0,
+        recipients => clickers as f64 / recipients as f64,
+    };
+    Ok(HttpResponse::Ok().json(MessageClicksResponse {
+        message_id: id,
+        recipients: stats.recipients,
+        clicks: stats.links.iter().map(|link| link.clicks).sum(),
+        unique_clicks: stats.clickers,
+        click_through_rate: rate(stats.clickers),
+        links: stats
+            .links
+            .iter()
+            .map(|link| LinkClicksInfo {
+                url: link.url.clone(),
+                clicks: link.clicks,
+                unique_clicks: link.clickers,
+                click_through_rate: rate(link.clickers),
+            })
+            .collect(),
+    }))
+}
+
+/// Records a click on a tracked link and redirects to where it points. Public, since
+/// it's opened by the recipients.
+#[get("/r/{token}")]
+pub async fn follow_link(
+    req: HttpRequest,
+    token: web::Path<String>,
+    pool: web::Data<SqlitePool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    let url = match crate::links::parse_click_token(&config.super_secret_key, &token) {
+        // Telegram fetching the link preview, not the recipient clicking
+        Some((link_token, _)) if is_link_preview(&req) => {
+            crate::db::get_tracked_link_url(&pool, link_token).await?
+        }
+        Some((link_token, telegram_id)) => {
+            crate::db::record_link_click(&pool, link_token, telegram_id).await?
+        }
+        None => None,
+    };
+    match url {
+        Some(url) => Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .finish()),
+        None => Err(ApiError::NotFound(
+            ErrorCode::LinkNotFound,
+            "Link not found",
+        )),
+    }
+}
+
+/// Whether the request comes from Telegram's servers building a link preview
+fn is_link_preview(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+        .is_some_and(|agent| agent.starts_with("TelegramBot"))
+}
+
+#[derive(Serialize)]
+pub struct SendPollResponse {
+    sent: usize,
+    errors: usize,
+    channel: String,
+    /// Id to fetch the results by, absent when nothing was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poll_id: Option<i64>,
+    /// telegram_ids of the recipients that weren't reached, by reason
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<Failure, Vec<i64>>,
+}
+
+#[post("/send-poll")]
+pub async fn send_poll(
+    tenant: Tenant,
+    req: web::Json<NewPoll>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let (poll_id, report) = services.send_poll(&tenant, &req).await?;
+    let report = report_for(&tenant, report);
+    Ok(HttpResponse::Ok().json(SendPollResponse {
+        sent: report.sent,
+        errors: report.errors,
+        channel: req.channel_name.clone(),
+        poll_id,
+        failures: report.failures,
+    }))
+}
+
+#[post("/send-location")]
+pub async fn send_location(
+    tenant: Tenant,
+    req: web::Json<NewLocation>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let report = services.send_location(&tenant, &req).await?;
+    let report = report_for(&tenant, report);
+    Ok(HttpResponse::Ok().json(SendMessageResponse {
+        sent: report.sent,
+        errors: report.errors,
+        channel: req.channel_name.clone(),
+        message_id: None,
+        pinned: None,
+        duplicate: false,
+        failures: report.failures,
+        failed_recipients: report.failed_recipients,
+    }))
+}
+
+#[post("/send-media-group")]
+pub async fn send_media_group(
+    tenant: Tenant,
+    req: web::Json<NewMediaGroup>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let report = services.send_media_group(&tenant, &req).await?;
+    let report = report_for(&tenant, report);
+    Ok(HttpResponse::Ok().json(SendMessageResponse {
+        sent: report.sent,
+        errors: report.errors,
+        channel: req.channel_name.clone(),
+        message_id: None,
+        pinned: None,
+        duplicate: false,
+        failures: report.failures,
+        failed_recipients: report.failed_recipients,
+    }))
+}
+
+/// A media message given as JSON, or as `multipart/form-data` with the file
+/// uploaded in the `media` part
+pub struct MediaRequest(NewMedia);
+
+impl actix_web::FromRequest for MediaRequest {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let is_multipart = req
+            .headers()
+            .get("Content-Type")
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|h| h.starts_with("multipart/form-data"));
+        if !is_multipart {
+            let json = web::Json::<NewMedia>::from_request(req, payload);
+            return Box::pin(async move { Ok(MediaRequest(json.await?.into_inner())) });
+        }
+
+        let services = req
+            .app_data::<web::Data<Services>>()
+            .expect("services should be registered");
+        let max_bytes = crate::media::max_upload_bytes(services.config.local_bot_api);
+        let multipart = Multipart::new(req.headers(), payload.take());
+        Box::pin(read_media_form(multipart, max_bytes))
+    }
+}
+
+async fn read_media_form(
+    mut multipart: Multipart,
+    max_bytes: usize,
+) -> Result<MediaRequest, actix_web::Error> {
+    let (mut channel_name, mut media) = (None, None);
+    let mut options = MediaOptions::default();
+    let mut supports_streaming = false;
+    while let Some(mut field) = multipart.try_next().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(str::to_string);
+
+        let mut data = BytesMut::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > max_bytes {
+                return Err(ApiError::PayloadTooLarge(format!(
+                    "File too large (max {} MB)",
+                    max_bytes / (1024 * 1024)
+                ))
+                .into());
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        if let ("media", Some(file_name)) = (name.as_str(), &file_name) {
+            media = Some(MediaSource::Upload {
+                file_name: file_name.clone(),
+                data: data.freeze(),
+            });
+            continue;
+        }
+        let text = String::from_utf8(data.to_vec())
+            .map_err(|_| ApiError::invalid(format!("Field '{}' must be text", name)))?;
+        match name.as_str() {
+            "channel_name" => channel_name = Some(text),
+            "caption" => options.caption = Some(text),
+            "parse_mode" => options.parse_mode = Some(parse_form_field(&name, text.into())?),
+            "caption_entities" => {
+                let entities = serde_json::from_str(&text).unwrap_or_default();
+                options.caption_entities = Some(parse_form_field(&name, entities)?);
+            }
+            "media" => media = Some(MediaSource::Reference(text)),
+            "supports_streaming" => supports_streaming = text == "true",
+            _ => {}
+        }
+    }
+
+    match (channel_name, media) {
+        (Some(channel_name), Some(media)) => Ok(MediaRequest(NewMedia {
+            channel_name,
+            media,
+            options,
+            supports_streaming,
+        })),
+        _ => Err(ApiError::invalid("Missing 'channel_name' or 'media'").into()),
+    }
+}
+
+/// Deserializes a form field given as (or parsed into) JSON
+fn parse_form_field<T: serde::de::DeserializeOwned>(name: &str, value: Value) -> Result<T> {
+    serde_json::from_value(value).map_err(|_| ApiError::invalid(format!("Invalid '{}'", name)))
+}
+
+async fn send_media(
+    tenant: Tenant,
+    kind: MediaKind,
+    media: NewMedia,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let report = services.send_media(&tenant, kind, &media).await?;
+    let report = report_for(&tenant, report);
+    Ok(HttpResponse::Ok().json(SendMessageResponse {
+        sent: report.sent,
+        errors: report.errors,
+        channel: media.channel_name,
+        message_id: None,
+        pinned: None,
+        duplicate: false,
+        failures: report.failures,
+        failed_recipients: report.failed_recipients,
+    }))
+}
+
+#[post("/send-animation")]
+pub async fn send_animation(
+    tenant: Tenant,
+    req: MediaRequest,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    send_media(tenant, MediaKind::Animation, req.0, services).await
+}
+
+#[post("/send-video")]
+pub async fn send_video(
+    tenant: Tenant,
+    req: MediaRequest,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    send_media(tenant, MediaKind::Video, req.0, services).await
+}
+
+#[post("/send-voice")]
+pub async fn send_voice(
+    tenant: Tenant,
+    req: MediaRequest,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    send_media(tenant, MediaKind::Voice, req.0, services).await
+}
+
+#[post("/send-audio")]
+pub async fn send_audio(
+    tenant: Tenant,
+    req: MediaRequest,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    send_media(tenant, MediaKind::Audio, req.0, services).await
+}
+
+#[post("/send-sticker")]
+pub async fn send_sticker(
+    tenant: Tenant,
+    req: web::Json<NewSticker>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let report = services.send_sticker(&tenant, &req).await?;
+    let report = report_for(&tenant, report);
+    Ok(HttpResponse::Ok().json(SendMessageResponse {
+        sent: report.sent,
+        errors: report.errors,
+        channel: req.channel_name.clone(),
+        message_id: None,
+        pinned: None,
+        duplicate: false,
+        failures: report.failures,
+        failed_recipients: report.failed_recipients,
+    }))
+}
+
+#[get("/polls/{id}/results")]
+pub async fn poll_results(
+    tenant: Tenant,
+    id: web::Path<i64>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let results = services.poll_results(&tenant, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+pub struct ChannelSettingsInfo {
+    /// `MarkdownV2`, `HTML` or `Markdown`, plain text when unset
+    #[validate(custom(function = "valid_parse_mode"))]
+    parse_mode: Option<String>,
+    #[serde(default)]
+    silent: bool,
+    #[serde(default)]
+    protect_content: bool,
+    #[serde(default)]
+    disable_link_preview: bool,
+    /// Handlebars templates placed above and below every message, `{{channel}}` being the
+    /// channel's name
+    #[validate(custom(function = "valid_template"))]
+    header: Option<String>,
+    #[validate(custom(function = "valid_template"))]
+    footer: Option<String>,
+    /// Seconds an identical message gets the earlier result instead of being sent again
+    dedup_window_secs: Option<u32>,
+    /// Messages sent per minute, later ones waiting until the last minute has room
+    #[validate(range(min = 1, message = "rate_limit_per_minute must be at least 1"))]
+    rate_limit_per_minute: Option<u32>,
+    /// Listed when users search channels in the bots' inline mode
+    #[serde(default)]
+    public: bool,
+    /// Subscriptions wait for the user to confirm them with an inline button
+    #[serde(default)]
+    double_opt_in: bool,
+    /// Messages carry an inline button unsubscribing the recipient
+    #[serde(default)]
+    unsubscribe_button: bool,
+}
+
+#[put("/channels/{name}")]
+pub async fn update_channel(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    name: web::Path<String>,
+    req: Valid<ChannelSettingsInfo>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let settings = ChannelSettings {
+        parse_mode: req.parse_mode.clone(),
+        silent: req.silent,
+        protect_content: req.protect_content,
+        disable_link_preview: req.disable_link_preview,
+        header: req.header.clone(),
+        footer: req.footer.clone(),
+        dedup_window_secs: req.dedup_window_secs.map(i64::from),
+        rate_limit_per_minute: req.rate_limit_per_minute.map(i64::from),
+        public: req.public,
+        double_opt_in: req.double_opt_in,
+        unsubscribe_button: req.unsubscribe_button,
+    };
+    match crate::db::update_channel_settings(&pool, &tenant.id, &name, &settings).await? {
+        true => Ok(HttpResponse::Ok().json(req.into_inner())),
+        false => Err(ApiError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        )),
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RenameChannelRequest {
+    #[validate(custom(function = "valid_channel_name"))]
+    new_name: String,
+    /// Tell the subscribers about the new name
+    #[serde(default)]
+    notify: bool,
+    /// Keep the old name as an alias, so integrations sending to it keep working
+    #[serde(default)]
+    keep_alias: bool,
+}
+
+#[derive(Serialize)]
+pub struct RenameChannelResponse {
+    channel_name: String,
+    /// Subscribers told about the new name, when notifying was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notified: Option<usize>,
+}
+
+/// Moves the channel with its subscribers, settings and history to a new name
+#[post("/channels/{name}/rename")]
+pub async fn rename_channel(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    name: web::Path<String>,
+    req: Valid<RenameChannelRequest>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let old_name = crate::db::normalize_channel_name(&name);
+    let new_name = crate::db::normalize_channel_name(&req.new_name);
+    if let Some(problem) = services.config.channel_names.subscribe_error(&new_name) {
+        return Err(ApiError::BadRequest(
+            ErrorCode::InvalidChannelName,
+            problem.to_string(),
+        ));
+    }
+
+    match crate::db::rename_channel(
+        &services.pool,
+        &tenant.id,
+        &old_name,
+        &new_name,
+        req.keep_alias,
+    )
+    .await?
+    {
+        RenameOutcome::Renamed => {}
+        RenameOutcome::NotFound => {
+            return Err(ApiError::NotFound(
+                ErrorCode::ChannelNotFound,
+                "Channel not found",
+            ));
+        }
+        RenameOutcome::NameTaken => {
+            return Err(ApiError::Conflict(
+                ErrorCode::ChannelExists,
+                "A channel or alias with that name already exists",
+            ));
+        }
+    }
+
+    // The rename is done either way, a failed notice is only logged
+    let notified = match req.notify {
+        true => {
+            let notice = format!(
+                "The channel '{}' is now called '{}'. You're still subscribed.",
+                old_name, new_name
+            );
+            match services.notify_channel(&tenant, &new_name, &notice).await {
+                Ok(report) => Some(report.sent),
+                Err(e) => {
+                    log::error!("Failed to notify '{}' of its rename: {}", new_name, e);
+                    Some(0)
+                }
+            }
+        }
+        false => None,
+    };
+
+    Ok(HttpResponse::Ok().json(RenameChannelResponse {
+        channel_name: new_name,
+        notified,
+    }))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ChannelAliasRequest {
+    #[validate(custom(function = "valid_channel_name"))]
+    channel_name: String,
+}
+
+#[derive(Serialize)]
+pub struct ChannelAliasInfo {
+    alias: String,
+    channel_name: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[get("/aliases")]
+pub async fn list_channel_aliases(
+    _auth: Viewer,
+    tenant: Tenant,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let aliases = crate::db::list_channel_aliases(&pool, &tenant.id).await?;
+    Ok(HttpResponse::Ok().json(
+        aliases
+            .into_iter()
+            .map(|a| ChannelAliasInfo {
+                alias: a.alias,
+                channel_name: a.channel_name,
+                created_at: DateTime::from_timestamp(a.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Makes sends and subscriptions to `alias` go to the channel, replacing its previous
+/// target
+#[put("/aliases/{alias}")]
+pub async fn set_channel_alias(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    alias: web::Path<String>,
+    req: Valid<ChannelAliasRequest>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let alias = crate::db::normalize_channel_name(&alias);
+    if let Some(problem) = services.config.channel_names.subscribe_error(&alias) {
+        return Err(ApiError::BadRequest(
+            ErrorCode::InvalidChannelName,
+            problem.to_string(),
+        ));
+    }
+
+    let channel_name = crate::db::normalize_channel_name(&req.channel_name);
+    match crate::db::set_channel_alias(&services.pool, &tenant.id, &alias, &channel_name).await? {
+        AliasOutcome::Set => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "alias": alias,
+            "channel_name": channel_name,
+        }))),
+        AliasOutcome::ChannelNotFound => Err(ApiError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        )),
+        AliasOutcome::NameTaken => Err(ApiError::Conflict(
+            ErrorCode::ChannelExists,
+            "A channel with that name already exists",
+        )),
+    }
+}
+
+#[delete("/aliases/{alias}")]
+pub async fn delete_channel_alias(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    alias: web::Path<String>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    if !crate::db::delete_channel_alias(&pool, &tenant.id, &alias).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::AliasNotFound,
+            "Alias not found",
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Validate)]
+pub struct MergeChannelRequest {
+    #[validate(custom(function = "valid_channel_name"))]
+    into: String,
+}
+
+#[derive(Serialize)]
+pub struct MergeChannelResponse {
+    channel_name: String,
+    /// Subscribers moved over, not counting those already subscribed to the target
+    moved: u64,
+}
+
+/// Folds a channel into another one served by the same bot, for consolidating
+/// overlapping topics
+#[post("/channels/{name}/merge")]
+pub async fn merge_channel(
+    _auth: Authenticated,
+    tenant: Tenant,
+    name: web::Path<String>,
+    req: Valid<MergeChannelRequest>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let from = crate::db::normalize_channel_name(&name);
+    let into = crate::db::normalize_channel_name(&req.into);
+    if from == into {
+        return Err(ApiError::invalid("Can't merge a channel into itself"));
+    }
+
+    match crate::db::merge_channels(&services.pool, &tenant.id, &from, &into).await? {
+        MergeOutcome::Merged {
+            moved,
+            unsubscribed,
+        } => {
+            services.publish_unsubscribed(&tenant.id, &from, unsubscribed);
+            Ok(HttpResponse::Ok().json(MergeChannelResponse {
+                channel_name: into,
+                moved,
+            }))
+        }
+        MergeOutcome::NotFound => Err(ApiError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        )),
+        MergeOutcome::DifferentBots => Err(ApiError::Conflict(
+            ErrorCode::BotMismatch,
+            "Can't merge channels served by different bots",
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteChannelQuery {
+    /// Tell the subscribers the channel is retired before removing it
+    #[serde(default)]
+    notify: bool,
+}
+
+#[derive(Serialize)]
+pub struct DeleteChannelResponse {
+    channel_name: String,
+    /// Subscriptions removed with the channel
+    unsubscribed: u64,
+    /// Subscribers told the channel is retired, when notifying was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notified: Option<usize>,
+}
+
+/// Removes the channel and its subscriptions, optionally saying goodbye first
+#[delete("/channels/{name}")]
+pub async fn delete_channel(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    name: web::Path<String>,
+    query: web::Query<DeleteChannelQuery>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let channel_name = crate::db::normalize_channel_name(&name);
+    if crate::db::get_channel_settings(&services.pool, &tenant.id, &channel_name)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        ));
+    }
+
+    // The channel goes either way, a failed farewell is only logged
+    let notified = match query.notify {
+        true => {
+            let notice = format!(
+                "The channel '{}' has been retired and you've been unsubscribed.",
+                channel_name
+            );
+            match services
+                .notify_channel(&tenant, &channel_name, &notice)
+                .await
+            {
+                Ok(report) => Some(report.sent),
+                Err(e) => {
+                    log::error!("Failed to notify '{}' of its removal: {}", channel_name, e);
+                    Some(0)
+                }
+            }
+        }
+        false => None,
+    };
+
+    let Some(subscribers) =
+        crate::db::delete_channel(&services.pool, &tenant.id, &channel_name).await?
+    else {
+        return Err(ApiError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        ));
+    };
+    let unsubscribed = subscribers.len() as u64;
+    services.publish_unsubscribed(&tenant.id, &channel_name, subscribers);
+
+    Ok(HttpResponse::Ok().json(DeleteChannelResponse {
+        channel_name,
+        unsubscribed,
+        notified,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct ChannelInfo {
+    name: String,
+    bot: String,
+    subscribers: i64,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[get("/channels")]
+pub async fn list_channels(
+    _auth: Viewer,
+    tenant: Tenant,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let channels = crate::db::list_channels(&pool, &tenant.id).await?;
+    Ok(HttpResponse::Ok().json(
+        channels
+            .into_iter()
+            .map(|c| ChannelInfo {
+                name: c.name,
+                bot: c.bot,
+                subscribers: c.subscribers,
+                created_at: DateTime::from_timestamp(c.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ListMessagesQuery {
+    #[serde(default = "default_messages_limit")]
+    limit: u32,
+}
+
+fn default_messages_limit() -> u32 {
+    20
+}
+
+#[derive(Serialize)]
+pub struct MessageInfo {
+    id: i64,
+    channel_name: Option<String>,
+    text: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+/// Latest messages sent through the API, newest first
+#[get("/messages")]
+pub async fn list_messages(
+    _auth: Viewer,
+    tenant: Tenant,
+    query: web::Query<ListMessagesQuery>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let limit = query.limit.min(100) as i64;
+    let messages = crate::db::list_messages(&pool, &tenant.id, limit).await?;
+    Ok(HttpResponse::Ok().json(
+        messages
+            .into_iter()
+            .map(|m| MessageInfo {
+                id: m.id,
+                channel_name: m.channel_name,
+                text: m.text,
+                created_at: DateTime::from_timestamp(m.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[derive(Serialize)]
+pub struct ChannelStatsResponse {
+    channel_name: String,
+    subscribers: i64,
+    /// Current subscribers who joined within the last 7 and 30 days
+    new_subscribers_7d: i64,
+    new_subscribers_30d: i64,
+    last_message_at: Option<DateTime<Utc>>,
+}
+
+#[get("/channels/{name}/stats")]
+pub async fn get_channel_stats(
+    _auth: Viewer,
+    tenant: Tenant,
+    name: web::Path<String>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    match crate::db::get_channel_stats(&pool, &tenant.id, &name).await? {
+        Some(stats) => Ok(HttpResponse::Ok().json(ChannelStatsResponse {
+            channel_name: name.into_inner(),
+            subscribers: stats.subscribers,
+            new_subscribers_7d: stats.new_subscribers_7d,
+            new_subscribers_30d: stats.new_subscribers_30d,
+            last_message_at: stats
+                .last_message_at
+                .and_then(|at| DateTime::from_timestamp(at, 0)),
+        })),
+        None => Err(ApiError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        )),
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GrowthBucketSize {
+    #[default]
+    Day,
+    /// Starting on Monday
+    Week,
+    Month,
+}
+
+impl GrowthBucketSize {
+    fn as_str(self) -> &'static str {
+        match self {
+            GrowthBucketSize::Day => "day",
+            GrowthBucketSize::Week => "week",
+            GrowthBucketSize::Month => "month",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChannelAnalyticsQuery {
+    /// First day included, 30 days ago by default
+    from: Option<NaiveDate>,
+    /// Last day included, today by default
+    to: Option<NaiveDate>,
+    #[serde(default)]
+    bucket: GrowthBucketSize,
+}
+
+#[derive(Serialize)]
+pub struct GrowthBucketInfo {
+    start: String,
+    subscribed: i64,
+    unsubscribed: i64,
+    net: i64,
+    /// Subscribers at the end of the bucket
+    subscribers: i64,
+}
+
+#[derive(Serialize)]
+pub struct ChannelAnalyticsResponse {
+    channel_name: String,
+    from: NaiveDate,
+    to: NaiveDate,
+    bucket: &'static str,
+    subscribers_at_start: i64,
+    subscribed: i64,
+    unsubscribed: i64,
+    net: i64,
+    buckets: Vec<GrowthBucketInfo>,
+}
+
+/// Subscribes, unsubscribes and net growth of a channel over time, from its subscription history
+#[get("/channels/{name}/analytics")]
+pub async fn get_channel_analytics(
+    _auth: Viewer,
+    tenant: Tenant,
+    name: web::Path<String>,
+    query: web::Query<ChannelAnalyticsQuery>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query.from.unwrap_or(to - chrono::Days::new(30));
+    if from > to {
+        return Err(ApiError::invalid("'from' must not be after 'to'"));
+    }
+
+    let Some(growth) = crate::db::get_channel_growth(
+        &pool,
+        &tenant.id,
+        &name,
+        &from.to_string(),
+        &to.to_string(),
+        query.bucket.as_str(),
+    )
+    .await?
+    else {
+        return Err(ApiError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        ));
+    };
+
+    let mut subscribers = growth.subscribers_before;
+    let buckets: Vec<_> = growth
+        .buckets
+        .into_iter()
+        .map(|b| {
+            let net = b.subscribed - b.unsubscribed;
+            subscribers += net;
+            GrowthBucketInfo {
+                start: b.start,
+                subscribed: b.subscribed,
+                unsubscribed: b.unsubscribed,
+                net,
+                subscribers,
+            }
+        })
+        .collect();
+    let subscribed = buckets.iter().map(|b| b.subscribed).sum();
+    let unsubscribed = buckets.iter().map(|b| b.unsubscribed).sum();
+    Ok(HttpResponse::Ok().json(ChannelAnalyticsResponse {
+        channel_name: crate::db::normalize_channel_name(&name),
+        from,
+        to,
+        bucket: query.bucket.as_str(),
+        subscribers_at_start: growth.subscribers_before,
+        subscribed,
+        unsubscribed,
+        net: subscribed - unsubscribed,
+        buckets,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    /// First day included, 30 days ago by default
+    from: Option<NaiveDate>,
+    /// Last day included, today by default
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+pub struct DayStats {
+    day: String,
+    /// Absent for broadcasts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_name: Option<String>,
+    sent: i64,
+    errors: i64,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    from: NaiveDate,
+    to: NaiveDate,
+    days: Vec<DayStats>,
+}
+
+#[get("/stats")]
+pub async fn get_stats(
+    _auth: Viewer,
+    tenant: Tenant,
+    query: web::Query<StatsQuery>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query.from.unwrap_or(to - chrono::Days::new(30));
+    if from > to {
+        return Err(ApiError::invalid("'from' must not be after 'to'"));
+    }
+
+    let rows =
+        crate::db::get_daily_stats(&pool, &tenant.id, &from.to_string(), &to.to_string()).await?;
+    Ok(HttpResponse::Ok().json(StatsResponse {
+        from,
+        to,
+        days: rows
+            .into_iter()
+            .map(|row| DayStats {
+                day: row.day,
+                channel_name: Some(row.channel_name).filter(|name| !name.is_empty()),
+                sent: row.sent,
+                errors: row.errors,
+            })
+            .collect(),
+    }))
+}
+
+#[get("/usage")]
+pub async fn get_usage(
+    tenant: Tenant,
+    pool: web::Data<SqlitePool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    let usage = Usage::today(&pool, &config.quotas, &tenant.id, tenant.api_key.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+#[derive(Serialize)]
+pub struct DeadLetterInfo {
+    id: i64,
+    /// `None` for broadcasts
+    channel_name: Option<String>,
+    telegram_id: i64,
+    bot: String,
+    message: String,
+    failure: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+/// Text messages recipients didn't get even after retrying, newest first
+#[get("/dead-letters")]
+pub async fn list_dead_letters(
+    _auth: Viewer,
+    tenant: Tenant,
+    query: web::Query<ListMessagesQuery>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    let limit = query.limit.min(100) as i64;
+    let letters = crate::db::list_dead_letters(&pool, &tenant.id, limit).await?;
+    Ok(HttpResponse::Ok().json(
+        letters
+            .into_iter()
+            .map(|l| DeadLetterInfo {
+                id: l.id,
+                channel_name: l.channel_name,
+                telegram_id: l.telegram_id,
+                bot: l.bot,
+                message: l.message,
+                failure: l.failure,
+                created_at: DateTime::from_timestamp(l.created_at, 0),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[derive(Serialize)]
+pub struct RetryDeadLetterResponse {
+    sent: usize,
+    errors: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<i64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<Failure, Vec<i64>>,
+}
+
+/// Sends a dead letter again; if it fails again it's replaced by a new one
+#[post("/dead-letters/{id}/retry")]
+pub async fn retry_dead_letter(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let report = services.retry_dead_letter(&tenant, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(RetryDeadLetterResponse {
+        sent: report.sent,
+        errors: report.errors,
+        message_id: report.message_id,
+        failures: report.failures,
+    }))
+}
+
+#[delete("/dead-letters/{id}")]
+pub async fn delete_dead_letter(
+    _auth: ChannelOwner,
+    tenant: Tenant,
+    id: web::Path<i64>,
+    pool: web::Data<SqlitePool>,
+) -> Result<HttpResponse> {
+    if !crate::db::delete_dead_letter(&pool, &tenant.id, id.into_inner()).await? {
+        return Err(ApiError::NotFound(
+            ErrorCode::DeadLetterNotFound,
+            "Dead letter not found",
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{TestRequest, call_service, init_service};
+
+    /// Services without any bot, for requests that don't reach Telegram
+    fn services(pool: SqlitePool) -> Services {
+        let config = Config::default();
+        Services {
+            pool,
+            bots: crate::bot::Bots::new(BTreeMap::new()),
+            events: crate::events::EventBus::new(),
+            lanes: config.lanes().unwrap(),
+            config: std::sync::Arc::new(config),
+            jobs: Default::default(),
+            channel_limits: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_report_for_anonymous_callers() {
+        let report = || {
+            let mut report = DeliveryReport::default();
+            report.record(111, None);
+            report.record(222, Some(Failure::BlockedByUser));
+            report
+        };
+        let anonymous = report_for(&Tenant::new("acme"), report());
+        assert_eq!((anonymous.sent, anonymous.errors), (1, 1));
+        assert!(anonymous.failures.is_empty() && anonymous.failed_recipients.is_empty());
+
+        let tenant = Tenant {
+            role: Some(Role::Viewer),
+            ..Tenant::new("acme")
+        };
+        let authenticated = report_for(&tenant, report());
+        assert_eq!(authenticated.failures[&Failure::BlockedByUser], vec![222]);
+        assert_eq!(authenticated.failed_recipients.len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn test_message_changes_need_a_role(pool: SqlitePool) -> anyhow::Result<()> {
+        let app = init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(services(pool.clone())))
+                .service(edit_message)
+                .service(delete_message)
+                .service(unpin_message),
+        )
+        .await;
+        let id = crate::db::create_message(&pool, crate::db::DEFAULT_TENANT, Some("news"), "Hello")
+            .await?;
+
+        let anonymous = [
+            TestRequest::post()
+                .uri(&format!("/messages/{}/edit", id))
+                .set_json(serde_json::json!({ "text": "Hacked" })),
+            TestRequest::delete().uri(&format!("/messages/{}", id)),
+            TestRequest::post().uri(&format!("/messages/{}/unpin", id)),
+        ];
+        for request in anonymous {
+            let response = call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let message = crate::db::find_message(&pool, crate::db::DEFAULT_TENANT, id).await?;
+        assert_eq!(message.unwrap().text, "Hello");
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_follow_link(pool: SqlitePool) -> anyhow::Result<()> {
+        let config = Config {
+            super_secret_key: "s3cret".to_string(),
+            ..Config::default()
+        };
+        let app = init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(config))
+                .service(follow_link),
+        )
+        .await;
+        let links = [("https://example.com".to_string(), "t0".to_string())];
+        crate::db::create_tracked_links(&pool, crate::db::DEFAULT_TENANT, &links).await?;
+        let id = crate::db::create_message(&pool, crate::db::DEFAULT_TENANT, None, "Hi").await?;
+        crate::db::attach_tracked_links(&pool, id, ["t0"]).await?;
+
+        let uri = |token: String| format!("/r/{}", token);
+        let clicked = TestRequest::get().uri(&uri(crate::links::click_token("s3cret", "t0", 111)));
+        let response = call_service(&app, clicked.to_request()).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let preview = TestRequest::get()
+            .uri(&uri(crate::links::click_token("s3cret", "t0", 222)))
+            .insert_header((header::USER_AGENT, "TelegramBot (like TwitterBot)"));
+        let response = call_service(&app, preview.to_request()).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        for forged in [
+            "t0.333".to_string(),
+            crate::links::click_token("guess", "t0", 333),
+        ] {
+            let response =
+                call_service(&app, TestRequest::get().uri(&uri(forged)).to_request()).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+
+        let clicks = crate::db::get_message_clicks(&pool, id).await?;
+        assert_eq!(clicks.clickers, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_button_validation() {
+        let request = |buttons: Value| -> SendMessageRequest {
+            serde_json::from_value(serde_json::json!({
+                "channel_name": "ops",
+                "message": "Deploy to production?",
+                "buttons": buttons,
+            }))
+            .unwrap()
+        };
+        let approve = serde_json::json!({"text": "Approve", "data": "deploy:42:yes"});
+        assert!(request(serde_json::json!([[approve]])).validate().is_ok());
+        for button in [
+            serde_json::json!({"text": " ", "data": "yes"}),
+            serde_json::json!({"text": "Approve", "data": ""}),
+            serde_json::json!({"text": "Approve", "data": "x".repeat(65)}),
+            serde_json::json!({"text": "Approve", "data": "onboarding:0"}),
+            serde_json::json!({"text": "Approve", "data": "challenge:0"}),
+            serde_json::json!({"text": "Approve", "data": "confirm:1"}),
+            serde_json::json!({"text": "Stop", "data": "unsubscribe:"}),
+        ] {
+            let errors = request(serde_json::json!([[approve], [button]]))
+                .validate()
+                .unwrap_err();
+            assert!(errors.field_errors().contains_key("buttons"));
+        }
+    }
+
+    #[test]
+    fn test_footer_override() {
+        let request = |footer: &str| -> SendMessageRequest {
+            serde_json::from_value(serde_json::json!({
+                "channel_name": "deals",
+                "message": "50% off today",
+                "footer": footer,
+            }))
+            .unwrap()
+        };
+        let with_footer = request("Reply /unsubscribe {{channel}} to stop");
+        assert!(with_footer.validate().is_ok());
+        assert_eq!(
+            with_footer.options().footer.as_deref(),
+            Some("Reply /unsubscribe {{channel}} to stop")
+        );
+        assert!(request("").validate().is_ok());
+        let errors = request("{{#if channel}}").validate().unwrap_err();
+        assert!(errors.field_errors().contains_key("footer"));
+    }
+
+    #[test]
+    fn test_validation_lists_every_field() {
+        let request: RecurringMessageRequest = serde_json::from_value(serde_json::json!({
+            "channel_name": "ops team",
+            "body": "",
+            "cron": "every day",
+            "timezone": "Europe/Rome",
+        }))
+        .unwrap();
+        let errors = request.validate().unwrap_err();
+        let fields = errors.field_errors();
+        let mut invalid = fields
+            .keys()
+            .map(|field| field.as_ref())
+            .collect::<Vec<_>>();
+        invalid.sort();
+        assert_eq!(invalid, ["body", "channel_name", "cron"]);
+        assert_eq!(
+            fields["body"][0].message.as_deref(),
+            Some("Must be between 1 and 1000 chars")
+        );
+
+        let settings: ChannelSettingsInfo = serde_json::from_value(serde_json::json!({
+            "parse_mode": "HTML",
+            "rate_limit_per_minute": 30,
+        }))
+        .unwrap();
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_message_length_counts_like_telegram() {
+        let request = |message: String| -> SendMessageRequest {
+            serde_json::from_value(serde_json::json!({
+                "channel_name": "ops",
+                "message": message,
+            }))
+            .unwrap()
+        };
+        assert!(request("你".repeat(1000)).validate().is_ok());
+        assert!(request("é".repeat(1000)).validate().is_ok());
+        assert!(request("a".repeat(1001)).validate().is_err());
+        assert!(request("👍".repeat(500)).validate().is_ok());
+        assert!(request("👍".repeat(501)).validate().is_err());
+    }
+
+    #[test]
+    fn test_error_codes() {
+        use actix_web::ResponseError;
+
+        let e = ApiError::from(ServiceError::NotFound(
+            ErrorCode::ChannelNotFound,
+            "Channel not found",
+        ));
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(e.code().as_str(), "CHANNEL_NOT_FOUND");
+        assert_eq!(e.to_string(), "Channel not found");
+
+        let e = ApiError::from(ServiceError::InvalidRequest(
+            ErrorCode::MessageTooLong,
+            "Message too long (max 1000 chars)",
+        ));
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(e.code().as_str(), "MESSAGE_TOO_LONG");
+
+        let e = ApiError::from(ServiceError::QuotaExceeded { remaining: Some(0) });
+        assert_eq!(e.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(e.code().as_str(), "QUOTA_EXCEEDED");
+
+        let e = ApiError::from(ServiceError::Telegram(Failure::RateLimited));
+        assert_eq!(e.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(e.code().as_str(), "RATE_LIMITED");
+
+        let e = ApiError::from(ServiceError::Telegram(Failure::Network));
+        assert_eq!(e.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(e.code().as_str(), "TELEGRAM_UNAVAILABLE");
+
+        let e = ApiError::from(anyhow::anyhow!("disk I/O error"));
+        assert_eq!(e.code().as_str(), "DATABASE_ERROR");
+        assert_eq!(e.to_string(), "Database error occurred");
+    }
 
     #[tokio::test]
     #[ignore = "manual"]
@@ -1,18 +1,1023 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 use anyhow::Result;
+use serde::Serialize;
 use sqlx::SqlitePool;
+use teloxide::dispatching::dialogue::InMemStorage;
 use teloxide::prelude::*;
+use teloxide::types::{
+    BotCommand, BotCommandScope, CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup,
+    InlineQuery, InlineQueryResult, InlineQueryResultArticle, InputMessageContent,
+    InputMessageContentText, KeyboardButton, KeyboardMarkup, MaybeAnonymousUser, Me, Poll,
+    PollAnswer,
+};
 use teloxide::utils::command::BotCommands;
 
-pub async fn run_bot(pool: SqlitePool) -> Result<()> {
-    log::info!("Starting Telegram bot");
-    let bot = Bot::from_env();
+use crate::db::SubscribeOutcome;
+use crate::delivery::{MAX_CALLBACK_DATA_BYTES, SendOptions};
+use crate::events::EventKind;
+use crate::roles::Role;
+use crate::service::{Services, Tenant};
+use crate::throttle::ChatLimits;
+
+/// Name of the bot configured through `TELOXIDE_TOKEN`
+pub const DEFAULT_BOT: &str = "default";
+
+/// Longest description Telegram accepts for a bot
+pub const MAX_BOT_DESCRIPTION_CHARS: usize = 512;
+/// Longest short description Telegram accepts for a bot
+pub const MAX_BOT_SHORT_DESCRIPTION_CHARS: usize = 120;
+
+#[derive(Clone)]
+pub struct BotIdentity {
+    pub bot: Bot,
+    /// Users subscribing through this bot join this tenant's channels
+    pub tenant_id: String,
+}
+
+/// Every configured bot identity by name
+#[derive(Clone)]
+pub struct Bots {
+    bots: BTreeMap<String, BotIdentity>,
+}
+
+impl Bots {
+    pub fn new(bots: BTreeMap<String, BotIdentity>) -> Self {
+        Self { bots }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bot> {
+        self.bots.get(name).map(|identity| &identity.bot)
+    }
+
+    /// Name of the first bot serving `tenant_id`, the default bot if none does
+    pub fn for_tenant(&self, tenant_id: &str) -> &str {
+        self.iter()
+            .find(|(_, identity)| identity.tenant_id == tenant_id)
+            .map_or(DEFAULT_BOT, |(name, _)| name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &BotIdentity)> {
+        self.bots
+            .iter()
+            .map(|(name, identity)| (name.as_str(), identity))
+    }
+}
+
+/// State shared by a bot's update handlers
+#[derive(Clone)]
+struct HandlerContext {
+    services: Services,
+    tenant_id: String,
+    bot_name: String,
+    http: reqwest::Client,
+    command_limits: ChatLimits,
+}
+
+/// Body posted to the reply callback for a non-command message
+#[derive(Serialize)]
+struct Reply<'a> {
+    tenant_id: &'a str,
+    bot: &'a str,
+    chat_id: i64,
+    text: &'a str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// Body posted to the button callback for a press of an inline button
+#[derive(Serialize)]
+struct ButtonPress<'a> {
+    tenant_id: &'a str,
+    bot: &'a str,
+    /// Recipient whose copy of the message the button was on
+    chat_id: i64,
+    /// Who pressed it, the recipient unless the chat is a group
+    user_id: i64,
+    /// Stored message, as returned by the send, `None` if it isn't known
+    message_id: Option<i64>,
+    telegram_message_id: i32,
+    data: &'a str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+pub async fn run_bot(bot_name: String, identity: BotIdentity, services: Services) -> Result<()> {
+    log::info!(
+        "Starting Telegram bot '{}' for tenant '{}'",
+        bot_name,
+        identity.tenant_id
+    );
+
+    let ctx = HandlerContext {
+        services,
+        tenant_id: identity.tenant_id,
+        bot_name,
+        http: reqwest::Client::new(),
+        command_limits: ChatLimits::default(),
+    };
+    let handler = dptree::entry()
+        .branch(
+            Update::filter_message()
+                .inspect_async(remember_sender)
+                .branch(
+                    dptree::entry()
+                        .filter_command::<Command>()
+                        .endpoint(handle_command),
+                )
+                .branch(dptree::filter_map(MenuAction::from_message).endpoint(handle_menu))
+                .branch(dptree::endpoint(forward_reply)),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .branch(
+                    dptree::filter(|query: CallbackQuery| {
+                        query
+                            .data
+                            .as_deref()
+                            .is_some_and(|data| data.starts_with(UNSUBSCRIBE_DATA))
+                    })
+                    .endpoint(unsubscribe_button),
+                )
+                .branch(
+                    dptree::filter_map(SubscriptionConfirmation::from_query)
+                        .endpoint(confirm_subscription),
+                )
+                .branch(
+                    dptree::filter_map(ChallengeAnswer::from_query)
+                        .enter_dialogue::<CallbackQuery, InMemStorage<Challenge>, Challenge>()
+                        .endpoint(answer_challenge),
+                )
+                .branch(
+                    dptree::filter_map(OnboardingChoice::from_query)
+                        .enter_dialogue::<CallbackQuery, InMemStorage<Onboarding>, Onboarding>()
+                        .endpoint(onboarding_step),
+                )
+                .branch(dptree::endpoint(forward_button_press)),
+        )
+        .branch(Update::filter_inline_query().endpoint(search_channels))
+        .branch(Update::filter_poll_answer().endpoint(record_poll_answer))
+        .branch(Update::filter_poll().endpoint(record_poll_state));
+
+    register_commands(&identity.bot, &ctx.services.config.admin_telegram_ids).await;
+    describe_bot(&identity.bot, &ctx.services.config).await;
+
+    Dispatcher::builder(identity.bot, handler)
+        .dependencies(dptree::deps![
+            ctx,
+            InMemStorage::<Onboarding>::new(),
+            InMemStorage::<Challenge>::new()
+        ])
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
+
+    Ok(())
+}
+
+/// Descriptions of the commands in languages other than English, by language code
+const COMMAND_TRANSLATIONS: &[(&str, &[(&str, &str)])] = &[(
+    "it",
+    &[
+        ("start", "Mostra i comandi"),
+        ("subscribe", "Iscriviti a un canale"),
+        ("unsubscribe", "Annulla l'iscrizione a un canale"),
+        ("stats", "Mostra iscritti e invii (viewer)"),
+        (
+            "broadcast",
+            "Invia un messaggio a tutti gli iscritti (admin)",
+        ),
+        ("subscribers", "Mostra gli iscritti di un canale (viewer)"),
+    ],
+)];
+
+/// The commands, named without their `/` as Telegram takes them, described in
+/// `language_code` when translated to it, in English otherwise
+fn localized_commands(language_code: Option<&str>) -> Vec<BotCommand> {
+    let translations = COMMAND_TRANSLATIONS
+        .iter()
+        .find(|(code, _)| Some(*code) == language_code)
+        .map_or(&[][..], |(_, translations)| translations);
+    Command::bot_commands()
+        .into_iter()
+        .map(|mut command| {
+            command.command = command.command.trim_start_matches('/').to_string();
+            if let Some((_, description)) = translations
+                .iter()
+                .find(|(name, _)| *name == command.command)
+            {
+                command.description = description.to_string();
+            }
+            command
+        })
+        .collect()
+}
+
+/// Sets the command list Telegram's command menu shows: the commands anyone can run,
+/// and every command in the chats of `ADMIN_TELEGRAM_IDS`. Users whose Telegram is set
+/// to a language in [`COMMAND_TRANSLATIONS`] see them described in it.
+async fn register_commands(bot: &Bot, admin_telegram_ids: &[i64]) {
+    let languages =
+        std::iter::once(None).chain(COMMAND_TRANSLATIONS.iter().map(|(code, _)| Some(*code)));
+    for language_code in languages {
+        let commands = localized_commands(language_code);
+        let public = commands.iter().filter(|command| {
+            Command::parse(&format!("/{}", command.command), "")
+                .is_ok_and(|cmd| cmd.required_role().is_none())
+        });
+        if let Err(e) = set_commands(bot, public.cloned(), None, language_code).await {
+            log::error!("Failed to register bot commands: {}", e);
+            return;
+        }
+        for &telegram_id in admin_telegram_ids {
+            let scope = BotCommandScope::Chat {
+                chat_id: ChatId(telegram_id).into(),
+            };
+            if let Err(e) = set_commands(bot, commands.clone(), Some(scope), language_code).await {
+                log::error!(
+                    "Failed to register bot commands for admin {}: {}",
+                    telegram_id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+async fn set_commands(
+    bot: &Bot,
+    commands: impl IntoIterator<Item = BotCommand>,
+    scope: Option<BotCommandScope>,
+    language_code: Option<&str>,
+) -> ResponseResult<()> {
+    let mut request = bot.set_my_commands(commands);
+    if let Some(scope) = scope {
+        request = request.scope(scope);
+    }
+    if let Some(language_code) = language_code {
+        request = request.language_code(language_code);
+    }
+    request.await?;
+    Ok(())
+}
+
+/// Sets the bot's descriptions from `BOT_DESCRIPTION` and `BOT_SHORT_DESCRIPTION`, leaving
+/// those not configured as they are
+async fn describe_bot(bot: &Bot, config: &crate::config::Config) {
+    if let Some(description) = &config.bot_description
+        && let Err(e) = bot.set_my_description().description(description).await
+    {
+        log::error!("Failed to set the bot description: {}", e);
+    }
+    if let Some(description) = &config.bot_short_description
+        && let Err(e) = bot
+            .set_my_short_description()
+            .short_description(description)
+            .await
+    {
+        log::error!("Failed to set the bot short description: {}", e);
+    }
+}
+
+/// Keeps the sender's Telegram profile, so admins can tell subscribers apart
+async fn remember_sender(msg: Message, ctx: HandlerContext) {
+    let Some(user) = &msg.from else {
+        return;
+    };
+    let profile = crate::db::TelegramProfile {
+        telegram_id: user.id.0 as i64,
+        username: user.username.clone(),
+        first_name: user.first_name.clone(),
+        language_code: user.language_code.clone(),
+    };
+    if let Err(e) = crate::db::remember_telegram_user(&ctx.services.pool, &profile).await {
+        log::error!("Database error: {}", e);
+    }
+}
+
+/// Posts free text (anything but a command) to the reply callback, if configured
+async fn forward_reply(msg: Message, ctx: HandlerContext) -> ResponseResult<()> {
+    let (Some(url), Some(text)) = (&ctx.services.config.reply_callback_url, msg.text()) else {
+        return Ok(());
+    };
+    if text.starts_with('/') {
+        return Ok(());
+    }
+
+    let reply = Reply {
+        tenant_id: &ctx.tenant_id,
+        bot: &ctx.bot_name,
+        chat_id: msg.chat.id.0,
+        text,
+        timestamp: msg.date,
+    };
+    if let Err(e) = post_json(&ctx.http, url, &reply).await {
+        log::error!("Reply callback failed: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Answers the press of an inline button, so Telegram stops showing it as loading, and
+/// posts it to the button callback, if configured
+async fn forward_button_press(
+    bot: Bot,
+    query: CallbackQuery,
+    ctx: HandlerContext,
+) -> ResponseResult<()> {
+    bot.answer_callback_query(query.id.clone()).await?;
+    let url = &ctx.services.config.button_callback_url;
+    let (Some(url), Some(message), Some(data)) = (url, &query.message, &query.data) else {
+        return Ok(());
+    };
+
+    let chat_id = message.chat().id.0;
+    let telegram_message_id = message.id().0;
+    let message_id = crate::db::find_message_by_copy(
+        &ctx.services.pool,
+        &ctx.tenant_id,
+        &ctx.bot_name,
+        chat_id,
+        telegram_message_id as i64,
+    )
+    .await
+    .inspect_err(|e| log::error!("Database error: {}", e))
+    .ok()
+    .flatten();
+    let press = ButtonPress {
+        tenant_id: &ctx.tenant_id,
+        bot: &ctx.bot_name,
+        chat_id,
+        user_id: query.from.id.0 as i64,
+        message_id,
+        telegram_message_id,
+        data,
+        timestamp: chrono::Utc::now(),
+    };
+    if let Err(e) = post_json(&ctx.http, url, &press).await {
+        log::error!("Button callback failed: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Buttons of the reply keyboard set on `/start`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MenuAction {
+    MySubscriptions,
+    BrowseChannels,
+    Help,
+}
+
+impl MenuAction {
+    const ALL: [MenuAction; 3] = [
+        MenuAction::MySubscriptions,
+        MenuAction::BrowseChannels,
+        MenuAction::Help,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            MenuAction::MySubscriptions => "My subscriptions",
+            MenuAction::BrowseChannels => "Browse channels",
+            MenuAction::Help => "Help",
+        }
+    }
+
+    /// The action of a message sent by pressing one of the buttons
+    fn from_message(msg: Message) -> Option<Self> {
+        Self::from_text(msg.text()?)
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.label() == text)
+    }
+}
+
+/// Reply keyboard kept open under the chat, so users don't need to remember commands
+fn menu_keyboard() -> KeyboardMarkup {
+    let button = |action: MenuAction| KeyboardButton::new(action.label());
+    KeyboardMarkup::new([
+        vec![
+            button(MenuAction::MySubscriptions),
+            button(MenuAction::BrowseChannels),
+        ],
+        vec![button(MenuAction::Help)],
+    ])
+    .persistent()
+    .resize_keyboard()
+}
+
+async fn handle_menu(
+    bot: Bot,
+    me: Me,
+    msg: Message,
+    action: MenuAction,
+    ctx: HandlerContext,
+) -> ResponseResult<()> {
+    let pool = &ctx.services.pool;
+    match action {
+        MenuAction::MySubscriptions => {
+            let reply = match crate::db::list_subscribed_channels(
+                pool,
+                &ctx.tenant_id,
+                msg.chat.id.0,
+            )
+            .await
+            {
+                Ok(channels) if channels.is_empty() => {
+                    "You aren't subscribed to any channel yet".to_string()
+                }
+                Ok(channels) => format!("You're subscribed to:\n{}", channels.join("\n")),
+                Err(e) => {
+                    log::error!("Database error: {}", e);
+                    "Error loading your subscriptions".to_string()
+                }
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        MenuAction::BrowseChannels => {
+            let channels =
+                crate::db::search_public_channels(pool, &ctx.tenant_id, "", MAX_INLINE_RESULTS)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Database error: {}", e);
+                        Vec::new()
+                    });
+            let buttons: Vec<_> = channels
+                .into_iter()
+                .filter_map(|channel| {
+                    let link = subscribe_link(me.username(), &channel.name)?;
+                    let text = format!("{} ({} subscribers)", channel.name, channel.subscribers);
+                    Some(vec![InlineKeyboardButton::url(text, link)])
+                })
+                .collect();
+            if buttons.is_empty() {
+                bot.send_message(msg.chat.id, "No channels to browse yet")
+                    .await?;
+            } else {
+                bot.send_message(msg.chat.id, "Tap a channel to subscribe")
+                    .reply_markup(InlineKeyboardMarkup::new(buttons))
+                    .await?;
+            }
+        }
+        MenuAction::Help => {
+            bot.send_message(msg.chat.id, Command::descriptions().to_string())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Step of the channel picker a new user is walked through on their first `/start`.
+/// Buttons carry an index into the options listed, since callback data is too short
+/// for channel names.
+#[derive(Clone, Default)]
+enum Onboarding {
+    #[default]
+    Idle,
+    /// Picking among categories, the top level of public channel names
+    PickingCategory {
+        categories: Vec<String>,
+    },
+    PickingChannel {
+        category: String,
+        channels: Vec<String>,
+    },
+    Confirming {
+        category: String,
+        channel: String,
+    },
+}
+
+type OnboardingDialogue = Dialogue<Onboarding, InMemStorage<Onboarding>>;
+
+/// Start of the callback data of the channel picker's buttons, whose presses aren't
+/// forwarded to the button callback
+const ONBOARDING_DATA_PREFIX: &str = "onboarding:";
+
+/// Most public channels the channel picker lists
+const MAX_ONBOARDING_CHANNELS: i64 = 100;
+
+/// Button of the channel picker
+#[derive(Clone, Copy)]
+enum OnboardingChoice {
+    /// The option at this index of those listed
+    Pick(usize),
+    Back,
+    Subscribe,
+    Done,
+}
+
+impl OnboardingChoice {
+    fn button(&self, text: impl Into<String>) -> InlineKeyboardButton {
+        let choice = match self {
+            OnboardingChoice::Pick(index) => index.to_string(),
+            OnboardingChoice::Back => "back".to_string(),
+            OnboardingChoice::Subscribe => "subscribe".to_string(),
+            OnboardingChoice::Done => "done".to_string(),
+        };
+        InlineKeyboardButton::callback(text, format!("{}{}", ONBOARDING_DATA_PREFIX, choice))
+    }
+
+    /// The choice of a press of one of the channel picker's buttons
+    fn from_query(query: CallbackQuery) -> Option<Self> {
+        let data = query.data?;
+        match data.strip_prefix(ONBOARDING_DATA_PREFIX)? {
+            "back" => Some(OnboardingChoice::Back),
+            "subscribe" => Some(OnboardingChoice::Subscribe),
+            "done" => Some(OnboardingChoice::Done),
+            index => index.parse().ok().map(OnboardingChoice::Pick),
+        }
+    }
+}
+
+/// Names of the tenant's public channels, most subscribed first
+async fn public_channel_names(pool: &SqlitePool, tenant_id: &str) -> Vec<String> {
+    crate::db::search_public_channels(pool, tenant_id, "", MAX_ONBOARDING_CHANNELS)
+        .await
+        .unwrap_or_else(|e| {
+            log::error!("Database error: {}", e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|channel| channel.name)
+        .collect()
+}
+
+/// Top level of a channel's name, grouping it with the channels nested under it
+fn channel_category(channel_name: &str) -> &str {
+    channel_name
+        .split(crate::db::CHANNEL_SEPARATOR)
+        .next()
+        .unwrap_or(channel_name)
+}
+
+/// Most buttons Telegram accepts in an inline keyboard
+const MAX_KEYBOARD_BUTTONS: usize = 100;
+
+/// Most options a step of the channel picker lists, leaving room for its last button
+const MAX_PICKER_OPTIONS: usize = MAX_KEYBOARD_BUTTONS - 1;
+
+/// A list of options to pick from, one per row, followed by `last`
+fn picker_keyboard(options: &[String], last: InlineKeyboardButton) -> InlineKeyboardMarkup {
+    let rows = options
+        .iter()
+        .enumerate()
+        .map(|(index, option)| vec![OnboardingChoice::Pick(index).button(option)]);
+    InlineKeyboardMarkup::new(rows.chain([vec![last]]))
+}
+
+/// The category picker, `None` if there are no public channels
+fn category_step(
+    channels: &[String],
+    intro: &str,
+) -> Option<(Onboarding, String, InlineKeyboardMarkup)> {
+    let mut categories: Vec<String> = Vec::new();
+    for category in channels.iter().map(|channel| channel_category(channel)) {
+        if !categories.iter().any(|known| known == category) {
+            categories.push(category.to_string());
+        }
+    }
+    categories.truncate(MAX_PICKER_OPTIONS);
+    if categories.is_empty() {
+        return None;
+    }
+    let keyboard = picker_keyboard(&categories, OnboardingChoice::Done.button("Done"));
+    let text = format!("{}Pick a category to see its channels", intro);
+    Some((Onboarding::PickingCategory { categories }, text, keyboard))
+}
+
+/// The picker of the channels in `category`
+fn channel_step(
+    channels: &[String],
+    category: String,
+) -> (Onboarding, String, InlineKeyboardMarkup) {
+    let channels: Vec<String> = channels
+        .iter()
+        .filter(|channel| channel_category(channel) == category)
+        .take(MAX_PICKER_OPTIONS)
+        .cloned()
+        .collect();
+    let keyboard = picker_keyboard(&channels, OnboardingChoice::Back.button("« Categories"));
+    let text = format!("Channels in '{}'", category);
+    (
+        Onboarding::PickingChannel { category, channels },
+        text,
+        keyboard,
+    )
+}
+
+/// Starts the channel picker for a user without subscriptions, returning whether it did
+async fn start_onboarding(
+    bot: &Bot,
+    dialogue: &OnboardingDialogue,
+    pool: &SqlitePool,
+    tenant_id: &str,
+) -> ResponseResult<bool> {
+    let chat_id = dialogue.chat_id();
+    match crate::db::list_subscribed_channels(pool, tenant_id, chat_id.0).await {
+        Ok(subscribed) if subscribed.is_empty() => {}
+        Ok(_) => return Ok(false),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(false);
+        }
+    }
+    let channels = public_channel_names(pool, tenant_id).await;
+    let Some((state, text, keyboard)) = category_step(&channels, "Welcome! ") else {
+        return Ok(false);
+    };
+    bot.send_message(chat_id, text)
+        .reply_markup(keyboard)
+        .await?;
+    update_dialogue(dialogue, state).await;
+    Ok(true)
+}
+
+/// Moves the channel picker along on a press of one of its buttons, editing its message
+/// into the next step
+async fn onboarding_step(
+    bot: Bot,
+    query: CallbackQuery,
+    choice: OnboardingChoice,
+    dialogue: OnboardingDialogue,
+    state: Onboarding,
+    ctx: HandlerContext,
+    challenges: Arc<InMemStorage<Challenge>>,
+) -> ResponseResult<()> {
+    let Some(message) = &query.message else {
+        bot.answer_callback_query(query.id.clone()).await?;
+        return Ok(());
+    };
+    let (chat_id, message_id) = (message.chat().id, message.id());
+
+    let channels = public_channel_names(&ctx.services.pool, &ctx.tenant_id).await;
+    let mut intro = String::new();
+    let next = match (state, choice) {
+        (_, OnboardingChoice::Done) => None,
+        (Onboarding::PickingCategory { categories }, OnboardingChoice::Pick(index))
+            if index < categories.len() =>
+        {
+            let category = categories[index].clone();
+            Some(channel_step(&channels, category))
+        }
+        (Onboarding::PickingChannel { category, channels }, OnboardingChoice::Pick(index))
+            if index < channels.len() =>
+        {
+            let channel = channels[index].clone();
+            let keyboard = InlineKeyboardMarkup::new([[
+                OnboardingChoice::Subscribe.button("Subscribe"),
+                OnboardingChoice::Back.button("« Back"),
+            ]]);
+            let text = format!("Subscribe to '{}'?", channel);
+            Some((Onboarding::Confirming { category, channel }, text, keyboard))
+        }
+        (Onboarding::PickingChannel { .. }, OnboardingChoice::Back) => category_step(&channels, ""),
+        (Onboarding::Confirming { category, .. }, OnboardingChoice::Back) => {
+            Some(channel_step(&channels, category))
+        }
+        (Onboarding::Confirming { channel, .. }, OnboardingChoice::Subscribe) => {
+            let challenge = ChallengeDialogue::new(challenges, chat_id);
+            if challenge_if_needed(&bot, &challenge, &ctx.services, &channel).await? {
+                bot.answer_callback_query(query.id.clone()).await?;
+                bot.edit_message_text(
+                    chat_id,
+                    message_id,
+                    format!("Answer the question below to subscribe to '{}'", channel),
+                )
+                .await?;
+                exit_dialogue(&dialogue).await;
+                return Ok(());
+            }
+            let (reply, confirm) = subscribe_chat(
+                &ctx.services,
+                &ctx.tenant_id,
+                &ctx.bot_name,
+                chat_id.0,
+                &channel,
+            )
+            .await;
+            match confirm {
+                // Confirmed in a message of its own, the picker carrying on
+                Some(confirm) => {
+                    bot.send_message(chat_id, reply)
+                        .reply_markup(confirm)
+                        .await?;
+                }
+                None => intro = format!("{}. ", reply),
+            }
+            category_step(&channels, &intro)
+        }
+        _ => {
+            bot.answer_callback_query(query.id.clone())
+                .text("This menu has expired, send /start again")
+                .await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    match next {
+        Some((state, text, keyboard)) => {
+            bot.edit_message_text(chat_id, message_id, text)
+                .reply_markup(keyboard)
+                .await?;
+            update_dialogue(&dialogue, state).await;
+        }
+        None => {
+            bot.edit_message_text(
+                chat_id,
+                message_id,
+                format!(
+                    "{}You're all set. Find more channels under Browse channels",
+                    intro
+                ),
+            )
+            .await?;
+            exit_dialogue(&dialogue).await;
+        }
+    }
+    Ok(())
+}
+
+async fn update_dialogue<D>(dialogue: &Dialogue<D, InMemStorage<D>>, state: D)
+where
+    D: Clone + Send + 'static,
+{
+    if let Err(e) = dialogue.update(state).await {
+        log::error!("Dialogue error: {}", e);
+    }
+}
+
+async fn exit_dialogue<D>(dialogue: &Dialogue<D, InMemStorage<D>>)
+where
+    D: Clone + Send + 'static,
+{
+    if let Err(e) = dialogue.exit().await {
+        log::error!("Dialogue error: {}", e);
+    }
+}
+
+/// Question a chat answers with an inline button before its first subscription, when
+/// `SUBSCRIBE_CHALLENGE` is on, to keep automated accounts out
+#[derive(Clone, Default)]
+enum Challenge {
+    #[default]
+    Idle,
+    Pending {
+        /// Index of the right button
+        answer: usize,
+        /// Subscribed to once the challenge is passed
+        channel: String,
+    },
+}
+
+type ChallengeDialogue = Dialogue<Challenge, InMemStorage<Challenge>>;
+
+/// Start of the callback data of the challenge's buttons
+const CHALLENGE_DATA_PREFIX: &str = "challenge:";
+
+/// Callback data the bot's own buttons use, which buttons sent through the API can't
+pub const RESERVED_DATA_PREFIXES: [&str; 4] = [
+    ONBOARDING_DATA_PREFIX,
+    CHALLENGE_DATA_PREFIX,
+    CONFIRM_DATA_PREFIX,
+    UNSUBSCRIBE_DATA,
+];
+
+/// What the challenge can ask to tap, by the button showing it
+const CHALLENGE_OPTIONS: &[(&str, &str)] = &[
+    ("🍎", "apple"),
+    ("🍌", "banana"),
+    ("🍇", "grapes"),
+    ("🍒", "cherries"),
+    ("🍋", "lemon"),
+    ("🥕", "carrot"),
+];
+
+/// Buttons the challenge offers, one of them right
+const CHALLENGE_BUTTONS: usize = 4;
+
+/// Index of the button pressed in a challenge
+#[derive(Clone, Copy)]
+struct ChallengeAnswer(usize);
+
+impl ChallengeAnswer {
+    fn from_query(query: CallbackQuery) -> Option<Self> {
+        Self::from_data(&query.data?)
+    }
+
+    fn from_data(data: &str) -> Option<Self> {
+        data.strip_prefix(CHALLENGE_DATA_PREFIX)?
+            .parse()
+            .ok()
+            .map(ChallengeAnswer)
+    }
+}
+
+/// A new challenge: what it asks, its buttons and the index of the right one
+fn new_challenge() -> (String, InlineKeyboardMarkup, usize) {
+    use rand::Rng;
+    use rand::seq::IndexedRandom;
+
+    let mut rng = rand::rng();
+    let options: Vec<_> = CHALLENGE_OPTIONS
+        .choose_multiple(&mut rng, CHALLENGE_BUTTONS)
+        .collect();
+    let answer = rng.random_range(0..options.len());
+    let buttons = options.iter().enumerate().map(|(index, (label, _))| {
+        InlineKeyboardButton::callback(*label, format!("{}{}", CHALLENGE_DATA_PREFIX, index))
+    });
+    let text = format!(
+        "Before your first subscription, please tap the {}",
+        options[answer].1
+    );
+    (text, InlineKeyboardMarkup::new([buttons]), answer)
+}
+
+/// Sends the chat a challenge, to pass before subscribing to `channel`, if it must.
+/// Returns whether it did.
+async fn challenge_if_needed(
+    bot: &Bot,
+    dialogue: &ChallengeDialogue,
+    services: &Services,
+    channel: &str,
+) -> ResponseResult<bool> {
+    if !services.config.subscribe_challenge {
+        return Ok(false);
+    }
+    let chat_id = dialogue.chat_id();
+    match crate::db::is_chat_verified(&services.pool, chat_id.0).await {
+        Ok(false) => {}
+        Ok(true) => return Ok(false),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(false);
+        }
+    }
+
+    let (text, keyboard, answer) = new_challenge();
+    bot.send_message(chat_id, text)
+        .reply_markup(keyboard)
+        .await?;
+    let channel = channel.to_string();
+    update_dialogue(dialogue, Challenge::Pending { answer, channel }).await;
+    Ok(true)
+}
 
-    Command::repl(bot, move |bot: Bot, msg: Message, cmd: Command| {
-        let pool = pool.clone();
-        async move { handle_command(bot, msg, cmd, pool).await }
-    })
+/// Subscribes the chat to the channel it wanted once it presses the right button of
+/// its challenge, and asks again with a new one otherwise
+async fn answer_challenge(
+    bot: Bot,
+    query: CallbackQuery,
+    pressed: ChallengeAnswer,
+    dialogue: ChallengeDialogue,
+    state: Challenge,
+    ctx: HandlerContext,
+) -> ResponseResult<()> {
+    let (Some(message), Challenge::Pending { answer, channel }) = (&query.message, state) else {
+        bot.answer_callback_query(query.id.clone())
+            .text("This question has expired, subscribe again")
+            .await?;
+        return Ok(());
+    };
+    let (chat_id, message_id) = (message.chat().id, message.id());
+
+    if pressed.0 != answer {
+        bot.answer_callback_query(query.id.clone())
+            .text("Not quite, try again")
+            .await?;
+        let (text, keyboard, answer) = new_challenge();
+        bot.edit_message_text(chat_id, message_id, text)
+            .reply_markup(keyboard)
+            .await?;
+        update_dialogue(&dialogue, Challenge::Pending { answer, channel }).await;
+        return Ok(());
+    }
+
+    bot.answer_callback_query(query.id.clone()).await?;
+    if let Err(e) = crate::db::mark_chat_verified(&ctx.services.pool, chat_id.0).await {
+        log::error!("Database error: {}", e);
+    }
+    exit_dialogue(&dialogue).await;
+    let (reply, confirm) = subscribe_chat(
+        &ctx.services,
+        &ctx.tenant_id,
+        &ctx.bot_name,
+        chat_id.0,
+        &channel,
+    )
     .await;
+    let mut edit = bot.edit_message_text(chat_id, message_id, reply);
+    if let Some(confirm) = confirm {
+        edit = edit.reply_markup(confirm);
+    }
+    edit.await?;
+    Ok(())
+}
+
+/// Most channels an inline search lists
+const MAX_INLINE_RESULTS: i64 = 20;
+
+/// Answers `@bot <query>` typed in any chat with the tenant's public channels matching
+/// it, each sharing a link that subscribes to the channel
+async fn search_channels(
+    bot: Bot,
+    me: Me,
+    query: InlineQuery,
+    ctx: HandlerContext,
+) -> ResponseResult<()> {
+    let channels = crate::db::search_public_channels(
+        &ctx.services.pool,
+        &ctx.tenant_id,
+        query.query.trim(),
+        MAX_INLINE_RESULTS,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        log::error!("Database error: {}", e);
+        Vec::new()
+    });
+
+    let results = channels.into_iter().filter_map(|channel| {
+        let link = subscribe_link(me.username(), &channel.name)?;
+        let text = format!("Subscribe to '{}': {}", channel.name, link);
+        let content = InputMessageContent::Text(InputMessageContentText::new(text));
+        let article = InlineQueryResultArticle::new(channel.name.clone(), channel.name, content)
+            .description(format!("{} subscribers", channel.subscribers))
+            .reply_markup(InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
+                "Subscribe",
+                link,
+            )]]));
+        Some(InlineQueryResult::Article(article))
+    });
+    bot.answer_inline_query(query.id, results)
+        .cache_time(60)
+        .await?;
+    Ok(())
+}
+
+/// `https://t.me/<bot>?start=<channel>`, which runs `/start <channel>` when opened.
+/// `None` for names a start parameter can't carry, as it only allows ASCII letters,
+/// digits, `_` and `-`.
+fn subscribe_link(bot_username: &str, channel_name: &str) -> Option<reqwest::Url> {
+    let payload = channel_name.replace(crate::db::CHANNEL_SEPARATOR, "-");
+    if !payload
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return None;
+    }
+    reqwest::Url::parse(&format!("https://t.me/{}?start={}", bot_username, payload)).ok()
+}
+
+/// Channel a [`subscribe_link`] is for, given its start parameter
+fn channel_from_start(payload: &str) -> String {
+    payload.replace('-', &crate::db::CHANNEL_SEPARATOR.to_string())
+}
+
+async fn post_json(
+    http: &reqwest::Client,
+    url: &str,
+    body: &impl Serialize,
+) -> reqwest::Result<()> {
+    http.post(url).json(body).send().await?.error_for_status()?;
+    Ok(())
+}
 
+/// Keeps answers to non-anonymous polls sent through the proxy
+async fn record_poll_answer(answer: PollAnswer, ctx: HandlerContext) -> ResponseResult<()> {
+    let voter_id = match &answer.voter {
+        MaybeAnonymousUser::User(user) => user.id.0 as i64,
+        MaybeAnonymousUser::Chat(chat) => chat.id.0,
+    };
+    if let Err(e) = crate::db::record_poll_answer(
+        &ctx.services.pool,
+        &answer.poll_id.0,
+        voter_id,
+        &answer.option_ids,
+    )
+    .await
+    {
+        log::error!("Database error: {}", e);
+    }
+    Ok(())
+}
+
+/// Keeps the vote counts of polls sent through the proxy, the only results of anonymous ones
+async fn record_poll_state(poll: Poll, ctx: HandlerContext) -> ResponseResult<()> {
+    let voter_counts: Vec<u32> = poll.options.iter().map(|o| o.voter_count).collect();
+    if let Err(e) = crate::db::record_poll_state(
+        &ctx.services.pool,
+        &poll.id.0,
+        &voter_counts,
+        poll.total_voter_count,
+    )
+    .await
+    {
+        log::error!("Database error: {}", e);
+    }
     Ok(())
 }
 
@@ -20,49 +1025,118 @@ async fn handle_command(
     bot: Bot,
     msg: Message,
     cmd: Command,
-    pool: SqlitePool,
+    ctx: HandlerContext,
+    onboarding: Arc<InMemStorage<Onboarding>>,
+    challenges: Arc<InMemStorage<Challenge>>,
 ) -> ResponseResult<()> {
+    let per_minute = ctx.services.config.bot_commands_per_minute;
+    if per_minute > 0
+        && let Err(wait) = ctx.command_limits.check(msg.chat.id.0, per_minute)
+    {
+        // Only the first throttled command is answered, the rest are dropped silently
+        if let Some(wait) = wait {
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "You're sending commands too quickly, please try again in {} seconds",
+                    wait.as_secs_f64().ceil()
+                ),
+            )
+            .await?;
+        }
+        return Ok(());
+    }
+
+    if let Some(required) = cmd.required_role()
+        && role(&msg, &ctx).await.is_none_or(|role| role < required)
+    {
+        bot.send_message(
+            msg.chat.id,
+            format!("This command requires the {} role", required.as_str()),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Opened from a subscribe link
+    let from_link = matches!(&cmd, Command::Start(payload) if !payload.is_empty());
+    let cmd = match cmd {
+        Command::Start(payload) if !payload.is_empty() => {
+            Command::Subscribe(channel_from_start(&payload))
+        }
+        cmd => cmd,
+    };
+
+    let HandlerContext {
+        services,
+        tenant_id,
+        bot_name,
+        ..
+    } = ctx;
+    let pool = &services.pool;
+    let events = &services.events;
+
     match cmd {
-        Command::Subscribe(channel_name) => {
-            if !crate::db::validate_channel_name(&channel_name) {
-                bot.send_message(
-                    msg.chat.id,
-                    "Invalid channel name. Only letters, numbers, and underscores are allowed.",
-                )
+        Command::Start(_) => {
+            bot.send_message(msg.chat.id, Command::descriptions().to_string())
+                .reply_markup(menu_keyboard())
                 .await?;
+            // First-time users are walked through picking channels
+            let dialogue = OnboardingDialogue::new(onboarding, msg.chat.id);
+            start_onboarding(&bot, &dialogue, pool, &tenant_id).await?;
+        }
+        Command::Subscribe(channel_name) => {
+            let challenge = ChallengeDialogue::new(challenges, msg.chat.id);
+            let valid = services
+                .config
+                .channel_names
+                .subscribe_error(&crate::db::normalize_channel_name(&channel_name))
+                .is_none();
+            if valid && challenge_if_needed(&bot, &challenge, &services, &channel_name).await? {
                 return Ok(());
             }
-
-            match crate::db::subscribe(&pool, msg.chat.id.0, &channel_name).await {
-                Ok(_) => {
-                    bot.send_message(
-                        msg.chat.id,
-                        format!("Successfully subscribed to '{}'", channel_name),
-                    )
-                    .await?;
-                }
-                Err(e) => {
-                    let error_msg = if e.to_string().contains("UNIQUE constraint failed") {
-                        format!("You are already subscribed to '{}'", channel_name)
-                    } else {
-                        format!("Error subscribing to '{}': {}", channel_name, e)
-                    };
-                    bot.send_message(msg.chat.id, error_msg).await?;
-                }
+            let (reply, confirm) = subscribe_chat(
+                &services,
+                &tenant_id,
+                &bot_name,
+                msg.chat.id.0,
+                &channel_name,
+            )
+            .await;
+            let mut request = bot.send_message(msg.chat.id, reply);
+            if let Some(confirm) = confirm {
+                request = request.reply_markup(confirm);
             }
+            request.await?;
         }
         Command::Unsubscribe(channel_name) => {
-            if !crate::db::validate_channel_name(&channel_name) {
-                bot.send_message(
-                    msg.chat.id,
-                    "Invalid channel name. Only letters, numbers, and underscores are allowed.",
-                )
-                .await?;
+            let channel_name = crate::db::normalize_channel_name(&channel_name);
+            if let Some(problem) = crate::db::channel_name_error(&channel_name) {
+                bot.send_message(msg.chat.id, problem).await?;
                 return Ok(());
             }
+            let channel_name =
+                match crate::db::resolve_channel_name(pool, &tenant_id, &channel_name).await {
+                    Ok(channel_name) => channel_name,
+                    Err(e) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!("Error unsubscribing from '{}': {}", channel_name, e),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                };
 
-            match crate::db::unsubscribe(&pool, msg.chat.id.0, &channel_name).await {
+            match crate::db::unsubscribe(pool, &tenant_id, msg.chat.id.0, &channel_name).await {
                 Ok(true) => {
+                    events.publish(
+                        &tenant_id,
+                        EventKind::Unsubscribed {
+                            telegram_id: msg.chat.id.0,
+                            channel_name: channel_name.clone(),
+                        },
+                    );
                     bot.send_message(
                         msg.chat.id,
                         format!("Successfully unsubscribed from '{}'", channel_name),
@@ -85,15 +1159,669 @@ async fn handle_command(
                 }
             }
         }
+        Command::Stats => {
+            let today = chrono::Utc::now().date_naive().to_string();
+            let stats = match crate::db::list_channels(pool, &tenant_id).await {
+                Ok(channels) => crate::db::get_daily_stats(pool, &tenant_id, &today, &today)
+                    .await
+                    .map(|days| format_stats(&channels, &days)),
+                Err(e) => Err(e),
+            };
+            let reply = stats.unwrap_or_else(|e| {
+                log::error!("Database error: {}", e);
+                "Failed to load stats".to_string()
+            });
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        Command::Broadcast(message) => {
+            let tenant = Tenant::new(&tenant_id);
+            let subscribers = match services.prepare_broadcast(&tenant, &message, None).await {
+                Ok(subscribers) => subscribers,
+                Err(e) => {
+                    bot.send_message(msg.chat.id, format!("Broadcast failed: {}", e))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let report = services
+                .deliver(
+                    &tenant,
+                    None,
+                    subscribers,
+                    &message,
+                    &SendOptions::default(),
+                    |_, _, _| {},
+                )
+                .await;
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Broadcast sent to {}, {} failed",
+                    report.sent, report.errors
+                ),
+            )
+            .await?;
+        }
+        Command::Subscribers(channel_name) => {
+            let channel_name = crate::db::normalize_channel_name(&channel_name);
+            let reply = match crate::db::get_channel_stats(pool, &tenant_id, &channel_name).await {
+                Ok(Some(stats)) => format!(
+                    "'{}' has {} subscribers ({} new in 7 days, {} in 30 days)",
+                    channel_name,
+                    stats.subscribers,
+                    stats.new_subscribers_7d,
+                    stats.new_subscribers_30d
+                ),
+                Ok(None) => format!("Channel '{}' not found", channel_name),
+                Err(e) => {
+                    log::error!("Database error: {}", e);
+                    format!("Error loading '{}'", channel_name)
+                }
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+    }
+    if from_link {
+        bot.send_message(msg.chat.id, "Find more channels under Browse channels")
+            .reply_markup(menu_keyboard())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Subscribes `chat_id` to `channel_name`, returning what to tell them. For double
+/// opt-in channels the subscription waits for them to press the Confirm button returned.
+async fn subscribe_chat(
+    services: &Services,
+    tenant_id: &str,
+    bot_name: &str,
+    chat_id: i64,
+    channel_name: &str,
+) -> (String, Option<InlineKeyboardMarkup>) {
+    let channel_name = crate::db::normalize_channel_name(channel_name);
+    if let Some(problem) = services.config.channel_names.subscribe_error(&channel_name) {
+        return (problem.to_string(), None);
+    }
+    let pool = &services.pool;
+    let channel_name = match crate::db::resolve_channel_name(pool, tenant_id, &channel_name).await {
+        Ok(channel_name) => channel_name,
+        Err(e) => {
+            return (
+                format!("Error subscribing to '{}': {}", channel_name, e),
+                None,
+            );
+        }
+    };
+
+    match request_confirmation(services, tenant_id, bot_name, chat_id, &channel_name).await {
+        Ok(Some(confirm)) => {
+            let text = format!("Press Confirm to finish subscribing to '{}'", channel_name);
+            return (text, Some(confirm));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            return (
+                format!("Error subscribing to '{}': {}", channel_name, e),
+                None,
+            );
+        }
+    }
+    let reply = add_subscription(services, tenant_id, bot_name, chat_id, &channel_name).await;
+    (reply, None)
+}
+
+/// Keeps a subscription to a double opt-in channel to confirm, returning the button that
+/// confirms it. `None` for other channels and channels the chat is already subscribed to.
+async fn request_confirmation(
+    services: &Services,
+    tenant_id: &str,
+    bot_name: &str,
+    chat_id: i64,
+    channel_name: &str,
+) -> anyhow::Result<Option<InlineKeyboardMarkup>> {
+    let pool = &services.pool;
+    let double_opt_in = crate::db::get_channel_settings(pool, tenant_id, channel_name)
+        .await?
+        .is_some_and(|settings| settings.double_opt_in);
+    if !double_opt_in
+        || crate::db::list_subscribed_channels(pool, tenant_id, chat_id)
+            .await?
+            .iter()
+            .any(|subscribed| subscribed == channel_name)
+    {
+        return Ok(None);
+    }
+
+    let expired_before =
+        chrono::Utc::now().timestamp() - services.config.double_opt_in_expiry_secs as i64;
+    let id = crate::db::request_subscription(
+        pool,
+        tenant_id,
+        chat_id,
+        channel_name,
+        bot_name,
+        expired_before,
+    )
+    .await?;
+    let button = InlineKeyboardButton::callback("Confirm", SubscriptionConfirmation(id).data());
+    Ok(Some(InlineKeyboardMarkup::new([[button]])))
+}
+
+async fn add_subscription(
+    services: &Services,
+    tenant_id: &str,
+    bot_name: &str,
+    chat_id: i64,
+    channel_name: &str,
+) -> String {
+    match crate::db::subscribe(&services.pool, tenant_id, chat_id, channel_name, bot_name).await {
+        Ok(SubscribeOutcome::NewlySubscribed) => {
+            services.events.publish(
+                tenant_id,
+                EventKind::Subscribed {
+                    telegram_id: chat_id,
+                    channel_name: channel_name.to_string(),
+                },
+            );
+            format!("Successfully subscribed to '{}'", channel_name)
+        }
+        Ok(SubscribeOutcome::AlreadySubscribed) => {
+            format!("You are already subscribed to '{}'", channel_name)
+        }
+        Err(e) => format!("Error subscribing to '{}': {}", channel_name, e),
+    }
+}
+
+/// Start of the callback data of Confirm buttons, followed by the pending subscription's id
+const CONFIRM_DATA_PREFIX: &str = "confirm:";
+
+/// Id of the pending subscription a Confirm button is for
+#[derive(Clone, Copy)]
+struct SubscriptionConfirmation(i64);
+
+impl SubscriptionConfirmation {
+    fn data(self) -> String {
+        format!("{}{}", CONFIRM_DATA_PREFIX, self.0)
+    }
+
+    fn from_query(query: CallbackQuery) -> Option<Self> {
+        Self::from_data(&query.data?)
+    }
+
+    fn from_data(data: &str) -> Option<Self> {
+        data.strip_prefix(CONFIRM_DATA_PREFIX)?
+            .parse()
+            .ok()
+            .map(SubscriptionConfirmation)
+    }
+}
+
+/// Subscribes the chat on a press of Confirm, unless the request expired
+async fn confirm_subscription(
+    bot: Bot,
+    query: CallbackQuery,
+    confirmation: SubscriptionConfirmation,
+    ctx: HandlerContext,
+) -> ResponseResult<()> {
+    bot.answer_callback_query(query.id.clone()).await?;
+    let Some(message) = &query.message else {
+        return Ok(());
+    };
+    let (chat_id, message_id) = (message.chat().id, message.id());
+
+    let expired_before =
+        chrono::Utc::now().timestamp() - ctx.services.config.double_opt_in_expiry_secs as i64;
+    let reply = match crate::db::take_pending_subscription(
+        &ctx.services.pool,
+        &ctx.tenant_id,
+        chat_id.0,
+        confirmation.0,
+    )
+    .await
+    {
+        Ok(Some(pending)) if pending.created_at >= expired_before => {
+            add_subscription(
+                &ctx.services,
+                &ctx.tenant_id,
+                &pending.bot,
+                chat_id.0,
+                &pending.channel_name,
+            )
+            .await
+        }
+        Ok(_) => "This confirmation has expired, please subscribe again".to_string(),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            "Error confirming your subscription".to_string()
+        }
+    };
+    bot.edit_message_text(chat_id, message_id, reply).await?;
+    Ok(())
+}
+
+/// Text of the button channels with `unsubscribe_button` add under their messages
+pub const UNSUBSCRIBE_BUTTON: &str = "🔕 Unsubscribe";
+/// Prefix of the callback data of that button, followed by the channel's name
+pub const UNSUBSCRIBE_DATA: &str = "unsubscribe:";
+
+/// Callback data of the Unsubscribe button under a message of `channel_name`. Names
+/// the channel when it fits, otherwise it's found from the stored message.
+pub fn unsubscribe_data(channel_name: &str) -> String {
+    let data = format!("{}{}", UNSUBSCRIBE_DATA, channel_name);
+    if data.len() <= MAX_CALLBACK_DATA_BYTES {
+        data
+    } else {
+        UNSUBSCRIBE_DATA.to_string()
+    }
+}
+
+/// Channel of the stored message `telegram_message_id` is a copy of, in the chat
+/// `telegram_id`, `None` if it isn't one or was sent to no channel
+async fn stored_channel(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    bot_name: &str,
+    telegram_id: i64,
+    telegram_message_id: i64,
+) -> Result<Option<String>> {
+    let id = crate::db::find_message_by_copy(
+        pool,
+        tenant_id,
+        bot_name,
+        telegram_id,
+        telegram_message_id,
+    )
+    .await?;
+    let Some(id) = id else {
+        return Ok(None);
+    };
+    let message = crate::db::find_message(pool, tenant_id, id).await?;
+    Ok(message.and_then(|message| message.channel_name))
+}
+
+/// Unsubscribes the chat `telegram_id` from the channel of the message the Unsubscribe
+/// button was pressed under: the one `data` names, or else the one of the stored message
+/// `telegram_message_id` is a copy of. Channels above it are kept, the reply pointing out
+/// those that keep delivering it. Returns the reply, and the channel when the chat was
+/// unsubscribed from it.
+async fn unsubscribe_pressed(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    bot_name: &str,
+    telegram_id: i64,
+    telegram_message_id: i64,
+    data: &str,
+) -> (String, Option<String>) {
+    let named = data
+        .strip_prefix(UNSUBSCRIBE_DATA)
+        .filter(|name| !name.is_empty());
+    let channel_name = match named {
+        Some(name) => Ok(Some(crate::db::normalize_channel_name(name))),
+        None => stored_channel(pool, tenant_id, bot_name, telegram_id, telegram_message_id).await,
+    };
+    let channel_name = match channel_name {
+        Ok(Some(channel_name)) => channel_name,
+        Ok(None) => {
+            return (
+                "Can't tell which channel this message is from, please use /unsubscribe"
+                    .to_string(),
+                None,
+            );
+        }
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return (
+                "Error unsubscribing, please try /unsubscribe".to_string(),
+                None,
+            );
+        }
+    };
+
+    let removed = crate::db::unsubscribe(pool, tenant_id, telegram_id, &channel_name).await;
+    let subscribed = crate::db::list_subscribed_channels(pool, tenant_id, telegram_id).await;
+    let (removed, subscribed) = match (removed, subscribed) {
+        (Ok(removed), Ok(subscribed)) => (removed, subscribed),
+        (Err(e), _) | (_, Err(e)) => {
+            log::error!("Database error: {}", e);
+            return (
+                "Error unsubscribing, please try /unsubscribe".to_string(),
+                None,
+            );
+        }
+    };
+    let parents: Vec<String> = crate::db::channel_and_parents(&channel_name)
+        .into_iter()
+        .filter(|name| *name != channel_name && subscribed.contains(name))
+        .map(|name| format!("'{}'", name))
+        .collect();
+
+    let reply = match (removed, parents.is_empty()) {
+        (true, true) => format!("Successfully unsubscribed from '{}'", channel_name),
+        (true, false) => format!(
+            "Unsubscribed from '{}', but your subscription to {} still delivers its messages, use /unsubscribe to stop them",
+            channel_name,
+            parents.join(", ")
+        ),
+        (false, true) => format!("You are not subscribed to '{}'", channel_name),
+        (false, false) => format!(
+            "You get '{}' through your subscription to {}, use /unsubscribe to stop its messages",
+            channel_name,
+            parents.join(", ")
+        ),
+    };
+    (reply, removed.then_some(channel_name))
+}
+
+/// Unsubscribes the chat from the channel of the message whose Unsubscribe button was
+/// pressed, see [`unsubscribe_pressed`]
+async fn unsubscribe_button(
+    bot: Bot,
+    query: CallbackQuery,
+    ctx: HandlerContext,
+) -> ResponseResult<()> {
+    bot.answer_callback_query(query.id.clone()).await?;
+    let Some(message) = &query.message else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+
+    let (reply, removed) = unsubscribe_pressed(
+        &ctx.services.pool,
+        &ctx.tenant_id,
+        &ctx.bot_name,
+        chat_id.0,
+        message.id().0 as i64,
+        query.data.as_deref().unwrap_or_default(),
+    )
+    .await;
+    if let Some(channel_name) = removed {
+        ctx.services.events.publish(
+            &ctx.tenant_id,
+            EventKind::Unsubscribed {
+                telegram_id: chat_id.0,
+                channel_name,
+            },
+        );
     }
+    bot.send_message(chat_id, reply).await?;
     Ok(())
 }
 
+/// Role of the sender: admin when listed in `ADMIN_TELEGRAM_IDS`, otherwise
+/// that of the user with their Telegram id
+async fn role(msg: &Message, ctx: &HandlerContext) -> Option<Role> {
+    let telegram_id = msg.from.as_ref()?.id.0 as i64;
+    if ctx
+        .services
+        .config
+        .admin_telegram_ids
+        .contains(&telegram_id)
+    {
+        return Some(Role::Admin);
+    }
+
+    match crate::db::find_user_by_telegram_id(&ctx.services.pool, telegram_id).await {
+        Ok(user) => user?.role(),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            None
+        }
+    }
+}
+
+/// Subscriber counts of every channel and today's delivery totals
+fn format_stats(channels: &[crate::db::ChannelSummary], days: &[crate::db::DailyStats]) -> String {
+    let (sent, errors) = days.iter().fold((0, 0), |(sent, errors), day| {
+        (sent + day.sent, errors + day.errors)
+    });
+    let mut lines = vec![format!("Today: {} sent, {} failed", sent, errors)];
+    lines.extend(
+        channels
+            .iter()
+            .map(|channel| format!("{}: {} subscribers", channel.name, channel.subscribers)),
+    );
+    if channels.is_empty() {
+        lines.push("No channels yet".to_string());
+    }
+    lines.join("\n")
+}
+
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command {
+    #[command(description = "Show the commands")]
+    Start(String),
     #[command(description = "Subscribe to a channel")]
     Subscribe(String),
     #[command(description = "Unsubscribe from a channel")]
     Unsubscribe(String),
+    #[command(description = "Show subscriber and delivery counts (viewers)")]
+    Stats,
+    #[command(description = "Send a message to every subscriber (admins)")]
+    Broadcast(String),
+    #[command(description = "Show a channel's subscriber count (viewers)")]
+    Subscribers(String),
+}
+
+impl Command {
+    /// Role needed to run the command, `None` if anyone can
+    fn required_role(&self) -> Option<Role> {
+        match self {
+            Command::Start(_) | Command::Subscribe(_) | Command::Unsubscribe(_) => None,
+            Command::Stats | Command::Subscribers(_) => Some(Role::Viewer),
+            Command::Broadcast(_) => Some(Role::Admin),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    fn button_count(keyboard: &InlineKeyboardMarkup) -> usize {
+        keyboard.inline_keyboard.iter().map(Vec::len).sum()
+    }
+
+    #[test]
+    fn test_picker_fits_keyboard_limit() {
+        let channels: Vec<String> = (0..MAX_ONBOARDING_CHANNELS)
+            .map(|i| format!("news/c{}", i))
+            .collect();
+        let (state, _, keyboard) = channel_step(&channels, "news".to_string());
+        assert_eq!(button_count(&keyboard), MAX_KEYBOARD_BUTTONS);
+        let Onboarding::PickingChannel {
+            channels: listed, ..
+        } = state
+        else {
+            panic!("expected the channel step");
+        };
+        assert_eq!(listed.len(), MAX_PICKER_OPTIONS);
+
+        let categories: Vec<String> = (0..150).map(|i| format!("c{}", i)).collect();
+        let (_, _, keyboard) = category_step(&categories, "").unwrap();
+        assert_eq!(button_count(&keyboard), MAX_KEYBOARD_BUTTONS);
+
+        let (_, _, keyboard) = category_step(&["news/a".to_string()], "").unwrap();
+        assert_eq!(button_count(&keyboard), 2);
+        assert!(category_step(&[], "").is_none());
+    }
+
+    #[sqlx::test]
+    async fn test_unsubscribe_pressed(pool: SqlitePool) -> Result<()> {
+        use crate::db::MessageCopy;
+
+        let press = |telegram_message_id, data| {
+            unsubscribe_pressed(&pool, "acme", DEFAULT_BOT, 1, telegram_message_id, data)
+        };
+        let id = crate::db::create_message(&pool, "acme", Some("ops/db"), "Disk full").await?;
+        let copy = MessageCopy {
+            telegram_id: 1,
+            bot: DEFAULT_BOT.to_string(),
+            telegram_message_id: 10,
+        };
+        crate::db::record_message_copies(&pool, id, &[copy]).await?;
+        for channel_name in ["ops", "ops/db", "news"] {
+            crate::db::subscribe(&pool, "acme", 1, channel_name, DEFAULT_BOT).await?;
+        }
+
+        let (reply, removed) = press(10, UNSUBSCRIBE_DATA).await;
+        assert_eq!(removed.as_deref(), Some("ops/db"));
+        assert!(reply.starts_with("Unsubscribed from 'ops/db'"));
+        assert!(reply.contains("'ops'"));
+        assert_eq!(
+            crate::db::list_subscribed_channels(&pool, "acme", 1).await?,
+            vec!["news", "ops"]
+        );
+        let (reply, removed) = press(10, UNSUBSCRIBE_DATA).await;
+        assert_eq!(removed, None);
+        assert!(reply.starts_with("You get 'ops/db' through your subscription to 'ops'"));
+
+        let (reply, removed) = press(11, "unsubscribe:News").await;
+        assert_eq!(removed.as_deref(), Some("news"));
+        assert_eq!(reply, "Successfully unsubscribed from 'news'");
+        let (reply, _) = press(11, "unsubscribe:news").await;
+        assert_eq!(reply, "You are not subscribed to 'news'");
+
+        let without_channel = crate::db::create_message(&pool, "acme", None, "Hi").await?;
+        let copy = MessageCopy {
+            telegram_id: 1,
+            bot: DEFAULT_BOT.to_string(),
+            telegram_message_id: 12,
+        };
+        crate::db::record_message_copies(&pool, without_channel, &[copy]).await?;
+        for telegram_message_id in [11, 12] {
+            let (reply, removed) = press(telegram_message_id, UNSUBSCRIBE_DATA).await;
+            assert_eq!(removed, None);
+            assert!(reply.starts_with("Can't tell which channel"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_translations() {
+        let english = localized_commands(None);
+        assert_eq!(english.len(), Command::bot_commands().len());
+        for (language_code, translations) in COMMAND_TRANSLATIONS {
+            assert_eq!(translations.len(), english.len());
+            let localized = localized_commands(Some(language_code));
+            for (command, english) in localized.iter().zip(&english) {
+                assert!(!command.command.starts_with('/'));
+                assert!(Command::parse(&format!("/{}", command.command), "").is_ok());
+                assert!(
+                    translations
+                        .iter()
+                        .any(|(name, _)| *name == command.command),
+                    "/{} isn't translated to {}",
+                    command.command,
+                    language_code
+                );
+                assert_ne!(command.description, english.description);
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_challenge() {
+        for _ in 0..50 {
+            let (text, keyboard, answer) = new_challenge();
+            let [buttons] = &keyboard.inline_keyboard[..] else {
+                panic!("expected a single row");
+            };
+            assert_eq!(buttons.len(), CHALLENGE_BUTTONS);
+            let (label, name) = CHALLENGE_OPTIONS
+                .iter()
+                .find(|(label, _)| *label == buttons[answer].text)
+                .expect("the answer is one of the options");
+            assert_eq!(
+                text,
+                format!("Before your first subscription, please tap the {}", name)
+            );
+            assert_eq!(
+                buttons
+                    .iter()
+                    .filter(|button| button.text == *label)
+                    .count(),
+                1
+            );
+            for (index, button) in buttons.iter().enumerate() {
+                let InlineKeyboardButtonKind::CallbackData(data) = &button.kind else {
+                    panic!("expected a callback button");
+                };
+                assert_eq!(
+                    ChallengeAnswer::from_data(data).map(|ChallengeAnswer(i)| i),
+                    Some(index)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_challenge_answer() {
+        assert!(matches!(
+            ChallengeAnswer::from_data("challenge:3"),
+            Some(ChallengeAnswer(3))
+        ));
+        for data in [
+            "challenge:",
+            "challenge:x",
+            "challenge:-1",
+            "challenge:1:2",
+            "challenge: 1",
+            "onboarding:1",
+            "1",
+        ] {
+            assert!(ChallengeAnswer::from_data(data).is_none(), "{}", data);
+        }
+    }
+
+    #[test]
+    fn test_subscription_confirmation() {
+        let parse = |data: &str| SubscriptionConfirmation::from_data(data).map(|c| c.0);
+        assert_eq!(parse("confirm:42"), Some(42));
+        assert_eq!(parse(&SubscriptionConfirmation(7).data()), Some(7));
+        for data in [
+            "confirm:",
+            "confirm:news:sports",
+            "confirm:a/b",
+            "confirm:4/2",
+            "confirm:42:1",
+            "unsubscribe:42",
+            "42",
+        ] {
+            assert_eq!(parse(data), None, "{}", data);
+        }
+    }
+
+    #[test]
+    fn test_menu_actions() {
+        for action in MenuAction::ALL {
+            assert_eq!(MenuAction::from_text(action.label()), Some(action));
+        }
+        let labels: Vec<_> = menu_keyboard()
+            .keyboard
+            .into_iter()
+            .flatten()
+            .map(|button| button.text)
+            .collect();
+        assert_eq!(labels.len(), MenuAction::ALL.len());
+        assert!(
+            labels
+                .iter()
+                .all(|label| MenuAction::from_text(label).is_some())
+        );
+        assert_eq!(MenuAction::from_text("help"), None);
+        assert_eq!(MenuAction::from_text("/help"), None);
+    }
+
+    #[test]
+    fn test_unsubscribe_data() {
+        assert_eq!(unsubscribe_data("ops/db"), "unsubscribe:ops/db");
+        let longest = "a".repeat(MAX_CALLBACK_DATA_BYTES - UNSUBSCRIBE_DATA.len());
+        assert_eq!(
+            unsubscribe_data(&longest),
+            format!("{}{}", UNSUBSCRIBE_DATA, longest)
+        );
+        assert_eq!(unsubscribe_data(&format!("{}a", longest)), UNSUBSCRIBE_DATA);
+    }
 }
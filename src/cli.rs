@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use sqlx::SqlitePool;
+
+use crate::bot::Bots;
+use crate::config::RetryConfig;
+use crate::db::DEFAULT_TENANT;
+use crate::delivery::Recipients;
+use crate::lanes::Lanes;
+
+#[derive(Parser)]
+#[command(version, about = "HTTP API and Telegram bot for channel subscriptions")]
+pub struct Cli {
+    /// TOML or YAML config file, environment variables take precedence
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP API and the bot (default)
+    Serve,
+    /// Run database migrations and exit
+    Migrate,
+    /// Send a message to every subscriber of a channel
+    Send {
+        #[arg(long, default_value = DEFAULT_TENANT)]
+        tenant: String,
+        channel_name: String,
+        message: String,
+    },
+    /// Send a message to every subscriber of every channel
+    Broadcast {
+        #[arg(long, default_value = DEFAULT_TENANT)]
+        tenant: String,
+        /// Only subscribers of this channel, repeatable
+        #[arg(long = "channel")]
+        channels: Vec<String>,
+        message: String,
+    },
+}
+
+pub async fn send(
+    bots: &Bots,
+    lanes: &Lanes,
+    retry: &RetryConfig,
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    message: &str,
+) -> Result<()> {
+    if let Some(problem) = crate::db::channel_name_error(channel_name) {
+        anyhow::bail!(problem);
+    }
+
+    let total = crate::db::count_subscribers(pool, tenant_id, channel_name, &[]).await?;
+    let subscribers = Recipients::from_db(
+        total as usize,
+        crate::db::stream_subscribers(pool, tenant_id, channel_name, &[]),
+    );
+    let report = crate::delivery::deliver(bots, lanes, retry, subscribers, message).await;
+
+    println!(
+        "Sent to {} subscribers of '{}' ({} errors)",
+        report.sent, channel_name, report.errors
+    );
+    Ok(())
+}
+
+pub async fn broadcast(
+    bots: &Bots,
+    lanes: &Lanes,
+    retry: &RetryConfig,
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channels: &[String],
+    message: &str,
+) -> Result<()> {
+    if let Some(name) = channels
+        .iter()
+        .find(|name| !crate::db::validate_channel_name(name))
+    {
+        anyhow::bail!("Invalid channel name '{}'", name);
+    }
+
+    let channels = Some(channels).filter(|channels| !channels.is_empty());
+    let total_subscribers = crate::db::count_all_subscribers(pool, tenant_id, channels).await?;
+    let subscribers = Recipients::from_db(
+        total_subscribers as usize,
+        crate::db::stream_all_subscribers(pool, tenant_id, channels),
+    );
+    let report = crate::delivery::deliver(bots, lanes, retry, subscribers, message).await;
+
+    println!(
+        "Sent to {}/{} subscribers ({} errors)",
+        report.sent, total_subscribers, report.errors
+    );
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Cross-origin requests from browser apps, e.g. an internal dashboard on another host
+
+use std::str::FromStr;
+
+use actix_cors::Cors;
+use actix_web::http::header::HeaderName;
+use actix_web::http::{Method, Uri};
+use anyhow::Result;
+
+use crate::config::CorsConfig;
+
+/// [`CorsConfig`] checked once at startup, so building the middleware for each worker
+/// can't fail
+#[derive(Clone)]
+pub struct CorsPolicy {
+    /// Any origin is allowed when unset
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    max_age_secs: usize,
+}
+
+impl CorsPolicy {
+    /// `None` when no origin is allowed, leaving cross-origin requests to the browser
+    pub fn new(config: &CorsConfig) -> Result<Option<Self>> {
+        if config.allowed_origins.is_empty() {
+            return Ok(None);
+        }
+        let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+            None
+        } else {
+            for origin in &config.allowed_origins {
+                Uri::from_str(origin)
+                    .map_err(|e| anyhow::anyhow!("Invalid CORS origin '{}': {}", origin, e))?;
+            }
+            Some(config.allowed_origins.clone())
+        };
+        let methods = config
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_str(&method.to_uppercase())
+                    .map_err(|e| anyhow::anyhow!("Invalid CORS method '{}': {}", method, e))
+            })
+            .collect::<Result<_>>()?;
+        let headers = config
+            .allowed_headers
+            .iter()
+            .map(|header| {
+                HeaderName::from_str(header)
+                    .map_err(|e| anyhow::anyhow!("Invalid CORS header '{}': {}", header, e))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(Self {
+            origins,
+            methods,
+            headers,
+            max_age_secs: config.max_age_secs,
+        }))
+    }
+
+    /// Requests from other origins get no CORS headers, so browsers block them
+    pub fn middleware(&self) -> Cors {
+        let cors = Cors::default()
+            .allowed_methods(self.methods.clone())
+            .allowed_headers(self.headers.clone())
+            .max_age(self.max_age_secs);
+        match &self.origins {
+            None => cors.allow_any_origin(),
+            Some(origins) => origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let mut config = CorsConfig::default();
+        assert!(CorsPolicy::new(&config).unwrap().is_none());
+
+        config.allowed_origins = vec!["https://dashboard.example.com".to_string()];
+        let policy = CorsPolicy::new(&config).unwrap().unwrap();
+        assert_eq!(
+            policy.origins,
+            Some(vec!["https://dashboard.example.com".to_string()])
+        );
+
+        config.allowed_origins.push("*".to_string());
+        assert!(CorsPolicy::new(&config).unwrap().unwrap().origins.is_none());
+
+        config.allowed_methods.push("not a method".to_string());
+        assert!(CorsPolicy::new(&config).is_err());
+    }
+}
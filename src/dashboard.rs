@@ -0,0 +1,13 @@
+//! Single-page admin dashboard for posting announcements without curl
+
+use actix_web::{HttpResponse, Result, get};
+
+/// The page holds no data: it asks for the admin key or a user token and calls the JSON API with it
+const PAGE: &str = include_str!("dashboard.html");
+
+#[get("/dashboard")]
+pub async fn page() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(PAGE))
+}
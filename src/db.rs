@@ -1,120 +1,3731 @@
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::Result;
-use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use sqlx::{
+    SqliteConnection, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+};
 
-pub async fn create_pool(database_url: &str) -> Result<SqlitePool> {
-    let pool = SqlitePool::connect_lazy_with(
-        SqliteConnectOptions::from_str(database_url)?.create_if_missing(true),
-    );
+use sha2::{Digest, Sha256};
+
+use crate::config::DatabaseConfig;
+use crate::roles::Role;
+
+pub async fn create_pool(database_url: &str, config: &DatabaseConfig) -> Result<SqlitePool> {
+    // WAL + a busy timeout let broadcasts read while the bot writes subscriptions
+    // without failing with "database is locked"
+    let options = SqliteConnectOptions::from_str(database_url)?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::from_str(&config.journal_mode)?)
+        .synchronous(SqliteSynchronous::from_str(&config.synchronous)?)
+        .busy_timeout(Duration::from_millis(config.busy_timeout_ms));
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(config.max_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(config.idle_timeout_secs))
+        .connect_lazy_with(options);
 
     sqlx::migrate!("./migrations").run(&pool).await?;
     Ok(pool)
 }
 
+/// Tenant used by unauthenticated requests and data created before tenants existed
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Longest channel (and tenant) name accepted
+pub const MAX_CHANNEL_NAME_CHARS: usize = 64;
+
 pub fn validate_channel_name(channel_name: &str) -> bool {
+    channel_name_error(channel_name).is_none()
+}
+
+/// Why `channel_name` isn't a valid channel name, `None` when it is. Levels of a
+/// hierarchy are separated by `/`, as in `deploys/backend`.
+pub fn channel_name_error(channel_name: &str) -> Option<&'static str> {
     if channel_name.is_empty() {
-        return false;
+        return Some("Channel name cannot be empty");
     }
+    if channel_name.chars().count() > MAX_CHANNEL_NAME_CHARS {
+        return Some("Channel name too long (max 64 chars)");
+    }
+    if !channel_name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == CHANNEL_SEPARATOR)
+    {
+        return Some(
+            "Invalid channel name. Only letters, numbers, and underscores are allowed, with / between levels.",
+        );
+    }
+    if channel_name.split(CHANNEL_SEPARATOR).any(str::is_empty) {
+        return Some("Channel name levels cannot be empty");
+    }
+    None
+}
+
+/// Separates the levels of a channel hierarchy
+pub const CHANNEL_SEPARATOR: char = '/';
+
+/// The channel and every channel above it, `deploys/backend` giving `deploys` and
+/// `deploys/backend`: a send to a channel reaches the subscribers of all of them
+pub fn channel_and_parents(channel_name: &str) -> Vec<String> {
+    let channel_name = normalize_channel_name(channel_name);
     channel_name
+        .match_indices(CHANNEL_SEPARATOR)
+        .map(|(i, _)| channel_name[..i].to_string())
+        .chain(std::iter::once(channel_name.clone()))
+        .collect()
+}
+
+/// Channel names are case-insensitive: every name is stored and looked up lowercased,
+/// non-ASCII letters included. SQLite's `lower()` only folds ASCII, so the migration
+/// that merged existing case variants left those with other uppercase letters as is.
+pub fn normalize_channel_name(channel_name: &str) -> String {
+    channel_name.to_lowercase()
+}
+
+/// `channels` normalized with their parents, as the JSON array the queries filter with
+fn channels_json(channels: &[String]) -> String {
+    let channels: Vec<_> = channels
+        .iter()
+        .flat_map(|name| channel_and_parents(name))
+        .collect();
+    serde_json::Value::from(channels).to_string()
+}
+
+/// Longest subscriber tag accepted
+pub const MAX_TAG_CHARS: usize = 64;
+
+/// Why `tag` isn't a valid subscriber tag, `None` when it is
+pub fn tag_error(tag: &str) -> Option<&'static str> {
+    if tag.is_empty() {
+        return Some("Tag cannot be empty");
+    }
+    if tag.chars().count() > MAX_TAG_CHARS {
+        return Some("Tag too long (max 64 chars)");
+    }
+    if !tag
         .chars()
-        .all(|c| c.is_alphanumeric() || c == '_')
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        return Some("Invalid tag. Only letters, numbers, underscores and hyphens are allowed.");
+    }
+    None
+}
+
+/// Tags are case-insensitive like channel names. `tags` lowercased and deduplicated,
+/// as the JSON array the queries filter with.
+fn tags_json(tags: &[String]) -> String {
+    let mut tags: Vec<_> = tags.iter().map(|tag| tag.to_ascii_lowercase()).collect();
+    tags.sort();
+    tags.dedup();
+    serde_json::Value::from(tags).to_string()
+}
+
+/// Tenant ids follow the same rules as channel names, without levels
+pub fn validate_tenant_id(tenant_id: &str) -> bool {
+    validate_channel_name(tenant_id) && !tenant_id.contains(CHANNEL_SEPARATOR)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    pub telegram_id: i64,
+    /// Name of the bot the subscription was made through
+    pub bot: String,
+}
+
+pub struct SubscriptionRow {
+    pub telegram_id: i64,
+    pub channel_name: String,
+    pub created_at: i64,
+    /// JSON object stored for the subscriber
+    pub metadata: Option<String>,
+    /// From their Telegram profile, once they talked to a bot
+    pub username: Option<String>,
+    pub first_name: Option<String>,
+    pub language_code: Option<String>,
+    pub last_delivered_at: Option<i64>,
+    /// Last time they messaged a bot
+    pub last_seen_at: Option<i64>,
+    /// Flagged by the pruning job as unreachable
+    pub flagged_at: Option<i64>,
+}
+
+pub struct ApiKey {
+    pub id: i64,
+    pub tenant_id: String,
+    pub name: String,
+    pub daily_quota: Option<i64>,
+    pub created_at: i64,
+}
+
+/// Outcome of [`subscribe`], which is safe to retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscribeOutcome {
+    NewlySubscribed,
+    AlreadySubscribed,
 }
 
-pub async fn subscribe(pool: &SqlitePool, telegram_id: i64, channel_name: &str) -> Result<()> {
+/// Subscribes through `bot_name`; a channel is bound to the bot that created it
+pub async fn subscribe(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+    channel_name: &str,
+    bot_name: &str,
+) -> Result<SubscribeOutcome> {
+    let channel_name = &normalize_channel_name(channel_name);
     if !validate_channel_name(channel_name) {
         return Err(anyhow::anyhow!("Invalid channel name"));
     }
 
+    let mut tx = pool.begin().await?;
+
     sqlx::query!(
-        "INSERT INTO subscriptions (telegram_id, channel_name) VALUES (?, ?)",
+        "
+        INSERT INTO channels (tenant_id, name, bot)
+        VALUES (?, ?, ?)
+        ON CONFLICT (tenant_id, name) DO NOTHING
+        ",
+        tenant_id,
+        channel_name,
+        bot_name
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let channel = sqlx::query!(
+        "SELECT bot FROM channels WHERE tenant_id = ? AND name = ?",
+        tenant_id,
+        channel_name
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if channel.bot != bot_name {
+        return Err(anyhow::anyhow!(
+            "Channel '{}' is served by another bot",
+            channel_name
+        ));
+    }
+
+    let result = sqlx::query!(
+        "
+        INSERT INTO subscriptions (tenant_id, telegram_id, channel_name)
+        VALUES (?, ?, ?)
+        ON CONFLICT DO NOTHING
+        ",
+        tenant_id,
+        telegram_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() > 0 {
+        sqlx::query!(
+            "
+            INSERT INTO subscription_events (tenant_id, telegram_id, channel_name, event, source)
+            VALUES (?, ?, ?, 'subscribed', 'bot')
+            ",
+            tenant_id,
+            telegram_id,
+            channel_name
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(if result.rows_affected() > 0 {
+        SubscribeOutcome::NewlySubscribed
+    } else {
+        SubscribeOutcome::AlreadySubscribed
+    })
+}
+
+pub async fn unsubscribe(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+    channel_name: &str,
+) -> Result<bool> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        "DELETE FROM subscriptions WHERE tenant_id = ? AND telegram_id = ? AND channel_name = ?",
+        tenant_id,
         telegram_id,
         channel_name
     )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() > 0 {
+        sqlx::query!(
+            "
+            INSERT INTO subscription_events (tenant_id, telegram_id, channel_name, event, source)
+            VALUES (?, ?, ?, 'unsubscribed', 'bot')
+            ",
+            tenant_id,
+            telegram_id,
+            channel_name
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Subscription waiting for the user to confirm it
+pub struct PendingSubscription {
+    pub channel_name: String,
+    pub bot: String,
+    pub created_at: i64,
+}
+
+/// Keeps a subscription to confirm, returning its id. Asking again restarts the wait.
+/// Requests made before `expired_before` are dropped on the way.
+pub async fn request_subscription(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+    channel_name: &str,
+    bot_name: &str,
+    expired_before: i64,
+) -> Result<i64> {
+    let channel_name = &normalize_channel_name(channel_name);
+    sqlx::query!(
+        "DELETE FROM pending_subscriptions WHERE created_at < ?",
+        expired_before
+    )
+    .execute(pool)
+    .await?;
+    let id = sqlx::query_scalar!(
+        "
+        INSERT INTO pending_subscriptions (tenant_id, telegram_id, channel_name, bot)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT (tenant_id, telegram_id, channel_name) DO UPDATE
+        SET bot = excluded.bot, created_at = unixepoch()
+        RETURNING id
+        ",
+        tenant_id,
+        telegram_id,
+        channel_name,
+        bot_name
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Removes the pending subscription `id` of `telegram_id`, returning it if it was there
+pub async fn take_pending_subscription(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+    id: i64,
+) -> Result<Option<PendingSubscription>> {
+    let pending = sqlx::query_as!(
+        PendingSubscription,
+        "
+        DELETE FROM pending_subscriptions
+        WHERE id = ? AND tenant_id = ? AND telegram_id = ?
+        RETURNING channel_name, bot, created_at
+        ",
+        id,
+        tenant_id,
+        telegram_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(pending)
+}
+
+/// Recipients read per query when streaming them
+const RECIPIENT_PAGE_SIZE: i64 = 1000;
+
+/// Streams recipients a page at a time, each page starting after the last telegram_id
+/// of the previous one, so a large audience is never held in memory at once
+fn paged<F, Fut>(page_size: i64, fetch_page: F) -> impl Stream<Item = Result<Recipient>> + Send
+where
+    F: Fn(i64, i64) -> Fut + Send,
+    Fut: Future<Output = Result<Vec<Recipient>>> + Send,
+{
+    futures::stream::try_unfold(Some(i64::MIN), move |after| {
+        let page = after.map(|after| fetch_page(after, page_size));
+        async move {
+            let Some(page) = page else {
+                return anyhow::Ok(None);
+            };
+            let page = page.await?;
+            let next = page
+                .last()
+                .filter(|_| page.len() as i64 == page_size)
+                .map(|recipient| recipient.telegram_id);
+            Ok(Some((futures::stream::iter(page).map(Ok), next)))
+        }
+    })
+    .try_flatten()
+}
+
+/// Subscribers of a channel and of the channels above it, each once, read a page at a
+/// time as the stream is consumed. Only those having every one of `tags` when given.
+pub fn stream_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    tags: &[String],
+) -> impl Stream<Item = Result<Recipient>> + Send + 'static {
+    let (pool, tenant_id, channels, tags) = (
+        pool.clone(),
+        tenant_id.to_string(),
+        channels_json(&[channel_name.to_string()]),
+        tags_json(tags),
+    );
+    paged(RECIPIENT_PAGE_SIZE, move |after, limit| {
+        let (pool, tenant_id, channels, tags) = (
+            pool.clone(),
+            tenant_id.clone(),
+            channels.clone(),
+            tags.clone(),
+        );
+        async move {
+            let rows = sqlx::query!(
+                r#"
+                SELECT s.telegram_id AS "telegram_id!",
+                       MIN(c.bot) AS "bot!: String"
+                FROM subscriptions s
+                         JOIN channels c ON c.tenant_id = s.tenant_id AND c.name = s.channel_name
+                         LEFT JOIN subscriber_tags t
+                                   ON t.tenant_id = s.tenant_id AND t.telegram_id = s.telegram_id
+                                       AND t.tag IN (SELECT value FROM json_each(?3))
+                WHERE s.tenant_id = ?1
+                  AND s.channel_name IN (SELECT value FROM json_each(?2))
+                  AND s.telegram_id > ?4
+                GROUP BY s.telegram_id
+                HAVING COUNT(DISTINCT t.tag) = json_array_length(?3)
+                ORDER BY s.telegram_id
+                LIMIT ?5
+                "#,
+                tenant_id,
+                channels,
+                tags,
+                after,
+                limit
+            )
+            .fetch_all(&pool)
+            .await?;
+            Ok(rows
+                .into_iter()
+                .map(|r| Recipient {
+                    telegram_id: r.telegram_id,
+                    bot: r.bot,
+                })
+                .collect())
+        }
+    })
+}
+
+/// Number of recipients [`stream_subscribers`] yields
+pub async fn count_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    tags: &[String],
+) -> Result<i64> {
+    let channels = channels_json(&[channel_name.to_string()]);
+    let tags = tags_json(tags);
+    let count = sqlx::query_scalar!(
+        "
+        SELECT COUNT(*)
+        FROM (SELECT s.telegram_id
+              FROM subscriptions s
+                       JOIN channels c ON c.tenant_id = s.tenant_id AND c.name = s.channel_name
+                       LEFT JOIN subscriber_tags t
+                                 ON t.tenant_id = s.tenant_id AND t.telegram_id = s.telegram_id
+                                     AND t.tag IN (SELECT value FROM json_each(?3))
+              WHERE s.tenant_id = ?1
+                AND s.channel_name IN (SELECT value FROM json_each(?2))
+              GROUP BY s.telegram_id
+              HAVING COUNT(DISTINCT t.tag) = json_array_length(?3))
+        ",
+        tenant_id,
+        channels,
+        tags
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// Every subscriber of the tenant once, or of `channels` when given, reached through
+/// one of the bots they subscribed with. Read a page at a time like [`stream_subscribers`].
+pub fn stream_all_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channels: Option<&[String]>,
+) -> impl Stream<Item = Result<Recipient>> + Send + 'static {
+    stream_subscribers_in(pool, tenant_id, channels.map(channels_json))
+}
+
+/// Subscribers of exactly `channel_name`, leaving out those of the channels above it.
+/// Read a page at a time like [`stream_subscribers`].
+pub fn stream_direct_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+) -> impl Stream<Item = Result<Recipient>> + Send + 'static {
+    stream_subscribers_in(pool, tenant_id, Some(exact_channel_json(channel_name)))
+}
+
+/// `channel_name` alone as the JSON array the queries filter with, without its parents
+fn exact_channel_json(channel_name: &str) -> String {
+    serde_json::json!([normalize_channel_name(channel_name)]).to_string()
+}
+
+/// Subscribers of the channels in the JSON array `channels`, of every channel when `None`
+fn stream_subscribers_in(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channels: Option<String>,
+) -> impl Stream<Item = Result<Recipient>> + Send + 'static {
+    let (pool, tenant_id) = (pool.clone(), tenant_id.to_string());
+    paged(RECIPIENT_PAGE_SIZE, move |after, limit| {
+        let (pool, tenant_id, channels) = (pool.clone(), tenant_id.clone(), channels.clone());
+        async move {
+            let rows = sqlx::query!(
+                r#"
+                SELECT s.telegram_id AS "telegram_id!",
+                       MIN(c.bot) AS "bot!: String"
+                FROM subscriptions s
+                         JOIN channels c ON c.tenant_id = s.tenant_id AND c.name = s.channel_name
+                WHERE s.tenant_id = ?1
+                  AND (?2 IS NULL OR s.channel_name IN (SELECT value FROM json_each(?2)))
+                  AND s.telegram_id > ?3
+                GROUP BY s.telegram_id
+                ORDER BY s.telegram_id
+                LIMIT ?4
+                "#,
+                tenant_id,
+                channels,
+                after,
+                limit
+            )
+            .fetch_all(&pool)
+            .await?;
+            Ok(rows
+                .into_iter()
+                .map(|r| Recipient {
+                    telegram_id: r.telegram_id,
+                    bot: r.bot,
+                })
+                .collect())
+        }
+    })
+}
+
+/// Number of recipients [`stream_all_subscribers`] yields
+pub async fn count_all_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channels: Option<&[String]>,
+) -> Result<i64> {
+    count_subscribers_in(pool, tenant_id, channels.map(channels_json)).await
+}
+
+/// Counts the subscribers of exactly `channel_name`, like [`stream_direct_subscribers`]
+pub async fn count_direct_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+) -> Result<i64> {
+    count_subscribers_in(pool, tenant_id, Some(exact_channel_json(channel_name))).await
+}
+
+async fn count_subscribers_in(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channels: Option<String>,
+) -> Result<i64> {
+    let count = sqlx::query_scalar!(
+        "
+        SELECT COUNT(DISTINCT s.telegram_id)
+        FROM subscriptions s
+                 JOIN channels c ON c.tenant_id = s.tenant_id AND c.name = s.channel_name
+        WHERE s.tenant_id = ?1
+          AND (?2 IS NULL OR s.channel_name IN (SELECT value FROM json_each(?2)))
+        ",
+        tenant_id,
+        channels
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// Channels of the tenant `telegram_id` is subscribed to, by name
+pub async fn list_subscribed_channels(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+) -> Result<Vec<String>> {
+    let channels = sqlx::query_scalar!(
+        "
+        SELECT channel_name FROM subscriptions
+        WHERE tenant_id = ? AND telegram_id = ?
+        ORDER BY channel_name
+        ",
+        tenant_id,
+        telegram_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(channels)
+}
+
+/// Whether the chat passed the bot's challenge, or subscribed before it was required
+pub async fn is_chat_verified(pool: &SqlitePool, telegram_id: i64) -> Result<bool> {
+    let verified = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM verified_chats WHERE telegram_id = ?1)
+            OR EXISTS (SELECT 1 FROM subscriptions WHERE telegram_id = ?1) as "verified!: bool"
+        "#,
+        telegram_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(verified)
+}
+
+pub async fn mark_chat_verified(pool: &SqlitePool, telegram_id: i64) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO verified_chats (telegram_id) VALUES (?) ON CONFLICT DO NOTHING",
+        telegram_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Tags of a subscriber, `None` when they aren't subscribed to any of the tenant's
+/// channels
+pub async fn get_subscriber_tags(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+) -> Result<Option<Vec<String>>> {
+    let subscribed = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM subscriptions WHERE tenant_id = ? AND telegram_id = ?",
+        tenant_id,
+        telegram_id
+    )
+    .fetch_one(pool)
+    .await?;
+    if subscribed == 0 {
+        return Ok(None);
+    }
+
+    let tags = sqlx::query_scalar!(
+        "SELECT tag FROM subscriber_tags WHERE tenant_id = ? AND telegram_id = ? ORDER BY tag",
+        tenant_id,
+        telegram_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(tags))
+}
+
+/// Tags a subscriber of the tenant, doing nothing for anyone else
+pub async fn add_subscriber_tag(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+    tag: &str,
+) -> Result<()> {
+    let tag = tag.to_ascii_lowercase();
+    sqlx::query!(
+        "
+        INSERT OR IGNORE INTO subscriber_tags (tenant_id, telegram_id, tag)
+        SELECT ?1, ?2, ?3
+        WHERE EXISTS (SELECT 1 FROM subscriptions WHERE tenant_id = ?1 AND telegram_id = ?2)
+        ",
+        tenant_id,
+        telegram_id,
+        tag
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_subscriber_tag(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+    tag: &str,
+) -> Result<()> {
+    let tag = tag.to_ascii_lowercase();
+    sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE tenant_id = ? AND telegram_id = ? AND tag = ?",
+        tenant_id,
+        telegram_id,
+        tag
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Replaces the metadata of a subscriber of the tenant, `metadata` being a JSON object.
+/// Returns false for anyone else.
+pub async fn set_subscriber_metadata(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    telegram_id: i64,
+    metadata: &str,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        "
+        INSERT INTO subscriber_metadata (tenant_id, telegram_id, metadata)
+        SELECT ?1, ?2, ?3
+        WHERE EXISTS (SELECT 1 FROM subscriptions WHERE tenant_id = ?1 AND telegram_id = ?2)
+        ON CONFLICT (tenant_id, telegram_id) DO UPDATE
+        SET metadata = excluded.metadata, updated_at = unixepoch()
+        ",
+        tenant_id,
+        telegram_id,
+        metadata
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Metadata of every subscriber of the tenant who has some, by Telegram id
+pub async fn list_subscriber_metadata(
+    pool: &SqlitePool,
+    tenant_id: &str,
+) -> Result<HashMap<i64, serde_json::Value>> {
+    let rows = sqlx::query!(
+        "SELECT telegram_id, metadata FROM subscriber_metadata WHERE tenant_id = ?",
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|r| Ok((r.telegram_id, serde_json::from_str(&r.metadata)?)))
+        .collect()
+}
+
+/// A subscribe or unsubscribe, as kept in the history
+pub struct SubscriptionEvent {
+    pub id: i64,
+    pub telegram_id: i64,
+    pub channel_name: String,
+    /// `subscribed` or `unsubscribed`
+    pub event: String,
+    /// `bot`, `api` or `pruning`
+    pub source: String,
+    pub created_at: i64,
+}
+
+/// Filters of the subscription history, unset ones match everything
+#[derive(Default)]
+pub struct SubscriptionEventFilter {
+    pub channel_name: Option<String>,
+    pub telegram_id: Option<i64>,
+    pub since: Option<i64>,
+    /// Only events before this id, for paging back through the history
+    pub before_id: Option<i64>,
+}
+
+/// Subscription history of the tenant, newest first
+pub async fn list_subscription_events(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    filter: &SubscriptionEventFilter,
+    limit: i64,
+) -> Result<Vec<SubscriptionEvent>> {
+    let channel_name = filter.channel_name.as_deref().map(normalize_channel_name);
+    let rows = sqlx::query_as!(
+        SubscriptionEvent,
+        "
+        SELECT id, telegram_id, channel_name, event, source, created_at
+        FROM subscription_events
+        WHERE tenant_id = ?1
+          AND (?2 IS NULL OR channel_name = ?2)
+          AND (?3 IS NULL OR telegram_id = ?3)
+          AND (?4 IS NULL OR created_at >= ?4)
+          AND (?5 IS NULL OR id < ?5)
+        ORDER BY id DESC
+        LIMIT ?6
+        ",
+        tenant_id,
+        channel_name,
+        filter.telegram_id,
+        filter.since,
+        filter.before_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Profile of a Telegram user as of their latest message to a bot
+pub struct TelegramProfile {
+    pub telegram_id: i64,
+    pub username: Option<String>,
+    pub first_name: String,
+    pub language_code: Option<String>,
+}
+
+/// Keeps the latest profile of a user and marks them as just seen
+pub async fn remember_telegram_user(pool: &SqlitePool, profile: &TelegramProfile) -> Result<()> {
+    sqlx::query!(
+        "
+        INSERT INTO telegram_users (telegram_id, username, first_name, language_code, last_seen_at)
+        VALUES (?, ?, ?, ?, unixepoch())
+        ON CONFLICT (telegram_id) DO UPDATE
+        SET updated_at = CASE
+                WHEN username IS NOT excluded.username
+                    OR first_name IS NOT excluded.first_name
+                    OR language_code IS NOT excluded.language_code
+                THEN unixepoch()
+                ELSE updated_at
+            END,
+            username = excluded.username,
+            first_name = excluded.first_name,
+            language_code = excluded.language_code,
+            last_seen_at = excluded.last_seen_at
+        ",
+        profile.telegram_id,
+        profile.username,
+        profile.first_name,
+        profile.language_code
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn list_subscriptions(
+    pool: &SqlitePool,
+    tenant_id: &str,
+) -> Result<Vec<SubscriptionRow>> {
+    let rows = sqlx::query_as!(
+        SubscriptionRow,
+        r#"
+        SELECT s.telegram_id,
+               s.channel_name,
+               s.created_at,
+               m.metadata AS "metadata?",
+               u.username AS "username?",
+               u.first_name AS "first_name?",
+               u.language_code AS "language_code?",
+               a.last_delivered_at AS "last_delivered_at?",
+               u.last_seen_at AS "last_seen_at?",
+               a.flagged_at AS "flagged_at?"
+        FROM subscriptions s
+                 LEFT JOIN subscriber_metadata m
+                           ON m.tenant_id = s.tenant_id AND m.telegram_id = s.telegram_id
+                 LEFT JOIN telegram_users u ON u.telegram_id = s.telegram_id
+                 LEFT JOIN subscriber_activity a
+                           ON a.tenant_id = s.tenant_id AND a.telegram_id = s.telegram_id
+        WHERE s.tenant_id = ?
+        ORDER BY s.channel_name, s.telegram_id
+        "#,
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Returns the new key's id and the plaintext key, which is not stored
+pub async fn create_api_key(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    name: &str,
+    daily_quota: Option<i64>,
+) -> Result<(i64, String)> {
+    if !validate_tenant_id(tenant_id) {
+        return Err(anyhow::anyhow!("Invalid tenant id"));
+    }
+
+    let key = format!("tbp_{}", hex::encode(rand::random::<[u8; 24]>()));
+    let key_hash = hash_api_key(&key);
+
+    let id = sqlx::query!(
+        "INSERT INTO api_keys (tenant_id, name, key_hash, daily_quota) VALUES (?, ?, ?, ?)",
+        tenant_id,
+        name,
+        key_hash,
+        daily_quota
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+
+    Ok((id, key))
+}
+
+pub async fn find_api_key(pool: &SqlitePool, key: &str) -> Result<Option<ApiKey>> {
+    let key_hash = hash_api_key(key);
+    let row = sqlx::query_as!(
+        ApiKey,
+        "SELECT id, tenant_id, name, daily_quota, created_at FROM api_keys WHERE key_hash = ?",
+        key_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn list_api_keys(pool: &SqlitePool) -> Result<Vec<ApiKey>> {
+    let rows = sqlx::query_as!(
+        ApiKey,
+        "
+        SELECT id, tenant_id, name, daily_quota, created_at
+        FROM api_keys
+        ORDER BY tenant_id, id
+        "
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_api_key(pool: &SqlitePool, id: i64) -> Result<bool> {
+    let result = sqlx::query!("DELETE FROM api_keys WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub telegram_id: Option<i64>,
+    pub roles: Vec<Role>,
+    pub created_at: i64,
+}
+
+impl User {
+    /// The most permissive of the user's roles
+    pub fn role(&self) -> Option<Role> {
+        self.roles.iter().copied().max()
+    }
+}
+
+struct UserRow {
+    id: i64,
+    name: String,
+    telegram_id: Option<i64>,
+    /// Comma-separated
+    roles: Option<String>,
+    created_at: i64,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        let mut roles: Vec<Role> = row
+            .roles
+            .iter()
+            .flat_map(|roles| roles.split(','))
+            .filter_map(|role| Role::try_from(role).ok())
+            .collect();
+        roles.sort();
+        Self {
+            id: row.id,
+            name: row.name,
+            telegram_id: row.telegram_id,
+            roles,
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// Users matching every filter that is set
+async fn query_users(
+    pool: &SqlitePool,
+    id: Option<i64>,
+    token_hash: Option<&str>,
+    telegram_id: Option<i64>,
+) -> Result<Vec<User>> {
+    let rows = sqlx::query_as!(
+        UserRow,
+        r#"
+        SELECT u.id, u.name, u.telegram_id, GROUP_CONCAT(r.role) as "roles?: String", u.created_at
+        FROM users u
+        LEFT JOIN user_roles r ON r.user_id = u.id
+        WHERE (?1 IS NULL OR u.id = ?1)
+          AND (?2 IS NULL OR u.token_hash = ?2)
+          AND (?3 IS NULL OR u.telegram_id = ?3)
+        GROUP BY u.id
+        ORDER BY u.id
+        "#,
+        id,
+        token_hash,
+        telegram_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(User::from).collect())
+}
+
+/// Returns the new user's id and their plaintext token, which is not stored,
+/// or `None` if the name or Telegram id is taken
+pub async fn create_user(
+    pool: &SqlitePool,
+    name: &str,
+    telegram_id: Option<i64>,
+    roles: &[Role],
+) -> Result<Option<(i64, String)>> {
+    let token = format!("tbu_{}", hex::encode(rand::random::<[u8; 24]>()));
+    let token_hash = hash_api_key(&token);
+
+    let mut tx = pool.begin().await?;
+    let inserted = sqlx::query!(
+        "INSERT INTO users (name, token_hash, telegram_id) VALUES (?, ?, ?)",
+        name,
+        token_hash,
+        telegram_id
+    )
+    .execute(&mut *tx)
+    .await;
+    let id = match inserted {
+        Ok(result) => result.last_insert_rowid(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    for role in roles {
+        let role = role.as_str();
+        sqlx::query!(
+            "INSERT INTO user_roles (user_id, role) VALUES (?, ?) ON CONFLICT DO NOTHING",
+            id,
+            role
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(Some((id, token)))
+}
+
+pub async fn get_user(pool: &SqlitePool, id: i64) -> Result<Option<User>> {
+    Ok(query_users(pool, Some(id), None, None).await?.pop())
+}
+
+pub async fn find_user_by_token(pool: &SqlitePool, token: &str) -> Result<Option<User>> {
+    let token_hash = hash_api_key(token);
+    Ok(query_users(pool, None, Some(&token_hash), None)
+        .await?
+        .pop())
+}
+
+pub async fn find_user_by_telegram_id(pool: &SqlitePool, telegram_id: i64) -> Result<Option<User>> {
+    Ok(query_users(pool, None, None, Some(telegram_id))
+        .await?
+        .pop())
+}
+
+pub async fn list_users(pool: &SqlitePool) -> Result<Vec<User>> {
+    query_users(pool, None, None, None).await
+}
+
+pub async fn delete_user(pool: &SqlitePool, id: i64) -> Result<bool> {
+    let result = sqlx::query!("DELETE FROM users WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Gives the user `role`, doing nothing if they already have it or don't exist
+pub async fn grant_role(pool: &SqlitePool, user_id: i64, role: Role) -> Result<()> {
+    let role = role.as_str();
+    sqlx::query!(
+        "
+        INSERT INTO user_roles (user_id, role)
+        SELECT id, ? FROM users WHERE id = ?
+        ON CONFLICT DO NOTHING
+        ",
+        role,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn revoke_role(pool: &SqlitePool, user_id: i64, role: Role) -> Result<()> {
+    let role = role.as_str();
+    sqlx::query!(
+        "DELETE FROM user_roles WHERE user_id = ? AND role = ?",
+        user_id,
+        role
+    )
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn unsubscribe(pool: &SqlitePool, telegram_id: i64, channel_name: &str) -> Result<bool> {
+/// Inbound webhook rendering its JSON body with handlebars templates
+pub struct Hook {
+    pub id: String,
+    pub tenant_id: String,
+    pub channel: String,
+    pub template: String,
+    pub created_at: i64,
+}
+
+/// Returns the id, which doubles as the secret part of the hook's URL
+pub async fn create_hook(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel: &str,
+    template: &str,
+) -> Result<String> {
+    let id = hex::encode(rand::random::<[u8; 16]>());
+    sqlx::query!(
+        "INSERT INTO hooks (id, tenant_id, channel, template) VALUES (?, ?, ?, ?)",
+        id,
+        tenant_id,
+        channel,
+        template
+    )
+    .execute(pool)
+    .await?;
+    Ok(id)
+}
+
+pub async fn find_hook(pool: &SqlitePool, id: &str) -> Result<Option<Hook>> {
+    let row = sqlx::query_as!(
+        Hook,
+        "SELECT id, tenant_id, channel, template, created_at FROM hooks WHERE id = ?",
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn list_hooks(pool: &SqlitePool, tenant_id: &str) -> Result<Vec<Hook>> {
+    let rows = sqlx::query_as!(
+        Hook,
+        "
+        SELECT id, tenant_id, channel, template, created_at
+        FROM hooks
+        WHERE tenant_id = ?
+        ORDER BY created_at, id
+        ",
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_hook(pool: &SqlitePool, tenant_id: &str, id: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM hooks WHERE tenant_id = ? AND id = ?",
+        tenant_id,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub struct Feed {
+    pub id: i64,
+    pub tenant_id: String,
+    pub url: String,
+    pub channel_name: String,
+    pub last_polled_at: Option<i64>,
+    pub created_at: i64,
+}
+
+pub async fn create_feed(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    url: &str,
+    channel_name: &str,
+) -> Result<i64> {
+    let channel_name = &normalize_channel_name(channel_name);
+    if !validate_channel_name(channel_name) {
+        return Err(anyhow::anyhow!("Invalid channel name"));
+    }
+
+    let id = sqlx::query!(
+        "INSERT INTO feeds (tenant_id, url, channel_name) VALUES (?, ?, ?)",
+        tenant_id,
+        url,
+        channel_name
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+    Ok(id)
+}
+
+pub async fn list_feeds(pool: &SqlitePool, tenant_id: &str) -> Result<Vec<Feed>> {
+    let rows = sqlx::query_as!(
+        Feed,
+        "
+        SELECT id, tenant_id, url, channel_name, last_polled_at, created_at
+        FROM feeds
+        WHERE tenant_id = ?
+        ORDER BY id
+        ",
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Feeds of every tenant, for the poller
+pub async fn list_all_feeds(pool: &SqlitePool) -> Result<Vec<Feed>> {
+    let rows = sqlx::query_as!(
+        Feed,
+        "SELECT id, tenant_id, url, channel_name, last_polled_at, created_at FROM feeds ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_feed(pool: &SqlitePool, tenant_id: &str, id: i64) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM feeds WHERE tenant_id = ? AND id = ?",
+        tenant_id,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Records an entry as seen, returning false if it already was
+pub async fn mark_feed_entry_seen(pool: &SqlitePool, feed_id: i64, guid: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        "INSERT INTO feed_entries (feed_id, guid) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        feed_id,
+        guid
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn mark_feed_polled(pool: &SqlitePool, feed_id: i64) -> Result<()> {
+    sqlx::query!(
+        "UPDATE feeds SET last_polled_at = unixepoch() WHERE id = ?",
+        feed_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub struct RecurringMessage {
+    pub id: i64,
+    pub tenant_id: String,
+    pub channel_name: String,
+    pub body: String,
+    pub cron: String,
+    pub timezone: String,
+    pub next_run_at: Option<i64>,
+    pub created_at: i64,
+}
+
+/// Fields of a recurring message set on create and update
+pub struct RecurringMessageFields<'a> {
+    pub channel_name: &'a str,
+    pub body: &'a str,
+    pub cron: &'a str,
+    pub timezone: &'a str,
+    pub next_run_at: Option<i64>,
+}
+
+pub async fn create_recurring_message(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    fields: &RecurringMessageFields<'_>,
+) -> Result<i64> {
+    let channel_name = &normalize_channel_name(fields.channel_name);
+    let id = sqlx::query!(
+        "
+        INSERT INTO recurring_messages (tenant_id, channel_name, body, cron, timezone, next_run_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ",
+        tenant_id,
+        channel_name,
+        fields.body,
+        fields.cron,
+        fields.timezone,
+        fields.next_run_at
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+    Ok(id)
+}
+
+pub async fn update_recurring_message(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    id: i64,
+    fields: &RecurringMessageFields<'_>,
+) -> Result<bool> {
+    let channel_name = &normalize_channel_name(fields.channel_name);
+    let result = sqlx::query!(
+        "
+        UPDATE recurring_messages
+        SET channel_name = ?, body = ?, cron = ?, timezone = ?, next_run_at = ?
+        WHERE tenant_id = ? AND id = ?
+        ",
+        channel_name,
+        fields.body,
+        fields.cron,
+        fields.timezone,
+        fields.next_run_at,
+        tenant_id,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_recurring_messages(
+    pool: &SqlitePool,
+    tenant_id: &str,
+) -> Result<Vec<RecurringMessage>> {
+    let rows = sqlx::query_as!(
+        RecurringMessage,
+        "
+        SELECT id, tenant_id, channel_name, body, cron, timezone, next_run_at, created_at
+        FROM recurring_messages
+        WHERE tenant_id = ?
+        ORDER BY id
+        ",
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_recurring_message(pool: &SqlitePool, tenant_id: &str, id: i64) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM recurring_messages WHERE tenant_id = ? AND id = ?",
+        tenant_id,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Recurring messages of every tenant whose next run is at or before `now`
+pub async fn due_recurring_messages(pool: &SqlitePool, now: i64) -> Result<Vec<RecurringMessage>> {
+    let rows = sqlx::query_as!(
+        RecurringMessage,
+        "
+        SELECT id, tenant_id, channel_name, body, cron, timezone, next_run_at, created_at
+        FROM recurring_messages
+        WHERE next_run_at <= ?
+        ORDER BY next_run_at
+        ",
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn set_recurring_next_run(
+    pool: &SqlitePool,
+    id: i64,
+    next_run_at: Option<i64>,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE recurring_messages SET next_run_at = ? WHERE id = ?",
+        next_run_at,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Defaults applied to a channel's messages when a request doesn't set them
+#[derive(Clone, Default)]
+pub struct ChannelSettings {
+    /// Telegram parse mode name, messages are plain text when unset
+    pub parse_mode: Option<String>,
+    pub silent: bool,
+    pub protect_content: bool,
+    pub disable_link_preview: bool,
+    /// Handlebars templates placed above and below every message
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    /// Seconds an identical message gets the earlier result instead of being sent again
+    pub dedup_window_secs: Option<i64>,
+    /// Messages sent per minute, later ones waiting until the last minute has room
+    pub rate_limit_per_minute: Option<i64>,
+    /// Listed when users search channels in the bots' inline mode
+    pub public: bool,
+    /// Subscriptions wait for the user to confirm them with an inline button
+    pub double_opt_in: bool,
+    /// Messages carry an inline button unsubscribing the recipient
+    pub unsubscribe_button: bool,
+}
+
+/// Settings of a channel, `None` until someone subscribes to it
+pub async fn get_channel_settings(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+) -> Result<Option<ChannelSettings>> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let row = sqlx::query_as!(
+        ChannelSettings,
+        r#"
+        SELECT parse_mode,
+               silent as "silent: bool",
+               protect_content as "protect_content: bool",
+               disable_link_preview as "disable_link_preview: bool",
+               header,
+               footer,
+               dedup_window_secs,
+               rate_limit_per_minute,
+               public as "public: bool",
+               double_opt_in as "double_opt_in: bool",
+               unsubscribe_button as "unsubscribe_button: bool"
+        FROM channels
+        WHERE tenant_id = ? AND name = ?
+        "#,
+        tenant_id,
+        channel_name
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Returns false if the channel doesn't exist
+pub async fn update_channel_settings(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    settings: &ChannelSettings,
+) -> Result<bool> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let result = sqlx::query!(
+        r#"
+        UPDATE channels
+        SET parse_mode = ?, silent = ?, protect_content = ?, disable_link_preview = ?,
+            header = ?, footer = ?, dedup_window_secs = ?, rate_limit_per_minute = ?,
+            public = ?, double_opt_in = ?, unsubscribe_button = ?
+        WHERE tenant_id = ? AND name = ?
+        "#,
+        settings.parse_mode,
+        settings.silent,
+        settings.protect_content,
+        settings.disable_link_preview,
+        settings.header,
+        settings.footer,
+        settings.dedup_window_secs,
+        settings.rate_limit_per_minute,
+        settings.public,
+        settings.double_opt_in,
+        settings.unsubscribe_button,
+        tenant_id,
+        channel_name
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameOutcome {
+    Renamed,
+    NotFound,
+    /// A channel with the new name already exists
+    NameTaken,
+}
+
+/// Points the subscriptions, aliases and history of channel `from` at `to`, subscribers
+/// of both keeping one subscription. Returns how many subscriptions moved.
+async fn move_channel_rows(
+    conn: &mut SqliteConnection,
+    tenant_id: &str,
+    from: &str,
+    to: &str,
+) -> Result<u64> {
+    let moved = sqlx::query!(
+        "
+        UPDATE OR IGNORE subscriptions SET channel_name = ?
+        WHERE tenant_id = ? AND channel_name = ?
+        ",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    // Those already subscribed to `to` keep that subscription
+    sqlx::query!(
+        "
+        INSERT INTO subscription_events (tenant_id, telegram_id, channel_name, event, source)
+        SELECT tenant_id, telegram_id, channel_name, 'unsubscribed', 'api'
+        FROM subscriptions
+        WHERE tenant_id = ? AND channel_name = ?
+        ",
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscriptions WHERE tenant_id = ? AND channel_name = ?",
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "
+        UPDATE subscription_events SET channel_name = ?
+        WHERE tenant_id = ? AND channel_name = ?
+        ",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE feeds SET channel_name = ? WHERE tenant_id = ? AND channel_name = ?",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE recurring_messages SET channel_name = ? WHERE tenant_id = ? AND channel_name = ?",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE messages SET channel_name = ? WHERE tenant_id = ? AND channel_name = ?",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE polls SET channel_name = ? WHERE tenant_id = ? AND channel_name = ?",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE dead_letters SET channel_name = ? WHERE tenant_id = ? AND channel_name = ?",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // Counts already under `to` add up
+    sqlx::query!(
+        "
+        INSERT INTO daily_stats (day, tenant_id, channel_name, sent, errors)
+        SELECT day, tenant_id, ?1, sent, errors
+        FROM daily_stats
+        WHERE tenant_id = ?2 AND channel_name = ?3
+        ON CONFLICT (day, tenant_id, channel_name) DO UPDATE
+        SET sent = sent + excluded.sent, errors = errors + excluded.errors
+        ",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM daily_stats WHERE tenant_id = ? AND channel_name = ?",
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE channel_aliases SET channel_name = ? WHERE tenant_id = ? AND channel_name = ?",
+        to,
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // Only used to skip duplicate sends, not worth carrying over
+    sqlx::query!(
+        "DELETE FROM recent_sends WHERE tenant_id = ? AND channel_name = ?",
+        tenant_id,
+        from
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(moved.rows_affected())
+}
+
+/// Moves the channel, its subscriptions, settings and history to `new_name` at once.
+/// With `keep_alias` the old name stays an alias of the channel.
+pub async fn rename_channel(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    new_name: &str,
+    keep_alias: bool,
+) -> Result<RenameOutcome> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let new_name = &normalize_channel_name(new_name);
+    if !validate_channel_name(new_name) {
+        return Err(anyhow::anyhow!("Invalid channel name"));
+    }
+
+    let mut tx = pool.begin().await?;
+    // An alias of the channel itself can become its name
+    let taken = sqlx::query_scalar!(
+        r#"
+        SELECT (SELECT COUNT(*) FROM channels WHERE tenant_id = ?1 AND name = ?2)
+             + (SELECT COUNT(*) FROM channel_aliases
+                WHERE tenant_id = ?1 AND alias = ?2 AND channel_name != ?3) AS "taken!: i64"
+        "#,
+        tenant_id,
+        new_name,
+        channel_name
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if taken > 0 {
+        tx.rollback().await?;
+        return Ok(RenameOutcome::NameTaken);
+    }
+
+    let renamed = sqlx::query!(
+        "UPDATE channels SET name = ? WHERE tenant_id = ? AND name = ?",
+        new_name,
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    if renamed.rows_affected() == 0 {
+        tx.rollback().await?;
+        return Ok(RenameOutcome::NotFound);
+    }
+
+    move_channel_rows(&mut tx, tenant_id, channel_name, new_name).await?;
+    sqlx::query!(
+        "DELETE FROM channel_aliases WHERE tenant_id = ? AND alias = ?",
+        tenant_id,
+        new_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    if keep_alias {
+        sqlx::query!(
+            "INSERT INTO channel_aliases (tenant_id, alias, channel_name) VALUES (?, ?, ?)",
+            tenant_id,
+            channel_name,
+            new_name
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(RenameOutcome::Renamed)
+}
+
+/// Removes the channel with its subscriptions, aliases, feeds and recurring messages, keeping
+/// the history of what it sent. Returns the telegram_ids of the subscribers it had, `None`
+/// if it doesn't exist.
+pub async fn delete_channel(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+) -> Result<Option<Vec<i64>>> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let mut tx = pool.begin().await?;
+    let deleted = sqlx::query!(
+        "DELETE FROM channels WHERE tenant_id = ? AND name = ?",
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        tx.rollback().await?;
+        return Ok(None);
+    }
+
+    sqlx::query!(
+        "
+        INSERT INTO subscription_events (tenant_id, telegram_id, channel_name, event, source)
+        SELECT tenant_id, telegram_id, channel_name, 'unsubscribed', 'api'
+        FROM subscriptions
+        WHERE tenant_id = ? AND channel_name = ?
+        ",
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    let subscribers = sqlx::query_scalar!(
+        "DELETE FROM subscriptions WHERE tenant_id = ? AND channel_name = ? RETURNING telegram_id",
+        tenant_id,
+        channel_name
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM feeds WHERE tenant_id = ? AND channel_name = ?",
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM recurring_messages WHERE tenant_id = ? AND channel_name = ?",
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM recent_sends WHERE tenant_id = ? AND channel_name = ?",
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM channel_aliases WHERE tenant_id = ? AND channel_name = ?",
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(subscribers))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    Merged {
+        /// Subscriptions moved over, not counting subscribers both channels had
+        moved: u64,
+        /// telegram_ids of everyone who was subscribed to the merged channel
+        unsubscribed: Vec<i64>,
+    },
+    /// Either channel doesn't exist
+    NotFound,
+    /// The channels are served by different bots, which `from`'s subscribers may never
+    /// have started a chat with
+    DifferentBots,
+}
+
+/// Folds channel `from` into `into`: its subscribers, feeds, recurring messages and
+/// history move over and `from` is removed. `into` keeps its settings. Both must be
+/// served by the same bot.
+pub async fn merge_channels(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    from: &str,
+    into: &str,
+) -> Result<MergeOutcome> {
+    let from = &normalize_channel_name(from);
+    let into = &normalize_channel_name(into);
+    if from == into {
+        return Err(anyhow::anyhow!("Can't merge a channel into itself"));
+    }
+
+    let mut tx = pool.begin().await?;
+    let bots = sqlx::query_scalar!(
+        "SELECT bot FROM channels WHERE tenant_id = ? AND name IN (?, ?)",
+        tenant_id,
+        from,
+        into
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let refusal = match bots.as_slice() {
+        [from_bot, into_bot] if from_bot != into_bot => Some(MergeOutcome::DifferentBots),
+        [_, _] => None,
+        _ => Some(MergeOutcome::NotFound),
+    };
+    if let Some(refusal) = refusal {
+        tx.rollback().await?;
+        return Ok(refusal);
+    }
+    sqlx::query!(
+        "DELETE FROM channels WHERE tenant_id = ? AND name = ?",
+        tenant_id,
+        from
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let unsubscribed = sqlx::query_scalar!(
+        "
+        SELECT telegram_id FROM subscriptions
+        WHERE tenant_id = ? AND channel_name = ?
+        ORDER BY telegram_id
+        ",
+        tenant_id,
+        from
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let moved = move_channel_rows(&mut tx, tenant_id, from, into).await?;
+    tx.commit().await?;
+    Ok(MergeOutcome::Merged {
+        moved,
+        unsubscribed,
+    })
+}
+
+/// The channel `channel_name` stands for: the one it is an alias of, or the channel of
+/// that name
+pub async fn resolve_channel_name(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+) -> Result<String> {
+    let channel_name = normalize_channel_name(channel_name);
+    let target = sqlx::query_scalar!(
+        "SELECT channel_name FROM channel_aliases WHERE tenant_id = ? AND alias = ?",
+        tenant_id,
+        channel_name
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(target.unwrap_or(channel_name))
+}
+
+pub struct ChannelAlias {
+    pub alias: String,
+    pub channel_name: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasOutcome {
+    Set,
+    ChannelNotFound,
+    /// A channel with the alias' name exists
+    NameTaken,
+}
+
+/// Makes `alias` resolve to the channel, replacing what it pointed at before
+pub async fn set_channel_alias(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    alias: &str,
+    channel_name: &str,
+) -> Result<AliasOutcome> {
+    let alias = &normalize_channel_name(alias);
+    let channel_name = &normalize_channel_name(channel_name);
+    if !validate_channel_name(alias) {
+        return Err(anyhow::anyhow!("Invalid channel name"));
+    }
+
+    let mut tx = pool.begin().await?;
+    let existing = sqlx::query!(
+        "SELECT name FROM channels WHERE tenant_id = ? AND name IN (?, ?)",
+        tenant_id,
+        alias,
+        channel_name
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let outcome = if existing.iter().any(|row| &row.name == alias) {
+        AliasOutcome::NameTaken
+    } else if existing.is_empty() {
+        AliasOutcome::ChannelNotFound
+    } else {
+        sqlx::query!(
+            "
+            INSERT INTO channel_aliases (tenant_id, alias, channel_name) VALUES (?, ?, ?)
+            ON CONFLICT (tenant_id, alias) DO UPDATE SET channel_name = excluded.channel_name
+            ",
+            tenant_id,
+            alias,
+            channel_name
+        )
+        .execute(&mut *tx)
+        .await?;
+        AliasOutcome::Set
+    };
+    tx.commit().await?;
+    Ok(outcome)
+}
+
+pub async fn list_channel_aliases(pool: &SqlitePool, tenant_id: &str) -> Result<Vec<ChannelAlias>> {
+    let rows = sqlx::query_as!(
+        ChannelAlias,
+        "
+        SELECT alias, channel_name, created_at
+        FROM channel_aliases
+        WHERE tenant_id = ?
+        ORDER BY alias
+        ",
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_channel_alias(pool: &SqlitePool, tenant_id: &str, alias: &str) -> Result<bool> {
+    let alias = &normalize_channel_name(alias);
+    let result = sqlx::query!(
+        "DELETE FROM channel_aliases WHERE tenant_id = ? AND alias = ?",
+        tenant_id,
+        alias
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub struct ChannelSummary {
+    pub name: String,
+    pub bot: String,
+    pub subscribers: i64,
+    pub created_at: i64,
+}
+
+pub async fn list_channels(pool: &SqlitePool, tenant_id: &str) -> Result<Vec<ChannelSummary>> {
+    let rows = sqlx::query_as!(
+        ChannelSummary,
+        r#"
+        SELECT c.name,
+               c.bot,
+               (SELECT COUNT(*) FROM subscriptions s
+                WHERE s.tenant_id = c.tenant_id AND s.channel_name = c.name) as "subscribers!: i64",
+               c.created_at
+        FROM channels c
+        WHERE c.tenant_id = ?
+        ORDER BY c.name
+        "#,
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Public channels of the tenant whose name contains `query`, those starting with it
+/// first, then the most subscribed
+pub async fn search_public_channels(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<ChannelSummary>> {
+    let query = normalize_channel_name(query);
+    let rows = sqlx::query_as!(
+        ChannelSummary,
+        r#"
+        SELECT c.name,
+               c.bot,
+               (SELECT COUNT(*) FROM subscriptions s
+                WHERE s.tenant_id = c.tenant_id AND s.channel_name = c.name) as "subscribers!: i64",
+               c.created_at
+        FROM channels c
+        WHERE c.tenant_id = ?1 AND c.public AND instr(c.name, ?2) > 0
+        ORDER BY instr(c.name, ?2) = 1 DESC, 3 DESC, c.name
+        LIMIT ?3
+        "#,
+        tenant_id,
+        query,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub struct ChannelStats {
+    pub subscribers: i64,
+    /// Current subscribers who joined within the last 7 and 30 days
+    pub new_subscribers_7d: i64,
+    pub new_subscribers_30d: i64,
+    pub last_message_at: Option<i64>,
+}
+
+/// Aggregates of a channel, `None` if it doesn't exist
+pub async fn get_channel_stats(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+) -> Result<Option<ChannelStats>> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let row = sqlx::query_as!(
+        ChannelStats,
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions s
+             WHERE s.tenant_id = c.tenant_id AND s.channel_name = c.name) as "subscribers!: i64",
+            (SELECT COUNT(*) FROM subscriptions s
+             WHERE s.tenant_id = c.tenant_id AND s.channel_name = c.name
+               AND s.created_at > unixepoch() - 7 * 86400) as "new_subscribers_7d!: i64",
+            (SELECT COUNT(*) FROM subscriptions s
+             WHERE s.tenant_id = c.tenant_id AND s.channel_name = c.name
+               AND s.created_at > unixepoch() - 30 * 86400) as "new_subscribers_30d!: i64",
+            (SELECT MAX(m.created_at) FROM messages m
+             WHERE m.tenant_id = c.tenant_id AND m.channel_name = c.name) as "last_message_at: i64"
+        FROM channels c
+        WHERE c.tenant_id = ? AND c.name = ?
+        "#,
+        tenant_id,
+        channel_name
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Subscribes and unsubscribes of a channel in one bucket of time
+pub struct GrowthBucket {
+    /// First day of the bucket, `YYYY-MM-DD`
+    pub start: String,
+    pub subscribed: i64,
+    pub unsubscribed: i64,
+}
+
+pub struct ChannelGrowth {
+    /// Subscribers the history counts before `from`
+    pub subscribers_before: i64,
+    /// Buckets with any events, oldest first
+    pub buckets: Vec<GrowthBucket>,
+}
+
+/// Growth of a channel between the days `from` and `to` (inclusive, UTC) from its
+/// subscription history, in buckets of a `day`, a `week` starting on Monday or a `month`.
+/// `None` if the channel doesn't exist.
+pub async fn get_channel_growth(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    from: &str,
+    to: &str,
+    bucket: &str,
+) -> Result<Option<ChannelGrowth>> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let Some(subscribers_before) = sqlx::query_scalar!(
+        r#"
+        SELECT (SELECT COALESCE(SUM(CASE e.event WHEN 'subscribed' THEN 1 ELSE -1 END), 0)
+                FROM subscription_events e
+                WHERE e.tenant_id = c.tenant_id AND e.channel_name = c.name
+                  AND e.created_at < unixepoch(?3)) AS "n!: i64"
+        FROM channels c
+        WHERE c.tenant_id = ?1 AND c.name = ?2
+        "#,
+        tenant_id,
+        channel_name,
+        from
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let buckets = sqlx::query_as!(
+        GrowthBucket,
+        r#"
+        SELECT CASE ?5
+                   WHEN 'month' THEN date(created_at, 'unixepoch', 'start of month')
+                   WHEN 'week' THEN date(created_at, 'unixepoch', 'weekday 0', '-6 days')
+                   ELSE date(created_at, 'unixepoch')
+               END AS "start!: String",
+               SUM(event = 'subscribed') AS "subscribed!: i64",
+               SUM(event = 'unsubscribed') AS "unsubscribed!: i64"
+        FROM subscription_events
+        WHERE tenant_id = ?1
+          AND channel_name = ?2
+          AND created_at >= unixepoch(?3)
+          AND created_at < unixepoch(?4, '+1 day')
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        tenant_id,
+        channel_name,
+        from,
+        to,
+        bucket
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(ChannelGrowth {
+        subscribers_before,
+        buckets,
+    }))
+}
+
+/// Outcome of an earlier send of the same message
+pub struct RecentSend {
+    pub sent: i64,
+    pub errors: i64,
+    pub message_id: Option<i64>,
+}
+
+/// Result of the same message sent to the channel within its dedup window
+pub async fn find_recent_send(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    message: &str,
+) -> Result<Option<RecentSend>> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let message_hash = hex::encode(Sha256::digest(message.as_bytes()));
+    let row = sqlx::query_as!(
+        RecentSend,
+        r#"
+        SELECT r.sent, r.errors, r.message_id
+        FROM recent_sends r
+        JOIN channels c ON c.tenant_id = r.tenant_id AND c.name = r.channel_name
+        WHERE r.tenant_id = ? AND r.channel_name = ? AND r.message_hash = ?
+          AND r.created_at > unixepoch() - c.dedup_window_secs
+        "#,
+        tenant_id,
+        channel_name,
+        message_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Remembers the result of a send when the channel has a dedup window, forgetting
+/// the channel's sends that fell out of it
+pub async fn record_recent_send(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    message: &str,
+    send: &RecentSend,
+) -> Result<()> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let message_hash = hex::encode(Sha256::digest(message.as_bytes()));
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM recent_sends
+        WHERE tenant_id = ? AND channel_name = ?
+          AND created_at <= unixepoch() - COALESCE(
+            (SELECT dedup_window_secs FROM channels WHERE tenant_id = ? AND name = ?), 0)
+        "#,
+        tenant_id,
+        channel_name,
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT OR REPLACE INTO recent_sends
+            (tenant_id, channel_name, message_hash, sent, errors, message_id)
+        SELECT tenant_id, name, ?, ?, ?, ?
+        FROM channels
+        WHERE tenant_id = ? AND name = ? AND dedup_window_secs IS NOT NULL
+        "#,
+        message_hash,
+        send.sent,
+        send.errors,
+        send.message_id,
+        tenant_id,
+        channel_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub struct StoredMessage {
+    pub id: i64,
+    pub channel_name: Option<String>,
+    pub text: String,
+    pub created_at: i64,
+}
+
+/// Telegram message a recipient received for a stored message
+pub struct MessageCopy {
+    pub telegram_id: i64,
+    pub bot: String,
+    pub telegram_message_id: i64,
+}
+
+pub async fn create_message(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: Option<&str>,
+    text: &str,
+) -> Result<i64> {
+    let channel_name = channel_name.map(normalize_channel_name);
+    let id = sqlx::query!(
+        "INSERT INTO messages (tenant_id, channel_name, text) VALUES (?, ?, ?)",
+        tenant_id,
+        channel_name,
+        text
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+    Ok(id)
+}
+
+/// Keeps the footer template a send chose instead of its channel's
+pub async fn set_message_footer(pool: &SqlitePool, id: i64, footer: &str) -> Result<()> {
+    sqlx::query!("UPDATE messages SET footer = ? WHERE id = ?", footer, id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Footer template the message was sent with instead of its channel's, if any
+pub async fn get_message_footer(pool: &SqlitePool, id: i64) -> Result<Option<String>> {
+    let footer = sqlx::query_scalar!("SELECT footer FROM messages WHERE id = ?", id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+    Ok(footer)
+}
+
+/// Keeps how the message was formatted, as JSON
+pub async fn set_message_format(pool: &SqlitePool, id: i64, format: &str) -> Result<()> {
+    sqlx::query!("UPDATE messages SET format = ? WHERE id = ?", format, id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// JSON of how the message was formatted, `None` for messages stored before it was kept
+pub async fn get_message_format(pool: &SqlitePool, id: i64) -> Result<Option<String>> {
+    let format = sqlx::query_scalar!("SELECT format FROM messages WHERE id = ?", id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+    Ok(format)
+}
+
+pub async fn record_message_copies(
+    pool: &SqlitePool,
+    message_id: i64,
+    copies: &[MessageCopy],
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for copy in copies {
+        sqlx::query!(
+            "
+            INSERT INTO message_copies (message_id, telegram_id, bot, telegram_message_id)
+            VALUES (?, ?, ?, ?)
+            ",
+            message_id,
+            copy.telegram_id,
+            copy.bot,
+            copy.telegram_message_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn find_message(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    id: i64,
+) -> Result<Option<StoredMessage>> {
+    let row = sqlx::query_as!(
+        StoredMessage,
+        "
+        SELECT id, channel_name, text, created_at
+        FROM messages
+        WHERE tenant_id = ? AND id = ?
+        ",
+        tenant_id,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Latest messages first
+pub async fn list_messages(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    limit: i64,
+) -> Result<Vec<StoredMessage>> {
+    let rows = sqlx::query_as!(
+        StoredMessage,
+        "
+        SELECT id, channel_name, text, created_at
+        FROM messages
+        WHERE tenant_id = ?
+        ORDER BY id DESC
+        LIMIT ?
+        ",
+        tenant_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn get_message_copies(pool: &SqlitePool, message_id: i64) -> Result<Vec<MessageCopy>> {
+    let rows = sqlx::query_as!(
+        MessageCopy,
+        "
+        SELECT telegram_id, bot, telegram_message_id
+        FROM message_copies
+        WHERE message_id = ?
+        ORDER BY telegram_id
+        ",
+        message_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Id of the tenant's stored message whose copy `telegram_id` got from `bot` as
+/// `telegram_message_id`
+pub async fn find_message_by_copy(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    bot: &str,
+    telegram_id: i64,
+    telegram_message_id: i64,
+) -> Result<Option<i64>> {
+    let id = sqlx::query_scalar!(
+        "
+        SELECT m.id
+        FROM message_copies c
+        JOIN messages m ON m.id = c.message_id
+        WHERE m.tenant_id = ? AND c.bot = ? AND c.telegram_id = ? AND c.telegram_message_id = ?
+        ",
+        tenant_id,
+        bot,
+        telegram_id,
+        telegram_message_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(id)
+}
+
+pub async fn update_message_text(pool: &SqlitePool, id: i64, text: &str) -> Result<()> {
+    sqlx::query!("UPDATE messages SET text = ? WHERE id = ?", text, id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_message(pool: &SqlitePool, tenant_id: &str, id: i64) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM messages WHERE tenant_id = ? AND id = ?",
+        tenant_id,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Stores the tracked links of a message about to be sent, as URL and token pairs in
+/// the order they appear in it
+pub async fn create_tracked_links(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    links: &[(String, String)],
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for (url, token) in links {
+        sqlx::query!(
+            "INSERT INTO tracked_links (token, tenant_id, url) VALUES (?, ?, ?)",
+            token,
+            tenant_id,
+            url
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Ties tracked links to the message they were sent in, once it's stored
+pub async fn attach_tracked_links(
+    pool: &SqlitePool,
+    message_id: i64,
+    tokens: impl IntoIterator<Item = &str>,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for token in tokens {
+        sqlx::query!(
+            "UPDATE tracked_links SET message_id = ? WHERE token = ?",
+            message_id,
+            token
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Records a click by `telegram_id` on a tracked link, returning where it points or
+/// `None` for an unknown token
+pub async fn record_link_click(
+    pool: &SqlitePool,
+    token: &str,
+    telegram_id: i64,
+) -> Result<Option<String>> {
+    let Some(link) = sqlx::query!("SELECT id, url FROM tracked_links WHERE token = ?", token)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+    sqlx::query!(
+        "INSERT INTO link_clicks (link_id, telegram_id) VALUES (?, ?)",
+        link.id,
+        telegram_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(Some(link.url))
+}
+
+/// Where a tracked link points, without recording a click
+pub async fn get_tracked_link_url(pool: &SqlitePool, token: &str) -> Result<Option<String>> {
+    Ok(
+        sqlx::query_scalar!("SELECT url FROM tracked_links WHERE token = ?", token)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+pub struct LinkClicks {
+    pub url: String,
+    pub clicks: i64,
+    /// Distinct recipients who clicked
+    pub clickers: i64,
+}
+
+pub struct MessageClicks {
+    /// Copies of the message delivered
+    pub recipients: i64,
+    /// Distinct recipients who clicked any link
+    pub clickers: i64,
+    /// In the order they appear in the message
+    pub links: Vec<LinkClicks>,
+}
+
+pub async fn get_message_clicks(pool: &SqlitePool, message_id: i64) -> Result<MessageClicks> {
+    let recipients = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "n!: i64" FROM message_copies WHERE message_id = ?"#,
+        message_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let clickers = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT c.telegram_id) AS "n!: i64"
+        FROM link_clicks c
+        JOIN tracked_links l ON l.id = c.link_id
+        WHERE l.message_id = ?
+        "#,
+        message_id
+    )
+    .fetch_one(pool)
+    .await?;
+    // A resumed broadcast tracks the same URL under more than one token
+    let links = sqlx::query_as!(
+        LinkClicks,
+        r#"
+        SELECT l.url AS "url!",
+               COUNT(c.link_id) AS "clicks!: i64",
+               COUNT(DISTINCT c.telegram_id) AS "clickers!: i64"
+        FROM tracked_links l
+        LEFT JOIN link_clicks c ON c.link_id = l.id
+        WHERE l.message_id = ?
+        GROUP BY l.url
+        ORDER BY MIN(l.id)
+        "#,
+        message_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(MessageClicks {
+        recipients,
+        clickers,
+        links,
+    })
+}
+
+pub struct StoredPoll {
+    pub id: i64,
+    pub question: String,
+    /// JSON array of option texts
+    pub options: String,
+    pub is_anonymous: bool,
+}
+
+pub async fn create_poll(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: &str,
+    question: &str,
+    options: &[String],
+    is_anonymous: bool,
+    allows_multiple_answers: bool,
+) -> Result<i64> {
+    let channel_name = &normalize_channel_name(channel_name);
+    let options = serde_json::to_string(options)?;
+    let id = sqlx::query!(
+        "
+        INSERT INTO polls (tenant_id, channel_name, question, options, is_anonymous, allows_multiple_answers)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ",
+        tenant_id,
+        channel_name,
+        question,
+        options,
+        is_anonymous,
+        allows_multiple_answers
+    )
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+    Ok(id)
+}
+
+/// Records the Telegram poll ids of the delivered copies, as `(telegram_id, telegram_poll_id)`
+pub async fn record_poll_copies(
+    pool: &SqlitePool,
+    poll_id: i64,
+    copies: &[(i64, String)],
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for (telegram_id, telegram_poll_id) in copies {
+        sqlx::query!(
+            "INSERT INTO poll_copies (poll_id, telegram_id, telegram_poll_id) VALUES (?, ?, ?)",
+            poll_id,
+            telegram_id,
+            telegram_poll_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn find_poll(pool: &SqlitePool, tenant_id: &str, id: i64) -> Result<Option<StoredPoll>> {
+    let row = sqlx::query_as!(
+        StoredPoll,
+        r#"
+        SELECT id, question, options, is_anonymous as "is_anonymous: bool"
+        FROM polls
+        WHERE tenant_id = ? AND id = ?
+        "#,
+        tenant_id,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Stores a voter's current choice, removing it when the vote was retracted.
+/// Answers to polls not sent through the proxy are ignored.
+pub async fn record_poll_answer(
+    pool: &SqlitePool,
+    telegram_poll_id: &str,
+    voter_id: i64,
+    option_ids: &[u8],
+) -> Result<()> {
+    if option_ids.is_empty() {
+        sqlx::query!(
+            "
+            DELETE FROM poll_answers
+            WHERE voter_id = ?
+              AND poll_id = (SELECT poll_id FROM poll_copies WHERE telegram_poll_id = ?)
+            ",
+            voter_id,
+            telegram_poll_id
+        )
+        .execute(pool)
+        .await?;
+        return Ok(());
+    }
+
+    let option_ids = serde_json::to_string(option_ids)?;
+    sqlx::query!(
+        "
+        INSERT INTO poll_answers (poll_id, voter_id, option_ids)
+        SELECT poll_id, ?, ? FROM poll_copies WHERE telegram_poll_id = ?
+        ON CONFLICT (poll_id, voter_id) DO UPDATE SET option_ids = excluded.option_ids
+        ",
+        voter_id,
+        option_ids,
+        telegram_poll_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Stores the votes per option and voter count of a poll copy from a poll update
+pub async fn record_poll_state(
+    pool: &SqlitePool,
+    telegram_poll_id: &str,
+    voter_counts: &[u32],
+    total_voters: u32,
+) -> Result<()> {
+    let voter_counts = serde_json::to_string(voter_counts)?;
+    sqlx::query!(
+        "UPDATE poll_copies SET voter_counts = ?, total_voters = ? WHERE telegram_poll_id = ?",
+        voter_counts,
+        total_voters,
+        telegram_poll_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Chosen option indexes of every recorded answer, as JSON arrays
+pub async fn get_poll_answers(pool: &SqlitePool, poll_id: i64) -> Result<Vec<String>> {
+    let rows = sqlx::query_scalar!(
+        "SELECT option_ids FROM poll_answers WHERE poll_id = ?",
+        poll_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Latest state of a poll copy reported by Telegram
+pub struct PollCopyState {
+    /// JSON array of votes per option
+    pub voter_counts: String,
+    pub total_voters: i64,
+}
+
+/// State of every copy with a poll update yet
+pub async fn get_poll_states(pool: &SqlitePool, poll_id: i64) -> Result<Vec<PollCopyState>> {
+    let rows = sqlx::query_as!(
+        PollCopyState,
+        r#"
+        SELECT voter_counts as "voter_counts!", total_voters as "total_voters!"
+        FROM poll_copies
+        WHERE poll_id = ? AND voter_counts IS NOT NULL
+        "#,
+        poll_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn count_poll_copies(pool: &SqlitePool, poll_id: i64) -> Result<i64> {
+    let count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM poll_copies WHERE poll_id = ?",
+        poll_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// Adds to today's (UTC) usage counters
+pub async fn record_usage(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    api_key_id: Option<i64>,
+    messages: i64,
+) -> Result<()> {
+    let api_key_id = api_key_id.unwrap_or(0);
+    sqlx::query!(
+        "
+        INSERT INTO daily_usage (day, tenant_id, api_key_id, messages)
+        VALUES (date('now'), ?, ?, ?)
+        ON CONFLICT (day, tenant_id, api_key_id) DO UPDATE SET messages = messages + excluded.messages
+        ",
+        tenant_id,
+        api_key_id,
+        messages
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Adds to today's delivery counts of the channel, `None` for broadcasts
+pub async fn record_daily_stats(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: Option<&str>,
+    sent: i64,
+    errors: i64,
+) -> Result<()> {
+    let channel_name = channel_name.map(normalize_channel_name).unwrap_or_default();
+    sqlx::query!(
+        "
+        INSERT INTO daily_stats (day, tenant_id, channel_name, sent, errors)
+        VALUES (date('now'), ?, ?, ?, ?)
+        ON CONFLICT (day, tenant_id, channel_name) DO UPDATE
+        SET sent = sent + excluded.sent, errors = errors + excluded.errors
+        ",
+        tenant_id,
+        channel_name,
+        sent,
+        errors
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// What a send to a subscriber says about whether they're still around
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reach {
+    Delivered,
+    /// Failed for a reason on their side, e.g. the chat no longer exists
+    Unreachable,
+    Blocked,
+}
+
+/// Records the outcome of sends from the tenant: a delivery ends a run of failures,
+/// a failure starts one unless it's already running
+pub async fn record_deliveries(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    outcomes: &[(i64, Reach)],
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for (telegram_id, reach) in outcomes {
+        let delivered = *reach == Reach::Delivered;
+        let blocked = *reach == Reach::Blocked;
+        sqlx::query!(
+            "
+            INSERT INTO subscriber_activity (tenant_id, telegram_id, last_delivered_at,
+                                             failing_since, blocked_at)
+            VALUES (?1, ?2,
+                    CASE WHEN ?3 THEN unixepoch() END,
+                    CASE WHEN NOT ?3 THEN unixepoch() END,
+                    CASE WHEN ?4 THEN unixepoch() END)
+            ON CONFLICT (tenant_id, telegram_id) DO UPDATE
+            SET last_delivered_at = COALESCE(excluded.last_delivered_at, last_delivered_at),
+                failing_since = CASE WHEN ?3 THEN NULL ELSE COALESCE(failing_since, excluded.failing_since) END,
+                blocked_at = CASE WHEN ?3 THEN NULL ELSE COALESCE(blocked_at, excluded.blocked_at) END,
+                flagged_at = CASE WHEN ?3 THEN NULL ELSE flagged_at END
+            ",
+            tenant_id,
+            telegram_id,
+            delivered,
+            blocked
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// A subscriber the pruning job removed or flagged
+pub struct PrunedSubscriber {
+    pub tenant_id: String,
+    pub telegram_id: i64,
+    /// `blocked` or `failing`
+    pub reason: String,
+    /// `removed` or `flagged`
+    pub action: String,
+    /// JSON array of the channels they were subscribed to
+    pub channels: String,
+    pub pruned_at: i64,
+}
+
+impl PrunedSubscriber {
+    /// The channels they were subscribed to
+    pub fn channel_names(&self) -> Vec<String> {
+        serde_json::from_str(&self.channels).unwrap_or_default()
+    }
+}
+
+/// Removes, or flags when `remove` is false, subscribers whose deliveries have failed
+/// since before `failing_before`, and with `blocked` those who blocked the bot. Anyone
+/// who messaged a bot since their deliveries started failing is spared.
+pub async fn prune_subscribers(
+    pool: &SqlitePool,
+    failing_before: i64,
+    blocked: bool,
+    remove: bool,
+) -> Result<Vec<PrunedSubscriber>> {
+    let mut tx = pool.begin().await?;
+    let action = if remove { "removed" } else { "flagged" };
+    let candidates = sqlx::query!(
+        r#"
+        SELECT a.tenant_id,
+               a.telegram_id,
+               CASE WHEN ?1 AND a.blocked_at IS NOT NULL THEN 'blocked' ELSE 'failing' END
+                   AS "reason!: String",
+               json_group_array(s.channel_name) AS "channels!: String"
+        FROM subscriber_activity a
+                 JOIN subscriptions s
+                      ON s.tenant_id = a.tenant_id AND s.telegram_id = a.telegram_id
+                 LEFT JOIN telegram_users u ON u.telegram_id = a.telegram_id
+        WHERE a.failing_since IS NOT NULL
+          AND ((?1 AND a.blocked_at IS NOT NULL) OR a.failing_since <= ?2)
+          AND (?3 OR a.flagged_at IS NULL)
+          AND (u.last_seen_at IS NULL OR u.last_seen_at < a.failing_since)
+        GROUP BY a.tenant_id, a.telegram_id
+        "#,
+        blocked,
+        failing_before,
+        remove
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut pruned = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        if remove {
+            sqlx::query!(
+                "
+                INSERT INTO subscription_events (tenant_id, telegram_id, channel_name, event,
+                                                 source)
+                SELECT tenant_id, telegram_id, channel_name, 'unsubscribed', 'pruning'
+                FROM subscriptions
+                WHERE tenant_id = ? AND telegram_id = ?
+                ",
+                candidate.tenant_id,
+                candidate.telegram_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "DELETE FROM subscriptions WHERE tenant_id = ? AND telegram_id = ?",
+                candidate.tenant_id,
+                candidate.telegram_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "DELETE FROM subscriber_activity WHERE tenant_id = ? AND telegram_id = ?",
+                candidate.tenant_id,
+                candidate.telegram_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else {
+            sqlx::query!(
+                "
+                UPDATE subscriber_activity SET flagged_at = unixepoch()
+                WHERE tenant_id = ? AND telegram_id = ?
+                ",
+                candidate.tenant_id,
+                candidate.telegram_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        let pruned_at = sqlx::query_scalar!(
+            "
+            INSERT INTO pruned_subscribers (tenant_id, telegram_id, reason, action, channels)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING pruned_at
+            ",
+            candidate.tenant_id,
+            candidate.telegram_id,
+            candidate.reason,
+            action,
+            candidate.channels
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        pruned.push(PrunedSubscriber {
+            tenant_id: candidate.tenant_id,
+            telegram_id: candidate.telegram_id,
+            reason: candidate.reason,
+            action: action.to_string(),
+            channels: candidate.channels,
+            pruned_at,
+        });
+    }
+    tx.commit().await?;
+    Ok(pruned)
+}
+
+/// Subscribers of the tenant pruned since `since`, newest first
+pub async fn list_pruned_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    since: i64,
+    limit: i64,
+) -> Result<Vec<PrunedSubscriber>> {
+    let rows = sqlx::query_as!(
+        PrunedSubscriber,
+        "
+        SELECT tenant_id, telegram_id, reason, action, channels, pruned_at
+        FROM pruned_subscribers
+        WHERE tenant_id = ? AND pruned_at >= ?
+        ORDER BY id DESC
+        LIMIT ?
+        ",
+        tenant_id,
+        since,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// How many subscribers of the tenant were pruned since `since`, by reason and action
+pub async fn count_pruned_subscribers(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    since: i64,
+) -> Result<Vec<(String, String, i64)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT reason, action, COUNT(*) AS "count!: i64"
+        FROM pruned_subscribers
+        WHERE tenant_id = ? AND pruned_at >= ?
+        GROUP BY reason, action
+        "#,
+        tenant_id,
+        since
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.reason, r.action, r.count))
+        .collect())
+}
+
+pub struct DailyStats {
+    pub day: String,
+    /// Empty for broadcasts
+    pub channel_name: String,
+    pub sent: i64,
+    pub errors: i64,
+}
+
+/// Delivery counts of every day from `from` to `to`, both included, as `YYYY-MM-DD`
+pub async fn get_daily_stats(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    from: &str,
+    to: &str,
+) -> Result<Vec<DailyStats>> {
+    let rows = sqlx::query_as!(
+        DailyStats,
+        "
+        SELECT day, channel_name, sent, errors
+        FROM daily_stats
+        WHERE tenant_id = ? AND day BETWEEN ? AND ?
+        ORDER BY day, channel_name
+        ",
+        tenant_id,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn get_tenant_usage_today(pool: &SqlitePool, tenant_id: &str) -> Result<i64> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COALESCE(SUM(messages), 0) AS "messages!: i64"
+        FROM daily_usage
+        WHERE day = date('now')
+          AND tenant_id = ?
+        "#,
+        tenant_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.messages)
+}
+
+pub async fn get_api_key_usage_today(pool: &SqlitePool, api_key_id: i64) -> Result<i64> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COALESCE(SUM(messages), 0) AS "messages!: i64"
+        FROM daily_usage
+        WHERE day = date('now')
+          AND api_key_id = ?
+        "#,
+        api_key_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.messages)
+}
+
+/// Background broadcast that hasn't finished delivering
+pub struct BroadcastJob {
+    pub id: String,
+    pub tenant_id: String,
+    pub message: String,
+    /// JSON send options
+    pub options: String,
+    pub pin: bool,
+    pub total: i64,
+    pub sent: i64,
+    pub errors: i64,
+}
+
+/// Queues a job for every subscriber of the tenant, or of `channels` when given, picked
+/// like [`stream_all_subscribers`]. Returns how many recipients it has, its `total`.
+pub async fn create_broadcast_job(
+    pool: &SqlitePool,
+    job: &BroadcastJob,
+    channels: Option<&[String]>,
+) -> Result<i64> {
+    let channels = channels.map(channels_json);
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "
+        INSERT INTO broadcast_jobs (id, tenant_id, message, options, pin, total, sent, errors)
+        VALUES (?, ?, ?, ?, ?, 0, ?, ?)
+        ",
+        job.id,
+        job.tenant_id,
+        job.message,
+        job.options,
+        job.pin,
+        job.sent,
+        job.errors
+    )
+    .execute(&mut *tx)
+    .await?;
+    let total = sqlx::query!(
+        "
+        INSERT INTO broadcast_job_recipients (job_id, telegram_id, bot)
+        SELECT ?1, s.telegram_id, MIN(c.bot)
+        FROM subscriptions s
+                 JOIN channels c ON c.tenant_id = s.tenant_id AND c.name = s.channel_name
+        WHERE s.tenant_id = ?2
+          AND (?3 IS NULL OR s.channel_name IN (SELECT value FROM json_each(?3)))
+        GROUP BY s.telegram_id
+        ",
+        job.id,
+        job.tenant_id,
+        channels
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected() as i64;
+    sqlx::query!(
+        "UPDATE broadcast_jobs SET total = ? WHERE id = ?",
+        total,
+        job.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(total)
+}
+
+/// Every job still in the queue, oldest first
+pub async fn list_broadcast_jobs(pool: &SqlitePool) -> Result<Vec<BroadcastJob>> {
+    let rows = sqlx::query_as!(
+        BroadcastJob,
+        r#"
+        SELECT id, tenant_id, message, options, pin as "pin: bool", total, sent, errors
+        FROM broadcast_jobs
+        ORDER BY created_at, id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Recipients of a job that haven't been attempted yet, read a page at a time
+pub fn stream_pending_job_recipients(
+    pool: &SqlitePool,
+    job_id: &str,
+) -> impl Stream<Item = Result<Recipient>> + Send + 'static {
+    let (pool, job_id) = (pool.clone(), job_id.to_string());
+    paged(RECIPIENT_PAGE_SIZE, move |after, limit| {
+        let (pool, job_id) = (pool.clone(), job_id.clone());
+        async move {
+            let rows = sqlx::query_as!(
+                Recipient,
+                "
+                SELECT telegram_id, bot
+                FROM broadcast_job_recipients
+                WHERE job_id = ? AND attempted = 0 AND telegram_id > ?
+                ORDER BY telegram_id
+                LIMIT ?
+                ",
+                job_id,
+                after,
+                limit
+            )
+            .fetch_all(&pool)
+            .await?;
+            Ok(rows)
+        }
+    })
+}
+
+/// Marks a chunk of recipients attempted in one transaction, each with the id of the
+/// Telegram message they got if delivered, and adds them to the job's counts
+pub async fn record_job_deliveries(
+    pool: &SqlitePool,
+    job_id: &str,
+    deliveries: &[(i64, Option<i64>)],
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let (mut sent, mut errors) = (0, 0);
+    for (telegram_id, telegram_message_id) in deliveries {
+        let updated = sqlx::query!(
+            "
+            UPDATE broadcast_job_recipients
+            SET attempted = 1, telegram_message_id = ?
+            WHERE job_id = ? AND telegram_id = ? AND attempted = 0
+            ",
+            telegram_message_id,
+            job_id,
+            telegram_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() > 0 {
+            match telegram_message_id {
+                Some(_) => sent += 1,
+                None => errors += 1,
+            }
+        }
+    }
+    sqlx::query!(
+        "UPDATE broadcast_jobs SET sent = sent + ?, errors = errors + ? WHERE id = ?",
+        sent,
+        errors,
+        job_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Copies delivered so far, across restarts
+pub async fn get_job_copies(pool: &SqlitePool, job_id: &str) -> Result<Vec<MessageCopy>> {
+    let rows = sqlx::query_as!(
+        MessageCopy,
+        r#"
+        SELECT telegram_id, bot, telegram_message_id as "telegram_message_id!"
+        FROM broadcast_job_recipients
+        WHERE job_id = ? AND telegram_message_id IS NOT NULL
+        "#,
+        job_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Takes a finished or cancelled job off the queue
+pub async fn delete_broadcast_job(pool: &SqlitePool, job_id: &str) -> Result<()> {
+    sqlx::query!("DELETE FROM broadcast_jobs WHERE id = ?", job_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Text message a recipient didn't get, kept to send it again
+pub struct DeadLetter {
+    pub id: i64,
+    pub channel_name: Option<String>,
+    pub telegram_id: i64,
+    pub bot: String,
+    pub message: String,
+    /// JSON send options
+    pub options: String,
+    pub failure: String,
+    pub created_at: i64,
+}
+
+pub async fn record_dead_letters(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    channel_name: Option<&str>,
+    message: &str,
+    options: &str,
+    failed: &[(Recipient, &str)],
+) -> Result<()> {
+    let channel_name = channel_name.map(normalize_channel_name);
+    let mut tx = pool.begin().await?;
+    for (recipient, failure) in failed {
+        sqlx::query!(
+            "
+            INSERT INTO dead_letters (tenant_id, channel_name, telegram_id, bot, message, options, failure)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ",
+            tenant_id,
+            channel_name,
+            recipient.telegram_id,
+            recipient.bot,
+            message,
+            options,
+            failure
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The tenant's dead letters, newest first
+pub async fn list_dead_letters(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    limit: i64,
+) -> Result<Vec<DeadLetter>> {
+    let rows = sqlx::query_as!(
+        DeadLetter,
+        "
+        SELECT id, channel_name, telegram_id, bot, message, options, failure, created_at
+        FROM dead_letters
+        WHERE tenant_id = ?
+        ORDER BY id DESC
+        LIMIT ?
+        ",
+        tenant_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn find_dead_letter(
+    pool: &SqlitePool,
+    tenant_id: &str,
+    id: i64,
+) -> Result<Option<DeadLetter>> {
+    let row = sqlx::query_as!(
+        DeadLetter,
+        "
+        SELECT id, channel_name, telegram_id, bot, message, options, failure, created_at
+        FROM dead_letters
+        WHERE tenant_id = ? AND id = ?
+        ",
+        tenant_id,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn delete_dead_letter(pool: &SqlitePool, tenant_id: &str, id: i64) -> Result<bool> {
     let result = sqlx::query!(
-        "DELETE FROM subscriptions WHERE telegram_id = ? AND channel_name = ?",
-        telegram_id,
-        channel_name
+        "DELETE FROM dead_letters WHERE tenant_id = ? AND id = ?",
+        tenant_id,
+        id
     )
     .execute(pool)
     .await?;
-
     Ok(result.rows_affected() > 0)
 }
 
-pub async fn get_subscribers(pool: &SqlitePool, channel_name: &str) -> Result<Vec<i64>> {
-    let rows = sqlx::query!(
-        "SELECT telegram_id FROM subscriptions WHERE channel_name = ?",
-        channel_name
-    )
-    .fetch_all(pool)
-    .await?;
-    Ok(rows.into_iter().map(|r| r.telegram_id).collect())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bot::DEFAULT_BOT;
 
     #[sqlx::test]
     async fn test_subscribe(pool: SqlitePool) -> Result<()> {
-        let result = subscribe(&pool, 123456, "news").await;
-        assert!(result.is_ok());
+        let result = subscribe(&pool, DEFAULT_TENANT, 123456, "news", DEFAULT_BOT).await;
+        assert_eq!(result.unwrap(), SubscribeOutcome::NewlySubscribed);
+        subscribe(&pool, DEFAULT_TENANT, 123456, "alerts", DEFAULT_BOT).await?;
+        subscribe(&pool, "acme", 123456, "sales", DEFAULT_BOT).await?;
+        assert_eq!(
+            list_subscribed_channels(&pool, DEFAULT_TENANT, 123456).await?,
+            ["alerts", "news"]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_verified_chats(pool: SqlitePool) -> Result<()> {
+        assert!(!is_chat_verified(&pool, 111).await?);
+        mark_chat_verified(&pool, 111).await?;
+        mark_chat_verified(&pool, 111).await?;
+        assert!(is_chat_verified(&pool, 111).await?);
+
+        // Subscribers from before the challenge don't need to pass it
+        subscribe(&pool, "acme", 222, "sales", DEFAULT_BOT).await?;
+        assert!(is_chat_verified(&pool, 222).await?);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_pending_subscriptions(pool: SqlitePool) -> Result<()> {
+        let id = request_subscription(&pool, "acme", 111, "News", DEFAULT_BOT, 0).await?;
+        let again = request_subscription(&pool, "acme", 111, "news", DEFAULT_BOT, 0).await?;
+        assert_eq!(id, again);
+
+        // Only the chat it was asked for can take it, once
+        assert!(
+            take_pending_subscription(&pool, "acme", 222, id)
+                .await?
+                .is_none()
+        );
+        let pending = take_pending_subscription(&pool, "acme", 111, id)
+            .await?
+            .unwrap();
+        assert_eq!(pending.channel_name, "news");
+        assert_eq!(pending.bot, DEFAULT_BOT);
+        assert!(
+            take_pending_subscription(&pool, "acme", 111, id)
+                .await?
+                .is_none()
+        );
+
+        let id = request_subscription(&pool, "acme", 111, "news", DEFAULT_BOT, 0).await?;
+        let now = chrono::Utc::now().timestamp();
+        request_subscription(&pool, "acme", 222, "news", DEFAULT_BOT, now + 1).await?;
+        assert!(
+            take_pending_subscription(&pool, "acme", 111, id)
+                .await?
+                .is_none()
+        );
         Ok(())
     }
 
     #[sqlx::test]
     async fn test_duplicate_subscription(pool: SqlitePool) -> Result<()> {
-        subscribe(&pool, 123456, "news").await.unwrap();
-        let result = subscribe(&pool, 123456, "news").await;
-        assert!(result.is_err());
+        subscribe(&pool, DEFAULT_TENANT, 123456, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        let result = subscribe(&pool, DEFAULT_TENANT, 123456, "news", DEFAULT_BOT).await;
+        assert_eq!(result.unwrap(), SubscribeOutcome::AlreadySubscribed);
+        assert_eq!(
+            stream_subscribers(&pool, DEFAULT_TENANT, "news", &[])
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
         Ok(())
     }
 
     #[sqlx::test]
     async fn test_get_subscribers(pool: SqlitePool) -> Result<()> {
-        subscribe(&pool, 111, "tech").await.unwrap();
-        subscribe(&pool, 222, "tech").await.unwrap();
-        subscribe(&pool, 333, "news").await.unwrap();
+        subscribe(&pool, DEFAULT_TENANT, 111, "tech", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, DEFAULT_TENANT, 222, "tech", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, DEFAULT_TENANT, 333, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
 
-        let subs = get_subscribers(&pool, "tech").await.unwrap();
+        let subs = stream_subscribers(&pool, DEFAULT_TENANT, "tech", &[])
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
         assert_eq!(subs.len(), 2);
-        assert!(subs.contains(&111));
-        assert!(subs.contains(&222));
+        assert!(subs.iter().any(|r| r.telegram_id == 111));
+        assert!(subs.iter().any(|r| r.telegram_id == 222));
+        assert!(subs.iter().all(|r| r.bot == DEFAULT_BOT));
         Ok(())
     }
 
     #[sqlx::test]
     async fn test_channel_name_with_space(pool: SqlitePool) -> Result<()> {
-        let result = subscribe(&pool, 123, "invalid channel").await;
+        let result = subscribe(&pool, DEFAULT_TENANT, 123, "invalid channel", DEFAULT_BOT).await;
         assert!(result.is_err());
         Ok(())
     }
 
     #[sqlx::test]
     async fn test_unsubscribe(pool: SqlitePool) -> Result<()> {
-        subscribe(&pool, 123, "news").await.unwrap();
-        let result = unsubscribe(&pool, 123, "news").await.unwrap();
+        subscribe(&pool, DEFAULT_TENANT, 123, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        let result = unsubscribe(&pool, DEFAULT_TENANT, 123, "news")
+            .await
+            .unwrap();
         assert!(result); // Should return true for successful unsubscribe
 
-        let subs = get_subscribers(&pool, "news").await.unwrap();
+        let subs = stream_subscribers(&pool, DEFAULT_TENANT, "news", &[])
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
         assert_eq!(subs.len(), 0);
         Ok(())
     }
 
     #[sqlx::test]
     async fn test_unsubscribe_not_subscribed(pool: SqlitePool) -> Result<()> {
-        let result = unsubscribe(&pool, 123, "news").await.unwrap();
+        let result = unsubscribe(&pool, DEFAULT_TENANT, 123, "news")
+            .await
+            .unwrap();
         assert!(!result); // Should return false when not subscribed
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn test_channel_names_ignore_case(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "News", DEFAULT_BOT).await?;
+        let result = subscribe(&pool, DEFAULT_TENANT, 111, "NEWS", DEFAULT_BOT).await?;
+        assert_eq!(result, SubscribeOutcome::AlreadySubscribed);
+        subscribe(&pool, DEFAULT_TENANT, 222, "news", DEFAULT_BOT).await?;
+
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "nEwS", &[]).await?,
+            2
+        );
+        let channels = ["NEWS".to_string()];
+        assert_eq!(
+            count_all_subscribers(&pool, DEFAULT_TENANT, Some(&channels)).await?,
+            2
+        );
+        let subscriptions = list_subscriptions(&pool, DEFAULT_TENANT).await?;
+        assert!(subscriptions.iter().all(|s| s.channel_name == "news"));
+
+        assert!(unsubscribe(&pool, DEFAULT_TENANT, 111, "News").await?);
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "news", &[]).await?,
+            1
+        );
+
+        subscribe(&pool, DEFAULT_TENANT, 111, "Café", DEFAULT_BOT).await?;
+        let result = subscribe(&pool, DEFAULT_TENANT, 111, "CAFÉ", DEFAULT_BOT).await?;
+        assert_eq!(result, SubscribeOutcome::AlreadySubscribed);
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "café", &[]).await?,
+            1
+        );
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_migration_merges_case_variants(pool: SqlitePool) -> Result<()> {
+        let mut migrator = sqlx::migrate!("./migrations");
+        let all = migrator.migrations.clone();
+        migrator.migrations = all
+            .iter()
+            .filter(|m| m.version < 20261016270000)
+            .cloned()
+            .collect();
+        migrator.run(&pool).await?;
+
+        sqlx::query(
+            "
+            INSERT INTO channels (tenant_id, name, bot, created_at, header)
+            VALUES ('default', 'News', 'default', 1, 'first'),
+                   ('default', 'news', 'other', 2, 'second'),
+                   ('default', 'Sports', 'default', 1, NULL);
+            INSERT INTO subscriptions (tenant_id, telegram_id, channel_name)
+            VALUES ('default', 111, 'News'),
+                   ('default', 111, 'news'),
+                   ('default', 222, 'news'),
+                   ('default', 333, 'Sports');
+            INSERT INTO daily_stats (day, tenant_id, channel_name, sent, errors)
+            VALUES ('2026-10-01', 'default', 'News', 3, 1),
+                   ('2026-10-01', 'default', 'news', 2, 0);
+            ",
+        )
+        .execute(&pool)
+        .await?;
+
+        migrator.migrations = all;
+        migrator.run(&pool).await?;
+
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "news", &[]).await?,
+            2
+        );
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "sports", &[]).await?,
+            1
+        );
+        let settings = get_channel_settings(&pool, DEFAULT_TENANT, "news")
+            .await?
+            .unwrap();
+        assert_eq!(settings.header.as_deref(), Some("first"));
+        let recipients = stream_subscribers(&pool, DEFAULT_TENANT, "news", &[])
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert!(recipients.iter().all(|r| r.bot == DEFAULT_BOT));
+
+        let stats = get_daily_stats(&pool, DEFAULT_TENANT, "2026-10-01", "2026-10-01").await?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].sent, stats[0].errors), (5, 1));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_rename_channel(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 333, "sports", DEFAULT_BOT).await?;
+        let feed_id = create_feed(&pool, DEFAULT_TENANT, "https://blog/rss", "news").await?;
+        record_daily_stats(&pool, DEFAULT_TENANT, Some("news"), 2, 1).await?;
+
+        assert_eq!(
+            rename_channel(&pool, DEFAULT_TENANT, "news", "Sports", false).await?,
+            RenameOutcome::NameTaken
+        );
+        assert_eq!(
+            rename_channel(&pool, DEFAULT_TENANT, "weather", "forecast", false).await?,
+            RenameOutcome::NotFound
+        );
+        assert_eq!(
+            rename_channel(&pool, DEFAULT_TENANT, "News", "Headlines", false).await?,
+            RenameOutcome::Renamed
+        );
+
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "news", &[]).await?,
+            0
+        );
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "headlines", &[]).await?,
+            2
+        );
+        assert!(
+            get_channel_settings(&pool, DEFAULT_TENANT, "news")
+                .await?
+                .is_none()
+        );
+        let feeds = list_feeds(&pool, DEFAULT_TENANT).await?;
+        assert_eq!(feeds[0].id, feed_id);
+        assert_eq!(feeds[0].channel_name, "headlines");
+        let stats = get_daily_stats(&pool, DEFAULT_TENANT, "2000-01-01", "2999-12-31").await?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].channel_name, "headlines");
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_channel_aliases(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "ops_alerts", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "news", DEFAULT_BOT).await?;
+
+        assert_eq!(
+            set_channel_alias(&pool, DEFAULT_TENANT, "Alerts", "ops_alerts").await?,
+            AliasOutcome::Set
+        );
+        assert_eq!(
+            set_channel_alias(&pool, DEFAULT_TENANT, "news", "ops_alerts").await?,
+            AliasOutcome::NameTaken
+        );
+        assert_eq!(
+            set_channel_alias(&pool, DEFAULT_TENANT, "weather", "forecast").await?,
+            AliasOutcome::ChannelNotFound
+        );
+        assert_eq!(
+            resolve_channel_name(&pool, DEFAULT_TENANT, "ALERTS").await?,
+            "ops_alerts"
+        );
+        assert_eq!(
+            resolve_channel_name(&pool, "acme", "alerts").await?,
+            "alerts"
+        );
+        assert_eq!(
+            resolve_channel_name(&pool, DEFAULT_TENANT, "News").await?,
+            "news"
+        );
+
+        // Another channel can't take the alias, the channel itself can
+        assert_eq!(
+            rename_channel(&pool, DEFAULT_TENANT, "news", "alerts", false).await?,
+            RenameOutcome::NameTaken
+        );
+        assert_eq!(
+            rename_channel(&pool, DEFAULT_TENANT, "ops_alerts", "incidents", true).await?,
+            RenameOutcome::Renamed
+        );
+        let aliases = list_channel_aliases(&pool, DEFAULT_TENANT).await?;
+        let aliases: Vec<_> = aliases
+            .iter()
+            .map(|a| (a.alias.as_str(), a.channel_name.as_str()))
+            .collect();
+        assert_eq!(
+            aliases,
+            [("alerts", "incidents"), ("ops_alerts", "incidents")]
+        );
+        assert_eq!(
+            rename_channel(&pool, DEFAULT_TENANT, "incidents", "alerts", false).await?,
+            RenameOutcome::Renamed
+        );
+        assert_eq!(list_channel_aliases(&pool, DEFAULT_TENANT).await?.len(), 1);
+
+        assert!(delete_channel_alias(&pool, DEFAULT_TENANT, "OPS_ALERTS").await?);
+        assert!(!delete_channel_alias(&pool, DEFAULT_TENANT, "ops_alerts").await?);
+        set_channel_alias(&pool, DEFAULT_TENANT, "latest", "news").await?;
+        delete_channel(&pool, DEFAULT_TENANT, "news").await?;
+        assert!(
+            list_channel_aliases(&pool, DEFAULT_TENANT)
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_delete_channel(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 111, "sports", DEFAULT_BOT).await?;
+        create_feed(&pool, DEFAULT_TENANT, "https://blog/rss", "news").await?;
+        create_message(&pool, DEFAULT_TENANT, Some("news"), "Hello").await?;
+
+        assert_eq!(
+            delete_channel(&pool, DEFAULT_TENANT, "News").await?,
+            Some(vec![111, 222])
+        );
+        assert_eq!(delete_channel(&pool, DEFAULT_TENANT, "news").await?, None);
+
+        assert!(
+            get_channel_settings(&pool, DEFAULT_TENANT, "news")
+                .await?
+                .is_none()
+        );
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "news", &[]).await?,
+            0
+        );
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "sports", &[]).await?,
+            1
+        );
+        assert!(list_feeds(&pool, DEFAULT_TENANT).await?.is_empty());
+        assert_eq!(list_messages(&pool, DEFAULT_TENANT, 10).await?.len(), 1);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_merge_channels(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "alerts", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 333, "alerts", DEFAULT_BOT).await?;
+        let id = create_recurring_message(
+            &pool,
+            DEFAULT_TENANT,
+            &RecurringMessageFields {
+                channel_name: "ops",
+                body: "Standup",
+                cron: "0 9 * * *",
+                timezone: "UTC",
+                next_run_at: None,
+            },
+        )
+        .await?;
+        record_daily_stats(&pool, DEFAULT_TENANT, Some("ops"), 2, 0).await?;
+        record_daily_stats(&pool, DEFAULT_TENANT, Some("alerts"), 3, 1).await?;
+
+        assert_eq!(
+            merge_channels(&pool, DEFAULT_TENANT, "ops", "weather").await?,
+            MergeOutcome::NotFound
+        );
+        subscribe(&pool, DEFAULT_TENANT, 444, "pager", "oncall").await?;
+        assert_eq!(
+            merge_channels(&pool, DEFAULT_TENANT, "ops", "pager").await?,
+            MergeOutcome::DifferentBots
+        );
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "ops", &[]).await?,
+            2
+        );
+        assert_eq!(
+            merge_channels(&pool, DEFAULT_TENANT, "Ops", "alerts").await?,
+            MergeOutcome::Merged {
+                moved: 1,
+                unsubscribed: vec![111, 222]
+            }
+        );
+        assert!(
+            merge_channels(&pool, DEFAULT_TENANT, "alerts", "ALERTS")
+                .await
+                .is_err()
+        );
+
+        assert!(
+            get_channel_settings(&pool, DEFAULT_TENANT, "ops")
+                .await?
+                .is_none()
+        );
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "ops", &[]).await?,
+            0
+        );
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "alerts", &[]).await?,
+            3
+        );
+        let recurring = list_recurring_messages(&pool, DEFAULT_TENANT).await?;
+        assert_eq!(recurring[0].id, id);
+        assert_eq!(recurring[0].channel_name, "alerts");
+        let stats = get_daily_stats(&pool, DEFAULT_TENANT, "2000-01-01", "2999-12-31").await?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].sent, stats[0].errors), (5, 1));
+        Ok(())
+    }
+
     #[test]
     fn test_validate_channel_name() {
         assert!(validate_channel_name("valid_channel123"));
@@ -125,11 +3736,1213 @@ mod tests {
         assert!(!validate_channel_name("invalid-channel"));
         assert!(!validate_channel_name("invalid.channel"));
         assert!(!validate_channel_name(""));
+        assert!(validate_channel_name(&"a".repeat(MAX_CHANNEL_NAME_CHARS)));
+        assert!(!validate_channel_name(
+            &"a".repeat(MAX_CHANNEL_NAME_CHARS + 1)
+        ));
+        assert_eq!(
+            channel_name_error(&"a".repeat(65)),
+            Some("Channel name too long (max 64 chars)")
+        );
+        assert_eq!(channel_name_error(""), Some("Channel name cannot be empty"));
+        assert!(validate_channel_name("deploys/backend/eu_west"));
+        assert!(!validate_channel_name("deploys/"));
+        assert!(!validate_channel_name("/deploys"));
+        assert!(!validate_channel_name("deploys//backend"));
+        assert!(!validate_tenant_id("acme/eu"));
+    }
+
+    #[sqlx::test]
+    async fn test_subscriber_tags(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 333, "news", DEFAULT_BOT).await?;
+        add_subscriber_tag(&pool, DEFAULT_TENANT, 111, "EU-region").await?;
+        add_subscriber_tag(&pool, DEFAULT_TENANT, 111, "oncall").await?;
+        add_subscriber_tag(&pool, DEFAULT_TENANT, 222, "eu-region").await?;
+        add_subscriber_tag(&pool, DEFAULT_TENANT, 333, "eu-region").await?;
+        // Not a subscriber of the tenant
+        add_subscriber_tag(&pool, "acme", 111, "eu-region").await?;
+
+        assert_eq!(
+            get_subscriber_tags(&pool, DEFAULT_TENANT, 111).await?,
+            Some(vec!["eu-region".to_string(), "oncall".to_string()])
+        );
+        assert_eq!(get_subscriber_tags(&pool, "acme", 111).await?, None);
+
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let recipients = |tags: Vec<String>| {
+            let pool = pool.clone();
+            async move {
+                stream_subscribers(&pool, DEFAULT_TENANT, "ops", &tags)
+                    .map_ok(|r| r.telegram_id)
+                    .try_collect::<Vec<_>>()
+                    .await
+            }
+        };
+        assert_eq!(recipients(tags(&["eu-region"])).await?, [111, 222]);
+        assert_eq!(recipients(tags(&["eu-region", "OnCall"])).await?, [111]);
+        assert!(recipients(tags(&["us-region"])).await?.is_empty());
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "ops", &tags(&["oncall", "oncall"])).await?,
+            1
+        );
+
+        remove_subscriber_tag(&pool, DEFAULT_TENANT, 111, "OnCall").await?;
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "ops", &tags(&["oncall"])).await?,
+            0
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_subscriber_metadata(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "ops", DEFAULT_BOT).await?;
+
+        assert!(
+            set_subscriber_metadata(&pool, DEFAULT_TENANT, 111, r#"{"team":"payments"}"#).await?
+        );
+        assert!(set_subscriber_metadata(&pool, DEFAULT_TENANT, 111, r#"{"team":"search"}"#).await?);
+        assert!(!set_subscriber_metadata(&pool, "acme", 111, r#"{"team":"search"}"#).await?);
+
+        let metadata = list_subscriber_metadata(&pool, DEFAULT_TENANT).await?;
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[&111]["team"], "search");
+
+        let subscriptions = list_subscriptions(&pool, DEFAULT_TENANT).await?;
+        assert_eq!(
+            subscriptions[0].metadata.as_deref(),
+            Some(r#"{"team":"search"}"#)
+        );
+        assert_eq!(subscriptions[1].metadata, None);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_subscription_events(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 111, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "sports", DEFAULT_BOT).await?;
+        unsubscribe(&pool, DEFAULT_TENANT, 111, "news").await?;
+        unsubscribe(&pool, DEFAULT_TENANT, 111, "news").await?;
+        // 222 is subscribed to both, losing the "news" subscription
+        merge_channels(&pool, DEFAULT_TENANT, "news", "sports").await?;
+
+        let events = list_subscription_events(
+            &pool,
+            DEFAULT_TENANT,
+            &SubscriptionEventFilter::default(),
+            10,
+        )
+        .await?;
+        let events: Vec<_> = events
+            .iter()
+            .map(|e| {
+                (
+                    e.telegram_id,
+                    e.channel_name.as_str(),
+                    e.event.as_str(),
+                    e.source.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (222, "sports", "unsubscribed", "api"),
+                (111, "sports", "unsubscribed", "bot"),
+                (222, "sports", "subscribed", "bot"),
+                (222, "sports", "subscribed", "bot"),
+                (111, "sports", "subscribed", "bot"),
+            ]
+        );
+
+        let filter = SubscriptionEventFilter {
+            telegram_id: Some(111),
+            ..Default::default()
+        };
+        let events = list_subscription_events(&pool, DEFAULT_TENANT, &filter, 10).await?;
+        assert_eq!(events.len(), 2);
+        let filter = SubscriptionEventFilter {
+            before_id: Some(events[1].id),
+            ..Default::default()
+        };
+        assert!(
+            list_subscription_events(&pool, DEFAULT_TENANT, &filter, 10)
+                .await?
+                .is_empty()
+        );
+        assert!(
+            list_subscription_events(&pool, "acme", &SubscriptionEventFilter::default(), 10)
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_channel_growth(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "news", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 333, "news", DEFAULT_BOT).await?;
+        unsubscribe(&pool, DEFAULT_TENANT, 333, "news").await?;
+        // A Wednesday, the Sunday after it and the Wednesday after that
+        sqlx::query!(
+            "
+            UPDATE subscription_events
+            SET created_at = CASE telegram_id
+                WHEN 111 THEN unixepoch('2026-10-07')
+                WHEN 222 THEN unixepoch('2026-10-11 23:00')
+                ELSE unixepoch('2026-10-14')
+            END
+            "
+        )
+        .execute(&pool)
+        .await?;
+
+        let growth = get_channel_growth(
+            &pool,
+            DEFAULT_TENANT,
+            "news",
+            "2026-10-08",
+            "2026-10-31",
+            "week",
+        )
+        .await?
+        .unwrap();
+        assert_eq!(growth.subscribers_before, 1);
+        let buckets: Vec<_> = growth
+            .buckets
+            .iter()
+            .map(|b| (b.start.as_str(), b.subscribed, b.unsubscribed))
+            .collect();
+        assert_eq!(buckets, [("2026-10-05", 1, 0), ("2026-10-12", 1, 1)]);
+
+        let growth = get_channel_growth(
+            &pool,
+            DEFAULT_TENANT,
+            "news",
+            "2026-09-01",
+            "2026-10-13",
+            "month",
+        )
+        .await?
+        .unwrap();
+        assert_eq!(growth.subscribers_before, 0);
+        let buckets: Vec<_> = growth
+            .buckets
+            .iter()
+            .map(|b| (b.start.as_str(), b.subscribed, b.unsubscribed))
+            .collect();
+        assert_eq!(buckets, [("2026-10-01", 2, 0)]);
+        assert!(
+            get_channel_growth(&pool, "acme", "news", "2026-10-01", "2026-10-31", "day")
+                .await?
+                .is_none()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_telegram_profiles_in_subscriptions(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "ops", DEFAULT_BOT).await?;
+        let mut profile = TelegramProfile {
+            telegram_id: 111,
+            username: Some("ada".to_string()),
+            first_name: "Ada".to_string(),
+            language_code: Some("en".to_string()),
+        };
+        remember_telegram_user(&pool, &profile).await?;
+        profile.username = None;
+        remember_telegram_user(&pool, &profile).await?;
+
+        let subscriptions = list_subscriptions(&pool, DEFAULT_TENANT).await?;
+        assert_eq!(subscriptions[0].first_name.as_deref(), Some("Ada"));
+        assert_eq!(subscriptions[0].username, None);
+        assert_eq!(subscriptions[0].language_code.as_deref(), Some("en"));
+        assert!(subscriptions[0].last_seen_at.is_some());
+        assert_eq!(subscriptions[1].first_name, None);
+        assert_eq!(subscriptions[1].last_seen_at, None);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_record_deliveries(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "ops", DEFAULT_BOT).await?;
+        subscribe(&pool, "acme", 111, "ops", DEFAULT_BOT).await?;
+        record_deliveries(&pool, DEFAULT_TENANT, &[(111, Reach::Delivered)]).await?;
+        record_deliveries(&pool, DEFAULT_TENANT, &[(111, Reach::Delivered)]).await?;
+
+        let subscriptions = list_subscriptions(&pool, DEFAULT_TENANT).await?;
+        assert!(subscriptions[0].last_delivered_at.is_some());
+        assert_eq!(subscriptions[1].last_delivered_at, None);
+        let subscriptions = list_subscriptions(&pool, "acme").await?;
+        assert_eq!(subscriptions[0].last_delivered_at, None);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_prune_subscribers(pool: SqlitePool) -> Result<()> {
+        for telegram_id in [111, 222, 333, 444] {
+            subscribe(&pool, DEFAULT_TENANT, telegram_id, "ops", DEFAULT_BOT).await?;
+        }
+        subscribe(&pool, DEFAULT_TENANT, 111, "deploys", DEFAULT_BOT).await?;
+        let outcomes = [
+            (111, Reach::Unreachable),
+            (222, Reach::Blocked),
+            (333, Reach::Unreachable),
+            (444, Reach::Delivered),
+        ];
+        record_deliveries(&pool, DEFAULT_TENANT, &outcomes).await?;
+        // 333 came back
+        record_deliveries(&pool, DEFAULT_TENANT, &[(333, Reach::Delivered)]).await?;
+
+        // Only blocked users are due yet
+        let pruned = prune_subscribers(&pool, 0, true, false).await?;
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(
+            (pruned[0].telegram_id, pruned[0].reason.as_str()),
+            (222, "blocked")
+        );
+        assert_eq!(list_subscriptions(&pool, DEFAULT_TENANT).await?.len(), 5);
+        // Flagged ones aren't flagged again
+        assert!(prune_subscribers(&pool, 0, true, false).await?.is_empty());
+
+        let now = chrono::Utc::now().timestamp() + 1;
+        let pruned = prune_subscribers(&pool, now, false, true).await?;
+        let removed = pruned.iter().find(|p| p.telegram_id == 111).unwrap();
+        assert_eq!(removed.channel_names(), ["deploys", "ops"]);
+        let mut pruned: Vec<_> = pruned
+            .iter()
+            .map(|p| (p.telegram_id, p.reason.as_str(), p.channels.as_str()))
+            .collect();
+        pruned.sort();
+        assert_eq!(
+            pruned,
+            [
+                (111, "failing", r#"["deploys","ops"]"#),
+                (222, "failing", r#"["ops"]"#)
+            ]
+        );
+        let subscribers: Vec<_> = list_subscriptions(&pool, DEFAULT_TENANT)
+            .await?
+            .into_iter()
+            .map(|s| s.telegram_id)
+            .collect();
+        assert_eq!(subscribers, [333, 444]);
+
+        let counts = count_pruned_subscribers(&pool, DEFAULT_TENANT, 0).await?;
+        assert_eq!(counts.iter().map(|(_, _, count)| count).sum::<i64>(), 3);
+        assert_eq!(
+            list_pruned_subscribers(&pool, DEFAULT_TENANT, 0, 2)
+                .await?
+                .len(),
+            2
+        );
+        assert!(
+            list_pruned_subscribers(&pool, "acme", 0, 10)
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_and_parents() {
+        assert_eq!(channel_and_parents("News"), ["news"]);
+        assert_eq!(channel_and_parents("Café/Über"), ["café", "café/über"]);
+        assert_eq!(
+            channel_and_parents("Deploys/backend/EU"),
+            ["deploys", "deploys/backend", "deploys/backend/eu"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_parent_channel_subscribers(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "deploys", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 111, "deploys/backend", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 222, "deploys/backend", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 333, "deploys/frontend", DEFAULT_BOT).await?;
+        subscribe(&pool, DEFAULT_TENANT, 444, "deploys_old", DEFAULT_BOT).await?;
+
+        let recipients = |channel_name: &'static str| {
+            stream_subscribers(&pool, DEFAULT_TENANT, channel_name, &[])
+                .map_ok(|r| r.telegram_id)
+                .try_collect::<Vec<_>>()
+        };
+        assert_eq!(recipients("deploys/backend").await?, [111, 222]);
+        assert_eq!(recipients("deploys/backend/eu").await?, [111, 222]);
+        assert_eq!(recipients("deploys").await?, [111]);
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "Deploys/Backend", &[]).await?,
+            2
+        );
+
+        let channels = ["deploys/frontend".to_string()];
+        assert_eq!(
+            count_all_subscribers(&pool, DEFAULT_TENANT, Some(&channels)).await?,
+            2
+        );
+
+        // Notices about a channel itself skip those who only get it through a parent
+        let direct = stream_direct_subscribers(&pool, DEFAULT_TENANT, "Deploys/Backend")
+            .map_ok(|r| r.telegram_id)
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(direct, [111, 222]);
+        assert_eq!(
+            count_direct_subscribers(&pool, DEFAULT_TENANT, "deploys/frontend").await?,
+            1
+        );
+        let direct = stream_direct_subscribers(&pool, DEFAULT_TENANT, "deploys/frontend")
+            .map_ok(|r| r.telegram_id)
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(direct, [333]);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_get_all_subscribers_distinct(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "tech", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, DEFAULT_TENANT, 111, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, DEFAULT_TENANT, 222, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+
+        let mut subs = stream_all_subscribers(&pool, DEFAULT_TENANT, None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.telegram_id)
+            .collect::<Vec<_>>();
+        subs.sort();
+        assert_eq!(subs, vec![111, 222]);
+
+        subscribe(&pool, DEFAULT_TENANT, 333, "sports", DEFAULT_BOT)
+            .await
+            .unwrap();
+        let channels = ["tech".to_string(), "news".to_string()];
+        let mut subs = stream_all_subscribers(&pool, DEFAULT_TENANT, Some(&channels))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.telegram_id)
+            .collect::<Vec<_>>();
+        subs.sort();
+        assert_eq!(subs, vec![111, 222]);
+        assert_eq!(
+            count_all_subscribers(&pool, DEFAULT_TENANT, Some(&channels))
+                .await
+                .unwrap(),
+            2
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_stream_subscribers_in_pages(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 1, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        sqlx::query(
+            "
+            WITH RECURSIVE ids(id) AS (SELECT 2 UNION ALL SELECT id + 1 FROM ids WHERE id < 2500)
+            INSERT INTO subscriptions (tenant_id, telegram_id, channel_name)
+            SELECT ?, id, 'news' FROM ids
+            ",
+        )
+        .bind(DEFAULT_TENANT)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let subs = stream_subscribers(&pool, DEFAULT_TENANT, "news", &[])
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let telegram_ids: Vec<_> = subs.iter().map(|r| r.telegram_id).collect();
+        assert_eq!(telegram_ids, (1..=2500).collect::<Vec<_>>());
+        assert_eq!(
+            count_subscribers(&pool, DEFAULT_TENANT, "news", &[])
+                .await
+                .unwrap(),
+            2500
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_channel_bound_to_first_bot(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, DEFAULT_TENANT, 111, "alerts", "alerts_bot")
+            .await
+            .unwrap();
+        let result = subscribe(&pool, DEFAULT_TENANT, 222, "alerts", DEFAULT_BOT).await;
+        assert!(result.is_err());
+
+        subscribe(&pool, DEFAULT_TENANT, 222, "alerts", "alerts_bot")
+            .await
+            .unwrap();
+        let subs = stream_subscribers(&pool, DEFAULT_TENANT, "alerts", &[])
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(subs.len(), 2);
+        assert!(subs.iter().all(|r| r.bot == "alerts_bot"));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_tenants_are_isolated(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, "acme", 111, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, "globex", 222, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        // Same user and channel name in another tenant is not a duplicate
+        subscribe(&pool, "globex", 111, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+
+        let acme = stream_subscribers(&pool, "acme", "news", &[])
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(acme.len(), 1);
+        assert_eq!(acme[0].telegram_id, 111);
+        assert_eq!(
+            stream_subscribers(&pool, "globex", "news", &[])
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(
+            stream_all_subscribers(&pool, DEFAULT_TENANT, None)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        assert!(!unsubscribe(&pool, "acme", 222, "news").await.unwrap());
+        assert_eq!(list_subscriptions(&pool, "acme").await.unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_api_keys(pool: SqlitePool) -> Result<()> {
+        let (id, key) = create_api_key(&pool, "acme", "ci", None).await.unwrap();
+
+        let found = find_api_key(&pool, &key).await.unwrap().unwrap();
+        assert_eq!(found.id, id);
+        assert_eq!(found.tenant_id, "acme");
+        assert!(find_api_key(&pool, "tbp_wrong").await.unwrap().is_none());
+
+        assert!(delete_api_key(&pool, id).await.unwrap());
+        assert!(find_api_key(&pool, &key).await.unwrap().is_none());
+        assert!(
+            create_api_key(&pool, "bad tenant", "ci", None)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_users(pool: SqlitePool) -> Result<()> {
+        let (id, token) = create_user(&pool, "alice", Some(42), &[Role::Viewer])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            create_user(&pool, "alice", None, &[])
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let user = find_user_by_token(&pool, &token).await.unwrap().unwrap();
+        assert_eq!(user.id, id);
+        assert_eq!(user.role(), Some(Role::Viewer));
+        assert!(
+            find_user_by_token(&pool, "tbu_wrong")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        grant_role(&pool, id, Role::Admin).await.unwrap();
+        grant_role(&pool, id, Role::Admin).await.unwrap();
+        let user = find_user_by_telegram_id(&pool, 42).await.unwrap().unwrap();
+        assert_eq!(user.roles, vec![Role::Viewer, Role::Admin]);
+        assert_eq!(user.role(), Some(Role::Admin));
+
+        revoke_role(&pool, id, Role::Admin).await.unwrap();
+        revoke_role(&pool, id, Role::Viewer).await.unwrap();
+        let user = get_user(&pool, id).await.unwrap().unwrap();
+        assert_eq!(user.role(), None);
+
+        grant_role(&pool, 999, Role::Admin).await.unwrap();
+        assert!(get_user(&pool, 999).await.unwrap().is_none());
+
+        assert!(delete_user(&pool, id).await.unwrap());
+        assert!(list_users(&pool).await.unwrap().is_empty());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_broadcast_jobs(pool: SqlitePool) -> Result<()> {
+        let job = BroadcastJob {
+            id: "job1".to_string(),
+            tenant_id: DEFAULT_TENANT.to_string(),
+            message: "Maintenance tonight".to_string(),
+            options: "{}".to_string(),
+            pin: true,
+            total: 0,
+            sent: 0,
+            errors: 0,
+        };
+        for (telegram_id, channel_name) in [(111, "news"), (222, "news"), (333, "tech")] {
+            subscribe(
+                &pool,
+                DEFAULT_TENANT,
+                telegram_id,
+                channel_name,
+                DEFAULT_BOT,
+            )
+            .await
+            .unwrap();
+        }
+        subscribe(&pool, DEFAULT_TENANT, 111, "tech", DEFAULT_BOT)
+            .await
+            .unwrap();
+        // Each subscriber once
+        assert_eq!(create_broadcast_job(&pool, &job, None).await.unwrap(), 3);
+
+        record_job_deliveries(&pool, "job1", &[(111, Some(9)), (222, None)])
+            .await
+            .unwrap();
+        // Already attempted, not counted twice
+        record_job_deliveries(&pool, "job1", &[(222, None)])
+            .await
+            .unwrap();
+
+        let jobs = list_broadcast_jobs(&pool).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert!(jobs[0].pin);
+        assert_eq!(jobs[0].total, 3);
+        assert_eq!((jobs[0].sent, jobs[0].errors), (1, 1));
+        let pending = stream_pending_job_recipients(&pool, "job1")
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            pending,
+            vec![Recipient {
+                telegram_id: 333,
+                bot: DEFAULT_BOT.to_string(),
+            }]
+        );
+        let copies = get_job_copies(&pool, "job1").await.unwrap();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].telegram_message_id, 9);
+
+        delete_broadcast_job(&pool, "job1").await.unwrap();
+        assert!(list_broadcast_jobs(&pool).await.unwrap().is_empty());
+        assert!(
+            stream_pending_job_recipients(&pool, "job1")
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_dead_letters(pool: SqlitePool) -> Result<()> {
+        let recipient = Recipient {
+            telegram_id: 111,
+            bot: DEFAULT_BOT.to_string(),
+        };
+        record_dead_letters(
+            &pool,
+            "acme",
+            Some("alerts"),
+            "Disk full",
+            "{}",
+            &[(recipient, "network")],
+        )
+        .await
+        .unwrap();
+
+        let letters = list_dead_letters(&pool, "acme", 10).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].failure, "network");
+        assert!(
+            list_dead_letters(&pool, "globex", 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let id = letters[0].id;
+        assert!(
+            find_dead_letter(&pool, "globex", id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let letter = find_dead_letter(&pool, "acme", id).await.unwrap().unwrap();
+        assert_eq!(letter.channel_name.as_deref(), Some("alerts"));
+        assert!(!delete_dead_letter(&pool, "globex", id).await.unwrap());
+        assert!(delete_dead_letter(&pool, "acme", id).await.unwrap());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_hooks(pool: SqlitePool) -> Result<()> {
+        let id = create_hook(&pool, "acme", "alerts", "{{text}}")
+            .await
+            .unwrap();
+
+        let hook = find_hook(&pool, &id).await.unwrap().unwrap();
+        assert_eq!(hook.tenant_id, "acme");
+        assert_eq!(hook.template, "{{text}}");
+        assert_eq!(list_hooks(&pool, "acme").await.unwrap().len(), 1);
+        assert!(list_hooks(&pool, "globex").await.unwrap().is_empty());
+
+        assert!(!delete_hook(&pool, "globex", &id).await.unwrap());
+        assert!(delete_hook(&pool, "acme", &id).await.unwrap());
+        assert!(find_hook(&pool, &id).await.unwrap().is_none());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_feeds(pool: SqlitePool) -> Result<()> {
+        let id = create_feed(&pool, "acme", "https://blog.example.com/rss", "blog")
+            .await
+            .unwrap();
+        assert!(
+            create_feed(&pool, "acme", "https://example.com", "bad name")
+                .await
+                .is_err()
+        );
+
+        assert!(mark_feed_entry_seen(&pool, id, "post-1").await.unwrap());
+        assert!(!mark_feed_entry_seen(&pool, id, "post-1").await.unwrap());
+        mark_feed_polled(&pool, id).await.unwrap();
+
+        let feeds = list_all_feeds(&pool).await.unwrap();
+        assert_eq!(feeds.len(), 1);
+        assert!(feeds[0].last_polled_at.is_some());
+        assert!(list_feeds(&pool, "globex").await.unwrap().is_empty());
+
+        assert!(delete_feed(&pool, "acme", id).await.unwrap());
+        let seen: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM feed_entries")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(seen, 0);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_recurring_messages(pool: SqlitePool) -> Result<()> {
+        let mut fields = RecurringMessageFields {
+            channel_name: "team",
+            body: "Standup!",
+            cron: "0 9 * * MON-FRI",
+            timezone: "Europe/Rome",
+            next_run_at: Some(100),
+        };
+        let id = create_recurring_message(&pool, "acme", &fields)
+            .await
+            .unwrap();
+
+        assert_eq!(due_recurring_messages(&pool, 99).await.unwrap().len(), 0);
+        assert_eq!(due_recurring_messages(&pool, 100).await.unwrap().len(), 1);
+
+        set_recurring_next_run(&pool, id, None).await.unwrap();
+        assert_eq!(due_recurring_messages(&pool, 1000).await.unwrap().len(), 0);
+
+        fields.body = "Retro!";
+        assert!(
+            !update_recurring_message(&pool, "globex", id, &fields)
+                .await
+                .unwrap()
+        );
+        assert!(
+            update_recurring_message(&pool, "acme", id, &fields)
+                .await
+                .unwrap()
+        );
+        let rows = list_recurring_messages(&pool, "acme").await.unwrap();
+        assert_eq!(rows[0].body, "Retro!");
+        assert_eq!(rows[0].next_run_at, Some(100));
+
+        assert!(delete_recurring_message(&pool, "acme", id).await.unwrap());
+        assert!(
+            list_recurring_messages(&pool, "acme")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_channel_settings(pool: SqlitePool) -> Result<()> {
+        let settings = ChannelSettings {
+            parse_mode: Some("HTML".to_string()),
+            silent: true,
+            protect_content: true,
+            disable_link_preview: true,
+            header: None,
+            footer: Some("/unsubscribe news".to_string()),
+            dedup_window_secs: None,
+            rate_limit_per_minute: Some(10),
+            public: true,
+            double_opt_in: true,
+            unsubscribe_button: true,
+        };
+        assert!(
+            !update_channel_settings(&pool, "acme", "news", &settings)
+                .await
+                .unwrap()
+        );
+        assert!(
+            get_channel_settings(&pool, "acme", "news")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        subscribe(&pool, "acme", 1, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        let stored = get_channel_settings(&pool, "acme", "news").await.unwrap();
+        assert!(!stored.unwrap().protect_content);
+
+        assert!(
+            update_channel_settings(&pool, "acme", "news", &settings)
+                .await
+                .unwrap()
+        );
+        let stored = get_channel_settings(&pool, "acme", "news")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.parse_mode.as_deref(), Some("HTML"));
+        assert!(stored.silent);
+        assert!(stored.protect_content);
+        assert!(stored.disable_link_preview);
+        assert_eq!(stored.footer.as_deref(), Some("/unsubscribe news"));
+        assert_eq!(stored.rate_limit_per_minute, Some(10));
+        assert!(stored.public);
+        assert!(stored.double_opt_in);
+        assert!(stored.unsubscribe_button);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_search_public_channels(pool: SqlitePool) -> Result<()> {
+        for (telegram_id, channel_name) in [
+            (1, "tech_news"),
+            (1, "news"),
+            (2, "news"),
+            (1, "deploys/news"),
+            (1, "sports"),
+            (1, "news_internal"),
+        ] {
+            subscribe(&pool, "acme", telegram_id, channel_name, DEFAULT_BOT).await?;
+        }
+        subscribe(&pool, "globex", 1, "news_globex", DEFAULT_BOT).await?;
+        let public = ChannelSettings {
+            public: true,
+            ..Default::default()
+        };
+        for channel_name in ["tech_news", "news", "deploys/news", "sports"] {
+            update_channel_settings(&pool, "acme", channel_name, &public).await?;
+        }
+        update_channel_settings(&pool, "globex", "news_globex", &public).await?;
+
+        let names = |channels: Vec<ChannelSummary>| -> Vec<String> {
+            channels.into_iter().map(|c| c.name).collect()
+        };
+        assert_eq!(
+            names(search_public_channels(&pool, "acme", "News", 10).await?),
+            ["news", "deploys/news", "tech_news"]
+        );
+        assert_eq!(
+            names(search_public_channels(&pool, "acme", "", 2).await?),
+            ["news", "deploys/news"]
+        );
+        assert!(
+            search_public_channels(&pool, "acme", "_globex", 10)
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_list_channels_and_messages(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, "acme", 1, "tech", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, "acme", 2, "tech", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, "acme", 1, "news", DEFAULT_BOT)
+            .await
+            .unwrap();
+        subscribe(&pool, "globex", 1, "ops", DEFAULT_BOT)
+            .await
+            .unwrap();
+
+        let channels = list_channels(&pool, "acme").await.unwrap();
+        let summary: Vec<_> = channels
+            .iter()
+            .map(|c| (c.name.as_str(), c.subscribers))
+            .collect();
+        assert_eq!(summary, [("news", 1), ("tech", 2)]);
+
+        for text in ["First", "Second", "Third"] {
+            create_message(&pool, "acme", Some("tech"), text)
+                .await
+                .unwrap();
+        }
+        let messages = list_messages(&pool, "acme", 2).await.unwrap();
+        let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["Third", "Second"]);
+        assert!(list_messages(&pool, "globex", 10).await.unwrap().is_empty());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_channel_stats(pool: SqlitePool) -> Result<()> {
+        assert!(
+            get_channel_stats(&pool, "acme", "news")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        for telegram_id in [1, 2, 3] {
+            subscribe(&pool, "acme", telegram_id, "news", DEFAULT_BOT)
+                .await
+                .unwrap();
+        }
+        subscribe(&pool, "acme", 4, "tech", DEFAULT_BOT)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE subscriptions SET created_at = unixepoch() - 10 * 86400 WHERE telegram_id = 1"
+        )
+        .execute(&pool)
+        .await?;
+
+        let stats = get_channel_stats(&pool, "acme", "news")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats.subscribers, 3);
+        assert_eq!(stats.new_subscribers_7d, 2);
+        assert_eq!(stats.new_subscribers_30d, 3);
+        assert!(stats.last_message_at.is_none());
+
+        create_message(&pool, "acme", Some("news"), "Hello")
+            .await
+            .unwrap();
+        let stats = get_channel_stats(&pool, "acme", "news")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stats.last_message_at.is_some());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_daily_stats(pool: SqlitePool) -> Result<()> {
+        record_daily_stats(&pool, "acme", Some("news"), 5, 1)
+            .await
+            .unwrap();
+        record_daily_stats(&pool, "acme", Some("news"), 2, 0)
+            .await
+            .unwrap();
+        record_daily_stats(&pool, "acme", None, 10, 0)
+            .await
+            .unwrap();
+        record_daily_stats(&pool, "globex", Some("news"), 1, 0)
+            .await
+            .unwrap();
+
+        let stats = get_daily_stats(&pool, "acme", "2000-01-01", "2999-12-31")
+            .await
+            .unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].channel_name, "");
+        assert_eq!(stats[1].channel_name, "news");
+        assert_eq!((stats[1].sent, stats[1].errors), (7, 1));
+
+        assert!(
+            get_daily_stats(&pool, "acme", "2000-01-01", "2000-12-31")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_recent_sends(pool: SqlitePool) -> Result<()> {
+        subscribe(&pool, "acme", 1, "alerts", DEFAULT_BOT)
+            .await
+            .unwrap();
+        let send = RecentSend {
+            sent: 1,
+            errors: 0,
+            message_id: None,
+        };
+
+        // Not remembered without a dedup window
+        record_recent_send(&pool, "acme", "alerts", "CPU high", &send)
+            .await
+            .unwrap();
+        let settings = ChannelSettings {
+            dedup_window_secs: Some(300),
+            ..Default::default()
+        };
+        update_channel_settings(&pool, "acme", "alerts", &settings)
+            .await
+            .unwrap();
+        assert!(
+            find_recent_send(&pool, "acme", "alerts", "CPU high")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        record_recent_send(&pool, "acme", "alerts", "CPU high", &send)
+            .await
+            .unwrap();
+        let recent = find_recent_send(&pool, "acme", "alerts", "CPU high")
+            .await
+            .unwrap();
+        assert_eq!(recent.map(|r| r.sent), Some(1));
+        assert!(
+            find_recent_send(&pool, "acme", "alerts", "CPU normal")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        sqlx::query!("UPDATE recent_sends SET created_at = created_at - 600")
+            .execute(&pool)
+            .await?;
+        assert!(
+            find_recent_send(&pool, "acme", "alerts", "CPU high")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_message_footer(pool: SqlitePool) -> Result<()> {
+        let id = create_message(&pool, "acme", Some("news"), "Hello").await?;
+        assert_eq!(get_message_footer(&pool, id).await?, None);
+        set_message_footer(&pool, id, "").await?;
+        assert_eq!(get_message_footer(&pool, id).await?.as_deref(), Some(""));
+
+        assert_eq!(get_message_format(&pool, id).await?, None);
+        set_message_format(&pool, id, r#"{"parse_mode":"HTML"}"#).await?;
+        assert_eq!(
+            get_message_format(&pool, id).await?.as_deref(),
+            Some(r#"{"parse_mode":"HTML"}"#)
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_message_copies(pool: SqlitePool) -> Result<()> {
+        let id = create_message(&pool, "acme", Some("news"), "Hello")
+            .await
+            .unwrap();
+        let copies = [
+            MessageCopy {
+                telegram_id: 2,
+                bot: DEFAULT_BOT.to_string(),
+                telegram_message_id: 20,
+            },
+            MessageCopy {
+                telegram_id: 1,
+                bot: DEFAULT_BOT.to_string(),
+                telegram_message_id: 10,
+            },
+        ];
+        record_message_copies(&pool, id, &copies).await.unwrap();
+        update_message_text(&pool, id, "Hello!").await.unwrap();
+
+        let message = find_message(&pool, "acme", id).await.unwrap().unwrap();
+        assert_eq!(message.text, "Hello!");
+        assert_eq!(message.channel_name.as_deref(), Some("news"));
+        assert!(find_message(&pool, "globex", id).await.unwrap().is_none());
+
+        let stored = get_message_copies(&pool, id).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[0].telegram_message_id, 10);
+        assert_eq!(
+            find_message_by_copy(&pool, "acme", DEFAULT_BOT, 2, 20).await?,
+            Some(id)
+        );
+        assert!(
+            find_message_by_copy(&pool, "acme", DEFAULT_BOT, 2, 10)
+                .await?
+                .is_none()
+        );
+        assert!(
+            find_message_by_copy(&pool, "globex", DEFAULT_BOT, 2, 20)
+                .await?
+                .is_none()
+        );
+
+        assert!(!delete_message(&pool, "globex", id).await.unwrap());
+        assert!(delete_message(&pool, "acme", id).await.unwrap());
+        assert!(get_message_copies(&pool, id).await.unwrap().is_empty());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_link_clicks(pool: SqlitePool) -> Result<()> {
+        let links = [
+            ("https://example.com/b".to_string(), "b1".to_string()),
+            ("https://example.com/a".to_string(), "a1".to_string()),
+        ];
+        create_tracked_links(&pool, "acme", &links).await?;
+        // The same URL tracked again by a resumed broadcast
+        create_tracked_links(
+            &pool,
+            "acme",
+            &[("https://example.com/b".into(), "b2".into())],
+        )
+        .await?;
+        let id = create_message(&pool, "acme", None, "Read https://example.com/b").await?;
+        let copies: Vec<_> = (1..=4)
+            .map(|telegram_id| MessageCopy {
+                telegram_id,
+                bot: DEFAULT_BOT.to_string(),
+                telegram_message_id: telegram_id * 10,
+            })
+            .collect();
+        record_message_copies(&pool, id, &copies).await?;
+        attach_tracked_links(&pool, id, ["b1", "a1", "b2"]).await?;
+
+        assert_eq!(
+            record_link_click(&pool, "b1", 1).await?.as_deref(),
+            Some("https://example.com/b")
+        );
+        record_link_click(&pool, "b2", 1).await?;
+        record_link_click(&pool, "b2", 2).await?;
+        record_link_click(&pool, "a1", 2).await?;
+        assert!(record_link_click(&pool, "zz", 1).await?.is_none());
+        // Looking a link up, as for Telegram's previews, isn't a click
+        assert_eq!(
+            get_tracked_link_url(&pool, "a1").await?.as_deref(),
+            Some("https://example.com/a")
+        );
+
+        let clicks = get_message_clicks(&pool, id).await?;
+        assert_eq!((clicks.recipients, clicks.clickers), (4, 2));
+        let links: Vec<_> = clicks
+            .links
+            .iter()
+            .map(|l| (l.url.as_str(), l.clicks, l.clickers))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                ("https://example.com/b", 3, 2),
+                ("https://example.com/a", 1, 1)
+            ]
+        );
+
+        delete_message(&pool, "acme", id).await?;
+        assert!(record_link_click(&pool, "b1", 1).await?.is_none());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_poll_answers(pool: SqlitePool) -> Result<()> {
+        let options = vec!["Yes".to_string(), "No".to_string()];
+        let id = create_poll(&pool, "acme", "team", "Lunch?", &options, false, true)
+            .await
+            .unwrap();
+        record_poll_copies(&pool, id, &[(1, "p1".to_string()), (2, "p2".to_string())])
+            .await
+            .unwrap();
+
+        record_poll_answer(&pool, "p1", 1, &[0]).await.unwrap();
+        record_poll_answer(&pool, "p2", 2, &[1]).await.unwrap();
+        record_poll_answer(&pool, "p2", 2, &[0, 1]).await.unwrap();
+        record_poll_answer(&pool, "p1", 1, &[]).await.unwrap();
+        // Not sent by the proxy
+        record_poll_answer(&pool, "other", 3, &[0]).await.unwrap();
+        assert_eq!(get_poll_answers(&pool, id).await.unwrap(), vec!["[0,1]"]);
+
+        record_poll_state(&pool, "p1", &[2, 1], 2).await.unwrap();
+        let states = get_poll_states(&pool, id).await.unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].voter_counts, "[2,1]");
+        assert_eq!(states[0].total_voters, 2);
+        assert_eq!(count_poll_copies(&pool, id).await.unwrap(), 2);
+
+        let poll = find_poll(&pool, "acme", id).await.unwrap().unwrap();
+        assert_eq!(poll.question, "Lunch?");
+        assert!(!poll.is_anonymous);
+        assert!(find_poll(&pool, "globex", id).await.unwrap().is_none());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_usage_counters(pool: SqlitePool) -> Result<()> {
+        let (key_id, _) = create_api_key(&pool, "acme", "ci", Some(100))
+            .await
+            .unwrap();
+
+        record_usage(&pool, "acme", Some(key_id), 3).await.unwrap();
+        record_usage(&pool, "acme", Some(key_id), 2).await.unwrap();
+        record_usage(&pool, "acme", None, 10).await.unwrap();
+        record_usage(&pool, "globex", None, 7).await.unwrap();
+
+        assert_eq!(get_api_key_usage_today(&pool, key_id).await.unwrap(), 5);
+        assert_eq!(get_tenant_usage_today(&pool, "acme").await.unwrap(), 15);
+        assert_eq!(get_tenant_usage_today(&pool, "initech").await.unwrap(), 0);
+        Ok(())
     }
 
     #[sqlx::test]
     async fn test_empty_channel_returns_empty(pool: SqlitePool) -> Result<()> {
-        let subs = get_subscribers(&pool, "nonexistent").await.unwrap();
+        let subs = stream_subscribers(&pool, DEFAULT_TENANT, "nonexistent", &[])
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
         assert_eq!(subs.len(), 0);
         Ok(())
     }
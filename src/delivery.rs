@@ -0,0 +1,619 @@
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::stream::{BoxStream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, LinkPreviewOptions, MessageId, ParseMode,
+    ReplyParameters,
+};
+use teloxide::{ApiError, RequestError};
+
+use crate::bot::Bots;
+use crate::config::RetryConfig;
+use crate::db::Recipient;
+use crate::lanes::{Lanes, Priority};
+use crate::links::TrackedLinks;
+
+/// Why a recipient didn't get a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// The user blocked the bot, deleted their account or removed the bot from the chat
+    BlockedByUser,
+    ChatNotFound,
+    RateLimited,
+    Network,
+    Other,
+}
+
+impl Failure {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Failure::BlockedByUser => "blocked_by_user",
+            Failure::ChatNotFound => "chat_not_found",
+            Failure::RateLimited => "rate_limited",
+            Failure::Network => "network",
+            Failure::Other => "other",
+        }
+    }
+}
+
+impl FromStr for Failure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        [
+            Failure::BlockedByUser,
+            Failure::ChatNotFound,
+            Failure::RateLimited,
+            Failure::Network,
+            Failure::Other,
+        ]
+        .into_iter()
+        .find(|failure| failure.as_str() == s)
+        .ok_or_else(|| anyhow::anyhow!("Unknown failure '{}'", s))
+    }
+}
+
+impl From<&RequestError> for Failure {
+    fn from(e: &RequestError) -> Self {
+        match e {
+            RequestError::Api(
+                ApiError::BotBlocked
+                | ApiError::UserDeactivated
+                | ApiError::BotKicked
+                | ApiError::BotKickedFromSupergroup
+                | ApiError::BotKickedFromChannel
+                | ApiError::CantInitiateConversation,
+            ) => Failure::BlockedByUser,
+            RequestError::Api(ApiError::ChatNotFound | ApiError::UserNotFound) => {
+                Failure::ChatNotFound
+            }
+            RequestError::RetryAfter(_) => Failure::RateLimited,
+            RequestError::Network(_) | RequestError::Io(_) => Failure::Network,
+            _ => Failure::Other,
+        }
+    }
+}
+
+impl From<RequestError> for Failure {
+    fn from(e: RequestError) -> Self {
+        Failure::from(&e)
+    }
+}
+
+/// Failed telegram_ids a report lists, `errors` still counts every failure
+const MAX_LISTED_FAILURES: usize = 1000;
+
+/// A recipient that wasn't reached, and why
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FailedRecipient {
+    pub telegram_id: i64,
+    pub category: Failure,
+}
+
+#[derive(Default)]
+pub struct DeliveryReport {
+    pub sent: usize,
+    pub errors: usize,
+    /// telegram_ids of the recipients that weren't reached, by reason, at most
+    /// [`MAX_LISTED_FAILURES`] of them
+    pub failures: BTreeMap<Failure, Vec<i64>>,
+    /// The same recipients in the order they failed, at most [`MAX_LISTED_FAILURES`]
+    pub failed_recipients: Vec<FailedRecipient>,
+    /// Stored message the copies were recorded under, used to edit them later
+    pub message_id: Option<i64>,
+    /// Result of an identical earlier send, nothing was delivered this time
+    pub duplicate: bool,
+}
+
+impl DeliveryReport {
+    pub fn record(&mut self, telegram_id: i64, failure: Option<Failure>) {
+        match failure {
+            None => self.sent += 1,
+            Some(failure) => {
+                self.errors += 1;
+                if self.listed_failures() < MAX_LISTED_FAILURES {
+                    self.failures.entry(failure).or_default().push(telegram_id);
+                }
+                if self.failed_recipients.len() < MAX_LISTED_FAILURES {
+                    self.failed_recipients.push(FailedRecipient {
+                        telegram_id,
+                        category: failure,
+                    });
+                }
+            }
+        }
+    }
+
+    fn listed_failures(&self) -> usize {
+        self.failures.values().map(Vec::len).sum()
+    }
+
+    /// Adds the counts of a report on other recipients of the same message
+    pub fn merge(&mut self, other: DeliveryReport) {
+        self.sent += other.sent;
+        self.errors += other.errors;
+        let room = MAX_LISTED_FAILURES - self.failed_recipients.len();
+        self.failed_recipients
+            .extend(other.failed_recipients.into_iter().take(room));
+        for (failure, telegram_ids) in other.failures {
+            let room = MAX_LISTED_FAILURES - self.listed_failures();
+            if room == 0 {
+                break;
+            }
+            self.failures
+                .entry(failure)
+                .or_default()
+                .extend(telegram_ids.into_iter().take(room));
+        }
+    }
+}
+
+/// Telegram send parameters chosen per request, unset ones falling back to the
+/// channel's defaults
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SendOptions {
+    /// Formatting of the message text
+    pub parse_mode: Option<ParseMode>,
+    /// Deliver without a notification sound
+    pub silent: Option<bool>,
+    /// Keep recipients from forwarding or saving the message
+    pub protect_content: Option<bool>,
+    /// How links in the message are previewed
+    pub link_preview: Option<LinkPreviewOptions>,
+    /// Stored message whose copies the new message replies to
+    pub reply_to: Option<i64>,
+    /// Lane the message waits in for send capacity, transactional when unset
+    pub priority: Option<Priority>,
+    /// Render the message per recipient as a template of their stored metadata
+    #[serde(default)]
+    pub personalize: bool,
+    /// Rewrite the message's links to record who clicks them
+    #[serde(default)]
+    pub track_clicks: bool,
+    /// Rows of inline buttons under the message, presses are posted to the button callback
+    #[serde(default)]
+    pub buttons: Vec<Vec<CallbackButton>>,
+    /// Footer template replacing the channel's, an empty one leaving the footer out
+    pub footer: Option<String>,
+}
+
+/// Inline button sending `data` back to the bot when pressed
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CallbackButton {
+    pub text: String,
+    /// 1 to [`MAX_CALLBACK_DATA_BYTES`] bytes
+    pub data: String,
+}
+
+/// Longest callback data Telegram accepts, in bytes
+pub const MAX_CALLBACK_DATA_BYTES: usize = 64;
+
+/// Inline keyboard of `buttons`, `None` without any
+pub fn keyboard(buttons: &[Vec<CallbackButton>]) -> Option<InlineKeyboardMarkup> {
+    if buttons.iter().all(Vec::is_empty) {
+        return None;
+    }
+    Some(InlineKeyboardMarkup::new(buttons.iter().map(|row| {
+        row.iter()
+            .map(|button| InlineKeyboardButton::callback(&button.text, &button.data))
+    })))
+}
+
+/// Longest message text accepted, in the units of [`text_len`]
+pub const MAX_MESSAGE_CHARS: usize = 1000;
+
+/// Length of `text` the way Telegram counts it, in UTF-16 code units, so an emoji
+/// outside the BMP counts twice and a CJK character once
+pub fn text_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Preview options that only turn the preview on or off
+pub fn link_preview(disabled: bool) -> LinkPreviewOptions {
+    LinkPreviewOptions {
+        is_disabled: disabled,
+        url: None,
+        prefer_small_media: false,
+        prefer_large_media: false,
+        show_above_text: false,
+    }
+}
+
+/// `message` with the header above and the footer below it, each set apart by a blank line
+pub fn frame(header: Option<&str>, message: &str, footer: Option<&str>) -> String {
+    [header, Some(message), footer]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Recipients of a send, counted up front and streamed as they're delivered to, so a
+/// large audience is never held in memory at once
+pub struct Recipients {
+    pub total: usize,
+    stream: BoxStream<'static, Recipient>,
+}
+
+impl Recipients {
+    /// Recipients read from the database. An error ends the stream, leaving out the
+    /// recipients after it.
+    pub fn from_db(
+        total: usize,
+        stream: impl Stream<Item = anyhow::Result<Recipient>> + Send + 'static,
+    ) -> Self {
+        let stream = stream.filter_map(|recipient| async move {
+            recipient
+                .inspect_err(|e| log::error!("Database error: {}", e))
+                .ok()
+        });
+        Self {
+            total,
+            stream: stream.boxed(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+}
+
+impl From<Vec<Recipient>> for Recipients {
+    fn from(recipients: Vec<Recipient>) -> Self {
+        Self {
+            total: recipients.len(),
+            stream: futures::stream::iter(recipients).boxed(),
+        }
+    }
+}
+
+impl Stream for Recipients {
+    type Item = Recipient;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Recipient>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+/// Sends `message` to every recipient concurrently, each through the bot they subscribed with
+pub async fn deliver(
+    bots: &Bots,
+    lanes: &Lanes,
+    retry: &RetryConfig,
+    recipients: impl Stream<Item = Recipient>,
+    message: &str,
+) -> DeliveryReport {
+    deliver_each(
+        bots,
+        lanes,
+        retry,
+        recipients,
+        message,
+        &SendOptions::default(),
+        &PerChat::default(),
+    )
+    .fold(
+        DeliveryReport::default(),
+        |mut report, (recipient, sent)| async move {
+            report.record(recipient.telegram_id, sent.err());
+            report
+        },
+    )
+    .await
+}
+
+/// What differs between the recipients of a message, by chat
+#[derive(Default)]
+pub struct PerChat {
+    /// Telegram message id each recipient gets the message as a reply to
+    pub replies: HashMap<i64, MessageId>,
+    /// Stored metadata the message is [`personalize`]d with, when personalizing
+    pub metadata: Option<HashMap<i64, Value>>,
+    /// Links rewritten per recipient, when tracking clicks
+    pub links: Option<TrackedLinks>,
+}
+
+/// Like [`deliver`], yielding each recipient as it completes with the id of the
+/// Telegram message it got, or why delivery failed, varying the message by `per_chat`.
+/// Each send waits for capacity in the lane of `options.priority`.
+pub fn deliver_each<'a>(
+    bots: &'a Bots,
+    lanes: &'a Lanes,
+    retry: &'a RetryConfig,
+    recipients: impl Stream<Item = Recipient> + 'a,
+    message: &'a str,
+    options: &'a SendOptions,
+    per_chat: &'a PerChat,
+) -> impl Stream<Item = (Recipient, Result<MessageId, Failure>)> + 'a {
+    let priority = options.priority.unwrap_or_default();
+    send_each(
+        bots,
+        lanes,
+        retry,
+        priority,
+        recipients,
+        move |bot, chat_id| async move {
+            let mut text = match &per_chat.metadata {
+                Some(metadata) => personalize(message, chat_id.0, metadata),
+                None => message.to_string(),
+            };
+            if let Some(links) = &per_chat.links {
+                text = links.apply(&text, options.parse_mode, chat_id.0);
+            }
+            let mut request = bot
+                .send_message(chat_id, text)
+                .disable_notification(options.silent.unwrap_or(false))
+                .protect_content(options.protect_content.unwrap_or(false));
+            if let Some(parse_mode) = options.parse_mode {
+                request = request.parse_mode(parse_mode);
+            }
+            if let Some(link_preview) = &options.link_preview {
+                request = request.link_preview_options(link_preview.clone());
+            }
+            if let Some(keyboard) = keyboard(&options.buttons) {
+                request = request.reply_markup(keyboard);
+            }
+            if let Some(&reply_to) = per_chat.replies.get(&chat_id.0) {
+                // Still delivered if the recipient deleted the original
+                request = request
+                    .reply_parameters(ReplyParameters::new(reply_to).allow_sending_without_reply());
+            }
+            Ok(request.await?.id)
+        },
+    )
+}
+
+/// `message` rendered as a Handlebars template for one recipient, given their
+/// `telegram_id` and `metadata` (an empty object when none is stored). Sent as is if
+/// it doesn't render.
+pub fn personalize(message: &str, telegram_id: i64, metadata: &HashMap<i64, Value>) -> String {
+    let data = serde_json::json!({
+        "telegram_id": telegram_id,
+        "metadata": metadata.get(&telegram_id).unwrap_or(&serde_json::json!({})),
+    });
+    crate::hooks::render(message, &data).unwrap_or_else(|e| {
+        log::error!("Failed to personalize a message for {}: {}", telegram_id, e);
+        message.to_string()
+    })
+}
+
+/// Runs `send` again for the failures `retry` allows, up to its `max_attempts` times.
+/// Each attempt holds a slot in the `priority` lane, released while waiting to retry.
+async fn with_retries<T, Fut>(
+    retry: &RetryConfig,
+    lanes: &Lanes,
+    priority: Priority,
+    send: impl Fn() -> Fut,
+) -> Result<T, RequestError>
+where
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let permit = lanes.acquire(priority).await;
+        let sent = send().await;
+        drop(permit);
+        match sent {
+            Err(e)
+                if attempt < retry.max_attempts && retry.retry_on.contains(&Failure::from(&e)) =>
+            {
+                tokio::time::sleep(retry_delay(retry, attempt, &e)).await
+            }
+            result => return result,
+        }
+        attempt += 1;
+    }
+}
+
+/// How long to wait after the `attempt`th failed with `error`: as long as Telegram asks
+/// when rate limited, otherwise the base delay doubled for each earlier attempt, plus jitter
+fn retry_delay(retry: &RetryConfig, attempt: u32, error: &RequestError) -> Duration {
+    let wait = match error {
+        RequestError::RetryAfter(wait) => wait.duration(),
+        _ => Duration::from_millis(
+            retry
+                .base_delay_ms
+                .saturating_mul(1 << (attempt - 1).min(16)),
+        ),
+    };
+    wait + Duration::from_millis(rand::random_range(0..=retry.jitter_ms))
+}
+
+/// Runs `send` concurrently for every recipient with the bot they subscribed with,
+/// yielding each as it completes with what `send` returned. Recipients are taken in
+/// order, no more at a time than `lanes` has capacity for, so dropping the stream
+/// leaves the rest untouched. Each send is attempted as many times as `retry` allows,
+/// every attempt first waiting for a slot in the `priority` lane.
+pub fn send_each<'a, T, F, Fut>(
+    bots: &'a Bots,
+    lanes: &'a Lanes,
+    retry: &'a RetryConfig,
+    priority: Priority,
+    recipients: impl Stream<Item = Recipient> + 'a,
+    send: F,
+) -> impl Stream<Item = (Recipient, Result<T, Failure>)> + 'a
+where
+    F: Fn(Bot, ChatId) -> Fut + Clone + 'a,
+    Fut: Future<Output = Result<T, RequestError>> + 'a,
+    T: 'a,
+{
+    recipients
+        .map(move |recipient| {
+            let bot = bots.get(&recipient.bot).cloned();
+            if bot.is_none() {
+                log::error!("Bot '{}' is not configured", recipient.bot);
+            }
+            let send = send.clone();
+            async move {
+                let sent = match bot {
+                    Some(bot) => {
+                        let chat_id = ChatId(recipient.telegram_id);
+                        with_retries(retry, lanes, priority, || send(bot.clone(), chat_id))
+                            .await
+                            .map_err(Failure::from)
+                    }
+                    None => Err(Failure::Other),
+                };
+                (recipient, sent)
+            }
+        })
+        .buffer_unordered(lanes.capacity())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_len() {
+        assert_eq!(text_len(""), 0);
+        assert_eq!(text_len("hello"), 5);
+        assert_eq!(text_len("caffè"), 5);
+        assert_eq!(text_len("你好世界"), 4);
+        assert_eq!(text_len("👍"), 2);
+        assert_eq!(text_len(&"你".repeat(MAX_MESSAGE_CHARS)), MAX_MESSAGE_CHARS);
+    }
+
+    #[test]
+    fn test_personalize() {
+        let metadata = HashMap::from([(111, serde_json::json!({ "team": "payments" }))]);
+        let message = "Hi {{metadata.team}} ({{telegram_id}})";
+        assert_eq!(personalize(message, 111, &metadata), "Hi payments (111)");
+        assert_eq!(personalize(message, 222, &metadata), "Hi  (222)");
+        assert_eq!(personalize("{{#if}}", 111, &metadata), "{{#if}}");
+    }
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(frame(None, "Disk full", None), "Disk full");
+        assert_eq!(
+            frame(Some("🚨 Ops"), "Disk full", Some("— /unsubscribe ops")),
+            "🚨 Ops\n\nDisk full\n\n— /unsubscribe ops"
+        );
+        assert_eq!(frame(Some(" "), "Disk full", None), "Disk full");
+    }
+
+    #[test]
+    fn test_failures() {
+        assert_eq!(
+            Failure::from(RequestError::Api(ApiError::BotBlocked)),
+            Failure::BlockedByUser
+        );
+        assert_eq!(
+            Failure::from(RequestError::Api(ApiError::ChatNotFound)),
+            Failure::ChatNotFound
+        );
+        assert_eq!(
+            Failure::from(RequestError::RetryAfter(
+                teloxide::types::Seconds::from_seconds(5)
+            )),
+            Failure::RateLimited
+        );
+
+        let mut report = DeliveryReport::default();
+        report.record(1, None);
+        report.record(2, Some(Failure::BlockedByUser));
+        let mut other = DeliveryReport::default();
+        other.record(3, Some(Failure::BlockedByUser));
+        report.merge(other);
+        assert_eq!((report.sent, report.errors), (1, 2));
+        assert_eq!(report.failures[&Failure::BlockedByUser], vec![2, 3]);
+        assert_eq!(
+            report.failed_recipients,
+            [2, 3].map(|telegram_id| FailedRecipient {
+                telegram_id,
+                category: Failure::BlockedByUser
+            })
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let retry = RetryConfig {
+            jitter_ms: 0,
+            ..Default::default()
+        };
+        let io = || RequestError::Io(std::io::Error::other("reset").into());
+        assert_eq!(retry_delay(&retry, 1, &io()), Duration::from_secs(1));
+        assert_eq!(retry_delay(&retry, 3, &io()), Duration::from_secs(4));
+        let rate_limited = RequestError::RetryAfter(teloxide::types::Seconds::from_seconds(7));
+        assert_eq!(
+            retry_delay(&retry, 1, &rate_limited),
+            Duration::from_secs(7)
+        );
+
+        let retry = RetryConfig::default();
+        let delay = retry_delay(&retry, 1, &io());
+        assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_millis(1250));
+    }
+
+    #[test]
+    fn test_failure_from_str() {
+        assert_eq!(
+            "chat_not_found".parse::<Failure>().unwrap(),
+            Failure::ChatNotFound
+        );
+        assert!("sometimes".parse::<Failure>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retry_releases_lane() {
+        let retry = RetryConfig {
+            max_attempts: 2,
+            base_delay_ms: 200,
+            jitter_ms: 0,
+            ..Default::default()
+        };
+        let lanes = Lanes::new(1);
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let retried = with_retries(&retry, &lanes, Priority::Bulk, || async {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err(RequestError::Io(std::io::Error::other("reset").into())),
+                _ => Ok(()),
+            }
+        });
+        let other = async {
+            // Once the first attempt failed, its slot is free during the wait
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            tokio::time::timeout(
+                Duration::from_millis(100),
+                lanes.acquire(Priority::Transactional),
+            )
+            .await
+            .is_ok()
+        };
+        let (retried, other_sent) = tokio::join!(retried, other);
+        assert!(retried.is_ok());
+        assert!(other_sent);
+        assert_eq!(attempts.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_failures_capped() {
+        let mut report = DeliveryReport::default();
+        for telegram_id in 0..MAX_LISTED_FAILURES as i64 + 5 {
+            report.record(telegram_id, Some(Failure::Network));
+        }
+        let mut other = DeliveryReport::default();
+        other.record(-1, Some(Failure::ChatNotFound));
+        report.merge(other);
+
+        assert_eq!(report.errors, MAX_LISTED_FAILURES + 6);
+        assert_eq!(
+            report.failures[&Failure::Network].len(),
+            MAX_LISTED_FAILURES
+        );
+        assert!(!report.failures.contains_key(&Failure::ChatNotFound));
+        assert_eq!(report.failed_recipients.len(), MAX_LISTED_FAILURES);
+        assert_eq!(report.failed_recipients[0].telegram_id, 0);
+    }
+}
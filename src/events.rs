@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Real-time notifications for `/ws` listeners; nothing is persisted or replayed
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    pub tenant_id: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    Subscribed {
+        telegram_id: i64,
+        channel_name: String,
+    },
+    Unsubscribed {
+        telegram_id: i64,
+        channel_name: String,
+    },
+    Delivery {
+        telegram_id: i64,
+        /// `None` for broadcasts
+        channel_name: Option<String>,
+        delivered: bool,
+    },
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(1024);
+        Self { sender }
+    }
+
+    pub fn publish(&self, tenant_id: &str, kind: EventKind) {
+        // Fails only when nobody is listening
+        let _ = self.sender.send(Event {
+            tenant_id: tenant_id.to_string(),
+            timestamp: Utc::now(),
+            kind,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json_shape() {
+        let bus = EventBus::new();
+        let mut rx = bus.subscribe();
+        bus.publish(
+            "acme",
+            EventKind::Subscribed {
+                telegram_id: 42,
+                channel_name: "news".to_string(),
+            },
+        );
+
+        let json = serde_json::to_value(rx.try_recv().unwrap()).unwrap();
+        assert_eq!(json["type"], "subscribed");
+        assert_eq!(json["tenant_id"], "acme");
+        assert_eq!(json["telegram_id"], 42);
+        assert_eq!(json["channel_name"], "news");
+    }
+
+    #[test]
+    fn test_publish_without_listeners() {
+        EventBus::new().publish(
+            "acme",
+            EventKind::Unsubscribed {
+                telegram_id: 42,
+                channel_name: "news".to_string(),
+            },
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Polls registered RSS/Atom feeds and posts new entries to their channel
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::db::Feed;
+use crate::service::{Services, Tenant};
+
+/// Polls every feed each `interval`, forever
+pub async fn run(services: Services, interval: Duration) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("HTTP client should build");
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        let feeds = match crate::db::list_all_feeds(&services.pool).await {
+            Ok(feeds) => feeds,
+            Err(e) => {
+                log::error!("Database error: {}", e);
+                continue;
+            }
+        };
+
+        for feed in feeds {
+            if let Err(e) = poll(&services, &client, &feed).await {
+                log::warn!("Polling feed {} ({}) failed: {}", feed.id, feed.url, e);
+            }
+        }
+    }
+}
+
+/// Posts entries not seen before; the first poll only records what's already there
+async fn poll(services: &Services, client: &reqwest::Client, feed: &Feed) -> Result<()> {
+    let body = client
+        .get(&feed.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let parsed = feed_rs::parser::parse(&body[..])?;
+    let first_poll = feed.last_polled_at.is_none();
+
+    // Feeds list the newest entry first
+    for entry in parsed.entries.iter().rev() {
+        let unseen = crate::db::mark_feed_entry_seen(&services.pool, feed.id, &entry.id).await?;
+        if !unseen || first_poll {
+            continue;
+        }
+
+        let title = entry
+            .title
+            .as_ref()
+            .map(|t| t.content.as_str())
+            .unwrap_or("New entry");
+        let message = match entry.links.first() {
+            Some(link) => format!("{}\n{}", title, link.href),
+            None => title.to_string(),
+        };
+        if let Err(e) = services
+            .send_message(&Tenant::new(&feed.tenant_id), &feed.channel_name, &message)
+            .await
+        {
+            log::warn!("Posting entry of feed {} failed: {}", feed.id, e);
+        }
+    }
+
+    crate::db::mark_feed_polled(&services.pool, feed.id).await
+}
@@ -0,0 +1,227 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use anyhow::Result;
+use futures::Stream;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Response, Status};
+
+use crate::delivery::{Failure, SendOptions};
+use crate::service::{ServiceError, Services, Tenant};
+
+pub mod proto {
+    tonic::include_proto!("telegram_proxy.v1");
+}
+
+use proto::telegram_proxy_server::{TelegramProxy, TelegramProxyServer};
+
+pub async fn serve(addr: SocketAddr, services: Services) -> Result<()> {
+    log::info!("Starting gRPC server on {}", addr);
+
+    tonic::transport::Server::builder()
+        .add_service(TelegramProxyServer::new(GrpcService { services }))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
+
+struct GrpcService {
+    services: Services,
+}
+
+impl GrpcService {
+    async fn tenant<T>(&self, request: &Request<T>) -> Result<Tenant, Status> {
+        let metadata = request.metadata();
+        let bearer_token = metadata
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let tenant_header = metadata.get("x-tenant-id").and_then(|v| v.to_str().ok());
+
+        self.services
+            .authenticate(bearer_token, tenant_header)
+            .await
+            .map_err(status)
+    }
+
+    async fn admin<T>(&self, request: &Request<T>) -> Result<Tenant, Status> {
+        let tenant = self.tenant(request).await?;
+        if !tenant.is_admin() {
+            return Err(Status::permission_denied("Requires the admin role"));
+        }
+        Ok(tenant)
+    }
+}
+
+/// Maps a service error to a status, carrying its [`ErrorCode`](crate::service::ErrorCode) in the `x-error-code`
+/// metadata
+fn status(e: ServiceError) -> Status {
+    let code = e.code();
+    let mut status = match e {
+        ServiceError::InvalidRequest(_, message) => Status::invalid_argument(message),
+        ServiceError::Unauthorized => Status::unauthenticated("Invalid or missing authorization"),
+        ServiceError::NotFound(_, message) => Status::not_found(message),
+        ServiceError::QuotaExceeded { .. } | ServiceError::Telegram(Failure::RateLimited) => {
+            Status::resource_exhausted(e.to_string())
+        }
+        ServiceError::Telegram(Failure::Network) => Status::unavailable(e.to_string()),
+        ServiceError::Telegram(_) => Status::unknown(e.to_string()),
+        ServiceError::Database(e) => {
+            log::error!("Database error: {}", e);
+            Status::internal("Database error occurred")
+        }
+    };
+    status
+        .metadata_mut()
+        .insert("x-error-code", MetadataValue::from_static(code.as_str()));
+    status
+}
+
+type DeliveryStatusStream =
+    Pin<Box<dyn Stream<Item = Result<proto::DeliveryStatus, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl TelegramProxy for GrpcService {
+    async fn send_message(
+        &self,
+        request: Request<proto::SendMessageRequest>,
+    ) -> Result<Response<proto::SendMessageResponse>, Status> {
+        let tenant = self.tenant(&request).await?;
+        let req = request.into_inner();
+
+        let report = self
+            .services
+            .send_message(&tenant, &req.channel_name, &req.message)
+            .await
+            .map_err(status)?;
+
+        Ok(Response::new(proto::SendMessageResponse {
+            sent: report.sent as u64,
+            errors: report.errors as u64,
+            channel: req.channel_name,
+        }))
+    }
+
+    async fn broadcast(
+        &self,
+        request: Request<proto::BroadcastRequest>,
+    ) -> Result<Response<proto::BroadcastResponse>, Status> {
+        let tenant = self.admin(&request).await?;
+        let req = request.into_inner();
+
+        let subscribers = self
+            .services
+            .prepare_broadcast(
+                &tenant,
+                &req.message,
+                Some(req.channels.as_slice()).filter(|channels| !channels.is_empty()),
+            )
+            .await
+            .map_err(status)?;
+        let total_subscribers = subscribers.total as u64;
+        let report = self
+            .services
+            .deliver(
+                &tenant,
+                None,
+                subscribers,
+                &req.message,
+                &SendOptions::default(),
+                |_, _, _| {},
+            )
+            .await;
+
+        Ok(Response::new(proto::BroadcastResponse {
+            sent: report.sent as u64,
+            errors: report.errors as u64,
+            total_subscribers,
+        }))
+    }
+
+    async fn list_subscriptions(
+        &self,
+        request: Request<proto::ListSubscriptionsRequest>,
+    ) -> Result<Response<proto::ListSubscriptionsResponse>, Status> {
+        let tenant = self.admin(&request).await?;
+
+        let subscriptions = self
+            .services
+            .list_subscriptions(&tenant)
+            .await
+            .map_err(status)?
+            .into_iter()
+            .map(|r| proto::Subscription {
+                telegram_id: r.telegram_id,
+                channel_name: r.channel_name,
+                created_at: r.created_at,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Response::new(proto::ListSubscriptionsResponse {
+            total: subscriptions.len() as u64,
+            subscriptions,
+        }))
+    }
+
+    type SendMessageWithStatusStream = DeliveryStatusStream;
+
+    async fn send_message_with_status(
+        &self,
+        request: Request<proto::SendMessageRequest>,
+    ) -> Result<Response<Self::SendMessageWithStatusStream>, Status> {
+        let tenant = self.tenant(&request).await?;
+        let req = request.into_inner();
+
+        let channel_name =
+            crate::db::resolve_channel_name(&self.services.pool, &tenant.id, &req.channel_name)
+                .await
+                .map_err(|e| status(e.into()))?;
+        let subscribers = self
+            .services
+            .prepare_send(&tenant, &channel_name, &req.message)
+            .await
+            .map_err(status)?;
+        let (message, options) = self
+            .services
+            .apply_channel_settings(
+                &tenant,
+                &channel_name,
+                &req.message,
+                &SendOptions::default(),
+            )
+            .await
+            .map_err(status)?;
+
+        // Unbounded so reporting never holds up delivery, at most one status per subscriber
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let services = self.services.clone();
+        let show_recipients = tenant.is_authenticated();
+
+        tokio::spawn(async move {
+            let total = subscribers.total as u64;
+            services
+                .deliver(
+                    &tenant,
+                    Some(&channel_name),
+                    subscribers,
+                    &message,
+                    &options,
+                    |recipient, delivered, report| {
+                        // Delivery carries on even if the client stops listening
+                        let _ = tx.send(Ok(proto::DeliveryStatus {
+                            telegram_id: Some(recipient.telegram_id).filter(|_| show_recipients),
+                            delivered,
+                            sent: report.sent as u64,
+                            errors: report.errors as u64,
+                            total,
+                        }));
+                    },
+                )
+                .await;
+        });
+
+        Ok(Response::new(Box::pin(UnboundedReceiverStream::new(rx))))
+    }
+}
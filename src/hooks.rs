@@ -0,0 +1,83 @@
+//! Inbound webhooks whose JSON body is rendered into a message by a stored template
+
+use std::collections::BTreeMap;
+
+use actix_web::{HttpResponse, post, web};
+use handlebars::Handlebars;
+use serde_json::Value;
+
+use crate::api::{ApiError, Result};
+use crate::service::{ErrorCode, Services, Tenant};
+
+/// Renders without HTML escaping, messages are plain text
+pub fn render(template: &str, data: &Value) -> Result<String, handlebars::RenderError> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.render_template(template, data)
+}
+
+/// Compile error of the template, if any
+pub fn validate(template: &str) -> Option<String> {
+    handlebars::Template::compile(template)
+        .err()
+        .map(|e| e.to_string())
+}
+
+#[post("/hooks/{id}")]
+pub async fn receive(
+    id: web::Path<String>,
+    payload: web::Json<Value>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let Some(hook) = crate::db::find_hook(&services.pool, &id).await? else {
+        return Err(ApiError::NotFound(
+            ErrorCode::HookNotFound,
+            "Hook not found",
+        ));
+    };
+
+    let rendered = render(&hook.channel, &payload)
+        .and_then(|channel| Ok((channel, render(&hook.template, &payload)?)));
+    let (channel_name, message) = match rendered {
+        Ok((channel, message)) => (channel.trim().to_string(), message),
+        Err(e) => {
+            return Err(ApiError::BadRequest(
+                ErrorCode::TemplateFailed,
+                format!("Failed to render template: {}", e),
+            ));
+        }
+    };
+
+    let tenant = Tenant::new(&hook.tenant_id);
+    let messages = BTreeMap::from([(channel_name, message)]);
+    crate::integrations::send_all(&services, &tenant, messages).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let data = serde_json::json!({
+            "build": {"status": "failed", "branch": "main"},
+            "steps": [{"name": "lint"}, {"name": "test"}],
+        });
+
+        assert_eq!(
+            render(
+                "Build on {{build.branch}} {{build.status}}: {{#each steps}}{{name}} {{/each}}",
+                &data
+            )
+            .unwrap(),
+            "Build on main failed: lint test "
+        );
+        assert_eq!(render("<{{build.branch}}>", &data).unwrap(), "<main>");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("{{build.status}}").is_none());
+        assert!(validate("{{#if build}}unclosed").is_some());
+    }
+}
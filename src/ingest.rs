@@ -0,0 +1,114 @@
+//! Messages arriving from message brokers rather than the HTTP API
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::delivery::DeliveryReport;
+use crate::service::{ServiceError, Services, Tenant};
+
+/// Payload of a broker record, mirroring `POST /send-message`
+#[derive(Debug, Deserialize)]
+#[cfg_attr(not(any(feature = "kafka", feature = "amqp")), allow(dead_code))]
+pub struct IngestRecord {
+    pub channel: String,
+    pub message: String,
+}
+
+/// Starts a consumer for every configured broker, failing if its feature wasn't built
+pub fn spawn_consumers(config: &Config, services: &Services) -> Result<()> {
+    #[cfg(feature = "kafka")]
+    if let Some(kafka) = &config.kafka {
+        spawn_consumer(
+            "Kafka",
+            crate::kafka::consume(kafka.clone(), services.clone()),
+        );
+    }
+    #[cfg(not(feature = "kafka"))]
+    if config.kafka.is_some() {
+        anyhow::bail!("Kafka is configured but the binary was built without the `kafka` feature");
+    }
+
+    #[cfg(feature = "amqp")]
+    if let Some(amqp) = &config.amqp {
+        spawn_consumer("AMQP", crate::amqp::consume(amqp.clone(), services.clone()));
+    }
+    #[cfg(not(feature = "amqp"))]
+    if config.amqp.is_some() {
+        anyhow::bail!("AMQP is configured but the binary was built without the `amqp` feature");
+    }
+
+    #[cfg(feature = "redis")]
+    if let Some(redis) = &config.redis_bridge {
+        spawn_consumer(
+            "Redis",
+            crate::redis_bridge::consume(redis.clone(), services.clone()),
+        );
+    }
+    #[cfg(not(feature = "redis"))]
+    if config.redis_bridge.is_some() {
+        anyhow::bail!(
+            "Redis bridge is configured but the binary was built without the `redis` feature"
+        );
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = &config.mqtt {
+        spawn_consumer("MQTT", crate::mqtt::consume(mqtt.clone(), services.clone()));
+    }
+    #[cfg(not(feature = "mqtt"))]
+    if config.mqtt.is_some() {
+        anyhow::bail!("MQTT is configured but the binary was built without the `mqtt` feature");
+    }
+
+    // Unused when built without any broker feature
+    let _ = services;
+    Ok(())
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "kafka",
+        feature = "amqp",
+        feature = "redis",
+        feature = "mqtt"
+    )),
+    allow(dead_code)
+)]
+fn spawn_consumer(name: &'static str, consumer: impl Future<Output = Result<()>> + Send + 'static) {
+    tokio::spawn(async move {
+        if let Err(e) = consumer.await {
+            log::error!("{} consumer error: {}", name, e);
+            std::process::exit(1);
+        }
+    });
+}
+
+/// Parses a JSON record and delivers it to the channel's subscribers in `tenant_id`
+#[cfg_attr(not(any(feature = "kafka", feature = "amqp")), allow(dead_code))]
+pub async fn deliver_record(
+    services: &Services,
+    tenant_id: &str,
+    payload: &[u8],
+) -> Result<DeliveryReport, ServiceError> {
+    let record: IngestRecord = serde_json::from_slice(payload)
+        .map_err(|_| ServiceError::invalid("Record must be JSON with channel and message"))?;
+    services
+        .send_message(&Tenant::new(tenant_id), &record.channel, &record.message)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_record() {
+        let record: IngestRecord =
+            serde_json::from_str(r#"{"channel": "alerts", "message": "disk full"}"#).unwrap();
+        assert_eq!(record.channel, "alerts");
+        assert_eq!(record.message, "disk full");
+
+        assert!(serde_json::from_str::<IngestRecord>(r#"{"channel": "alerts"}"#).is_err());
+    }
+}
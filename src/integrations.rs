@@ -0,0 +1,94 @@
+//! Endpoints turning third-party webhook payloads into channel messages
+
+pub mod alertmanager;
+pub mod github;
+pub mod grafana;
+pub mod sentry;
+pub mod slack;
+
+use std::collections::BTreeMap;
+
+use actix_web::HttpResponse;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::api::Result;
+use crate::delivery::{MAX_MESSAGE_CHARS, text_len};
+use crate::service::{Services, Tenant};
+
+#[derive(Serialize)]
+struct ChannelReport {
+    sent: usize,
+    errors: usize,
+}
+
+/// Sends each channel its message, answering with the per-channel delivery report
+pub async fn send_all(
+    services: &Services,
+    tenant: &Tenant,
+    messages: BTreeMap<String, String>,
+) -> Result<HttpResponse> {
+    let mut channels = BTreeMap::new();
+    for (channel_name, message) in messages {
+        let message = truncate(&message, MAX_MESSAGE_CHARS);
+        let report = services
+            .send_message(tenant, &channel_name, &message)
+            .await?;
+        channels.insert(
+            channel_name,
+            ChannelReport {
+                sent: report.sent,
+                errors: report.errors,
+            },
+        );
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "channels": channels })))
+}
+
+/// Checks a hex HMAC-SHA256 of the raw body in constant time
+fn verify_hmac(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(expected) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Cuts `text` to at most `max` chars as Telegram counts them, ending with an ellipsis
+/// if cut
+fn truncate(text: &str, max: usize) -> String {
+    if text_len(text) <= max {
+        return text.to_string();
+    }
+
+    let mut len = '…'.len_utf16();
+    let mut cut: String = text
+        .chars()
+        .take_while(|c| {
+            len += c.len_utf16();
+            len <= max
+        })
+        .collect();
+    cut.push('…');
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("abcdefghijkl", 10), "abcdefghi…");
+        assert_eq!(truncate(&"é".repeat(600), 1000), "é".repeat(600));
+        assert_eq!(
+            truncate(&"👍".repeat(600), 1000),
+            format!("{}…", "👍".repeat(499))
+        );
+    }
+}
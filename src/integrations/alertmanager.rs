@@ -0,0 +1,153 @@
+//! Prometheus Alertmanager webhook receiver
+
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{HttpResponse, post, web};
+use serde::Deserialize;
+
+use crate::api::Result;
+use crate::config::AlertmanagerConfig;
+use crate::service::{Services, Tenant};
+
+/// Alertmanager's webhook body, only the fields we render
+#[derive(Deserialize)]
+pub struct Notification {
+    alerts: Vec<Alert>,
+}
+
+#[derive(Deserialize)]
+struct Alert {
+    status: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(rename = "generatorURL", default)]
+    generator_url: String,
+}
+
+#[post("/integrations/alertmanager")]
+pub async fn receive(
+    tenant: Tenant,
+    notification: web::Json<Notification>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let messages = route(&services.config.integrations.alertmanager, &notification);
+    super::send_all(&services, &tenant, messages).await
+}
+
+/// Alerts grouped into one message per channel, picked by the configured label
+fn route(config: &AlertmanagerConfig, notification: &Notification) -> BTreeMap<String, String> {
+    let mut messages: BTreeMap<String, String> = BTreeMap::new();
+    for alert in &notification.alerts {
+        let Some(channel_name) = alert
+            .labels
+            .get(&config.channel_label)
+            .or(config.default_channel.as_ref())
+        else {
+            log::warn!(
+                "Dropping alert without a '{}' label and no default channel",
+                config.channel_label
+            );
+            continue;
+        };
+
+        let message = messages.entry(channel_name.clone()).or_default();
+        if !message.is_empty() {
+            message.push_str("\n\n");
+        }
+        message.push_str(&format_alert(alert));
+    }
+    messages
+}
+
+fn format_alert(alert: &Alert) -> String {
+    let resolved = alert.status == "resolved";
+    let severity = alert.labels.get("severity").map(String::as_str);
+    let emoji = match (resolved, severity) {
+        (true, _) => "✅",
+        (false, Some("critical")) => "🔴",
+        (false, Some("warning")) => "🟠",
+        (false, Some("info")) => "🔵",
+        (false, _) => "🔥",
+    };
+    let name = alert
+        .labels
+        .get("alertname")
+        .map(String::as_str)
+        .unwrap_or("Alert");
+
+    let mut message = format!(
+        "{} [{}] {}",
+        emoji,
+        if resolved { "RESOLVED" } else { "FIRING" },
+        name
+    );
+    if let Some(severity) = severity {
+        message.push_str(&format!("\nSeverity: {}", severity));
+    }
+    for key in ["summary", "description"] {
+        if let Some(text) = alert.annotations.get(key) {
+            message.push_str(&format!("\n{}", text));
+        }
+    }
+    if !alert.generator_url.is_empty() {
+        message.push_str(&format!("\n{}", alert.generator_url));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        serde_json::from_str(
+            r#"{
+                "version": "4",
+                "status": "firing",
+                "alerts": [
+                    {
+                        "status": "firing",
+                        "labels": {"alertname": "HighCPU", "severity": "critical", "channel": "ops"},
+                        "annotations": {"summary": "CPU above 90%"},
+                        "generatorURL": "http://prometheus/graph"
+                    },
+                    {
+                        "status": "resolved",
+                        "labels": {"alertname": "DiskFull", "channel": "ops"}
+                    },
+                    {
+                        "status": "firing",
+                        "labels": {"alertname": "Orphan"}
+                    }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route_by_label() {
+        let config = AlertmanagerConfig::default();
+        let messages = route(&config, &notification());
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages["ops"],
+            "🔴 [FIRING] HighCPU\nSeverity: critical\nCPU above 90%\nhttp://prometheus/graph\n\n\
+             ✅ [RESOLVED] DiskFull"
+        );
+    }
+
+    #[test]
+    fn test_default_channel() {
+        let config = AlertmanagerConfig {
+            default_channel: Some("alerts".to_string()),
+            ..Default::default()
+        };
+        let messages = route(&config, &notification());
+
+        assert_eq!(messages["alerts"], "🔥 [FIRING] Orphan");
+    }
+}
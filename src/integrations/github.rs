@@ -0,0 +1,239 @@
+//! GitHub webhook receiver for push, pull request, issue and release events
+
+use std::collections::BTreeMap;
+
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use serde_json::Value;
+
+use crate::api::{ApiError, Result};
+use crate::config::GithubConfig;
+use crate::service::{ErrorCode, Services, Tenant};
+
+/// Commits listed in a push summary
+const MAX_COMMITS: usize = 5;
+
+#[post("/integrations/github")]
+pub async fn receive(
+    req: HttpRequest,
+    body: web::Bytes,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let config = &services.config.integrations.github;
+    if config.secret.is_empty() {
+        return Err(ApiError::Misconfigured(
+            "GitHub webhook received but no secret is configured",
+        ));
+    }
+
+    let signature = req
+        .headers()
+        .get("X-Hub-Signature-256")
+        .and_then(|h| h.to_str().ok());
+    if !signature.is_some_and(|s| verify_signature(&config.secret, &body, s)) {
+        return Err(ApiError::Unauthorized(
+            ErrorCode::InvalidSignature,
+            "Invalid signature",
+        ));
+    }
+
+    let event = req
+        .headers()
+        .get("X-GitHub-Event")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let Ok(payload) = serde_json::from_slice::<Value>(&body) else {
+        return Err(ApiError::BadRequest(
+            ErrorCode::InvalidJson,
+            "Invalid JSON payload".to_string(),
+        ));
+    };
+
+    let (Some(channel_name), Some(message)) =
+        (channel_for(config, &payload), format_event(event, &payload))
+    else {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "ignored": event })));
+    };
+
+    let tenant = Tenant::new(&config.tenant);
+    let messages = BTreeMap::from([(channel_name, message)]);
+    super::send_all(&services, &tenant, messages).await
+}
+
+/// Checks `sha256=<hex>` against the HMAC of the raw body
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    signature
+        .strip_prefix("sha256=")
+        .is_some_and(|hex| super::verify_hmac(secret, body, hex))
+}
+
+/// Channel mapped from `owner/repo`, then `repo`, then the default
+fn channel_for(config: &GithubConfig, payload: &Value) -> Option<String> {
+    let full_name = text(payload, "/repository/full_name");
+    let name = text(payload, "/repository/name");
+    config
+        .repositories
+        .get(full_name)
+        .or_else(|| config.repositories.get(name))
+        .or(config.default_channel.as_ref())
+        .cloned()
+}
+
+/// Human-readable summary, `None` for events and actions we don't report
+fn format_event(event: &str, payload: &Value) -> Option<String> {
+    let repo = text(payload, "/repository/full_name");
+    match event {
+        "push" => {
+            let branch = text(payload, "/ref").trim_start_matches("refs/heads/");
+            let commits = payload["commits"].as_array().map(Vec::as_slice)?;
+            if commits.is_empty() {
+                return None;
+            }
+
+            let mut message = format!(
+                "📦 {} pushed {} commit{} to {}:{}",
+                text(payload, "/pusher/name"),
+                commits.len(),
+                if commits.len() == 1 { "" } else { "s" },
+                repo,
+                branch
+            );
+            for commit in commits.iter().take(MAX_COMMITS) {
+                let id = text(commit, "/id");
+                let summary = text(commit, "/message").lines().next().unwrap_or_default();
+                message.push_str(&format!("\n• {} {}", &id[..id.len().min(7)], summary));
+            }
+            if commits.len() > MAX_COMMITS {
+                message.push_str(&format!("\n… and {} more", commits.len() - MAX_COMMITS));
+            }
+            message.push_str(&format!("\n{}", text(payload, "/compare")));
+            Some(message)
+        }
+        "pull_request" => {
+            let action = match text(payload, "/action") {
+                "closed" if payload["pull_request"]["merged"].as_bool() == Some(true) => "merged",
+                action @ ("opened" | "closed" | "reopened" | "ready_for_review") => action,
+                _ => return None,
+            };
+            Some(format!(
+                "🔀 {} PR #{} {} by {}: {}\n{}",
+                repo,
+                payload["pull_request"]["number"],
+                action,
+                text(payload, "/sender/login"),
+                text(payload, "/pull_request/title"),
+                text(payload, "/pull_request/html_url")
+            ))
+        }
+        "issues" => {
+            let action @ ("opened" | "closed" | "reopened") = text(payload, "/action") else {
+                return None;
+            };
+            Some(format!(
+                "🐛 {} issue #{} {} by {}: {}\n{}",
+                repo,
+                payload["issue"]["number"],
+                action,
+                text(payload, "/sender/login"),
+                text(payload, "/issue/title"),
+                text(payload, "/issue/html_url")
+            ))
+        }
+        "release" if text(payload, "/action") == "published" => Some(format!(
+            "🚀 {} released {}: {}\n{}",
+            repo,
+            text(payload, "/release/tag_name"),
+            text(payload, "/release/name"),
+            text(payload, "/release/html_url")
+        )),
+        _ => None,
+    }
+}
+
+fn text<'a>(value: &'a Value, pointer: &str) -> &'a str {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    #[test]
+    fn test_verify_signature() {
+        let body = br#"{"zen": "Keep it logically awesome."}"#;
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+        mac.update(body);
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+        assert!(verify_signature("s3cret", body, &signature));
+        assert!(!verify_signature("other", body, &signature));
+        assert!(!verify_signature("s3cret", body, "sha256=zz"));
+        assert!(!verify_signature("s3cret", body, "sha1=abc"));
+    }
+
+    #[test]
+    fn test_format_push() {
+        let payload = serde_json::json!({
+            "ref": "refs/heads/main",
+            "compare": "https://github.com/acme/api/compare/a...b",
+            "repository": {"name": "api", "full_name": "acme/api"},
+            "pusher": {"name": "octocat"},
+            "commits": [
+                {"id": "0123456789abcdef", "message": "Fix login\n\nDetails"},
+            ],
+        });
+
+        assert_eq!(
+            format_event("push", &payload).unwrap(),
+            "📦 octocat pushed 1 commit to acme/api:main\n• 0123456 Fix login\n\
+             https://github.com/acme/api/compare/a...b"
+        );
+    }
+
+    #[test]
+    fn test_format_merged_pull_request() {
+        let payload = serde_json::json!({
+            "action": "closed",
+            "repository": {"name": "api", "full_name": "acme/api"},
+            "sender": {"login": "octocat"},
+            "pull_request": {
+                "number": 42,
+                "merged": true,
+                "title": "Add retries",
+                "html_url": "https://github.com/acme/api/pull/42",
+            },
+        });
+
+        assert_eq!(
+            format_event("pull_request", &payload).unwrap(),
+            "🔀 acme/api PR #42 merged by octocat: Add retries\nhttps://github.com/acme/api/pull/42"
+        );
+        assert!(format_event("pull_request", &serde_json::json!({"action": "labeled"})).is_none());
+    }
+
+    #[test]
+    fn test_channel_for() {
+        let config = GithubConfig {
+            repositories: BTreeMap::from([
+                ("acme/api".to_string(), "backend".to_string()),
+                ("web".to_string(), "frontend".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let repo = |full_name: &str, name: &str| serde_json::json!({"repository": {"full_name": full_name, "name": name}});
+
+        assert_eq!(
+            channel_for(&config, &repo("acme/api", "api")).unwrap(),
+            "backend"
+        );
+        assert_eq!(
+            channel_for(&config, &repo("acme/web", "web")).unwrap(),
+            "frontend"
+        );
+        assert!(channel_for(&config, &repo("acme/docs", "docs")).is_none());
+    }
+}
@@ -0,0 +1,101 @@
+//! Grafana unified alerting webhook receiver
+
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{HttpResponse, post, web};
+use serde::Deserialize;
+
+use crate::api::Result;
+use crate::service::{Services, Tenant};
+
+/// Grafana's webhook body, only the fields we render
+#[derive(Deserialize)]
+pub struct Notification {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    state: String,
+    #[serde(default)]
+    alerts: Vec<Alert>,
+}
+
+#[derive(Deserialize)]
+struct Alert {
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(rename = "panelURL", default)]
+    panel_url: String,
+}
+
+#[post("/integrations/grafana")]
+pub async fn receive(
+    tenant: Tenant,
+    notification: web::Json<Notification>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let channel_name = services.config.integrations.grafana.channel.clone();
+    let messages = BTreeMap::from([(channel_name, format_notification(&notification))]);
+    super::send_all(&services, &tenant, messages).await
+}
+
+fn format_notification(notification: &Notification) -> String {
+    let emoji = match notification.state.as_str() {
+        "alerting" => "🚨",
+        "ok" => "✅",
+        _ => "ℹ️",
+    };
+
+    let mut message = format!("{} {}", emoji, notification.title);
+    if !notification.state.is_empty() {
+        message.push_str(&format!("\nState: {}", notification.state));
+    }
+    for alert in &notification.alerts {
+        let name = alert
+            .labels
+            .get("alertname")
+            .map(String::as_str)
+            .unwrap_or("Alert");
+        match alert.annotations.get("summary") {
+            Some(summary) => message.push_str(&format!("\n• {}: {}", name, summary)),
+            None => message.push_str(&format!("\n• {}", name)),
+        }
+    }
+    if let Some(alert) = notification.alerts.iter().find(|a| !a.panel_url.is_empty()) {
+        message.push_str(&format!("\nPanel: {}", alert.panel_url));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_notification() {
+        let notification: Notification = serde_json::from_str(
+            r#"{
+                "receiver": "telegram",
+                "status": "firing",
+                "state": "alerting",
+                "title": "[FIRING:1] HighCPU",
+                "alerts": [
+                    {
+                        "status": "firing",
+                        "labels": {"alertname": "HighCPU"},
+                        "annotations": {"summary": "CPU above 90%"},
+                        "panelURL": "http://grafana/d/abc?viewPanel=2"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            format_notification(&notification),
+            "🚨 [FIRING:1] HighCPU\nState: alerting\n• HighCPU: CPU above 90%\n\
+             Panel: http://grafana/d/abc?viewPanel=2"
+        );
+    }
+}
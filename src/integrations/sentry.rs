@@ -0,0 +1,145 @@
+//! Sentry issue alert webhook receiver
+
+use std::collections::BTreeMap;
+
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use serde_json::Value;
+
+use crate::api::{ApiError, Result};
+use crate::service::{ErrorCode, Services, Tenant};
+
+/// Accepts both the legacy WebHooks plugin body and internal integration `event_alert`
+/// and `issue` resources; `Sentry-Hook-Signature` is checked when a secret is configured
+#[post("/integrations/sentry")]
+pub async fn receive(
+    req: HttpRequest,
+    body: web::Bytes,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let config = &services.config.integrations.sentry;
+    if !config.secret.is_empty() {
+        let signature = req
+            .headers()
+            .get("Sentry-Hook-Signature")
+            .and_then(|h| h.to_str().ok());
+        if !signature.is_some_and(|s| super::verify_hmac(&config.secret, &body, s)) {
+            return Err(ApiError::Unauthorized(
+                ErrorCode::InvalidSignature,
+                "Invalid signature",
+            ));
+        }
+    }
+
+    let Ok(payload) = serde_json::from_slice::<Value>(&body) else {
+        return Err(ApiError::BadRequest(
+            ErrorCode::InvalidJson,
+            "Invalid JSON payload".to_string(),
+        ));
+    };
+
+    let tenant = Tenant::new(&config.tenant);
+    let messages = BTreeMap::from([(config.channel.clone(), format_alert(&payload))]);
+    super::send_all(&services, &tenant, messages).await
+}
+
+fn format_alert(payload: &Value) -> String {
+    let project = first(
+        payload,
+        &[
+            "/project_name",
+            "/data/issue/project/name",
+            "/data/event/project_name",
+            "/project",
+        ],
+    );
+    let title = first(
+        payload,
+        &[
+            "/event/title",
+            "/data/event/title",
+            "/data/issue/title",
+            "/message",
+        ],
+    );
+    let level = first(
+        payload,
+        &["/level", "/data/event/level", "/data/issue/level"],
+    );
+    let link = first(
+        payload,
+        &["/url", "/data/event/web_url", "/data/issue/web_url"],
+    );
+
+    let mut message = format!(
+        "🐞 [{}] {}",
+        project.unwrap_or("sentry"),
+        title.unwrap_or("New issue")
+    );
+    let count = payload.pointer("/data/issue/count").and_then(|c| {
+        c.as_str()
+            .map(str::to_string)
+            .or_else(|| c.as_u64().map(|n| n.to_string()))
+    });
+    match (level, count) {
+        (Some(level), Some(count)) => {
+            message.push_str(&format!("\nLevel: {} · Events: {}", level, count))
+        }
+        (Some(level), None) => message.push_str(&format!("\nLevel: {}", level)),
+        (None, Some(count)) => message.push_str(&format!("\nEvents: {}", count)),
+        (None, None) => {}
+    }
+    if let Some(link) = link {
+        message.push_str(&format!("\n{}", link));
+    }
+    message
+}
+
+/// First non-empty string among the JSON pointers
+fn first<'a>(payload: &'a Value, pointers: &[&str]) -> Option<&'a str> {
+    pointers
+        .iter()
+        .filter_map(|p| payload.pointer(p).and_then(Value::as_str))
+        .find(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_webhook() {
+        let payload = serde_json::json!({
+            "project_name": "api",
+            "message": "ZeroDivisionError",
+            "level": "error",
+            "url": "https://sentry.io/acme/api/issues/1/",
+            "event": {"title": "ZeroDivisionError: division by zero"},
+        });
+
+        assert_eq!(
+            format_alert(&payload),
+            "🐞 [api] ZeroDivisionError: division by zero\nLevel: error\n\
+             https://sentry.io/acme/api/issues/1/"
+        );
+    }
+
+    #[test]
+    fn test_issue_resource() {
+        let payload = serde_json::json!({
+            "action": "created",
+            "data": {"issue": {
+                "title": "TypeError: x is undefined",
+                "level": "error",
+                "count": "12",
+                "web_url": "https://sentry.io/issues/2/",
+                "project": {"name": "web"},
+            }},
+        });
+
+        assert_eq!(
+            format_alert(&payload),
+            "🐞 [web] TypeError: x is undefined\nLevel: error · Events: 12\n\
+             https://sentry.io/issues/2/"
+        );
+    }
+}
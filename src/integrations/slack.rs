@@ -0,0 +1,144 @@
+//! Slack incoming-webhook compatible endpoint
+
+use actix_web::{HttpResponse, post, web};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::api::Result;
+use crate::service::{Services, Tenant};
+
+/// Slack's incoming webhook body; attachments and blocks are rendered best-effort
+#[derive(Deserialize)]
+pub struct SlackMessage {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    #[serde(default)]
+    blocks: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct Attachment {
+    pretext: Option<String>,
+    title: Option<String>,
+    title_link: Option<String>,
+    text: Option<String>,
+    fallback: Option<String>,
+    #[serde(default)]
+    fields: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    title: String,
+    value: String,
+}
+
+/// Answers `ok` like Slack does, so tools checking the body keep working
+#[post("/slack/{channel_name}")]
+pub async fn receive(
+    tenant: Tenant,
+    channel_name: web::Path<String>,
+    message: web::Json<SlackMessage>,
+    services: web::Data<Services>,
+) -> Result<HttpResponse> {
+    let text = super::truncate(&format_message(&message), super::MAX_MESSAGE_CHARS);
+    services.send_message(&tenant, &channel_name, &text).await?;
+    Ok(HttpResponse::Ok().content_type("text/plain").body("ok"))
+}
+
+fn format_message(message: &SlackMessage) -> String {
+    let mut parts = Vec::new();
+    if !message.text.is_empty() {
+        parts.push(message.text.clone());
+    }
+
+    for block in &message.blocks {
+        if let Some(text) = block.pointer("/text/text").and_then(Value::as_str) {
+            parts.push(text.to_string());
+        }
+        for field in block["fields"].as_array().into_iter().flatten() {
+            if let Some(text) = field["text"].as_str() {
+                parts.push(text.to_string());
+            }
+        }
+    }
+
+    for attachment in &message.attachments {
+        let mut lines = Vec::new();
+        lines.extend(attachment.pretext.clone());
+        match (&attachment.title, &attachment.title_link) {
+            (Some(title), Some(link)) => lines.push(format!("{} ({})", title, link)),
+            (Some(title), None) => lines.push(title.clone()),
+            _ => {}
+        }
+        lines.extend(attachment.text.clone());
+        for field in &attachment.fields {
+            lines.push(format!("{}: {}", field.title, field.value));
+        }
+        if lines.is_empty() {
+            lines.extend(attachment.fallback.clone());
+        }
+        parts.push(lines.join("\n"));
+    }
+
+    unlink(&parts.join("\n\n"))
+}
+
+/// Rewrites Slack's `<url|label>` links as `label (url)` and `<url>` as `url`
+fn unlink(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        match rest[start + 1..start + len].split_once('|') {
+            Some((url, label)) => out.push_str(&format!("{} ({})", label, url)),
+            None => out.push_str(&rest[start + 1..start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_only() {
+        let message: SlackMessage =
+            serde_json::from_str(r#"{"text": "Deploy done <https://ci/1|#1>"}"#).unwrap();
+        assert_eq!(format_message(&message), "Deploy done #1 (https://ci/1)");
+    }
+
+    #[test]
+    fn test_attachments_and_blocks() {
+        let message: SlackMessage = serde_json::from_str(
+            r#"{
+                "blocks": [
+                    {"type": "header", "text": {"type": "plain_text", "text": "Deploy"}},
+                    {"type": "section", "fields": [{"type": "mrkdwn", "text": "*Env:* prod"}]}
+                ],
+                "attachments": [
+                    {
+                        "title": "Build 42",
+                        "title_link": "https://ci/42",
+                        "fields": [{"title": "Status", "value": "passed", "short": true}]
+                    },
+                    {"fallback": "Plain fallback"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            format_message(&message),
+            "Deploy\n\n*Env:* prod\n\nBuild 42 (https://ci/42)\nStatus: passed\n\nPlain fallback"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Progress of background broadcasts, kept in memory; the queue itself is in the database
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+/// Random id for a new job
+pub fn new_id() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// How long a finished job can still be watched
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct JobProgress {
+    pub total: usize,
+    pub sent: usize,
+    pub errors: usize,
+    /// Recipients left out because the job was cancelled, known once it's done
+    pub skipped: usize,
+    pub cancelled: bool,
+    pub done: bool,
+    /// Stored message the broadcast was recorded under, once done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
+}
+
+/// Result of [`BroadcastJobs::cancel`]
+#[derive(Debug, PartialEq)]
+pub enum Cancellation {
+    Cancelled(JobProgress),
+    AlreadyDone,
+    NotFound,
+}
+
+struct Job {
+    tenant_id: String,
+    progress: watch::Sender<JobProgress>,
+}
+
+#[derive(Clone, Default)]
+pub struct BroadcastJobs {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+impl BroadcastJobs {
+    /// Registers a job starting from `progress`, returning the sender to report progress on
+    pub fn register(
+        &self,
+        id: &str,
+        tenant_id: &str,
+        progress: JobProgress,
+    ) -> watch::Sender<JobProgress> {
+        let (sender, _) = watch::channel(progress);
+        self.jobs.lock().expect("jobs lock poisoned").insert(
+            id.to_string(),
+            Job {
+                tenant_id: tenant_id.to_string(),
+                progress: sender.clone(),
+            },
+        );
+        sender
+    }
+
+    /// Progress of a job, `None` if it doesn't exist or belongs to another tenant
+    pub fn watch(&self, id: &str, tenant_id: &str) -> Option<watch::Receiver<JobProgress>> {
+        let jobs = self.jobs.lock().expect("jobs lock poisoned");
+        jobs.get(id)
+            .filter(|job| job.tenant_id == tenant_id)
+            .map(|job| job.progress.subscribe())
+    }
+
+    /// Asks a running job to stop delivering to the recipients it hasn't reached yet
+    pub fn cancel(&self, id: &str, tenant_id: &str) -> Cancellation {
+        let jobs = self.jobs.lock().expect("jobs lock poisoned");
+        let Some(job) = jobs.get(id).filter(|job| job.tenant_id == tenant_id) else {
+            return Cancellation::NotFound;
+        };
+        if job.progress.borrow().done {
+            return Cancellation::AlreadyDone;
+        }
+
+        job.progress
+            .send_modify(|progress| progress.cancelled = true);
+        Cancellation::Cancelled(job.progress.borrow().clone())
+    }
+
+    /// Marks the job done and forgets it after [`FINISHED_JOB_RETENTION`]
+    pub async fn finish(&self, id: String, message_id: Option<i64>) {
+        if let Some(job) = self.jobs.lock().expect("jobs lock poisoned").get(&id) {
+            job.progress.send_modify(|progress| {
+                progress.skipped = progress.total - progress.sent - progress.errors;
+                progress.message_id = message_id;
+                progress.done = true;
+            });
+        }
+
+        tokio::time::sleep(FINISHED_JOB_RETENTION).await;
+        self.jobs.lock().expect("jobs lock poisoned").remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_is_scoped_to_tenant() {
+        let jobs = BroadcastJobs::default();
+        let id = new_id();
+        let sender = jobs.register(
+            &id,
+            "acme",
+            JobProgress {
+                total: 10,
+                ..Default::default()
+            },
+        );
+        sender.send_modify(|progress| progress.sent = 3);
+
+        let receiver = jobs.watch(&id, "acme").unwrap();
+        assert_eq!(receiver.borrow().sent, 3);
+        assert_eq!(receiver.borrow().total, 10);
+        assert!(jobs.watch(&id, "globex").is_none());
+        assert!(jobs.watch("missing", "acme").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let jobs = BroadcastJobs::default();
+        let id = new_id();
+        let sender = jobs.register(
+            &id,
+            "acme",
+            JobProgress {
+                total: 10,
+                ..Default::default()
+            },
+        );
+        sender.send_modify(|progress| progress.sent = 3);
+
+        assert_eq!(jobs.cancel(&id, "globex"), Cancellation::NotFound);
+        let Cancellation::Cancelled(progress) = jobs.cancel(&id, "acme") else {
+            panic!("job should be cancelled");
+        };
+        assert!(progress.cancelled && !progress.done);
+
+        tokio::spawn({
+            let jobs = jobs.clone();
+            let id = id.clone();
+            async move { jobs.finish(id, None).await }
+        });
+        tokio::task::yield_now().await;
+        let receiver = jobs.watch(&id, "acme").unwrap();
+        assert_eq!(receiver.borrow().skipped, 7);
+        assert_eq!(jobs.cancel(&id, "acme"), Cancellation::AlreadyDone);
+    }
+}
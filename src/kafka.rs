@@ -0,0 +1,40 @@
+//! Consumes `{channel, message}` records from a Kafka topic
+
+use anyhow::Result;
+use rdkafka::ClientConfig;
+use rdkafka::Message;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+
+use crate::config::KafkaConfig;
+use crate::service::Services;
+
+/// Delivers every record of the topic, committing its offset once delivery finished
+pub async fn consume(config: KafkaConfig, services: Services) -> Result<()> {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &config.brokers)
+        .set("group.id", &config.group_id)
+        .set("enable.auto.commit", "false")
+        .create()?;
+    consumer.subscribe(&[&config.topic])?;
+    log::info!(
+        "Consuming Kafka topic '{}' for tenant '{}'",
+        config.topic,
+        config.tenant
+    );
+
+    loop {
+        let message = consumer.recv().await?;
+        if let Some(payload) = message.payload() {
+            // A bad record is logged and skipped, it would fail again on redelivery
+            if let Err(e) = crate::ingest::deliver_record(&services, &config.tenant, payload).await
+            {
+                log::error!(
+                    "Dropping Kafka record at offset {}: {}",
+                    message.offset(),
+                    e
+                );
+            }
+        }
+        consumer.commit_message(&message, CommitMode::Async)?;
+    }
+}
@@ -0,0 +1,146 @@
+//! Send capacity shared by every delivery, handed to transactional messages first
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// OTPs, incident alerts: never wait behind bulk messages
+    #[default]
+    Transactional,
+    /// Broadcasts and marketing, sent when no transactional message is waiting
+    Bulk,
+}
+
+struct State {
+    available: usize,
+    /// Transactional sends waiting for capacity, bulk ones hold back while any are
+    transactional_waiting: usize,
+}
+
+#[derive(Clone)]
+pub struct Lanes {
+    capacity: usize,
+    state: Arc<Mutex<State>>,
+    released: Arc<Notify>,
+}
+
+impl Lanes {
+    /// Lanes sharing `capacity` requests in flight at once
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Arc::new(Mutex::new(State {
+                available: capacity,
+                transactional_waiting: 0,
+            })),
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Waits for a free slot, held until the returned permit is dropped
+    pub async fn acquire(&self, priority: Priority) -> Permit {
+        let mut waiting = None;
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            {
+                let mut state = self.state.lock().expect("lanes lock poisoned");
+                let may_take =
+                    priority == Priority::Transactional || state.transactional_waiting == 0;
+                if state.available > 0 && may_take {
+                    state.available -= 1;
+                    break;
+                }
+                if priority == Priority::Transactional && waiting.is_none() {
+                    state.transactional_waiting += 1;
+                    waiting = Some(Waiting { lanes: self });
+                }
+            }
+
+            released.await;
+        }
+
+        drop(waiting);
+        Permit {
+            lanes: self.clone(),
+        }
+    }
+}
+
+/// Counts a transactional send as waiting until dropped, also when the send is abandoned
+struct Waiting<'a> {
+    lanes: &'a Lanes,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.lanes
+            .state
+            .lock()
+            .expect("lanes lock poisoned")
+            .transactional_waiting -= 1;
+        self.lanes.released.notify_waiters();
+    }
+}
+
+pub struct Permit {
+    lanes: Lanes,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.lanes
+            .state
+            .lock()
+            .expect("lanes lock poisoned")
+            .available += 1;
+        self.lanes.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transactional_goes_first() {
+        let lanes = Lanes::new(1);
+        let held = lanes.acquire(Priority::Bulk).await;
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let bulk = tokio::spawn({
+            let (lanes, order) = (lanes.clone(), order.clone());
+            async move {
+                let _permit = lanes.acquire(Priority::Bulk).await;
+                order.lock().unwrap().push(Priority::Bulk);
+            }
+        });
+        tokio::task::yield_now().await;
+        let transactional = tokio::spawn({
+            let (lanes, order) = (lanes.clone(), order.clone());
+            async move {
+                let _permit = lanes.acquire(Priority::Transactional).await;
+                order.lock().unwrap().push(Priority::Transactional);
+            }
+        });
+        tokio::task::yield_now().await;
+
+        drop(held);
+        bulk.await.unwrap();
+        transactional.await.unwrap();
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec![Priority::Transactional, Priority::Bulk]
+        );
+    }
+}
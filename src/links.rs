@@ -0,0 +1,246 @@
+//! Click tracking: links in outgoing messages rewritten to pass through `GET /r/{token}`
+
+use std::collections::HashMap;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use teloxide::types::ParseMode;
+
+/// Bytes of the HMAC kept in a click token, enough that guessing one isn't practical
+const SIGNATURE_LEN: usize = 16;
+
+/// Tracked links of one message and where their clicks are recorded
+pub struct TrackedLinks {
+    /// Public URL of the server, without a trailing slash
+    pub base_url: String,
+    /// Token of each link, by the URL it redirects to
+    pub tokens: HashMap<String, String>,
+    /// Key signing the recipient into each redirect
+    pub secret: String,
+}
+
+impl TrackedLinks {
+    /// `message` with every tracked link pointing to a redirect that records a click
+    /// by `telegram_id`
+    pub fn apply(&self, message: &str, parse_mode: Option<ParseMode>, telegram_id: i64) -> String {
+        rewrite_urls(message, parse_mode, |url| {
+            let token = self.tokens.get(url)?;
+            Some(format!(
+                "{}/r/{}",
+                self.base_url,
+                click_token(&self.secret, token, telegram_id)
+            ))
+        })
+    }
+}
+
+/// Random token of a new tracked link
+pub fn new_token() -> String {
+    hex::encode(rand::random::<[u8; 8]>())
+}
+
+/// Token of the redirect a recipient follows: the link's token and their telegram_id,
+/// signed with `secret` so clicks can't be recorded for someone else
+pub fn click_token(secret: &str, link_token: &str, telegram_id: i64) -> String {
+    let payload = format!("{}.{}", link_token, telegram_id);
+    let signature = hex::encode(&mac(secret, &payload).finalize().into_bytes()[..SIGNATURE_LEN]);
+    format!("{}.{}", payload, signature)
+}
+
+/// The link's token and the recipient's telegram_id of a [`click_token`], if it was
+/// signed with `secret`
+pub fn parse_click_token<'a>(secret: &str, token: &'a str) -> Option<(&'a str, i64)> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let signature = hex::decode(signature).ok()?;
+    if signature.len() != SIGNATURE_LEN {
+        return None;
+    }
+    mac(secret, payload)
+        .verify_truncated_left(&signature)
+        .ok()?;
+    let (link_token, telegram_id) = payload.split_once('.')?;
+    Some((link_token, telegram_id.parse().ok()?))
+}
+
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Distinct URLs [`TrackedLinks::apply`] would rewrite in `message`, in order
+pub fn find_urls(message: &str, parse_mode: Option<ParseMode>) -> Vec<String> {
+    let mut urls = Vec::new();
+    rewrite_urls(message, parse_mode, |url| {
+        if !urls.iter().any(|known| known == url) {
+            urls.push(url.to_string());
+        }
+        None
+    });
+    urls
+}
+
+/// `message` with each http(s) URL replaced by what `rewrite` returns for it, if
+/// anything. In Markdown only link targets, `[text](url)`, are considered, since a
+/// bare URL would need escaping. In HTML `&amp;` is decoded before `rewrite` sees it.
+fn rewrite_urls(
+    message: &str,
+    parse_mode: Option<ParseMode>,
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let markdown = !matches!(parse_mode, None | Some(ParseMode::Html));
+    let mut rewritten = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = ["https://", "http://"]
+        .into_iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        rewritten.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = if markdown {
+            link_target_len(rest)
+        } else {
+            bare_url_len(rest)
+        };
+        let (raw, after) = rest.split_at(len);
+        let url = match parse_mode {
+            Some(ParseMode::Html) => raw.replace("&amp;", "&"),
+            Some(_) => raw.replace("\\)", ")").replace("\\\\", "\\"),
+            None => raw.to_string(),
+        };
+        let has_host = raw
+            .split_once("://")
+            .is_some_and(|(_, host)| !host.is_empty());
+        let eligible = has_host && (!markdown || rewritten.ends_with("]("));
+        match eligible.then(|| rewrite(&url)).flatten() {
+            Some(replacement) => rewritten.push_str(&replacement),
+            None => rewritten.push_str(raw),
+        }
+        rest = after;
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Length of the Markdown link target `url` starts, up to its unescaped `)`
+fn link_target_len(url: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in url.char_indices() {
+        match c {
+            ')' if !escaped => return i,
+            c if c.is_whitespace() => return i,
+            _ => escaped = c == '\\' && !escaped,
+        }
+    }
+    url.len()
+}
+
+/// Length of the URL `text` starts, leaving out punctuation ending a sentence around it
+fn bare_url_len(text: &str) -> usize {
+    let end = text
+        .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>'))
+        .unwrap_or(text.len());
+    let mut url = &text[..end];
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(inner) if inner.matches('(').count() <= inner.matches(')').count() => inner,
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            return url.len();
+        }
+        url = trimmed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(urls: &[&str]) -> TrackedLinks {
+        TrackedLinks {
+            base_url: "https://proxy.example.com".to_string(),
+            tokens: urls
+                .iter()
+                .enumerate()
+                .map(|(i, url)| (url.to_string(), format!("t{}", i)))
+                .collect(),
+            secret: "s3cret".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_find_urls() {
+        assert_eq!(
+            find_urls(
+                "Status (see https://status.example.com). Docs: http://docs.example.com/a?b=1, \
+                 again https://status.example.com",
+                None
+            ),
+            [
+                "https://status.example.com",
+                "http://docs.example.com/a?b=1"
+            ]
+        );
+        assert_eq!(
+            find_urls("https://en.wikipedia.org/wiki/Rust_(language)!", None),
+            ["https://en.wikipedia.org/wiki/Rust_(language)"]
+        );
+        assert_eq!(
+            find_urls(
+                r#"<a href="https://example.com/?a=1&amp;b=2">https://example.com</a>"#,
+                Some(ParseMode::Html)
+            ),
+            ["https://example.com/?a=1&b=2", "https://example.com"]
+        );
+        assert!(find_urls("no links, only https://", None).is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let tracked = links(&["https://example.com/a", "https://example.com/b(1)"]);
+        let redirect = |link_token, telegram_id| {
+            format!(
+                "https://proxy.example.com/r/{}",
+                click_token("s3cret", link_token, telegram_id)
+            )
+        };
+        assert_eq!(
+            tracked.apply("Read https://example.com/a.", None, 111),
+            format!("Read {}.", redirect("t0", 111))
+        );
+        assert_eq!(
+            tracked.apply(
+                "[Read](https://example.com/a) or [this](https://example.com/b(1\\)) or https://example\\.com/a",
+                Some(ParseMode::MarkdownV2),
+                -222
+            ),
+            format!(
+                "[Read]({}) or [this]({}) or https://example\\.com/a",
+                redirect("t0", -222),
+                redirect("t1", -222)
+            )
+        );
+        assert_eq!(
+            tracked.apply("https://other.example.com", None, 111),
+            "https://other.example.com"
+        );
+    }
+
+    #[test]
+    fn test_click_token() {
+        let token = click_token("s3cret", "t0", -222);
+        assert_eq!(parse_click_token("s3cret", &token), Some(("t0", -222)));
+        assert_eq!(parse_click_token("other", &token), None);
+        // Someone else's click, with the signature of another recipient
+        let signature = token.rsplit_once('.').unwrap().1;
+        assert_eq!(
+            parse_click_token("s3cret", &format!("t0.333.{}", signature)),
+            None
+        );
+        assert_eq!(parse_click_token("s3cret", "t0.-222"), None);
+        assert_eq!(parse_click_token("s3cret", "t0"), None);
+    }
+}
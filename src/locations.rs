@@ -0,0 +1,134 @@
+//! Location and venue messages
+
+use futures::stream::Stream;
+use serde::Deserialize;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardMarkup, LivePeriod, MessageId, Seconds};
+
+use crate::bot::Bots;
+use crate::config::RetryConfig;
+use crate::db::Recipient;
+use crate::delivery::{Failure, text_len};
+use crate::lanes::{Lanes, Priority};
+
+/// Bounds Telegram accepts for a live location's period, in seconds
+const LIVE_PERIOD_SECS: std::ops::RangeInclusive<u32> = 60..=86400;
+
+#[derive(Deserialize)]
+pub struct NewLocation {
+    pub channel_name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Seconds the location can be updated for, making it a live location
+    pub live_period: Option<u32>,
+    /// Sends a venue at the location instead
+    pub venue: Option<Venue>,
+}
+
+#[derive(Deserialize)]
+pub struct Venue {
+    pub title: String,
+    pub address: String,
+}
+
+impl NewLocation {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            return Err("Invalid coordinates");
+        }
+        if let Some(period) = self.live_period
+            && !LIVE_PERIOD_SECS.contains(&period)
+        {
+            return Err("Live period must be between 60 and 86400 seconds");
+        }
+        if let Some(venue) = &self.venue {
+            if self.live_period.is_some() {
+                return Err("A venue can't be a live location");
+            }
+            if venue.title.trim().is_empty() || venue.address.trim().is_empty() {
+                return Err("Venue title and address cannot be empty");
+            }
+            if text_len(&venue.title) + text_len(&venue.address) > 1000 {
+                return Err("Venue too long (max 1000 chars)");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends the location to every recipient, yielding each as it completes with the
+/// id of the Telegram message it got, or why delivery failed, with `keyboard` under it
+pub fn send_each<'a>(
+    bots: &'a Bots,
+    lanes: &'a Lanes,
+    retry: &'a RetryConfig,
+    recipients: impl Stream<Item = Recipient> + 'a,
+    location: &'a NewLocation,
+    keyboard: &'a Option<InlineKeyboardMarkup>,
+) -> impl Stream<Item = (Recipient, Result<MessageId, Failure>)> + 'a {
+    crate::delivery::send_each(
+        bots,
+        lanes,
+        retry,
+        Priority::default(),
+        recipients,
+        move |bot, chat_id| async move {
+            let (latitude, longitude) = (location.latitude, location.longitude);
+            let reply_markup = keyboard.clone().map(Into::into);
+            let sent = match (&location.venue, location.live_period) {
+                (Some(venue), _) => {
+                    let mut request =
+                        bot.send_venue(chat_id, latitude, longitude, &venue.title, &venue.address);
+                    request.reply_markup = reply_markup;
+                    request.await
+                }
+                (None, live_period) => {
+                    let mut request = bot.send_location(chat_id, latitude, longitude);
+                    request.live_period = live_period
+                        .map(|period| LivePeriod::Timeframe(Seconds::from_seconds(period)));
+                    request.reply_markup = reply_markup;
+                    request.await
+                }
+            };
+            Ok(sent?.id)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location() -> NewLocation {
+        NewLocation {
+            channel_name: "incidents".to_string(),
+            latitude: 45.46,
+            longitude: 9.19,
+            live_period: None,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(location().validate().is_ok());
+
+        let mut out_of_range = location();
+        out_of_range.latitude = 91.0;
+        assert!(out_of_range.validate().is_err());
+
+        let mut live = location();
+        live.live_period = Some(30);
+        assert!(live.validate().is_err());
+        live.live_period = Some(3600);
+        assert!(live.validate().is_ok());
+
+        live.venue = Some(Venue {
+            title: "Server room".to_string(),
+            address: "Via Roma 1".to_string(),
+        });
+        assert!(live.validate().is_err());
+        live.live_period = None;
+        assert!(live.validate().is_ok());
+    }
+}
@@ -1,46 +1,333 @@
+#[cfg(feature = "amqp")]
+mod amqp;
 mod api;
 mod bot;
+mod cli;
+mod config;
+mod cors;
+mod dashboard;
 mod db;
+mod delivery;
+mod events;
+mod feeds;
+mod grpc;
+mod hooks;
+mod ingest;
+mod integrations;
+mod jobs;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod lanes;
+mod links;
+mod locations;
+mod media;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod polls;
+mod pruning;
+mod quota;
+mod recurring;
+#[cfg(feature = "redis")]
+mod redis_bridge;
+mod roles;
+mod service;
+mod throttle;
+mod tls;
+mod webhooks;
 
+use std::time::Duration;
+
+use actix_web::middleware::Condition;
 use actix_web::{App, HttpServer, web};
 use anyhow::Result;
-use teloxide::Bot;
+use clap::Parser;
+use sqlx::SqlitePool;
+use teloxide::prelude::*;
+
+use crate::cli::{Cli, Command};
+use crate::config::{BotMode, Config};
 
 #[actix_web::main]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
     env_logger::init();
 
-    let database_url = std::env::var("DATABASE_URL").expect("DB url should be present");
-    let pool = db::create_pool(&database_url).await?;
-    let bot = Bot::from_env();
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
+    let pool = db::create_pool(&config.database_url, &config.database).await?;
 
-    let bot_pool = pool.clone();
-    tokio::spawn(async move {
-        // This is the poll loop, it'll never stop (hopefully)
-        if let Err(e) = bot::run_bot(bot_pool).await {
-            log::error!("Bot error: {}", e);
-            std::process::exit(1);
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config, pool).await,
+        Command::Migrate => {
+            log::info!("Migrations applied");
+            Ok(())
         }
-    });
+        Command::Send {
+            tenant,
+            channel_name,
+            message,
+        } => {
+            cli::send(
+                &config.bots()?,
+                &config.lanes()?,
+                &config.retry,
+                &pool,
+                &tenant,
+                &channel_name,
+                &message,
+            )
+            .await
+        }
+        Command::Broadcast {
+            tenant,
+            channels,
+            message,
+        } => {
+            cli::broadcast(
+                &config.bots()?,
+                &config.lanes()?,
+                &config.retry,
+                &pool,
+                &tenant,
+                &channels,
+                &message,
+            )
+            .await
+        }
+    }
+}
+
+async fn serve(config: Config, pool: SqlitePool) -> Result<()> {
+    let bots = config.bots()?;
+    let events = events::EventBus::new();
+
+    let shutdown_grace = Duration::from_secs(config.shutdown_grace_secs);
+    let config = web::Data::new(config);
+    let services = service::Services {
+        pool: pool.clone(),
+        bots,
+        events,
+        config: config.clone().into_inner(),
+        jobs: jobs::BroadcastJobs::default(),
+        lanes: config.lanes()?,
+        channel_limits: throttle::ChannelLimits::default(),
+    };
+
+    if let Err(e) = services.resume_broadcasts().await {
+        log::error!("Failed to resume queued broadcasts: {}", e);
+    }
+
+    if config.bot_mode == BotMode::Polling {
+        for (name, identity) in services.bots.iter() {
+            let bot_name = name.to_string();
+            let identity = identity.clone();
+            let bot_services = services.clone();
+            tokio::spawn(async move {
+                // This is the poll loop, it'll never stop (hopefully)
+                if let Err(e) = bot::run_bot(bot_name, identity, bot_services).await {
+                    log::error!("Bot error: {}", e);
+                    std::process::exit(1);
+                }
+            });
+        }
+    } else {
+        log::info!("Bot polling disabled");
+    }
+
+    if !config.webhooks.urls.is_empty() || !config.webhooks.channels.is_empty() {
+        tokio::spawn(webhooks::run(
+            config.webhooks.clone(),
+            services.events.clone(),
+        ));
+    }
+
+    let readiness = web::Data::new(api::Readiness::default());
+    tokio::spawn(validate_bot_tokens(
+        services.bots.clone(),
+        readiness.clone(),
+    ));
 
     // Start web server
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8100".to_string());
-    let bind_address = format!("0.0.0.0:{}", port);
-    log::info!("Starting web server on {}", bind_address);
+    let bind_address = format!("0.0.0.0:{}", config.port);
+    let scheme = if config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    log::info!("Starting web server on {}://{}", scheme, bind_address);
+
+    ingest::spawn_consumers(&config, &services)?;
+    tokio::spawn(recurring::run(services.clone()));
+    if config.feed_poll_interval_secs > 0 {
+        let interval = Duration::from_secs(config.feed_poll_interval_secs);
+        tokio::spawn(feeds::run(services.clone(), interval));
+    }
+    if config.pruning.after_days > 0 {
+        tokio::spawn(pruning::run(services.clone(), config.pruning.clone()));
+    }
 
-    HttpServer::new(move || {
+    if let Some(grpc_port) = config.grpc_port {
+        let addr = format!("0.0.0.0:{}", grpc_port).parse()?;
+        let grpc = grpc::serve(addr, services.clone());
+        tokio::spawn(async move {
+            if let Err(e) = grpc.await {
+                log::error!("gRPC server error: {}", e);
+                std::process::exit(1);
+            }
+        });
+    }
+    let app_readiness = readiness.clone();
+    let tls = config.tls.clone();
+    let cors = cors::CorsPolicy::new(&config.cors)?;
+    let max_json_bytes = config.max_json_bytes;
+
+    let server = HttpServer::new(move || {
+        let cors_middleware = cors.as_ref().map(cors::CorsPolicy::middleware);
         App::new()
+            .wrap(Condition::new(
+                cors_middleware.is_some(),
+                cors_middleware.unwrap_or_default(),
+            ))
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(max_json_bytes)
+                    .content_type_required(true)
+                    .error_handler(move |e, _| api::json_error(e, max_json_bytes)),
+            )
+            .app_data(config.clone())
+            .app_data(app_readiness.clone())
             .app_data(web::Data::new(pool.clone()))
-            .app_data(web::Data::new(bot.clone()))
+            .app_data(web::Data::new(services.clone()))
+            .service(dashboard::page)
             .service(api::health_check)
+            .service(api::live)
+            .service(api::ready)
+            .service(api::metrics)
             .service(api::send_message)
+            .service(api::edit_message)
+            .service(api::list_messages)
+            .service(api::delete_message)
+            .service(api::unpin_message)
+            .service(api::get_message_clicks)
+            .service(api::follow_link)
+            .service(api::send_poll)
+            .service(api::send_location)
+            .service(api::send_media_group)
+            .service(api::send_sticker)
+            .service(api::send_animation)
+            .service(api::send_video)
+            .service(api::send_voice)
+            .service(api::send_audio)
+            .service(api::poll_results)
             .service(api::broadcast)
+            .service(api::get_broadcast)
+            .service(api::broadcast_events)
+            .service(api::cancel_broadcast)
             .service(api::get_subscriptions)
-    })
-    .bind(&bind_address)?
-    .run()
-    .await?;
+            .service(api::get_subscriber_tags)
+            .service(api::add_subscriber_tag)
+            .service(api::remove_subscriber_tag)
+            .service(api::set_subscriber_metadata)
+            .service(api::list_subscription_events)
+            .service(api::pruning_report)
+            .service(api::list_channels)
+            .service(api::update_channel)
+            .service(api::rename_channel)
+            .service(api::merge_channel)
+            .service(api::list_channel_aliases)
+            .service(api::set_channel_alias)
+            .service(api::delete_channel_alias)
+            .service(api::delete_channel)
+            .service(api::get_channel_stats)
+            .service(api::get_channel_analytics)
+            .service(api::get_stats)
+            .service(api::list_dead_letters)
+            .service(api::retry_dead_letter)
+            .service(api::delete_dead_letter)
+            .service(api::create_api_key)
+            .service(api::list_api_keys)
+            .service(api::delete_api_key)
+            .service(api::create_user)
+            .service(api::list_users)
+            .service(api::delete_user)
+            .service(api::grant_role)
+            .service(api::revoke_role)
+            .service(api::get_usage)
+            .service(api::events_ws)
+            .service(integrations::alertmanager::receive)
+            .service(integrations::grafana::receive)
+            .service(integrations::github::receive)
+            .service(integrations::sentry::receive)
+            .service(integrations::slack::receive)
+            .service(api::create_hook)
+            .service(api::list_hooks)
+            .service(api::delete_hook)
+            .service(hooks::receive)
+            .service(api::create_feed)
+            .service(api::list_feeds)
+            .service(api::delete_feed)
+            .service(api::create_recurring_message)
+            .service(api::list_recurring_messages)
+            .service(api::update_recurring_message)
+            .service(api::delete_recurring_message)
+    });
+    let server = match tls {
+        Some(tls) => {
+            let (tls_config, certificate) = tls::server_config(&tls)?;
+            tokio::spawn(tls::reload_on_sighup(certificate));
+            server.bind_rustls_0_23(&bind_address, tls_config)?
+        }
+        None => server.bind(&bind_address)?,
+    }
+    .disable_signals()
+    .run();
+
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!("Shutting down, waiting {:?} before closing", shutdown_grace);
+        readiness.mark_shutting_down();
+        tokio::time::sleep(shutdown_grace).await;
+        handle.stop(true).await;
+    });
+
+    server.await?;
 
     Ok(())
 }
+
+/// Retries `getMe` until Telegram accepts every token
+async fn validate_bot_tokens(bots: bot::Bots, readiness: web::Data<api::Readiness>) {
+    for (name, identity) in bots.iter() {
+        loop {
+            match identity.bot.get_me().await {
+                Ok(me) => {
+                    log::info!("Bot '{}' token validated for @{}", name, me.username());
+                    break;
+                }
+                Err(e) => {
+                    log::warn!("Bot '{}' token validation failed: {}", name, e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+    readiness.mark_bot_validated();
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("SIGTERM handler should install");
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    ctrl_c.await.ok();
+}
@@ -0,0 +1,514 @@
+//! Photos, videos and other files sent to a channel's subscribers
+
+use bytes::Bytes;
+use futures::stream::{Stream, StreamExt};
+use serde::Deserialize;
+use teloxide::prelude::*;
+use teloxide::types::{
+    FileId, InlineKeyboardMarkup, InputFile, InputMedia, InputMediaPhoto, InputMediaVideo,
+    MessageEntity, MessageId, ParseMode, StickerSet,
+};
+
+use crate::bot::Bots;
+use crate::config::RetryConfig;
+use crate::db::Recipient;
+use crate::delivery::{Failure, text_len};
+use crate::lanes::{Lanes, Priority};
+
+/// Telegram's limit on media captions
+pub const MAX_CAPTION_CHARS: usize = 1024;
+/// Telegram's bounds on the number of items in an album
+const ALBUM_SIZE: std::ops::RangeInclusive<usize> = 2..=10;
+
+/// Caption and its formatting, accepted by every media message that has one
+#[derive(Deserialize, Default)]
+pub struct MediaOptions {
+    pub caption: Option<String>,
+    pub parse_mode: Option<ParseMode>,
+    /// Formatting given as Telegram message entities, instead of `parse_mode`
+    pub caption_entities: Option<Vec<MessageEntity>>,
+}
+
+impl MediaOptions {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self
+            .caption
+            .as_ref()
+            .is_some_and(|caption| text_len(caption) > MAX_CAPTION_CHARS)
+        {
+            return Err("Caption too long (max 1024 chars)");
+        }
+        if self.parse_mode.is_some() && self.caption_entities.is_some() {
+            return Err("Use either parse_mode or caption_entities");
+        }
+        if self.caption.is_none() && (self.parse_mode.is_some() || self.caption_entities.is_some())
+        {
+            return Err("Formatting requires a caption");
+        }
+        Ok(())
+    }
+}
+
+/// Sets the caption fields of a Telegram request or input media from [`MediaOptions`]
+macro_rules! with_options {
+    ($target:expr, $options:expr) => {
+        $target.caption = $options.caption.clone();
+        $target.parse_mode = $options.parse_mode;
+        $target.caption_entities = $options.caption_entities.clone();
+    };
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumItemKind {
+    Photo,
+    Video,
+}
+
+#[derive(Deserialize)]
+pub struct AlbumItem {
+    #[serde(rename = "type")]
+    pub kind: AlbumItemKind,
+    /// URL Telegram downloads the file from, or the file_id of a file it already has
+    pub media: String,
+    #[serde(flatten)]
+    pub options: MediaOptions,
+}
+
+#[derive(Deserialize)]
+pub struct NewMediaGroup {
+    pub channel_name: String,
+    pub media: Vec<AlbumItem>,
+}
+
+impl NewMediaGroup {
+    /// `local_bot_api` allows `file://` paths, which only a local Bot API server reads
+    pub fn validate(&self, local_bot_api: bool) -> Result<(), &'static str> {
+        if !ALBUM_SIZE.contains(&self.media.len()) {
+            return Err("An album needs between 2 and 10 photos or videos");
+        }
+        for item in &self.media {
+            if item.media.trim().is_empty() {
+                return Err("Media cannot be empty");
+            }
+            if is_local_path(&item.media) && !local_bot_api {
+                return Err(LOCAL_PATH_UNSUPPORTED);
+            }
+            item.options.validate()?;
+        }
+        Ok(())
+    }
+
+    fn input_media(&self) -> Vec<InputMedia> {
+        self.media
+            .iter()
+            .map(|item| {
+                let file = input_file(&item.media);
+                match item.kind {
+                    AlbumItemKind::Photo => {
+                        let mut photo = InputMediaPhoto::new(file);
+                        with_options!(photo, item.options);
+                        InputMedia::Photo(photo)
+                    }
+                    AlbumItemKind::Video => {
+                        let mut video = InputMediaVideo::new(file);
+                        with_options!(video, item.options);
+                        InputMedia::Video(video)
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Telegram's limit on files uploaded by bots
+const MAX_UPLOAD_BYTES: usize = 50 * 1024 * 1024;
+/// Limit on files uploaded through a Bot API server running with `--local`
+const MAX_LOCAL_UPLOAD_BYTES: usize = 2000 * 1024 * 1024;
+
+/// Largest file the proxy accepts for upload
+pub fn max_upload_bytes(local_bot_api: bool) -> usize {
+    if local_bot_api {
+        MAX_LOCAL_UPLOAD_BYTES
+    } else {
+        MAX_UPLOAD_BYTES
+    }
+}
+
+/// Kind of a single-file media message
+#[derive(Clone, Copy)]
+pub enum MediaKind {
+    Animation,
+    Voice,
+    Audio,
+    Video,
+}
+
+/// Where the file of a media message comes from
+#[derive(Deserialize, Clone)]
+#[serde(from = "String")]
+pub enum MediaSource {
+    /// URL Telegram downloads the file from, or the file_id of a file it already has
+    Reference(String),
+    /// File uploaded to the proxy, sent along with each message
+    Upload { file_name: String, data: Bytes },
+}
+
+impl From<String> for MediaSource {
+    fn from(reference: String) -> Self {
+        MediaSource::Reference(reference)
+    }
+}
+
+impl MediaSource {
+    fn input_file(&self) -> InputFile {
+        match self {
+            MediaSource::Reference(reference) => input_file(reference),
+            MediaSource::Upload { file_name, data } => {
+                InputFile::memory(data.clone()).file_name(file_name.clone())
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            MediaSource::Reference(reference) => reference.trim().is_empty(),
+            MediaSource::Upload { data, .. } => data.is_empty(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewMedia {
+    pub channel_name: String,
+    pub media: MediaSource,
+    #[serde(flatten)]
+    pub options: MediaOptions,
+    /// For videos, whether they can start playing before fully downloaded
+    #[serde(default)]
+    pub supports_streaming: bool,
+}
+
+impl NewMedia {
+    /// `local_bot_api` allows `file://` paths, which only a local Bot API server reads
+    pub fn validate(&self, local_bot_api: bool) -> Result<(), &'static str> {
+        if self.media.is_empty() {
+            return Err("Media cannot be empty");
+        }
+        if let MediaSource::Reference(reference) = &self.media
+            && is_local_path(reference)
+            && !local_bot_api
+        {
+            return Err(LOCAL_PATH_UNSUPPORTED);
+        }
+        self.options.validate()
+    }
+}
+
+/// Sends the file from `source` with the caption and flags of `media` to every
+/// recipient, yielding each as it completes with the Telegram message it got,
+/// or why delivery failed, with `keyboard` under it
+#[allow(clippy::too_many_arguments)]
+pub fn send_media_each<'a>(
+    bots: &'a Bots,
+    lanes: &'a Lanes,
+    retry: &'a RetryConfig,
+    recipients: impl Stream<Item = Recipient> + 'a,
+    kind: MediaKind,
+    media: &'a NewMedia,
+    source: &'a MediaSource,
+    keyboard: &'a Option<InlineKeyboardMarkup>,
+) -> impl Stream<Item = (Recipient, Result<Message, Failure>)> + 'a {
+    crate::delivery::send_each(
+        bots,
+        lanes,
+        retry,
+        Priority::default(),
+        recipients,
+        move |bot, chat_id| async move {
+            let file = source.input_file();
+            match kind {
+                MediaKind::Animation => {
+                    let mut request = bot.send_animation(chat_id, file);
+                    with_options!(request, media.options);
+                    request.reply_markup = keyboard.clone().map(Into::into);
+                    request.await
+                }
+                MediaKind::Voice => {
+                    let mut request = bot.send_voice(chat_id, file);
+                    with_options!(request, media.options);
+                    request.reply_markup = keyboard.clone().map(Into::into);
+                    request.await
+                }
+                MediaKind::Audio => {
+                    let mut request = bot.send_audio(chat_id, file);
+                    with_options!(request, media.options);
+                    request.reply_markup = keyboard.clone().map(Into::into);
+                    request.await
+                }
+                MediaKind::Video => {
+                    let mut request = bot
+                        .send_video(chat_id, file)
+                        .supports_streaming(media.supports_streaming);
+                    with_options!(request, media.options);
+                    request.reply_markup = keyboard.clone().map(Into::into);
+                    request.await
+                }
+            }
+        },
+    )
+}
+
+/// file_id Telegram assigned to the file of a sent media message, for sending it
+/// again without uploading
+pub fn sent_file_id(message: &Message, kind: MediaKind) -> Option<FileId> {
+    let file = match kind {
+        MediaKind::Animation => message.animation().map(|animation| &animation.file),
+        MediaKind::Voice => message.voice().map(|voice| &voice.file),
+        MediaKind::Audio => message.audio().map(|audio| &audio.file),
+        MediaKind::Video => message.video().map(|video| &video.file),
+    };
+    // Telegram sends some files as documents, e.g. animations it can't convert
+    file.or_else(|| message.document().map(|document| &document.file))
+        .map(|file| file.id.clone())
+}
+
+#[derive(Deserialize)]
+pub struct NewSticker {
+    pub channel_name: String,
+    /// file_id of the sticker
+    pub sticker: Option<String>,
+    /// Sticker set to pick the sticker for `emoji` from, instead of a file_id
+    pub set_name: Option<String>,
+    pub emoji: Option<String>,
+}
+
+impl NewSticker {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match (&self.sticker, &self.set_name, &self.emoji) {
+            (Some(sticker), None, None) if !sticker.trim().is_empty() => Ok(()),
+            (None, Some(set_name), Some(emoji))
+                if !set_name.trim().is_empty() && !emoji.is_empty() =>
+            {
+                Ok(())
+            }
+            _ => Err("Provide either a sticker file_id or a set_name and emoji"),
+        }
+    }
+}
+
+/// First sticker of the set matching the emoji
+pub fn pick_sticker(set: &StickerSet, emoji: &str) -> Option<FileId> {
+    set.stickers
+        .iter()
+        .find(|sticker| sticker.emoji.as_deref() == Some(emoji))
+        .map(|sticker| sticker.file.id.clone())
+}
+
+/// Sends the sticker to every recipient, yielding each as it completes with the
+/// id of the Telegram message it got, or why delivery failed, with `keyboard` under it
+pub fn send_sticker_each<'a>(
+    bots: &'a Bots,
+    lanes: &'a Lanes,
+    retry: &'a RetryConfig,
+    recipients: impl Stream<Item = Recipient> + 'a,
+    sticker: &'a FileId,
+    keyboard: &'a Option<InlineKeyboardMarkup>,
+) -> impl Stream<Item = (Recipient, Result<MessageId, Failure>)> + 'a {
+    crate::delivery::send_each(
+        bots,
+        lanes,
+        retry,
+        Priority::default(),
+        recipients,
+        move |bot, chat_id| async move {
+            let mut request = bot.send_sticker(chat_id, InputFile::file_id(sticker.clone()));
+            request.reply_markup = keyboard.clone().map(Into::into);
+            Ok(request.await?.id)
+        },
+    )
+}
+
+/// A URL for Telegram to fetch, or a `file://` path a local Bot API server reads,
+/// anything else is taken as a file_id
+pub fn input_file(media: &str) -> InputFile {
+    match reqwest::Url::parse(media) {
+        Ok(url) if matches!(url.scheme(), "http" | "https" | "file") => InputFile::url(url),
+        _ => InputFile::file_id(FileId(media.to_string())),
+    }
+}
+
+const LOCAL_PATH_UNSUPPORTED: &str = "file:// paths require LOCAL_BOT_API";
+
+fn is_local_path(media: &str) -> bool {
+    reqwest::Url::parse(media).is_ok_and(|url| url.scheme() == "file")
+}
+
+/// Sends the album to every recipient, yielding each as it completes with the
+/// id of its first Telegram message, or why delivery failed
+pub fn send_group_each<'a>(
+    bots: &'a Bots,
+    lanes: &'a Lanes,
+    retry: &'a RetryConfig,
+    recipients: impl Stream<Item = Recipient> + 'a,
+    group: &'a NewMediaGroup,
+) -> impl Stream<Item = (Recipient, Result<MessageId, Failure>)> + 'a {
+    crate::delivery::send_each(
+        bots,
+        lanes,
+        retry,
+        Priority::default(),
+        recipients,
+        move |bot, chat_id| async move { bot.send_media_group(chat_id, group.input_media()).await },
+    )
+    .map(|(recipient, sent)| {
+        let first = sent.and_then(|messages| {
+            messages
+                .first()
+                .map(|message| message.id)
+                .ok_or(Failure::Other)
+        });
+        (recipient, first)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(kind: AlbumItemKind, media: &str) -> AlbumItem {
+        AlbumItem {
+            kind,
+            media: media.to_string(),
+            options: MediaOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_input_file() {
+        assert_eq!(
+            serde_json::to_value(input_file("AgACAgIAAxkBAAI")).unwrap(),
+            "AgACAgIAAxkBAAI"
+        );
+        assert_eq!(
+            serde_json::to_value(input_file("https://example.com/a.png")).unwrap(),
+            "https://example.com/a.png"
+        );
+    }
+
+    #[test]
+    fn test_pick_sticker() {
+        let sticker = |file_id: &str, emoji: &str| {
+            serde_json::json!({
+                "file_id": file_id,
+                "file_unique_id": file_id,
+                "file_size": 100,
+                "width": 512,
+                "height": 512,
+                "type": "regular",
+                "is_animated": false,
+                "is_video": false,
+                "emoji": emoji,
+            })
+        };
+        let set: StickerSet = serde_json::from_value(serde_json::json!({
+            "name": "party",
+            "title": "Party",
+            "sticker_type": "regular",
+            "stickers": [sticker("a", "🎉"), sticker("b", "🚀"), sticker("c", "🚀")],
+        }))
+        .unwrap();
+
+        assert_eq!(pick_sticker(&set, "🚀"), Some(FileId("b".to_string())));
+        assert_eq!(pick_sticker(&set, "🐛"), None);
+    }
+
+    #[test]
+    fn test_validate_media() {
+        let mut media = NewMedia {
+            channel_name: "status".to_string(),
+            media: MediaSource::from("https://example.com/deploy.gif".to_string()),
+            options: MediaOptions {
+                caption: Some("Deploy *finished*".to_string()),
+                parse_mode: Some(ParseMode::MarkdownV2),
+                caption_entities: None,
+            },
+            supports_streaming: false,
+        };
+        assert!(media.validate(false).is_ok());
+
+        media.options.caption_entities = Some(Vec::new());
+        assert!(media.validate(false).is_err());
+
+        media.options = MediaOptions {
+            caption: Some("x".repeat(1025)),
+            ..Default::default()
+        };
+        assert!(media.validate(false).is_err());
+
+        media.options.caption = None;
+        media.media = MediaSource::from("file:///srv/reports/nightly.pdf".to_string());
+        assert!(media.validate(false).is_err());
+        assert!(media.validate(true).is_ok());
+
+        media.media = MediaSource::Upload {
+            file_name: "note.ogg".to_string(),
+            data: Bytes::new(),
+        };
+        assert!(media.validate(false).is_err());
+    }
+
+    #[test]
+    fn test_media_options() {
+        let media: NewMedia = serde_json::from_value(serde_json::json!({
+            "channel_name": "status",
+            "media": "https://example.com/deploy.gif",
+            "caption": "Deploy finished",
+            "caption_entities": [{"type": "bold", "offset": 0, "length": 6}],
+        }))
+        .unwrap();
+        assert_eq!(media.options.caption_entities.map(|e| e.len()), Some(1));
+
+        let options = MediaOptions {
+            parse_mode: Some(ParseMode::Html),
+            ..Default::default()
+        };
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_sticker() {
+        let mut sticker = NewSticker {
+            channel_name: "community".to_string(),
+            sticker: Some("CAACAgIAAxkBAAE".to_string()),
+            set_name: None,
+            emoji: None,
+        };
+        assert!(sticker.validate().is_ok());
+
+        sticker.set_name = Some("party".to_string());
+        assert!(sticker.validate().is_err());
+
+        sticker.sticker = None;
+        assert!(sticker.validate().is_err());
+        sticker.emoji = Some("🎉".to_string());
+        assert!(sticker.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut group = NewMediaGroup {
+            channel_name: "releases".to_string(),
+            media: vec![item(AlbumItemKind::Photo, "https://example.com/a.png")],
+        };
+        assert!(group.validate(false).is_err());
+
+        group
+            .media
+            .push(item(AlbumItemKind::Video, "https://example.com/b.mp4"));
+        assert!(group.validate(false).is_ok());
+
+        group.media[1].options.caption = Some("x".repeat(1025));
+        assert!(group.validate(false).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Delivers MQTT publishes to the channels their topic is mapped to
+
+use std::time::Duration;
+
+use anyhow::Result;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+
+use crate::config::{MqttConfig, MqttRule};
+use crate::service::{Services, Tenant};
+
+pub async fn consume(config: MqttConfig, services: Services) -> Result<()> {
+    if config.rules.is_empty() {
+        anyhow::bail!("MQTT is configured without any topic rules");
+    }
+    if let Some(rule) = config
+        .rules
+        .iter()
+        .find(|r| !rumqttc::valid_filter(&r.topic))
+    {
+        anyhow::bail!("Invalid MQTT topic filter '{}'", rule.topic);
+    }
+
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(options, 100);
+    log::info!(
+        "Consuming MQTT from {}:{} for tenant '{}'",
+        config.host,
+        config.port,
+        config.tenant
+    );
+
+    loop {
+        match eventloop.poll().await {
+            // Subscriptions don't survive a clean session, renew them on every connect
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                for rule in &config.rules {
+                    client.subscribe(&rule.topic, QoS::AtLeastOnce).await?;
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let message = String::from_utf8_lossy(&publish.payload).into_owned();
+                for channel_name in channels_for(&config.rules, &publish.topic) {
+                    let services = services.clone();
+                    let tenant = Tenant::new(&config.tenant);
+                    let channel_name = channel_name.to_string();
+                    let message = message.clone();
+                    // Delivering inline would stall the event loop and its keep-alives
+                    tokio::spawn(async move {
+                        if let Err(e) = services
+                            .send_message(&tenant, &channel_name, &message)
+                            .await
+                        {
+                            log::error!("Dropping MQTT message for '{}': {}", channel_name, e);
+                        }
+                    });
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                // The next poll reconnects
+                log::warn!("MQTT connection error: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+/// Distinct channels of every rule whose filter matches the topic
+fn channels_for<'a>(rules: &'a [MqttRule], topic: &str) -> Vec<&'a str> {
+    let mut channels = Vec::new();
+    for rule in rules {
+        if rumqttc::matches(topic, &rule.topic) && !channels.contains(&rule.channel.as_str()) {
+            channels.push(rule.channel.as_str());
+        }
+    }
+    channels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(topic: &str, channel: &str) -> MqttRule {
+        MqttRule {
+            topic: topic.to_string(),
+            channel: channel.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_channels_for() {
+        let rules = vec![
+            rule("home/+/temperature", "sensors"),
+            rule("home/#", "home"),
+            rule("home/kitchen/temperature", "sensors"),
+        ];
+
+        assert_eq!(
+            channels_for(&rules, "home/kitchen/temperature"),
+            vec!["sensors", "home"]
+        );
+        assert_eq!(channels_for(&rules, "home/door"), vec!["home"]);
+        assert!(channels_for(&rules, "garden/humidity").is_empty());
+    }
+}
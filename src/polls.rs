@@ -0,0 +1,214 @@
+//! Native Telegram polls and their aggregated results
+
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardMarkup, InputPollOption};
+
+use crate::bot::Bots;
+use crate::config::RetryConfig;
+use crate::db::{PollCopyState, Recipient, StoredPoll};
+use crate::delivery::{Failure, text_len};
+use crate::lanes::{Lanes, Priority};
+
+const MAX_QUESTION_CHARS: usize = 300;
+const MAX_OPTION_CHARS: usize = 100;
+const MAX_OPTIONS: usize = 10;
+
+#[derive(Deserialize)]
+pub struct NewPoll {
+    pub channel_name: String,
+    pub question: String,
+    pub options: Vec<String>,
+    /// Telegram's default, answers of anonymous polls can only be counted per option
+    #[serde(default = "default_anonymous")]
+    pub is_anonymous: bool,
+    #[serde(default)]
+    pub allows_multiple_answers: bool,
+}
+
+fn default_anonymous() -> bool {
+    true
+}
+
+impl NewPoll {
+    /// Checks the limits Telegram puts on polls
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.question.trim().is_empty() {
+            return Err("Question cannot be empty");
+        }
+        if text_len(&self.question) > MAX_QUESTION_CHARS {
+            return Err("Question too long (max 300 chars)");
+        }
+        if !(2..=MAX_OPTIONS).contains(&self.options.len()) {
+            return Err("A poll needs between 2 and 10 options");
+        }
+        if self
+            .options
+            .iter()
+            .any(|option| option.trim().is_empty() || text_len(option) > MAX_OPTION_CHARS)
+        {
+            return Err("Options must be between 1 and 100 chars");
+        }
+        Ok(())
+    }
+}
+
+/// Sends the poll to every recipient, yielding each as it completes with the
+/// Telegram poll id of its copy, or why delivery failed, with `keyboard` under it
+pub fn send_each<'a>(
+    bots: &'a Bots,
+    lanes: &'a Lanes,
+    retry: &'a RetryConfig,
+    recipients: impl Stream<Item = Recipient> + 'a,
+    poll: &'a NewPoll,
+    keyboard: &'a Option<InlineKeyboardMarkup>,
+) -> impl Stream<Item = (Recipient, Result<String, Failure>)> + 'a {
+    crate::delivery::send_each(
+        bots,
+        lanes,
+        retry,
+        Priority::default(),
+        recipients,
+        move |bot, chat_id| async move {
+            let options = poll.options.iter().map(InputPollOption::new);
+            let mut request = bot
+                .send_poll(chat_id, &poll.question, options)
+                .is_anonymous(poll.is_anonymous)
+                .allows_multiple_answers(poll.allows_multiple_answers);
+            request.reply_markup = keyboard.clone().map(Into::into);
+            request.await
+        },
+    )
+    .map(|(recipient, sent)| {
+        let poll_id =
+            sent.and_then(|message| message.poll().map(|p| p.id.0.clone()).ok_or(Failure::Other));
+        (recipient, poll_id)
+    })
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OptionResult {
+    pub text: String,
+    pub votes: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PollResults {
+    pub id: i64,
+    pub question: String,
+    pub is_anonymous: bool,
+    pub options: Vec<OptionResult>,
+    /// People who answered across every copy
+    pub voters: u64,
+    /// Recipients the poll was delivered to
+    pub recipients: u64,
+}
+
+/// Aggregates the answers of a poll: recorded answers when it isn't anonymous,
+/// otherwise the per-option counts of every copy
+pub fn tally(
+    poll: &StoredPoll,
+    answers: &[String],
+    states: &[PollCopyState],
+    recipients: u64,
+) -> anyhow::Result<PollResults> {
+    let texts: Vec<String> = serde_json::from_str(&poll.options)?;
+    let mut votes = vec![0u64; texts.len()];
+    let voters = if poll.is_anonymous {
+        for state in states {
+            let counts: Vec<u64> = serde_json::from_str(&state.voter_counts)?;
+            for (total, count) in votes.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+        states.iter().map(|state| state.total_voters as u64).sum()
+    } else {
+        for answer in answers {
+            let option_ids: Vec<usize> = serde_json::from_str(answer)?;
+            for id in option_ids {
+                if let Some(total) = votes.get_mut(id) {
+                    *total += 1;
+                }
+            }
+        }
+        answers.len() as u64
+    };
+
+    Ok(PollResults {
+        id: poll.id,
+        question: poll.question.clone(),
+        is_anonymous: poll.is_anonymous,
+        options: texts
+            .into_iter()
+            .zip(votes)
+            .map(|(text, votes)| OptionResult { text, votes })
+            .collect(),
+        voters,
+        recipients,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(is_anonymous: bool) -> StoredPoll {
+        StoredPoll {
+            id: 1,
+            question: "Lunch?".to_string(),
+            options: r#"["Pizza","Sushi"]"#.to_string(),
+            is_anonymous,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut new_poll = NewPoll {
+            channel_name: "team".to_string(),
+            question: "Lunch?".to_string(),
+            options: vec!["Pizza".to_string(), "Sushi".to_string()],
+            is_anonymous: true,
+            allows_multiple_answers: false,
+        };
+        assert!(new_poll.validate().is_ok());
+
+        new_poll.options.truncate(1);
+        assert!(new_poll.validate().is_err());
+
+        new_poll.options = vec!["Pizza".to_string(), " ".to_string()];
+        assert!(new_poll.validate().is_err());
+
+        new_poll.options = vec!["Pizza".to_string(), "Sushi".to_string()];
+        new_poll.question = "?".repeat(301);
+        assert!(new_poll.validate().is_err());
+    }
+
+    #[test]
+    fn test_tally_answers() {
+        let answers = ["[0]".to_string(), "[0,1]".to_string(), "[5]".to_string()];
+        let results = tally(&poll(false), &answers, &[], 4).unwrap();
+        assert_eq!(results.options[0].votes, 2);
+        assert_eq!(results.options[1].votes, 1);
+        assert_eq!(results.voters, 3);
+        assert_eq!(results.recipients, 4);
+    }
+
+    #[test]
+    fn test_tally_anonymous() {
+        let states = [
+            PollCopyState {
+                voter_counts: "[1,0]".to_string(),
+                total_voters: 1,
+            },
+            PollCopyState {
+                voter_counts: "[2,3]".to_string(),
+                total_voters: 4,
+            },
+        ];
+        let results = tally(&poll(true), &[], &states, 2).unwrap();
+        assert_eq!(results.options[0].votes, 3);
+        assert_eq!(results.options[1].votes, 3);
+        assert_eq!(results.voters, 5);
+    }
+}
@@ -0,0 +1,45 @@
+//! Removes or flags subscribers who can no longer be reached
+
+use std::time::Duration;
+
+use crate::config::{PruneAction, PruningConfig};
+use crate::service::Services;
+
+/// How often the job looks for subscribers to prune
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+pub async fn run(services: Services, config: PruningConfig) {
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let failing_before = chrono::Utc::now().timestamp() - config.after_days as i64 * DAY_SECS;
+        let remove = config.action == PruneAction::Remove;
+        match crate::db::prune_subscribers(&services.pool, failing_before, config.blocked, remove)
+            .await
+        {
+            Ok(pruned) => {
+                for subscriber in pruned {
+                    log::info!(
+                        "Subscriber {} of tenant '{}' {} ({})",
+                        subscriber.telegram_id,
+                        subscriber.tenant_id,
+                        subscriber.action,
+                        subscriber.reason
+                    );
+                    if remove {
+                        for channel_name in subscriber.channel_names() {
+                            services.publish_unsubscribed(
+                                &subscriber.tenant_id,
+                                &channel_name,
+                                [subscriber.telegram_id],
+                            );
+                        }
+                    }
+                }
+            }
+            Err(e) => log::error!("Database error: {}", e),
+        }
+    }
+}
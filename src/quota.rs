@@ -0,0 +1,106 @@
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::config::QuotaConfig;
+use crate::db::ApiKey;
+
+#[derive(Debug, Serialize)]
+pub struct Allowance {
+    used: i64,
+    /// `None` means unlimited
+    limit: Option<i64>,
+    remaining: Option<i64>,
+}
+
+impl Allowance {
+    fn new(used: i64, limit: Option<i64>) -> Self {
+        Self {
+            used,
+            limit,
+            remaining: limit.map(|limit| (limit - used).max(0)),
+        }
+    }
+
+    fn allows(&self, messages: i64) -> bool {
+        self.remaining.is_none_or(|remaining| messages <= remaining)
+    }
+}
+
+/// Today's (UTC) usage for the tenant and, when the request used one, the API key
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    tenant: Allowance,
+    api_key: Option<Allowance>,
+}
+
+impl Usage {
+    pub async fn today(
+        pool: &SqlitePool,
+        config: &QuotaConfig,
+        tenant_id: &str,
+        api_key: Option<&ApiKey>,
+    ) -> Result<Self> {
+        let tenant = Allowance::new(
+            crate::db::get_tenant_usage_today(pool, tenant_id).await?,
+            config.tenant_limit(tenant_id),
+        );
+
+        let api_key = match api_key {
+            Some(key) => Some(Allowance::new(
+                crate::db::get_api_key_usage_today(pool, key.id).await?,
+                key.daily_quota,
+            )),
+            None => None,
+        };
+
+        Ok(Self { tenant, api_key })
+    }
+
+    /// Whether `messages` more can be sent without exceeding any quota
+    pub fn allows(&self, messages: usize) -> bool {
+        let messages = messages as i64;
+        self.tenant.allows(messages) && self.api_key.as_ref().is_none_or(|a| a.allows(messages))
+    }
+
+    pub fn remaining(&self) -> Option<i64> {
+        let key_remaining = self.api_key.as_ref().and_then(|a| a.remaining);
+        match (self.tenant.remaining, key_remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_allows_anything() {
+        let usage = Usage {
+            tenant: Allowance::new(1_000_000, None),
+            api_key: None,
+        };
+        assert!(usage.allows(1_000_000));
+        assert_eq!(usage.remaining(), None);
+    }
+
+    #[test]
+    fn test_tightest_quota_wins() {
+        let usage = Usage {
+            tenant: Allowance::new(90, Some(100)),
+            api_key: Some(Allowance::new(0, Some(50))),
+        };
+        assert_eq!(usage.remaining(), Some(10));
+        assert!(usage.allows(10));
+        assert!(!usage.allows(11));
+    }
+
+    #[test]
+    fn test_remaining_never_negative() {
+        let allowance = Allowance::new(120, Some(100));
+        assert_eq!(allowance.remaining, Some(0));
+        assert!(!allowance.allows(1));
+    }
+}
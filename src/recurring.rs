@@ -0,0 +1,107 @@
+//! Sends recurring messages when their cron schedule comes due
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+
+use crate::service::{Services, Tenant};
+
+/// How often the scheduler looks for due messages
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Parses a standard 5-field cron expression, or the 6/7-field form with seconds
+pub fn parse_schedule(cron: &str) -> Result<Schedule, String> {
+    let cron = cron.trim();
+    let expression = if cron.split_whitespace().count() == 5 {
+        format!("0 {}", cron)
+    } else {
+        cron.to_string()
+    };
+    Schedule::from_str(&expression).map_err(|e| e.to_string())
+}
+
+pub fn parse_timezone(timezone: &str) -> Result<Tz, String> {
+    Tz::from_str(timezone).map_err(|_| format!("Unknown timezone '{}'", timezone))
+}
+
+/// Unix time of the first occurrence after `after`, `None` if the schedule has ended
+pub fn next_run(schedule: &Schedule, timezone: Tz, after: DateTime<Utc>) -> Option<i64> {
+    schedule
+        .after(&after.with_timezone(&timezone))
+        .next()
+        .map(|at| at.timestamp())
+}
+
+pub async fn run(services: Services) {
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let now = Utc::now();
+        let due = match crate::db::due_recurring_messages(&services.pool, now.timestamp()).await {
+            Ok(due) => due,
+            Err(e) => {
+                log::error!("Database error: {}", e);
+                continue;
+            }
+        };
+
+        for message in due {
+            // Rows are validated on write, a bad one just stops recurring
+            let next = match (
+                parse_schedule(&message.cron),
+                parse_timezone(&message.timezone),
+            ) {
+                (Ok(schedule), Ok(timezone)) => next_run(&schedule, timezone, now),
+                _ => None,
+            };
+
+            // Advanced before sending, so a crash skips a run instead of repeating it
+            if let Err(e) =
+                crate::db::set_recurring_next_run(&services.pool, message.id, next).await
+            {
+                log::error!("Database error: {}", e);
+                continue;
+            }
+
+            let tenant = Tenant::new(&message.tenant_id);
+            if let Err(e) = services
+                .send_message(&tenant, &message.channel_name, &message.body)
+                .await
+            {
+                log::warn!("Recurring message {} failed: {}", message.id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_five_field_cron() {
+        let schedule = parse_schedule("30 9 * * Mon").unwrap();
+        let timezone = parse_timezone("Europe/Rome").unwrap();
+        // Friday 2026-10-16 12:00 UTC
+        let now = DateTime::parse_from_rfc3339("2026-10-16T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let next = next_run(&schedule, timezone, now).unwrap();
+        // Monday 09:30 in Rome is 07:30 UTC (CEST)
+        assert_eq!(
+            DateTime::from_timestamp(next, 0).unwrap().to_rfc3339(),
+            "2026-10-19T07:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(parse_schedule("not a cron").is_err());
+        assert!(parse_timezone("Mars/Olympus").is_err());
+        assert!(parse_timezone("UTC").is_ok());
+    }
+}
@@ -0,0 +1,37 @@
+//! Forwards messages published on Redis channels to the proxy channel of the same name
+
+use anyhow::Result;
+use futures::StreamExt;
+
+use crate::config::RedisBridgeConfig;
+use crate::service::{Services, Tenant};
+
+pub async fn consume(config: RedisBridgeConfig, services: Services) -> Result<()> {
+    let client = redis::Client::open(config.url.as_str())?;
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.psubscribe(&config.pattern).await?;
+    log::info!(
+        "Forwarding Redis channels matching '{}' to tenant '{}'",
+        config.pattern,
+        config.tenant
+    );
+
+    let tenant = Tenant::new(&config.tenant);
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let channel_name = msg.get_channel_name();
+        let message: String = match msg.get_payload() {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Dropping Redis message on '{}': {}", channel_name, e);
+                continue;
+            }
+        };
+
+        if let Err(e) = services.send_message(&tenant, channel_name, &message).await {
+            log::error!("Dropping Redis message on '{}': {}", channel_name, e);
+        }
+    }
+
+    anyhow::bail!("Redis subscription to '{}' closed", config.pattern)
+}
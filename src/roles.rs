@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+/// What a user may do, each role allowing everything the ones before it do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Read channels, messages and stats
+    Viewer,
+    /// Also manage channel settings, hooks, feeds and recurring messages
+    ChannelOwner,
+    /// Everything the admin key can do, including broadcasts, API keys and users
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::ChannelOwner => "channel_owner",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl TryFrom<&str> for Role {
+    type Error = anyhow::Error;
+
+    fn try_from(role: &str) -> Result<Self, Self::Error> {
+        match role {
+            "viewer" => Ok(Role::Viewer),
+            "channel_owner" => Ok(Role::ChannelOwner),
+            "admin" => Ok(Role::Admin),
+            _ => Err(anyhow::anyhow!("Unknown role '{}'", role)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles() {
+        for role in [Role::Viewer, Role::ChannelOwner, Role::Admin] {
+            assert_eq!(Role::try_from(role.as_str()).unwrap(), role);
+        }
+        assert!(Role::try_from("owner").is_err());
+        assert!(Role::Admin > Role::ChannelOwner && Role::ChannelOwner > Role::Viewer);
+    }
+}
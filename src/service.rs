@@ -0,0 +1,1805 @@
+//! Request handling shared by the REST and gRPC APIs
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::StreamExt;
+use futures::stream::{FuturesUnordered, Stream};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use teloxide::RequestError;
+use teloxide::prelude::*;
+use teloxide::types::{FileId, InlineKeyboardMarkup, MessageId, ParseMode};
+
+use crate::bot::Bots;
+use crate::config::Config;
+use crate::db::{
+    ApiKey, BroadcastJob, ChannelSettings, DEFAULT_TENANT, MessageCopy, Reach, RecentSend,
+    Recipient, StoredMessage, SubscriptionRow,
+};
+use crate::delivery::{
+    CallbackButton, DeliveryReport, Failure, MAX_MESSAGE_CHARS, PerChat, Recipients, SendOptions,
+    link_preview, text_len,
+};
+use crate::events::{EventBus, EventKind};
+use crate::jobs::{BroadcastJobs, JobProgress};
+use crate::lanes::{Lanes, Priority};
+use crate::links::TrackedLinks;
+use crate::locations::NewLocation;
+use crate::media::{MediaKind, MediaSource, NewMedia, NewMediaGroup, NewSticker};
+use crate::polls::{NewPoll, PollResults};
+use crate::quota::Usage;
+use crate::roles::Role;
+use crate::throttle::ChannelLimits;
+
+/// Stable identifier of why a request failed, for clients to branch on instead of
+/// parsing the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidRequest,
+    ValidationFailed,
+    InvalidJson,
+    InvalidChannelName,
+    InvalidTenantId,
+    InvalidTag,
+    MessageEmpty,
+    MessageTooLong,
+    InvalidSchedule,
+    TemplateFailed,
+    Unauthorized,
+    InvalidSignature,
+    Forbidden,
+    ChannelNotFound,
+    AliasNotFound,
+    SubscriberNotFound,
+    MessageNotFound,
+    BroadcastNotFound,
+    UserNotFound,
+    ApiKeyNotFound,
+    HookNotFound,
+    FeedNotFound,
+    RecurringMessageNotFound,
+    DeadLetterNotFound,
+    PollNotFound,
+    StickerNotFound,
+    LinkNotFound,
+    BroadcastFinished,
+    ChannelExists,
+    BotMismatch,
+    UserExists,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    QuotaExceeded,
+    RateLimited,
+    TelegramUnavailable,
+    TelegramError,
+    DatabaseError,
+    ServerMisconfigured,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::InvalidJson => "INVALID_JSON",
+            ErrorCode::InvalidChannelName => "INVALID_CHANNEL_NAME",
+            ErrorCode::InvalidTenantId => "INVALID_TENANT_ID",
+            ErrorCode::InvalidTag => "INVALID_TAG",
+            ErrorCode::MessageEmpty => "MESSAGE_EMPTY",
+            ErrorCode::MessageTooLong => "MESSAGE_TOO_LONG",
+            ErrorCode::InvalidSchedule => "INVALID_SCHEDULE",
+            ErrorCode::TemplateFailed => "TEMPLATE_FAILED",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::InvalidSignature => "INVALID_SIGNATURE",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::ChannelNotFound => "CHANNEL_NOT_FOUND",
+            ErrorCode::AliasNotFound => "ALIAS_NOT_FOUND",
+            ErrorCode::SubscriberNotFound => "SUBSCRIBER_NOT_FOUND",
+            ErrorCode::MessageNotFound => "MESSAGE_NOT_FOUND",
+            ErrorCode::BroadcastNotFound => "BROADCAST_NOT_FOUND",
+            ErrorCode::UserNotFound => "USER_NOT_FOUND",
+            ErrorCode::ApiKeyNotFound => "API_KEY_NOT_FOUND",
+            ErrorCode::HookNotFound => "HOOK_NOT_FOUND",
+            ErrorCode::FeedNotFound => "FEED_NOT_FOUND",
+            ErrorCode::RecurringMessageNotFound => "RECURRING_MESSAGE_NOT_FOUND",
+            ErrorCode::DeadLetterNotFound => "DEAD_LETTER_NOT_FOUND",
+            ErrorCode::PollNotFound => "POLL_NOT_FOUND",
+            ErrorCode::StickerNotFound => "STICKER_NOT_FOUND",
+            ErrorCode::LinkNotFound => "LINK_NOT_FOUND",
+            ErrorCode::BroadcastFinished => "BROADCAST_FINISHED",
+            ErrorCode::ChannelExists => "CHANNEL_EXISTS",
+            ErrorCode::BotMismatch => "BOT_MISMATCH",
+            ErrorCode::UserExists => "USER_EXISTS",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ErrorCode::UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::TelegramUnavailable => "TELEGRAM_UNAVAILABLE",
+            ErrorCode::TelegramError => "TELEGRAM_ERROR",
+            ErrorCode::DatabaseError => "DATABASE_ERROR",
+            ErrorCode::ServerMisconfigured => "SERVER_MISCONFIGURED",
+        }
+    }
+}
+
+pub enum ServiceError {
+    InvalidRequest(ErrorCode, &'static str),
+    Unauthorized,
+    NotFound(ErrorCode, &'static str),
+    QuotaExceeded {
+        remaining: Option<i64>,
+    },
+    /// A Telegram call the request depends on failed
+    Telegram(Failure),
+    Database(anyhow::Error),
+}
+
+impl ServiceError {
+    /// A rejected request without a more specific [`ErrorCode`]
+    pub fn invalid(message: &'static str) -> Self {
+        ServiceError::InvalidRequest(ErrorCode::InvalidRequest, message)
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ServiceError::InvalidRequest(code, _) | ServiceError::NotFound(code, _) => *code,
+            ServiceError::Unauthorized => ErrorCode::Unauthorized,
+            ServiceError::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            ServiceError::Telegram(Failure::RateLimited) => ErrorCode::RateLimited,
+            ServiceError::Telegram(Failure::Network) => ErrorCode::TelegramUnavailable,
+            ServiceError::Telegram(_) => ErrorCode::TelegramError,
+            ServiceError::Database(_) => ErrorCode::DatabaseError,
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::InvalidRequest(_, message) => write!(f, "{}", message),
+            ServiceError::Unauthorized => write!(f, "Invalid or missing authorization"),
+            ServiceError::NotFound(_, message) => write!(f, "{}", message),
+            ServiceError::QuotaExceeded { .. } => write!(f, "Daily message quota exceeded"),
+            ServiceError::Telegram(Failure::RateLimited) => {
+                write!(f, "Rate limited by Telegram, try again later")
+            }
+            ServiceError::Telegram(Failure::Network) => write!(f, "Telegram could not be reached"),
+            ServiceError::Telegram(failure) => write!(f, "Telegram error: {}", failure.as_str()),
+            ServiceError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ServiceError {
+    fn from(e: anyhow::Error) -> Self {
+        ServiceError::Database(e)
+    }
+}
+
+/// Recipients an upload is tried on before giving up on reusing its file_id
+const MAX_UPLOAD_ATTEMPTS: usize = 3;
+
+/// Telegram only lets bots delete messages for 48 hours after sending
+const DELETE_WINDOW_SECS: i64 = 48 * 60 * 60;
+
+/// Broadcast recipients whose attempts are recorded together, at most this many are
+/// sent again if the process stops before recording them
+const CHECKPOINT_SIZE: usize = 100;
+
+/// Tenant a request acts on: the one owning the API key, the `X-Tenant-Id` header
+/// when using the admin key or a user token, or the default tenant for anonymous requests
+pub struct Tenant {
+    pub id: String,
+    pub api_key: Option<ApiKey>,
+    /// `Admin` for `SUPER_SECRET_KEY`, the user's role for a user token
+    pub role: Option<Role>,
+}
+
+impl Tenant {
+    /// Acting on behalf of the server itself, e.g. for broker ingestion
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            api_key: None,
+            role: None,
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Some(Role::Admin)
+    }
+
+    /// Whether the request carried credentials, anonymous ones don't learn who
+    /// subscribers are
+    pub fn is_authenticated(&self) -> bool {
+        self.role.is_some() || self.api_key.is_some()
+    }
+}
+
+/// State the REST and gRPC handlers share
+#[derive(Clone)]
+pub struct Services {
+    pub pool: SqlitePool,
+    pub bots: Bots,
+    pub events: EventBus,
+    pub config: Arc<Config>,
+    pub jobs: BroadcastJobs,
+    pub lanes: Lanes,
+    pub channel_limits: ChannelLimits,
+}
+
+impl Services {
+    pub async fn authenticate(
+        &self,
+        bearer_token: Option<&str>,
+        tenant_header: Option<&str>,
+    ) -> Result<Tenant, ServiceError> {
+        let Some(token) = bearer_token else {
+            return Ok(Tenant::new(DEFAULT_TENANT));
+        };
+
+        if let Some(role) = self.role(token).await? {
+            let id = tenant_header.unwrap_or(DEFAULT_TENANT);
+            if !crate::db::validate_tenant_id(id) {
+                return Err(ServiceError::InvalidRequest(
+                    ErrorCode::InvalidTenantId,
+                    "Invalid tenant id",
+                ));
+            }
+
+            return Ok(Tenant {
+                id: id.to_string(),
+                api_key: None,
+                role: Some(role),
+            });
+        }
+
+        match crate::db::find_api_key(&self.pool, token).await? {
+            Some(key) => Ok(Tenant {
+                id: key.tenant_id.clone(),
+                api_key: Some(key),
+                role: None,
+            }),
+            None => Err(ServiceError::Unauthorized),
+        }
+    }
+
+    /// Role granted by `SUPER_SECRET_KEY` or a user token, `None` for anything else
+    pub async fn role(&self, token: &str) -> Result<Option<Role>, ServiceError> {
+        let super_secret_key = &self.config.super_secret_key;
+        if !super_secret_key.is_empty() && token == super_secret_key {
+            return Ok(Some(Role::Admin));
+        }
+
+        let user = crate::db::find_user_by_token(&self.pool, token).await?;
+        Ok(user.and_then(|user| user.role()))
+    }
+
+    /// Validates a channel send and resolves its recipients, checking the quota. Waits
+    /// while the channel is at its rate limit.
+    pub async fn prepare_send(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+        message: &str,
+    ) -> Result<Recipients, ServiceError> {
+        self.prepare_tagged_send(tenant, channel_name, message, &[])
+            .await
+    }
+
+    /// Like [`Services::prepare_send`], only to the subscribers having every one of `tags`
+    pub async fn prepare_tagged_send(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+        message: &str,
+        tags: &[String],
+    ) -> Result<Recipients, ServiceError> {
+        if text_len(message) > MAX_MESSAGE_CHARS {
+            return Err(ServiceError::InvalidRequest(
+                ErrorCode::MessageTooLong,
+                "Message too long (max 1000 chars)",
+            ));
+        }
+
+        if let Some(problem) = crate::db::channel_name_error(channel_name) {
+            return Err(ServiceError::InvalidRequest(
+                ErrorCode::InvalidChannelName,
+                problem,
+            ));
+        }
+
+        if let Some(problem) = tags.iter().find_map(|tag| crate::db::tag_error(tag)) {
+            return Err(ServiceError::InvalidRequest(ErrorCode::InvalidTag, problem));
+        }
+
+        let total = crate::db::count_subscribers(&self.pool, &tenant.id, channel_name, tags).await?
+            as usize;
+        if total > 0 {
+            self.check_quota(tenant, total).await?;
+            if let Some(settings) =
+                crate::db::get_channel_settings(&self.pool, &tenant.id, channel_name).await?
+                && let Some(per_minute) = settings.rate_limit_per_minute
+            {
+                self.channel_limits
+                    .wait(&tenant.id, channel_name, per_minute as u32)
+                    .await;
+            }
+        }
+
+        Ok(Recipients::from_db(
+            total,
+            crate::db::stream_subscribers(&self.pool, &tenant.id, channel_name, tags),
+        ))
+    }
+
+    /// Sends a notice about the channel itself, e.g. that it was renamed, to its own
+    /// subscribers. Those who only get it through a channel above it aren't concerned.
+    pub async fn notify_channel(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+        notice: &str,
+    ) -> Result<DeliveryReport, ServiceError> {
+        let total = crate::db::count_direct_subscribers(&self.pool, &tenant.id, channel_name)
+            .await? as usize;
+        if total > 0 {
+            self.check_quota(tenant, total).await?;
+        }
+        let subscribers = Recipients::from_db(
+            total,
+            crate::db::stream_direct_subscribers(&self.pool, &tenant.id, channel_name),
+        );
+        let (message, options) = self
+            .apply_channel_settings(tenant, channel_name, notice, &SendOptions::default())
+            .await?;
+        Ok(self
+            .deliver(
+                tenant,
+                Some(channel_name),
+                subscribers,
+                &message,
+                &options,
+                |_, _, _| {},
+            )
+            .await)
+    }
+
+    /// Validates a broadcast and resolves the tenant's subscribers, only those of
+    /// `channels` when given, checking the quota
+    pub async fn prepare_broadcast(
+        &self,
+        tenant: &Tenant,
+        message: &str,
+        channels: Option<&[String]>,
+    ) -> Result<Recipients, ServiceError> {
+        if message.is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                ErrorCode::MessageEmpty,
+                "Message cannot be empty",
+            ));
+        }
+
+        if text_len(message) > MAX_MESSAGE_CHARS {
+            return Err(ServiceError::InvalidRequest(
+                ErrorCode::MessageTooLong,
+                "Message too long (max 1000 chars)",
+            ));
+        }
+
+        if let Some(channels) = channels {
+            if channels.is_empty() {
+                return Err(ServiceError::invalid("Channels cannot be empty"));
+            }
+            if let Some(problem) = channels
+                .iter()
+                .find_map(|name| crate::db::channel_name_error(name))
+            {
+                return Err(ServiceError::InvalidRequest(
+                    ErrorCode::InvalidChannelName,
+                    problem,
+                ));
+            }
+        }
+        let channels = match channels {
+            Some(channels) => {
+                let mut resolved = Vec::with_capacity(channels.len());
+                for channel_name in channels {
+                    resolved.push(
+                        crate::db::resolve_channel_name(&self.pool, &tenant.id, channel_name)
+                            .await?,
+                    );
+                }
+                Some(resolved)
+            }
+            None => None,
+        };
+        let channels = channels.as_deref();
+
+        let total =
+            crate::db::count_all_subscribers(&self.pool, &tenant.id, channels).await? as usize;
+        if total > 0 {
+            self.check_quota(tenant, total).await?;
+        }
+
+        Ok(Recipients::from_db(
+            total,
+            crate::db::stream_all_subscribers(&self.pool, &tenant.id, channels),
+        ))
+    }
+
+    /// Sends a broadcast only to the Telegram admins, through the tenant's bot, to
+    /// check its formatting. Nothing is stored or counted against the quota.
+    pub async fn preview_broadcast(
+        &self,
+        tenant: &Tenant,
+        message: &str,
+        options: &SendOptions,
+    ) -> Result<DeliveryReport, ServiceError> {
+        let mut telegram_ids = self.config.admin_telegram_ids.clone();
+        telegram_ids.extend(
+            crate::db::list_users(&self.pool)
+                .await?
+                .into_iter()
+                .filter(|user| user.role() == Some(Role::Admin))
+                .filter_map(|user| user.telegram_id),
+        );
+        telegram_ids.sort();
+        telegram_ids.dedup();
+        if telegram_ids.is_empty() {
+            return Err(ServiceError::invalid("No admin Telegram ids to preview to"));
+        }
+
+        let bot = self.bots.for_tenant(&tenant.id);
+        let admins = futures::stream::iter(telegram_ids.into_iter().map(|telegram_id| Recipient {
+            telegram_id,
+            bot: bot.to_string(),
+        }));
+        let options = SendOptions {
+            reply_to: None,
+            priority: Some(Priority::Transactional),
+            ..options.clone()
+        };
+        let per_chat = PerChat {
+            metadata: self.personalization(tenant, &options).await,
+            ..Default::default()
+        };
+        Ok(crate::delivery::deliver_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            admins,
+            message,
+            &options,
+            &per_chat,
+        )
+        .fold(
+            DeliveryReport::default(),
+            |mut report, (recipient, sent)| async move {
+                report.record(recipient.telegram_id, sent.err());
+                report
+            },
+        )
+        .await)
+    }
+
+    pub async fn send_message(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+        message: &str,
+    ) -> Result<DeliveryReport, ServiceError> {
+        self.send_message_with(tenant, channel_name, message, &SendOptions::default(), &[])
+            .await
+    }
+
+    /// Like [`Services::send_message`], with the send parameters chosen in the request,
+    /// only to the subscribers having every one of `tags` when given
+    pub async fn send_message_with(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+        message: &str,
+        options: &SendOptions,
+        tags: &[String],
+    ) -> Result<DeliveryReport, ServiceError> {
+        let channel_name =
+            &crate::db::resolve_channel_name(&self.pool, &tenant.id, channel_name).await?;
+        let subscribers = self
+            .prepare_tagged_send(tenant, channel_name, message, tags)
+            .await?;
+        let (message, options) = self
+            .apply_channel_settings(tenant, channel_name, message, options)
+            .await?;
+        // Dedup covers the whole channel, a targeted send reaches other recipients
+        if tags.is_empty()
+            && let Some(recent) =
+                crate::db::find_recent_send(&self.pool, &tenant.id, channel_name, &message).await?
+        {
+            return Ok(DeliveryReport {
+                sent: recent.sent as usize,
+                errors: recent.errors as usize,
+                message_id: recent.message_id,
+                duplicate: true,
+                ..Default::default()
+            });
+        }
+
+        let report = self
+            .deliver(
+                tenant,
+                Some(channel_name),
+                subscribers,
+                &message,
+                &options,
+                |_, _, _| {},
+            )
+            .await;
+        if !tags.is_empty() {
+            return Ok(report);
+        }
+        let recent = RecentSend {
+            sent: report.sent as i64,
+            errors: report.errors as i64,
+            message_id: report.message_id,
+        };
+        if let Err(e) =
+            crate::db::record_recent_send(&self.pool, &tenant.id, channel_name, &message, &recent)
+                .await
+        {
+            log::error!("Database error: {}", e);
+        }
+        Ok(report)
+    }
+
+    /// Checks the message replied to, then applies the channel's settings: its header
+    /// and footer around `message`, and its defaults for what the request left unset
+    pub async fn apply_channel_settings(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+        message: &str,
+        options: &SendOptions,
+    ) -> Result<(String, SendOptions), ServiceError> {
+        self.check_click_tracking(options)?;
+        if let Some(id) = options.reply_to
+            && crate::db::find_message(&self.pool, &tenant.id, id)
+                .await?
+                .is_none()
+        {
+            return Err(ServiceError::NotFound(
+                ErrorCode::MessageNotFound,
+                "Message to reply to not found",
+            ));
+        }
+
+        let settings = crate::db::get_channel_settings(&self.pool, &tenant.id, channel_name)
+            .await?
+            .unwrap_or_default();
+        let settings = with_footer(settings, options.footer.as_deref());
+        let mut options = options.clone();
+        if options.parse_mode.is_none() {
+            options.parse_mode = settings
+                .parse_mode
+                .as_deref()
+                .and_then(|mode| ParseMode::try_from(mode).ok());
+        }
+        options.silent.get_or_insert(settings.silent);
+        options
+            .protect_content
+            .get_or_insert(settings.protect_content);
+        options
+            .link_preview
+            .get_or_insert_with(|| link_preview(settings.disable_link_preview));
+        options
+            .buttons
+            .extend(channel_buttons(channel_name, &settings));
+        Ok((framed(channel_name, &settings, message), options))
+    }
+
+    /// Keyboard of the buttons the channel adds under messages other than text
+    async fn channel_keyboard(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+    ) -> Result<Option<InlineKeyboardMarkup>, ServiceError> {
+        let settings = crate::db::get_channel_settings(&self.pool, &tenant.id, channel_name)
+            .await?
+            .unwrap_or_default();
+        Ok(crate::delivery::keyboard(&channel_buttons(
+            channel_name,
+            &settings,
+        )))
+    }
+
+    /// Rejects tracking clicks when there's no public URL for the redirects
+    pub fn check_click_tracking(&self, options: &SendOptions) -> Result<(), ServiceError> {
+        if options.track_clicks && self.config.public_url.is_none() {
+            return Err(ServiceError::invalid(
+                "Tracking clicks needs PUBLIC_URL to be set",
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn list_subscriptions(
+        &self,
+        tenant: &Tenant,
+    ) -> Result<Vec<SubscriptionRow>, ServiceError> {
+        Ok(crate::db::list_subscriptions(&self.pool, &tenant.id).await?)
+    }
+
+    /// Delivers to recipients returned by one of the `prepare_*` methods, recording usage,
+    /// the delivered copies and publishing an event per recipient. `on_progress` sees each
+    /// recipient as it completes along with the running report.
+    pub async fn deliver(
+        &self,
+        tenant: &Tenant,
+        channel_name: Option<&str>,
+        subscribers: Recipients,
+        message: &str,
+        options: &SendOptions,
+        on_progress: impl FnMut(&Recipient, bool, &DeliveryReport),
+    ) -> DeliveryReport {
+        if subscribers.is_empty() {
+            return DeliveryReport::default();
+        }
+
+        let per_chat = PerChat {
+            replies: self.reply_targets(options.reply_to).await,
+            metadata: self.personalization(tenant, options).await,
+            links: self.click_tracking(tenant, message, options).await,
+        };
+        let mut failed = Vec::new();
+        let deliveries = crate::delivery::deliver_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            subscribers,
+            message,
+            options,
+            &per_chat,
+        )
+        .inspect(|(recipient, result)| {
+            if let Err(failure) = result {
+                failed.push((recipient.clone(), *failure));
+            }
+        });
+        let (mut report, sent) = self
+            .track(tenant, channel_name, deliveries, on_progress)
+            .await;
+        self.record_dead_letters(tenant, channel_name, message, options, &failed)
+            .await;
+        let copies: Vec<_> = sent
+            .into_iter()
+            .map(|(recipient, id)| MessageCopy {
+                telegram_id: recipient.telegram_id,
+                bot: recipient.bot,
+                telegram_message_id: id.0 as i64,
+            })
+            .collect();
+
+        report.message_id = self
+            .store_message(
+                tenant,
+                channel_name,
+                message,
+                &copies,
+                per_chat.links.as_ref(),
+                options,
+            )
+            .await;
+        report
+    }
+
+    /// Keeps a text message for the recipients it couldn't reach, so it can be sent
+    /// again once the cause is fixed. Users who blocked the bot are left out.
+    async fn record_dead_letters(
+        &self,
+        tenant: &Tenant,
+        channel_name: Option<&str>,
+        message: &str,
+        options: &SendOptions,
+        failed: &[(Recipient, Failure)],
+    ) {
+        let failed: Vec<_> = failed
+            .iter()
+            .filter(|(_, failure)| *failure != Failure::BlockedByUser)
+            .map(|(recipient, failure)| (recipient.clone(), failure.as_str()))
+            .collect();
+        if failed.is_empty() {
+            return;
+        }
+
+        let options = serde_json::to_string(options).expect("send options serialize");
+        if let Err(e) = crate::db::record_dead_letters(
+            &self.pool,
+            &tenant.id,
+            channel_name,
+            message,
+            &options,
+            &failed,
+        )
+        .await
+        {
+            log::error!("Failed to record dead letters: {}", e);
+        }
+    }
+
+    /// Sends a dead letter again, replacing it with a new one if it fails again
+    pub async fn retry_dead_letter(
+        &self,
+        tenant: &Tenant,
+        id: i64,
+    ) -> Result<DeliveryReport, ServiceError> {
+        let letter = crate::db::find_dead_letter(&self.pool, &tenant.id, id)
+            .await?
+            .ok_or(ServiceError::NotFound(
+                ErrorCode::DeadLetterNotFound,
+                "Dead letter not found",
+            ))?;
+        let options: SendOptions =
+            serde_json::from_str(&letter.options).map_err(|e| ServiceError::Database(e.into()))?;
+        self.check_quota(tenant, 1).await?;
+
+        let recipient = Recipient {
+            telegram_id: letter.telegram_id,
+            bot: letter.bot,
+        };
+        let report = self
+            .deliver(
+                tenant,
+                letter.channel_name.as_deref(),
+                vec![recipient].into(),
+                &letter.message,
+                &options,
+                |_, _, _| {},
+            )
+            .await;
+        crate::db::delete_dead_letter(&self.pool, &tenant.id, id).await?;
+        Ok(report)
+    }
+
+    /// Telegram message id of each recipient's copy of a stored message, by chat
+    async fn reply_targets(&self, message_id: Option<i64>) -> HashMap<i64, MessageId> {
+        let Some(message_id) = message_id else {
+            return HashMap::new();
+        };
+        match crate::db::get_message_copies(&self.pool, message_id).await {
+            Ok(copies) => copies
+                .into_iter()
+                .map(|copy| {
+                    let id = MessageId(copy.telegram_message_id as i32);
+                    (copy.telegram_id, id)
+                })
+                .collect(),
+            Err(e) => {
+                log::error!("Database error: {}", e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Stored metadata of the tenant's subscribers by chat, when `options` asks to
+    /// personalize. Without it the message is still rendered, with empty metadata.
+    async fn personalization(
+        &self,
+        tenant: &Tenant,
+        options: &SendOptions,
+    ) -> Option<HashMap<i64, serde_json::Value>> {
+        if !options.personalize {
+            return None;
+        }
+        let metadata = crate::db::list_subscriber_metadata(&self.pool, &tenant.id)
+            .await
+            .inspect_err(|e| log::error!("Database error: {}", e))
+            .unwrap_or_default();
+        Some(metadata)
+    }
+
+    /// Tracked links for the URLs in `message`, when `options` asks to track clicks and
+    /// there are any
+    async fn click_tracking(
+        &self,
+        tenant: &Tenant,
+        message: &str,
+        options: &SendOptions,
+    ) -> Option<TrackedLinks> {
+        if !options.track_clicks {
+            return None;
+        }
+        let Some(base_url) = &self.config.public_url else {
+            log::warn!("Not tracking clicks without PUBLIC_URL");
+            return None;
+        };
+        let links: Vec<_> = crate::links::find_urls(message, options.parse_mode)
+            .into_iter()
+            .map(|url| (url, crate::links::new_token()))
+            .collect();
+        if links.is_empty() {
+            return None;
+        }
+        if let Err(e) = crate::db::create_tracked_links(&self.pool, &tenant.id, &links).await {
+            log::error!("Failed to track links for tenant '{}': {}", tenant.id, e);
+            return None;
+        }
+        Some(TrackedLinks {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            tokens: links.into_iter().collect(),
+            secret: self.config.super_secret_key.clone(),
+        })
+    }
+
+    /// Drives a stream of deliveries, publishing an event per recipient and recording
+    /// usage, returning the report and what each successful send returned
+    async fn track<T>(
+        &self,
+        tenant: &Tenant,
+        channel_name: Option<&str>,
+        deliveries: impl Stream<Item = (Recipient, Result<T, Failure>)>,
+        mut on_progress: impl FnMut(&Recipient, bool, &DeliveryReport),
+    ) -> (DeliveryReport, Vec<(Recipient, T)>) {
+        let mut report = DeliveryReport::default();
+        let mut sent = Vec::new();
+        let mut outcomes = Vec::new();
+        let mut deliveries = std::pin::pin!(deliveries);
+        while let Some((recipient, result)) = deliveries.next().await {
+            let delivered = result.is_ok();
+            report.record(recipient.telegram_id, result.as_ref().err().copied());
+            self.publish_delivery(tenant, channel_name, &recipient, delivered);
+            on_progress(&recipient, delivered, &report);
+            let reach = match &result {
+                Ok(_) => Some(Reach::Delivered),
+                Err(Failure::BlockedByUser) => Some(Reach::Blocked),
+                Err(Failure::ChatNotFound) => Some(Reach::Unreachable),
+                // Not the recipient's doing
+                Err(_) => None,
+            };
+            if let Some(reach) = reach {
+                outcomes.push((recipient.telegram_id, reach));
+                if outcomes.len() >= CHECKPOINT_SIZE {
+                    self.record_deliveries(tenant, &std::mem::take(&mut outcomes))
+                        .await;
+                }
+            }
+            if let Ok(result) = result {
+                sent.push((recipient, result));
+            }
+        }
+        self.record_deliveries(tenant, &outcomes).await;
+
+        let attempted = report.sent + report.errors;
+        if attempted > 0 {
+            self.record_usage(tenant, attempted).await;
+            self.record_stats(tenant, channel_name, &report).await;
+        }
+        (report, sent)
+    }
+
+    /// Keeps the delivered copies of a message so they can be edited later, along with
+    /// the links tracked in it
+    async fn store_message(
+        &self,
+        tenant: &Tenant,
+        channel_name: Option<&str>,
+        message: &str,
+        copies: &[MessageCopy],
+        links: Option<&TrackedLinks>,
+        options: &SendOptions,
+    ) -> Option<i64> {
+        let result = async {
+            let id =
+                crate::db::create_message(&self.pool, &tenant.id, channel_name, message).await?;
+            if let Some(footer) = &options.footer {
+                crate::db::set_message_footer(&self.pool, id, footer).await?;
+            }
+            let format = MessageFormat {
+                parse_mode: options.parse_mode,
+                buttons: options.buttons.clone(),
+            };
+            crate::db::set_message_format(&self.pool, id, &serde_json::to_string(&format)?).await?;
+            crate::db::record_message_copies(&self.pool, id, copies).await?;
+            if let Some(links) = links {
+                let tokens = links.tokens.values().map(String::as_str);
+                crate::db::attach_tracked_links(&self.pool, id, tokens).await?;
+            }
+            anyhow::Ok(id)
+        }
+        .await;
+        match result {
+            Ok(id) => Some(id),
+            Err(e) => {
+                log::error!("Failed to store message for tenant '{}': {}", tenant.id, e);
+                None
+            }
+        }
+    }
+
+    /// Replaces the text of every delivered copy of a stored message, returning the
+    /// updated message and how many copies were edited
+    pub async fn edit_message(
+        &self,
+        tenant: &Tenant,
+        id: i64,
+        text: &str,
+    ) -> Result<(StoredMessage, DeliveryReport), ServiceError> {
+        if text.is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                ErrorCode::MessageEmpty,
+                "Message cannot be empty",
+            ));
+        }
+
+        if text_len(text) > MAX_MESSAGE_CHARS {
+            return Err(ServiceError::InvalidRequest(
+                ErrorCode::MessageTooLong,
+                "Message too long (max 1000 chars)",
+            ));
+        }
+
+        let Some(mut message) = crate::db::find_message(&self.pool, &tenant.id, id).await? else {
+            return Err(ServiceError::NotFound(
+                ErrorCode::MessageNotFound,
+                "Message not found",
+            ));
+        };
+
+        // Without the parse mode and buttons the copies were sent with, Telegram would
+        // show the markup as is and drop the keyboard
+        let format: MessageFormat = crate::db::get_message_format(&self.pool, id)
+            .await?
+            .and_then(|format| serde_json::from_str(&format).ok())
+            .unwrap_or_default();
+        // Keeps the header and footer the copies were sent with, including a footer
+        // the send chose instead of the channel's
+        let (text, buttons) = match &message.channel_name {
+            Some(channel_name) => {
+                let settings =
+                    crate::db::get_channel_settings(&self.pool, &tenant.id, channel_name)
+                        .await?
+                        .unwrap_or_default();
+                let footer = crate::db::get_message_footer(&self.pool, id).await?;
+                let settings = with_footer(settings, footer.as_deref());
+                (
+                    framed(channel_name, &settings, text),
+                    edited_buttons(format.buttons, channel_name, &settings),
+                )
+            }
+            None => (text.to_string(), format.buttons),
+        };
+        let text = text.as_str();
+        let keyboard = &crate::delivery::keyboard(&buttons);
+        let parse_mode = format.parse_mode;
+
+        let report = self
+            .for_each_copy(id, |bot, chat_id, message_id| async move {
+                let mut request = bot.edit_message_text(chat_id, message_id, text);
+                request.parse_mode = parse_mode;
+                request.reply_markup = keyboard.clone();
+                request.await
+            })
+            .await?;
+
+        crate::db::update_message_text(&self.pool, id, text).await?;
+        message.text = text.to_string();
+        Ok((message, report))
+    }
+
+    /// Deletes every delivered copy of a stored message from the recipients' chats
+    /// and forgets it, within the window Telegram allows bots to delete messages in
+    pub async fn delete_message(
+        &self,
+        tenant: &Tenant,
+        id: i64,
+    ) -> Result<DeliveryReport, ServiceError> {
+        let Some(message) = crate::db::find_message(&self.pool, &tenant.id, id).await? else {
+            return Err(ServiceError::NotFound(
+                ErrorCode::MessageNotFound,
+                "Message not found",
+            ));
+        };
+
+        if chrono::Utc::now().timestamp() - message.created_at > DELETE_WINDOW_SECS {
+            return Err(ServiceError::invalid(
+                "Messages can only be deleted within 48 hours of sending",
+            ));
+        }
+
+        let report = self
+            .for_each_copy(id, |bot, chat_id, message_id| async move {
+                bot.delete_message(chat_id, message_id).await
+            })
+            .await?;
+
+        crate::db::delete_message(&self.pool, &tenant.id, id).await?;
+        Ok(report)
+    }
+
+    /// Pins every delivered copy of a stored message in its recipient's chat
+    pub async fn pin_message(
+        &self,
+        tenant: &Tenant,
+        id: i64,
+    ) -> Result<DeliveryReport, ServiceError> {
+        if crate::db::find_message(&self.pool, &tenant.id, id)
+            .await?
+            .is_none()
+        {
+            return Err(ServiceError::NotFound(
+                ErrorCode::MessageNotFound,
+                "Message not found",
+            ));
+        }
+
+        self.for_each_copy(id, |bot, chat_id, message_id| async move {
+            bot.pin_chat_message(chat_id, message_id).await
+        })
+        .await
+    }
+
+    /// Unpins every delivered copy of a stored message
+    pub async fn unpin_message(
+        &self,
+        tenant: &Tenant,
+        id: i64,
+    ) -> Result<DeliveryReport, ServiceError> {
+        if crate::db::find_message(&self.pool, &tenant.id, id)
+            .await?
+            .is_none()
+        {
+            return Err(ServiceError::NotFound(
+                ErrorCode::MessageNotFound,
+                "Message not found",
+            ));
+        }
+
+        self.for_each_copy(id, |bot, chat_id, message_id| async move {
+            bot.unpin_chat_message(chat_id).message_id(message_id).await
+        })
+        .await
+    }
+
+    /// Runs `action` concurrently on every delivered copy of a stored message, counting
+    /// the copies it succeeded on as sent
+    async fn for_each_copy<F, Fut, T>(
+        &self,
+        message_id: i64,
+        action: F,
+    ) -> Result<DeliveryReport, ServiceError>
+    where
+        F: Fn(Bot, ChatId, MessageId) -> Fut,
+        Fut: Future<Output = Result<T, RequestError>>,
+    {
+        let copies = crate::db::get_message_copies(&self.pool, message_id).await?;
+        let report = copies
+            .into_iter()
+            .map(|copy| {
+                let bot = self.bots.get(&copy.bot).cloned();
+                if bot.is_none() {
+                    log::error!("Bot '{}' is not configured", copy.bot);
+                }
+                let action = bot.map(|bot| {
+                    action(
+                        bot,
+                        ChatId(copy.telegram_id),
+                        MessageId(copy.telegram_message_id as i32),
+                    )
+                });
+                async move {
+                    let failure = match action {
+                        Some(action) => action.await.err().map(Failure::from),
+                        None => Some(Failure::Other),
+                    };
+                    (copy.telegram_id, failure)
+                }
+            })
+            .collect::<FuturesUnordered<_>>()
+            .fold(
+                DeliveryReport::default(),
+                |mut report, (telegram_id, failure)| async move {
+                    report.record(telegram_id, failure);
+                    report
+                },
+            )
+            .await;
+        Ok(report)
+    }
+
+    /// Sends a native poll to a channel's subscribers, returning the id to fetch its
+    /// results by (`None` when nobody got it) and the delivery report
+    pub async fn send_poll(
+        &self,
+        tenant: &Tenant,
+        poll: &NewPoll,
+    ) -> Result<(Option<i64>, DeliveryReport), ServiceError> {
+        poll.validate().map_err(ServiceError::invalid)?;
+        let channel_name =
+            &crate::db::resolve_channel_name(&self.pool, &tenant.id, &poll.channel_name).await?;
+        let subscribers = self
+            .prepare_send(tenant, channel_name, &poll.question)
+            .await?;
+
+        if subscribers.is_empty() {
+            return Ok((None, DeliveryReport::default()));
+        }
+
+        let keyboard = self.channel_keyboard(tenant, channel_name).await?;
+        let deliveries = crate::polls::send_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            subscribers,
+            poll,
+            &keyboard,
+        );
+        let (report, sent) = self
+            .track(tenant, Some(channel_name), deliveries, |_, _, _| {})
+            .await;
+        let copies: Vec<_> = sent
+            .into_iter()
+            .map(|(recipient, telegram_poll_id)| (recipient.telegram_id, telegram_poll_id))
+            .collect();
+
+        let id = crate::db::create_poll(
+            &self.pool,
+            &tenant.id,
+            channel_name,
+            &poll.question,
+            &poll.options,
+            poll.is_anonymous,
+            poll.allows_multiple_answers,
+        )
+        .await?;
+        crate::db::record_poll_copies(&self.pool, id, &copies).await?;
+        Ok((Some(id), report))
+    }
+
+    /// Sends a location, live or not, or a venue to a channel's subscribers
+    pub async fn send_location(
+        &self,
+        tenant: &Tenant,
+        location: &NewLocation,
+    ) -> Result<DeliveryReport, ServiceError> {
+        location.validate().map_err(ServiceError::invalid)?;
+        let channel_name =
+            &crate::db::resolve_channel_name(&self.pool, &tenant.id, &location.channel_name)
+                .await?;
+        let subscribers = self.prepare_send(tenant, channel_name, "").await?;
+        let keyboard = self.channel_keyboard(tenant, channel_name).await?;
+
+        let deliveries = crate::locations::send_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            subscribers,
+            location,
+            &keyboard,
+        );
+        let (report, _) = self
+            .track(tenant, Some(channel_name), deliveries, |_, _, _| {})
+            .await;
+        Ok(report)
+    }
+
+    /// Sends photos and videos as one album per subscriber
+    pub async fn send_media_group(
+        &self,
+        tenant: &Tenant,
+        group: &NewMediaGroup,
+    ) -> Result<DeliveryReport, ServiceError> {
+        group
+            .validate(self.config.local_bot_api)
+            .map_err(ServiceError::invalid)?;
+        let channel_name =
+            &crate::db::resolve_channel_name(&self.pool, &tenant.id, &group.channel_name).await?;
+        let subscribers = self.prepare_send(tenant, channel_name, "").await?;
+
+        let deliveries = crate::media::send_group_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            subscribers,
+            group,
+        );
+        let (report, _) = self
+            .track(tenant, Some(channel_name), deliveries, |_, _, _| {})
+            .await;
+        Ok(report)
+    }
+
+    /// Sends a single file, e.g. an animation, with an optional caption
+    pub async fn send_media(
+        &self,
+        tenant: &Tenant,
+        kind: MediaKind,
+        media: &NewMedia,
+    ) -> Result<DeliveryReport, ServiceError> {
+        media
+            .validate(self.config.local_bot_api)
+            .map_err(ServiceError::invalid)?;
+        let channel_name =
+            &crate::db::resolve_channel_name(&self.pool, &tenant.id, &media.channel_name).await?;
+        let subscribers = self.prepare_send(tenant, channel_name, "").await?;
+        let keyboard = self.channel_keyboard(tenant, channel_name).await?;
+        if let MediaSource::Upload { .. } = media.media {
+            return Ok(self
+                .send_upload(tenant, channel_name, kind, media, &keyboard, subscribers)
+                .await);
+        }
+
+        let deliveries = crate::media::send_media_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            subscribers,
+            kind,
+            media,
+            &media.media,
+            &keyboard,
+        );
+        let (report, _) = self
+            .track(tenant, Some(channel_name), deliveries, |_, _, _| {})
+            .await;
+        Ok(report)
+    }
+
+    /// Uploads the file one recipient at a time until Telegram accepts it, then sends
+    /// the remaining recipients the file_id Telegram assigned to it. file_ids belong to
+    /// a bot, and a channel is always served by the same one.
+    async fn send_upload(
+        &self,
+        tenant: &Tenant,
+        channel_name: &str,
+        kind: MediaKind,
+        media: &NewMedia,
+        keyboard: &Option<InlineKeyboardMarkup>,
+        mut subscribers: Recipients,
+    ) -> DeliveryReport {
+        let channel_name = Some(channel_name);
+        let mut report = DeliveryReport::default();
+        let mut file_id = None;
+        let mut attempts = 0;
+        while file_id.is_none() && attempts < MAX_UPLOAD_ATTEMPTS {
+            let Some(recipient) = subscribers.next().await else {
+                return report;
+            };
+            attempts += 1;
+            let upload = crate::media::send_media_each(
+                &self.bots,
+                &self.lanes,
+                &self.config.retry,
+                futures::stream::iter([recipient]),
+                kind,
+                media,
+                &media.media,
+                keyboard,
+            );
+            let (uploaded, sent) = self.track(tenant, channel_name, upload, |_, _, _| {}).await;
+            report.merge(uploaded);
+            file_id = sent
+                .first()
+                .and_then(|(_, message)| crate::media::sent_file_id(message, kind));
+        }
+
+        // Falls back to uploading to each of them if no upload went through
+        let source = match file_id {
+            Some(file_id) => MediaSource::Reference(file_id.0),
+            None => media.media.clone(),
+        };
+        let deliveries = crate::media::send_media_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            subscribers,
+            kind,
+            media,
+            &source,
+            keyboard,
+        );
+        let (rest, _) = self
+            .track(tenant, channel_name, deliveries, |_, _, _| {})
+            .await;
+        report.merge(rest);
+        report
+    }
+
+    /// Sends a sticker, given by file_id or picked from a set by emoji
+    pub async fn send_sticker(
+        &self,
+        tenant: &Tenant,
+        sticker: &NewSticker,
+    ) -> Result<DeliveryReport, ServiceError> {
+        sticker.validate().map_err(ServiceError::invalid)?;
+        let channel_name =
+            &crate::db::resolve_channel_name(&self.pool, &tenant.id, &sticker.channel_name).await?;
+        let mut subscribers = self
+            .prepare_send(tenant, channel_name, "")
+            .await?
+            .peekable();
+        let Some(first) = Pin::new(&mut subscribers).peek().await else {
+            return Ok(DeliveryReport::default());
+        };
+
+        let file_id = match (&sticker.sticker, &sticker.set_name, &sticker.emoji) {
+            (Some(file_id), _, _) => FileId(file_id.clone()),
+            (None, Some(set_name), Some(emoji)) => {
+                // file_ids belong to a bot, and a channel is always served by the same one
+                let bot = self
+                    .bots
+                    .get(&first.bot)
+                    .ok_or(ServiceError::invalid("The channel's bot is not configured"))?;
+                let set =
+                    bot.get_sticker_set(set_name)
+                        .await
+                        .map_err(|e| match Failure::from(&e) {
+                            failure @ (Failure::RateLimited | Failure::Network) => {
+                                ServiceError::Telegram(failure)
+                            }
+                            _ => ServiceError::NotFound(
+                                ErrorCode::StickerNotFound,
+                                "Sticker set not found",
+                            ),
+                        })?;
+                crate::media::pick_sticker(&set, emoji).ok_or(ServiceError::NotFound(
+                    ErrorCode::StickerNotFound,
+                    "No sticker for that emoji in the set",
+                ))?
+            }
+            _ => {
+                return Err(ServiceError::invalid(
+                    "Provide either a sticker file_id or a set_name and emoji",
+                ));
+            }
+        };
+
+        let keyboard = self.channel_keyboard(tenant, channel_name).await?;
+        let deliveries = crate::media::send_sticker_each(
+            &self.bots,
+            &self.lanes,
+            &self.config.retry,
+            subscribers,
+            &file_id,
+            &keyboard,
+        );
+        let (report, _) = self
+            .track(tenant, Some(channel_name), deliveries, |_, _, _| {})
+            .await;
+        Ok(report)
+    }
+
+    pub async fn poll_results(
+        &self,
+        tenant: &Tenant,
+        id: i64,
+    ) -> Result<PollResults, ServiceError> {
+        let Some(poll) = crate::db::find_poll(&self.pool, &tenant.id, id).await? else {
+            return Err(ServiceError::NotFound(
+                ErrorCode::PollNotFound,
+                "Poll not found",
+            ));
+        };
+
+        let answers = crate::db::get_poll_answers(&self.pool, id).await?;
+        let states = crate::db::get_poll_states(&self.pool, id).await?;
+        let recipients = crate::db::count_poll_copies(&self.pool, id).await?;
+        Ok(crate::polls::tally(
+            &poll,
+            &answers,
+            &states,
+            recipients as u64,
+        )?)
+    }
+
+    /// Queues a prepared broadcast and delivers it in the background, returning the
+    /// job id to follow it by
+    pub async fn start_broadcast(
+        &self,
+        tenant: Tenant,
+        channels: Option<&[String]>,
+        message: String,
+        options: SendOptions,
+        pin: bool,
+    ) -> Result<String, ServiceError> {
+        let mut job = BroadcastJob {
+            id: crate::jobs::new_id(),
+            tenant_id: tenant.id.clone(),
+            message,
+            options: serde_json::to_string(&options).expect("send options serialize"),
+            pin,
+            total: 0,
+            sent: 0,
+            errors: 0,
+        };
+        job.total = crate::db::create_broadcast_job(&self.pool, &job, channels).await?;
+
+        let id = job.id.clone();
+        self.run_broadcast(tenant, job);
+        Ok(id)
+    }
+
+    /// Picks up the broadcasts still queued when the process last stopped, delivering
+    /// to the recipients they hadn't reached. They no longer count against the quota
+    /// of the API key that started them, only the tenant's.
+    pub async fn resume_broadcasts(&self) -> anyhow::Result<()> {
+        for job in crate::db::list_broadcast_jobs(&self.pool).await? {
+            log::info!(
+                "Resuming broadcast {} with {} of {} recipients left",
+                job.id,
+                job.total - job.sent - job.errors,
+                job.total
+            );
+            self.run_broadcast(Tenant::new(&job.tenant_id), job);
+        }
+        Ok(())
+    }
+
+    /// Delivers a queued broadcast to the recipients it hasn't reached, recording the
+    /// attempts a chunk at a time so a restart resumes after the last one recorded, and
+    /// takes it off the queue once done. Progress is reported as each chunk is recorded.
+    fn run_broadcast(&self, tenant: Tenant, job: BroadcastJob) {
+        let (sent_before, errors_before) = (job.sent as usize, job.errors as usize);
+        let progress = self.jobs.register(
+            &job.id,
+            &tenant.id,
+            JobProgress {
+                total: job.total as usize,
+                sent: sent_before,
+                errors: errors_before,
+                ..Default::default()
+            },
+        );
+        let options: SendOptions = serde_json::from_str(&job.options).unwrap_or_else(|e| {
+            log::error!("Invalid options for broadcast {}: {}", job.id, e);
+            SendOptions::default()
+        });
+        let services = self.clone();
+
+        tokio::spawn(async move {
+            let mut cancellation = progress.subscribe();
+            let cancelled = async move {
+                // The sender lives as long as this task, so this only returns once cancelled
+                let _ = cancellation.wait_for(|p| p.cancelled).await;
+            };
+            let per_chat = PerChat {
+                replies: services.reply_targets(options.reply_to).await,
+                metadata: services.personalization(&tenant, &options).await,
+                links: services
+                    .click_tracking(&tenant, &job.message, &options)
+                    .await,
+            };
+            let (pool, job_id) = (&services.pool, job.id.as_str());
+            let recipients = Recipients::from_db(
+                job.total as usize - sent_before - errors_before,
+                crate::db::stream_pending_job_recipients(pool, job_id),
+            );
+            let mut failed = Vec::new();
+            // Recipients still queued when the job is cancelled are left out
+            let deliveries = crate::delivery::deliver_each(
+                &services.bots,
+                &services.lanes,
+                &services.config.retry,
+                recipients,
+                &job.message,
+                &options,
+                &per_chat,
+            )
+            .take_until(cancelled)
+            .chunks(CHECKPOINT_SIZE)
+            .then(|chunk| checkpoint(pool, job_id, chunk))
+            .flat_map(futures::stream::iter)
+            .inspect(|(recipient, result)| {
+                if let Err(failure) = result {
+                    failed.push((recipient.clone(), *failure));
+                }
+            });
+            services
+                .track(&tenant, None, deliveries, |_, _, report| {
+                    progress.send_modify(|p| {
+                        p.sent = sent_before + report.sent;
+                        p.errors = errors_before + report.errors;
+                    })
+                })
+                .await;
+            services
+                .record_dead_letters(&tenant, None, &job.message, &options, &failed)
+                .await;
+
+            let (attempted, cancelled) = {
+                let progress = progress.borrow();
+                (progress.sent + progress.errors, progress.cancelled)
+            };
+            let mut message_id = None;
+            if attempted > 0 {
+                // Includes the copies delivered before a restart
+                let copies = crate::db::get_job_copies(pool, job_id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Database error: {}", e);
+                        Vec::new()
+                    });
+                message_id = services
+                    .store_message(
+                        &tenant,
+                        None,
+                        &job.message,
+                        &copies,
+                        per_chat.links.as_ref(),
+                        &options,
+                    )
+                    .await;
+            }
+            if let Some(message_id) = message_id.filter(|_| job.pin && !cancelled)
+                && let Err(e) = services.pin_message(&tenant, message_id).await
+            {
+                log::error!("Failed to pin broadcast {}: {}", message_id, e);
+            }
+            if let Err(e) = crate::db::delete_broadcast_job(pool, job_id).await {
+                log::error!("Database error: {}", e);
+            }
+            services.jobs.finish(job.id, message_id).await;
+        });
+    }
+
+    pub fn publish_delivery(
+        &self,
+        tenant: &Tenant,
+        channel_name: Option<&str>,
+        recipient: &Recipient,
+        delivered: bool,
+    ) {
+        self.events.publish(
+            &tenant.id,
+            EventKind::Delivery {
+                telegram_id: recipient.telegram_id,
+                channel_name: channel_name.map(crate::db::normalize_channel_name),
+                delivered,
+            },
+        );
+    }
+
+    /// Tells listeners that subscriptions were removed other than by the subscribers
+    /// themselves, such as with their channel
+    pub fn publish_unsubscribed(
+        &self,
+        tenant_id: &str,
+        channel_name: &str,
+        telegram_ids: impl IntoIterator<Item = i64>,
+    ) {
+        for telegram_id in telegram_ids {
+            self.events.publish(
+                tenant_id,
+                EventKind::Unsubscribed {
+                    telegram_id,
+                    channel_name: channel_name.to_string(),
+                },
+            );
+        }
+    }
+
+    async fn check_quota(&self, tenant: &Tenant, messages: usize) -> Result<(), ServiceError> {
+        let usage = Usage::today(
+            &self.pool,
+            &self.config.quotas,
+            &tenant.id,
+            tenant.api_key.as_ref(),
+        )
+        .await?;
+        if usage.allows(messages) {
+            Ok(())
+        } else {
+            Err(ServiceError::QuotaExceeded {
+                remaining: usage.remaining(),
+            })
+        }
+    }
+
+    pub async fn record_usage(&self, tenant: &Tenant, messages: usize) {
+        let api_key_id = tenant.api_key.as_ref().map(|key| key.id);
+        if let Err(e) =
+            crate::db::record_usage(&self.pool, &tenant.id, api_key_id, messages as i64).await
+        {
+            log::error!("Failed to record usage for tenant '{}': {}", tenant.id, e);
+        }
+    }
+
+    async fn record_deliveries(&self, tenant: &Tenant, outcomes: &[(i64, Reach)]) {
+        if outcomes.is_empty() {
+            return;
+        }
+        if let Err(e) = crate::db::record_deliveries(&self.pool, &tenant.id, outcomes).await {
+            log::error!(
+                "Failed to record deliveries for tenant '{}': {}",
+                tenant.id,
+                e
+            );
+        }
+    }
+
+    async fn record_stats(
+        &self,
+        tenant: &Tenant,
+        channel_name: Option<&str>,
+        report: &DeliveryReport,
+    ) {
+        if let Err(e) = crate::db::record_daily_stats(
+            &self.pool,
+            &tenant.id,
+            channel_name,
+            report.sent as i64,
+            report.errors as i64,
+        )
+        .await
+        {
+            log::error!("Failed to record stats for tenant '{}': {}", tenant.id, e);
+        }
+    }
+}
+
+/// Records the attempts of a chunk of broadcast recipients, handing the chunk back
+async fn checkpoint(
+    pool: &SqlitePool,
+    job_id: &str,
+    chunk: Vec<(Recipient, Result<MessageId, Failure>)>,
+) -> Vec<(Recipient, Result<MessageId, Failure>)> {
+    let attempts: Vec<_> = chunk
+        .iter()
+        .map(|(recipient, result)| {
+            let telegram_message_id = result.as_ref().ok().map(|id| id.0 as i64);
+            (recipient.telegram_id, telegram_message_id)
+        })
+        .collect();
+    if let Err(e) = crate::db::record_job_deliveries(pool, job_id, &attempts).await {
+        log::error!("Database error: {}", e);
+    }
+    chunk
+}
+
+/// `settings` with the footer template a send chose instead of the channel's, `""`
+/// leaving the footer out
+fn with_footer(mut settings: ChannelSettings, footer: Option<&str>) -> ChannelSettings {
+    if let Some(footer) = footer {
+        settings.footer = Some(footer.to_string()).filter(|footer| !footer.is_empty());
+    }
+    settings
+}
+
+/// Parse mode and buttons a message was sent with, stored so edits keep them
+#[derive(Default, Serialize, Deserialize)]
+struct MessageFormat {
+    parse_mode: Option<ParseMode>,
+    #[serde(default)]
+    buttons: Vec<Vec<CallbackButton>>,
+}
+
+/// Buttons to keep under an edited message: those the send chose, and the ones the
+/// channel adds now in place of those it added then
+fn edited_buttons(
+    mut sent: Vec<Vec<CallbackButton>>,
+    channel_name: &str,
+    settings: &ChannelSettings,
+) -> Vec<Vec<CallbackButton>> {
+    sent.retain(|row| {
+        !row.iter()
+            .any(|button| button.data.starts_with(crate::bot::UNSUBSCRIBE_DATA))
+    });
+    sent.extend(channel_buttons(channel_name, settings));
+    sent
+}
+
+/// Rows of buttons the channel's `settings` add under its messages
+fn channel_buttons(channel_name: &str, settings: &ChannelSettings) -> Vec<Vec<CallbackButton>> {
+    if !settings.unsubscribe_button {
+        return Vec::new();
+    }
+    vec![vec![CallbackButton {
+        text: crate::bot::UNSUBSCRIBE_BUTTON.to_string(),
+        data: crate::bot::unsubscribe_data(channel_name),
+    }]]
+}
+
+/// `message` between the channel's rendered header and footer
+fn framed(channel_name: &str, settings: &ChannelSettings, message: &str) -> String {
+    let data = serde_json::json!({ "channel": channel_name });
+    let render = |template: &Option<String>| {
+        let template = template.as_deref()?;
+        crate::hooks::render(template, &data)
+            .map_err(|e| log::error!("Failed to render template of '{}': {}", channel_name, e))
+            .ok()
+    };
+    crate::delivery::frame(
+        render(&settings.header).as_deref(),
+        message,
+        render(&settings.footer).as_deref(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_broadcast_to_alias(pool: SqlitePool) -> anyhow::Result<()> {
+        let config = Config::default();
+        let services = Services {
+            pool: pool.clone(),
+            bots: Bots::new(std::collections::BTreeMap::new()),
+            events: EventBus::new(),
+            lanes: config.lanes()?,
+            config: Arc::new(config),
+            jobs: Default::default(),
+            channel_limits: Default::default(),
+        };
+        let tenant = Tenant::new(crate::db::DEFAULT_TENANT);
+        let subscribe = |telegram_id, channel_name| {
+            crate::db::subscribe(
+                &pool,
+                crate::db::DEFAULT_TENANT,
+                telegram_id,
+                channel_name,
+                crate::bot::DEFAULT_BOT,
+            )
+        };
+        subscribe(111, "incidents").await?;
+        subscribe(222, "news").await?;
+        crate::db::set_channel_alias(&pool, crate::db::DEFAULT_TENANT, "alerts", "incidents")
+            .await?;
+
+        let channels = ["Alerts".to_string()];
+        let recipients = services
+            .prepare_broadcast(&tenant, "Outage", Some(&channels))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(recipients.total, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_footer_override() {
+        let settings = ChannelSettings {
+            header: Some("{{channel}} deals".to_string()),
+            footer: Some("Reply /unsubscribe {{channel}} to stop".to_string()),
+            ..Default::default()
+        };
+        let frame = |footer: Option<&str>| {
+            framed("deals", &with_footer(settings.clone(), footer), "50% off")
+        };
+        assert_eq!(
+            frame(None),
+            "deals deals\n\n50% off\n\nReply /unsubscribe deals to stop"
+        );
+        assert_eq!(
+            frame(Some("Ends {{channel}} week")),
+            "deals deals\n\n50% off\n\nEnds deals week"
+        );
+        assert_eq!(frame(Some("")), "deals deals\n\n50% off");
+    }
+
+    #[test]
+    fn test_edited_buttons() {
+        let button = |text: &str, data: &str| CallbackButton {
+            text: text.to_string(),
+            data: data.to_string(),
+        };
+        let mut settings = ChannelSettings {
+            unsubscribe_button: true,
+            ..Default::default()
+        };
+        let sent = channel_buttons("deals", &settings);
+        let sent = [vec![vec![button("Approve", "approve:1")]], sent].concat();
+
+        // The keyboard survives the edit, the Unsubscribe button once
+        let kept = edited_buttons(sent.clone(), "deals", &settings);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0][0].data, "approve:1");
+        assert_eq!(kept[1][0].data, crate::bot::unsubscribe_data("deals"));
+        assert!(crate::delivery::keyboard(&kept).is_some());
+
+        settings.unsubscribe_button = false;
+        let kept = edited_buttons(sent, "deals", &settings);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0][0].data, "approve:1");
+    }
+}
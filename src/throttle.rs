@@ -0,0 +1,135 @@
+//! Per-channel limits on how many messages go out each minute, and per-chat limits on
+//! how many bot commands are handled
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// When each channel's messages in the last minute went out, by tenant and channel name
+type Sent = HashMap<(String, String), VecDeque<Instant>>;
+
+#[derive(Clone, Default)]
+pub struct ChannelLimits {
+    sent: Arc<Mutex<Sent>>,
+}
+
+impl ChannelLimits {
+    /// Waits until the channel has sent fewer than `per_minute` messages in the last
+    /// minute, then counts one more
+    pub async fn wait(&self, tenant_id: &str, channel_name: &str, per_minute: u32) {
+        let key = (
+            tenant_id.to_string(),
+            crate::db::normalize_channel_name(channel_name),
+        );
+        loop {
+            let wait = {
+                let mut sent = self.sent.lock().expect("throttle lock poisoned");
+                let times = sent.entry(key.clone()).or_default();
+                match reserve(times, Instant::now(), per_minute) {
+                    None => return,
+                    Some(wait) => wait,
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// When each chat's commands in the last minute were handled, by chat id
+#[derive(Clone, Default)]
+pub struct ChatLimits {
+    received: Arc<Mutex<HashMap<i64, ChatWindow>>>,
+}
+
+/// A chat's commands handled in the last minute
+#[derive(Default)]
+struct ChatWindow {
+    times: VecDeque<Instant>,
+    /// It was told it's over the limit since its last command went through
+    warned: bool,
+}
+
+impl ChatLimits {
+    /// Counts a command from `chat_id` if it sent fewer than `per_minute` in the last
+    /// minute. Otherwise returns how long until it may send another, only the first time
+    /// it hits the limit, so it's told once, and `None` for the commands after that.
+    pub fn check(&self, chat_id: i64, per_minute: u32) -> Result<(), Option<Duration>> {
+        let now = Instant::now();
+        let mut received = self.received.lock().expect("throttle lock poisoned");
+        // Forget chats quiet for a minute, so the map only holds active ones
+        received.retain(|_, window| {
+            window
+                .times
+                .back()
+                .is_some_and(|&last| now.duration_since(last) < WINDOW)
+        });
+        let window = received.entry(chat_id).or_default();
+        match reserve(&mut window.times, now, per_minute) {
+            None => {
+                window.warned = false;
+                Ok(())
+            }
+            Some(wait) => {
+                let first = !window.warned;
+                window.warned = true;
+                Err(Some(wait).filter(|_| first))
+            }
+        }
+    }
+}
+
+/// Records a message sent at `now` if the window has room, otherwise returns how long
+/// until the oldest one leaves it
+fn reserve(times: &mut VecDeque<Instant>, now: Instant, per_minute: u32) -> Option<Duration> {
+    while times
+        .front()
+        .is_some_and(|&sent| now.duration_since(sent) >= WINDOW)
+    {
+        times.pop_front();
+    }
+    if times.len() < per_minute as usize {
+        times.push_back(now);
+        return None;
+    }
+    times
+        .front()
+        .map(|&oldest| WINDOW - now.duration_since(oldest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let start = Instant::now();
+        let mut times = VecDeque::new();
+        assert_eq!(reserve(&mut times, start, 2), None);
+        let later = start + Duration::from_secs(20);
+        assert_eq!(reserve(&mut times, later, 2), None);
+
+        let full = start + Duration::from_secs(30);
+        assert_eq!(reserve(&mut times, full, 2), Some(Duration::from_secs(30)));
+        assert_eq!(times.len(), 2);
+
+        // The first message left the window
+        assert_eq!(reserve(&mut times, start + WINDOW, 2), None);
+        assert_eq!(times, [later, start + WINDOW]);
+    }
+
+    #[test]
+    fn test_chat_limits() {
+        let limits = ChatLimits::default();
+        assert!(limits.check(1, 2).is_ok());
+        assert!(limits.check(1, 2).is_ok());
+        let wait = limits.check(1, 2).unwrap_err().unwrap();
+        assert!(wait > Duration::ZERO && wait <= WINDOW);
+        // Told once, then ignored until the window has room again
+        assert_eq!(limits.check(1, 2), Err(None));
+        assert_eq!(limits.check(1, 2), Err(None));
+        // Other chats have their own window
+        assert!(limits.check(2, 2).is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+//! HTTPS for the API server, with the certificate read again on SIGHUP
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result};
+use rustls::ServerConfig;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+use crate::config::TlsConfig;
+
+/// Certificate and key last loaded from the files in [`TlsConfig`], handed to every handshake
+#[derive(Debug)]
+pub struct Certificate {
+    tls: TlsConfig,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl Certificate {
+    fn new(tls: &TlsConfig, provider: Arc<CryptoProvider>) -> Result<Self> {
+        let current = load(tls, &provider)?;
+        Ok(Self {
+            tls: tls.clone(),
+            provider,
+            current: RwLock::new(Arc::new(current)),
+        })
+    }
+
+    /// Reads the files again, keeping the current certificate if they can't be loaded
+    pub fn reload(&self) -> Result<()> {
+        let reloaded = load(&self.tls, &self.provider)?;
+        *self.current.write().expect("certificate lock poisoned") = Arc::new(reloaded);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for Certificate {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .expect("certificate lock poisoned")
+                .clone(),
+        )
+    }
+}
+
+fn load(tls: &TlsConfig, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    let chain = CertificateDer::pem_file_iter(&tls.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificate {}", tls.cert_path.display()))?;
+    if chain.is_empty() {
+        anyhow::bail!("No certificate in {}", tls.cert_path.display());
+    }
+    let key = PrivateKeyDer::from_pem_file(&tls.key_path)
+        .with_context(|| format!("Failed to read private key {}", tls.key_path.display()))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .with_context(|| format!("Unsupported private key {}", tls.key_path.display()))?;
+
+    let certified = CertifiedKey::new(chain, key);
+    certified
+        .keys_match()
+        .context("The private key doesn't belong to the certificate")?;
+    Ok(certified)
+}
+
+/// Server config presenting the certificate from `tls`, reloadable through the returned handle
+pub fn server_config(tls: &TlsConfig) -> Result<(ServerConfig, Arc<Certificate>)> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let certificate = Arc::new(Certificate::new(tls, provider.clone())?);
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(certificate.clone());
+    Ok((config, certificate))
+}
+
+/// Reloads the certificate each time the process gets SIGHUP, e.g. after a renewal
+pub async fn reload_on_sighup(certificate: Arc<Certificate>) {
+    #[cfg(unix)]
+    {
+        let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("SIGHUP handler should install");
+        while sighup.recv().await.is_some() {
+            match certificate.reload() {
+                Ok(()) => log::info!("TLS certificate reloaded"),
+                Err(e) => log::error!("Failed to reload TLS certificate: {:#}", e),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    drop(certificate);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_files() {
+        let tls = TlsConfig {
+            cert_path: "/nonexistent/cert.pem".into(),
+            key_path: "/nonexistent/key.pem".into(),
+        };
+        let e = server_config(&tls).unwrap_err();
+        assert!(e.to_string().contains("/nonexistent/cert.pem"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Signed POSTs to operator endpoints when users subscribe or unsubscribe
+
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::WebhookConfig;
+use crate::events::{Event, EventBus, EventKind};
+
+/// Forwards subscription events from the bus to the configured URLs until the bus closes
+pub async fn run(config: WebhookConfig, events: EventBus) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("HTTP client should build");
+    let mut receiver = events.subscribe();
+
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                log::warn!("Webhooks lagging, skipped {} events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let urls = targets(&config, &event);
+        if urls.is_empty() {
+            continue;
+        }
+
+        let body = serde_json::to_vec(&event).expect("events serialize");
+        let signature = sign(&config.secret, &body);
+        for url in urls {
+            let request = client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header("X-Signature", format!("sha256={}", signature))
+                .body(body.clone());
+            tokio::spawn(async move {
+                match request.send().await.and_then(|r| r.error_for_status()) {
+                    Ok(_) => {}
+                    Err(e) => log::error!("Webhook delivery failed: {}", e),
+                }
+            });
+        }
+    }
+}
+
+/// URLs interested in the event: the global ones plus those of its channel
+fn targets<'a>(config: &'a WebhookConfig, event: &Event) -> Vec<&'a str> {
+    let channel_name = match &event.kind {
+        EventKind::Subscribed { channel_name, .. }
+        | EventKind::Unsubscribed { channel_name, .. } => channel_name,
+        EventKind::Delivery { .. } => return Vec::new(),
+    };
+
+    config
+        .urls
+        .iter()
+        .chain(
+            config
+                .channels
+                .iter()
+                .filter(|(name, _)| name.to_lowercase() == channel_name.to_lowercase())
+                .flat_map(|(_, urls)| urls),
+        )
+        .map(String::as_str)
+        .collect()
+}
+
+/// Hex HMAC-SHA256 of the body, so receivers can check the request came from us
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn event(kind: EventKind) -> Event {
+        Event {
+            tenant_id: "default".to_string(),
+            timestamp: chrono::Utc::now(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_targets() {
+        let config = WebhookConfig {
+            secret: String::new(),
+            urls: vec!["https://crm/all".to_string()],
+            channels: BTreeMap::from([("news".to_string(), vec!["https://crm/news".to_string()])]),
+        };
+
+        let subscribed = event(EventKind::Subscribed {
+            telegram_id: 1,
+            channel_name: "news".to_string(),
+        });
+        assert_eq!(
+            targets(&config, &subscribed),
+            vec!["https://crm/all", "https://crm/news"]
+        );
+
+        let unsubscribed = event(EventKind::Unsubscribed {
+            telegram_id: 1,
+            channel_name: "sports".to_string(),
+        });
+        assert_eq!(targets(&config, &unsubscribed), vec!["https://crm/all"]);
+
+        let delivery = event(EventKind::Delivery {
+            telegram_id: 1,
+            channel_name: Some("news".to_string()),
+            delivered: true,
+        });
+        assert!(targets(&config, &delivery).is_empty());
+    }
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}